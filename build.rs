@@ -1,5 +1,11 @@
 fn main() {
-    windows::build! {
-        Windows::Win32::Foundation::{BOOL, HANDLE, PWSTR},
+    // Only the Windows drivers need the Windows bindings
+    if std::env::var("CARGO_CFG_TARGET_OS").map_or(false, |os| os == "windows") {
+        windows::build! {
+            Windows::Devices::Enumeration::{DeviceInformation, DeviceInformationCollection},
+            Windows::Devices::Midi::{IMidiOutPort, MidiOutPort},
+            Windows::Storage::Streams::{DataWriter, IBuffer},
+            Windows::Win32::Foundation::{BOOL, HANDLE, PWSTR},
+        }
     }
 }
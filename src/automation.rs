@@ -0,0 +1,85 @@
+use std::ops::RangeInclusive;
+
+use rimd::MetaCommand;
+
+use crate::midi_file::{self, DataEvent, LocalEvent};
+
+/// A controller moving from one value to another over a span of bars.
+#[derive(Clone)]
+pub struct Ramp {
+    pub channel: u8,
+    pub controller: u8,
+    pub from: u8,
+    pub to: u8,
+    /// Bars counted from 1, the ramp ending with the last one.
+    pub bars: RangeInclusive<u32>,
+}
+
+impl Ramp {
+    /// Control changes at absolute ticks, one for every value on the way.
+    fn messages(&self, bar_length: u64) -> Vec<(u64, [u8; 3])> {
+        let start = (*self.bars.start() as u64).saturating_sub(1) * bar_length;
+        let end = (*self.bars.end() as u64) * bar_length;
+        let steps = self.to.abs_diff(self.from) as u64;
+        let status = 0xb0 | (self.channel & 0x0f);
+
+        (0..=steps)
+            .map(|step| {
+                let time = match steps {
+                    0 => start,
+                    _ => start + (end.saturating_sub(start)) * step / steps,
+                };
+                let value = if self.to >= self.from {
+                    self.from + step as u8
+                } else {
+                    self.from - step as u8
+                };
+
+                (time, [status, self.controller, value])
+            })
+            .collect()
+    }
+}
+
+/// Interleaves the ramps with the events. Bars follow the first time
+/// signature, or 4/4 without one, and ramp values win over the sequence's
+/// own at the same tick.
+pub fn apply(events: Vec<DataEvent>, division: u64, ramps: &[Ramp]) -> Vec<DataEvent> {
+    if ramps.is_empty() {
+        return events;
+    }
+
+    let bar_length = bar_length(&events, division);
+    let mut messages: Vec<(u64, [u8; 3])> = ramps
+        .iter()
+        .flat_map(|ramp| ramp.messages(bar_length))
+        .collect();
+    messages.sort_by_key(|&(time, _)| time);
+
+    midi_file::merge_messages(events, messages)
+}
+
+fn bar_length(events: &[DataEvent], division: u64) -> u64 {
+    let mut time = 0;
+
+    for event in events {
+        time += event.delta_time;
+        if time > 0 {
+            break;
+        }
+
+        if let LocalEvent::Meta(meta) = &event.data {
+            match meta.command {
+                MetaCommand::TimeSignature if meta.data.len() >= 2 => {
+                    let numerator = meta.data[0] as u64;
+                    let denominator = 1u64 << meta.data[1].min(6);
+
+                    return division * 4 * numerator / denominator;
+                }
+                _ => {}
+            };
+        }
+    }
+
+    division * 4
+}
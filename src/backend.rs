@@ -0,0 +1,328 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+#[cfg(windows)]
+pub use crate::driver::WinMidiInput as InputPort;
+#[cfg(not(windows))]
+pub use crate::midir_driver::MidirInput as InputPort;
+
+#[cfg(windows)]
+use crate::driver::WinMidiPort;
+#[cfg(not(windows))]
+use crate::midir_driver::MidirPort;
+use crate::rtp_midi::RtpMidiPort;
+#[cfg(windows)]
+use crate::synth_driver::SynthPort;
+#[cfg(windows)]
+use crate::winrt_driver::WinRtPort;
+
+static SELECTED: AtomicU8 = AtomicU8::new(Backend::Native as u8);
+static SELECTED_RESET: AtomicU8 = AtomicU8::new(Reset::GsGm as u8);
+static SELECTED_SOUNDFONT: Mutex<Option<PathBuf>> = Mutex::new(None);
+static SELECTED_BLE_LATENCY: AtomicU64 = AtomicU64::new(DEFAULT_BLE_LATENCY_MICROS);
+static SELECTED_RTP_SESSIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// A common BLE connection interval, which messages wait for at most.
+const DEFAULT_BLE_LATENCY_MICROS: u64 = 15_000;
+
+pub const GM1_RESET: &[u8] = &[0xf0, 0x7e, 0x7f, 0x09, 0x01, 0xf7];
+pub const GS1_RESET: &[u8] = &[
+    0xf0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7f, 0x00, 0x41, 0xf7,
+];
+pub const XG_RESET: &[u8] = &[0xf0, 0x43, 0x10, 0x4c, 0x00, 0x00, 0x7e, 0x00, 0xf7];
+
+/// Output API used for every port opened by the process.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Backend {
+    /// WinMM on Windows, ALSA or CoreMIDI elsewhere.
+    Native,
+    #[cfg(windows)]
+    WinRt,
+    /// The built-in SoundFont synthesizer, playing on the default audio
+    /// device, for when there is no usable MIDI device.
+    #[cfg(windows)]
+    Synth,
+}
+
+impl Backend {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "native" | "winmm" => Ok(Backend::Native),
+            #[cfg(windows)]
+            "winrt" => Ok(Backend::WinRt),
+            #[cfg(windows)]
+            "synth" => Ok(Backend::Synth),
+            _ => Err(anyhow!("Unknown backend: {}", value)),
+        }
+    }
+}
+
+/// Chooses the output API, before any port is enumerated or opened.
+pub fn select(backend: Backend) {
+    SELECTED.store(backend as u8, Ordering::Relaxed);
+}
+
+/// Chooses the SoundFont the synth backend plays.
+pub fn select_soundfont(path: PathBuf) {
+    *SELECTED_SOUNDFONT.lock().unwrap() = Some(path);
+}
+
+pub fn selected_soundfont() -> Option<PathBuf> {
+    SELECTED_SOUNDFONT.lock().unwrap().clone()
+}
+
+/// Chooses how late BLE MIDI devices sound compared to wired ones.
+pub fn select_ble_latency(latency: Duration) {
+    SELECTED_BLE_LATENCY.store(latency.as_micros() as u64, Ordering::Relaxed);
+}
+
+pub fn selected_ble_latency() -> Duration {
+    Duration::from_micros(SELECTED_BLE_LATENCY.load(Ordering::Relaxed))
+}
+
+/// Chooses the RTP-MIDI hosts, as `host:port` of their control port, listed
+/// after the ports of the output API.
+pub fn select_rtp_sessions(sessions: Vec<String>) {
+    *SELECTED_RTP_SESSIONS.lock().unwrap() = sessions;
+}
+
+pub fn selected_rtp_sessions() -> Vec<String> {
+    SELECTED_RTP_SESSIONS.lock().unwrap().clone()
+}
+
+/// SysEx reset sent when a port is opened and again when it is closed, as
+/// sound modules differ in which they understand.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Reset {
+    /// GM System On.
+    Gm,
+    /// Roland GS reset.
+    Gs,
+    /// Yamaha XG System On.
+    Xg,
+    /// GS reset then GM System On, which most modules take one of.
+    GsGm,
+    None,
+}
+
+impl Reset {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "gm" => Ok(Reset::Gm),
+            "gs" => Ok(Reset::Gs),
+            "xg" => Ok(Reset::Xg),
+            "gs+gm" => Ok(Reset::GsGm),
+            "none" => Ok(Reset::None),
+            _ => Err(anyhow!("Unknown reset: {}", value)),
+        }
+    }
+
+    pub fn messages(self) -> &'static [&'static [u8]] {
+        match self {
+            Reset::Gm => &[GM1_RESET],
+            Reset::Gs => &[GS1_RESET],
+            Reset::Xg => &[XG_RESET],
+            Reset::GsGm => &[GS1_RESET, GM1_RESET],
+            Reset::None => &[],
+        }
+    }
+}
+
+/// Chooses the reset of every port opened from then on.
+pub fn select_reset(reset: Reset) {
+    SELECTED_RESET.store(reset as u8, Ordering::Relaxed);
+}
+
+pub fn selected_reset() -> Reset {
+    match SELECTED_RESET.load(Ordering::Relaxed) {
+        value if value == Reset::Gm as u8 => Reset::Gm,
+        value if value == Reset::Gs as u8 => Reset::Gs,
+        value if value == Reset::Xg as u8 => Reset::Xg,
+        value if value == Reset::None as u8 => Reset::None,
+        _ => Reset::GsGm,
+    }
+}
+
+#[cfg(windows)]
+fn selected() -> Backend {
+    match SELECTED.load(Ordering::Relaxed) {
+        value if value == Backend::WinRt as u8 => Backend::WinRt,
+        value if value == Backend::Synth as u8 => Backend::Synth,
+        _ => Backend::Native,
+    }
+}
+
+/// A connection to a MIDI output port on the platform's native MIDI API.
+pub trait MidiOutput: Sized {
+    fn count() -> u32;
+
+    fn name(port_number: u32) -> Result<String>;
+
+    fn connect(port_number: u32) -> Result<Self>;
+
+    fn send(&mut self, message: &[u8]) -> Result<()>;
+
+    fn send_reset(&mut self) -> Result<()> {
+        for message in selected_reset().messages() {
+            self.send(message).context("Failed to send reset message")?;
+        }
+
+        Ok(())
+    }
+
+    /// Releases long messages the device has finished with.
+    fn check_inflight(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// How much later than sent messages sound, on top of a wired port.
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Blocks until the device is ready to accept the next event.
+    fn wait_ready(&self) {}
+
+    /// Wakes up a pending `wait_ready` call.
+    fn mark_ready(&self) {}
+}
+
+/// A connection to a MIDI input port that forwards every received message to a
+/// channel for as long as it is open.
+pub trait MidiInput: Sized {
+    fn count() -> u32;
+
+    fn name(port_number: u32) -> Result<String>;
+
+    fn connect(port_number: u32, sender: Sender<Vec<u8>>) -> Result<Self>;
+}
+
+/// Output port on whichever API was selected, or an RTP-MIDI session.
+pub enum OutputPort {
+    #[cfg(windows)]
+    WinMm(WinMidiPort),
+    #[cfg(windows)]
+    WinRt(WinRtPort),
+    #[cfg(windows)]
+    Synth(SynthPort),
+    #[cfg(not(windows))]
+    Midir(MidirPort),
+    Rtp(RtpMidiPort),
+}
+
+/// Calls the same method on whichever port is inside.
+macro_rules! dispatch {
+    ($port:expr, $method:ident($($arg:expr),*)) => {
+        match $port {
+            #[cfg(windows)]
+            OutputPort::WinMm(port) => port.$method($($arg),*),
+            #[cfg(windows)]
+            OutputPort::WinRt(port) => port.$method($($arg),*),
+            #[cfg(windows)]
+            OutputPort::Synth(port) => port.$method($($arg),*),
+            #[cfg(not(windows))]
+            OutputPort::Midir(port) => port.$method($($arg),*),
+            OutputPort::Rtp(port) => port.$method($($arg),*),
+        }
+    };
+}
+
+#[cfg(windows)]
+fn device_count() -> u32 {
+    match selected() {
+        Backend::Native => WinMidiPort::count() + WinRtPort::ble_count(),
+        Backend::WinRt => WinRtPort::count(),
+        Backend::Synth => SynthPort::count(),
+    }
+}
+
+#[cfg(windows)]
+fn device_name(port_number: u32) -> Result<String> {
+    match selected() {
+        // BLE devices follow the WinMM ports, as WinMM cannot reach them
+        Backend::Native => match port_number.checked_sub(WinMidiPort::count()) {
+            Some(index) => WinRtPort::ble_name(index).map(|name| format!("{} (BLE)", name)),
+            None => WinMidiPort::name(port_number),
+        },
+        Backend::WinRt => WinRtPort::name(port_number),
+        Backend::Synth => SynthPort::name(port_number),
+    }
+}
+
+#[cfg(windows)]
+fn device_connect(port_number: u32) -> Result<OutputPort> {
+    match selected() {
+        Backend::Native => match port_number.checked_sub(WinMidiPort::count()) {
+            Some(index) => WinRtPort::connect_ble(index).map(OutputPort::WinRt),
+            None => WinMidiPort::connect(port_number).map(OutputPort::WinMm),
+        },
+        Backend::WinRt => WinRtPort::connect(port_number).map(OutputPort::WinRt),
+        Backend::Synth => SynthPort::connect(port_number).map(OutputPort::Synth),
+    }
+}
+
+#[cfg(not(windows))]
+fn device_count() -> u32 {
+    MidirPort::count()
+}
+
+#[cfg(not(windows))]
+fn device_name(port_number: u32) -> Result<String> {
+    MidirPort::name(port_number)
+}
+
+#[cfg(not(windows))]
+fn device_connect(port_number: u32) -> Result<OutputPort> {
+    MidirPort::connect(port_number).map(OutputPort::Midir)
+}
+
+impl MidiOutput for OutputPort {
+    fn count() -> u32 {
+        device_count() + RtpMidiPort::count()
+    }
+
+    // Sessions follow the device ports, whose numbers stay the same with or
+    // without them
+    fn name(port_number: u32) -> Result<String> {
+        match port_number.checked_sub(device_count()) {
+            Some(index) => RtpMidiPort::name(index),
+            None => device_name(port_number),
+        }
+    }
+
+    fn connect(port_number: u32) -> Result<Self> {
+        match port_number.checked_sub(device_count()) {
+            Some(index) => RtpMidiPort::connect(index).map(OutputPort::Rtp),
+            None => device_connect(port_number),
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        dispatch!(self, send(message))
+    }
+
+    fn send_reset(&mut self) -> Result<()> {
+        dispatch!(self, send_reset())
+    }
+
+    fn check_inflight(&mut self) -> Result<()> {
+        dispatch!(self, check_inflight())
+    }
+
+    fn latency(&self) -> Duration {
+        dispatch!(self, latency())
+    }
+
+    fn wait_ready(&self) {
+        dispatch!(self, wait_ready())
+    }
+
+    fn mark_ready(&self) {
+        dispatch!(self, mark_ready())
+    }
+}
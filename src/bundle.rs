@@ -0,0 +1,125 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::config::{self, Config};
+
+/// Settings naming files that are packed along with the settings, with the
+/// folder of the bundle they go in.
+const FILE_SETTINGS: &[(&str, &str)] =
+    &[("profile", "profiles"), ("autoplay-on-start", "setlists")];
+
+/// Packs the settings into a zip file at `path`, along with the device
+/// profile and playlist they name, so a rig can be set up
+/// the same way on another machine. Returns the files packed.
+pub fn export(config: &Config, path: &Path) -> Result<Vec<String>> {
+    let mut bundled = Config::from_text(PathBuf::from(config::FILE_NAME), &config.to_text())?;
+    let mut files = Vec::new();
+
+    for &(key, folder) in FILE_SETTINGS {
+        let source = match config.get(key) {
+            Some(source) => PathBuf::from(source),
+            None => continue,
+        };
+        let name = source
+            .file_name()
+            .with_context(|| format!("No file name in {}: {}", key, source.display()))?;
+        let name = format!("{}/{}", folder, name.to_string_lossy());
+        let data =
+            fs::read(&source).with_context(|| format!("Failed to read {}", source.display()))?;
+
+        bundled.set(key, &name);
+        files.push((name, data));
+    }
+
+    let file =
+        File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut zip = ZipWriter::new(file);
+    let write_error = || format!("Failed to write {}", path.display());
+
+    zip.start_file(config::FILE_NAME, FileOptions::default())
+        .with_context(write_error)?;
+    zip.write_all(bundled.to_text().as_bytes())
+        .with_context(write_error)?;
+
+    for (name, data) in &files {
+        zip.start_file(name.as_str(), FileOptions::default())
+            .with_context(write_error)?;
+        zip.write_all(data).with_context(write_error)?;
+    }
+    zip.finish().with_context(write_error)?;
+
+    let mut names = vec![config::FILE_NAME.to_string()];
+    names.extend(files.into_iter().map(|(name, _)| name));
+
+    Ok(names)
+}
+
+/// Unpacks a bundle made by `export` next to the settings file, replacing
+/// the settings with its own pointed at the unpacked files. Returns the
+/// files unpacked.
+pub fn import(config: &Config, path: &Path) -> Result<Vec<PathBuf>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut zip =
+        ZipArchive::new(file).with_context(|| format!("Invalid bundle {}", path.display()))?;
+    let dir = config.path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut settings = None;
+    let mut unpacked = Vec::new();
+
+    for i in 0..zip.len() {
+        let mut entry = zip
+            .by_index(i)
+            .with_context(|| format!("Invalid bundle {}", path.display()))?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        // Nothing may land outside the settings folder
+        let name = entry
+            .enclosed_name()
+            .map(Path::to_path_buf)
+            .with_context(|| format!("Unsafe path in bundle: {}", entry.name()))?;
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {} from the bundle", name.display()))?;
+
+        if name == Path::new(config::FILE_NAME) {
+            settings = Some(String::from_utf8(data).context("Bundled settings are not UTF-8")?);
+            continue;
+        }
+
+        let target = dir.join(&name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&target, data)
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+
+        unpacked.push(target);
+    }
+
+    let text = settings.with_context(|| format!("No {} in the bundle", config::FILE_NAME))?;
+    let mut imported = Config::from_text(config.path.clone(), &text)
+        .with_context(|| format!("Invalid settings in {}", path.display()))?;
+
+    for &(key, _) in FILE_SETTINGS {
+        if let Some(name) = imported.get(key) {
+            let target = dir.join(name);
+            imported.set(key, &target.to_string_lossy());
+        }
+    }
+
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(&imported.path, imported.to_text())
+        .with_context(|| format!("Failed to write {}", imported.path.display()))?;
+    unpacked.insert(0, imported.path);
+
+    Ok(unpacked)
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::backend::{InputPort, MidiInput, MidiOutput, OutputPort};
+use crate::cancel::CancellationToken;
+use crate::checksum;
+use crate::midi_file::write_varlen;
+use crate::syx;
+
+/// How long to wait after the last message before taking a dump as complete.
+pub const DEFAULT_IDLE: Duration = Duration::from_secs(2);
+
+/// How long a device gets to start answering a dump request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Dump requests for devices that answer them, by name.
+const DEVICE_REQUESTS: &[(&str, &[u8])] = &[
+    // Universal device inquiry, answered by most devices from the 90s on
+    ("identity", &[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7]),
+    // Yamaha DX7 edit buffer and 32 voice bank on channel 1
+    ("dx7-voice", &[0xf0, 0x43, 0x20, 0x00, 0xf7]),
+    ("dx7-bank", &[0xf0, 0x43, 0x20, 0x09, 0xf7]),
+];
+
+pub struct CaptureSettings {
+    pub input_port: u32,
+    /// Sent to the output port once listening, so the device dumps by itself.
+    pub request: Option<Vec<u8>>,
+    pub idle: Duration,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            input_port: 0,
+            request: None,
+            idle: DEFAULT_IDLE,
+        }
+    }
+}
+
+/// A SysEx message with when it arrived after the first one.
+pub struct CapturedMessage {
+    pub time: Duration,
+    pub data: Vec<u8>,
+}
+
+/// Looks up a known device request by name, or parses a request written as
+/// hex bytes, with its Roland or Yamaha checksum filled in.
+pub fn parse_request(value: &str) -> Result<Vec<u8>> {
+    if let Some((_, request)) = DEVICE_REQUESTS.iter().find(|(name, _)| *name == value) {
+        return Ok(request.to_vec());
+    }
+
+    let digits: Vec<char> = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ',')
+        .collect();
+
+    let mut request = Vec::with_capacity(digits.len() / 2);
+    for pair in digits.chunks(2) {
+        let byte = match pair {
+            [high, low] => format!("{}{}", high, low),
+            _ => String::new(),
+        };
+
+        match u8::from_str_radix(&byte, 16) {
+            Ok(byte) => request.push(byte),
+            Err(_) => {
+                return Err(anyhow!(
+                    "Unknown request {:?}, known devices are: {}",
+                    value,
+                    request_names()
+                ));
+            }
+        };
+    }
+
+    match syx::split_messages(&request) {
+        Ok(messages) if messages.len() == 1 => {
+            // Templates can leave the checksum as anything
+            checksum::patch(&mut request);
+            Ok(request)
+        }
+        _ => Err(anyhow!("Request must be a single F0 ... F7 message")),
+    }
+}
+
+pub fn request_names() -> String {
+    DEVICE_REQUESTS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Collects SysEx from the input port until nothing arrives for
+/// `settings.idle`. Without a request it waits for the dump to be started on
+/// the device until `cancel` is cancelled.
+pub fn capture(
+    port_id: u32,
+    settings: &CaptureSettings,
+    cancel: CancellationToken,
+) -> Result<Vec<CapturedMessage>> {
+    let (sender, receiver) = mpsc::channel();
+    let _input = InputPort::connect(settings.input_port, sender)
+        .with_context(|| format!("Failed to open input port {}", settings.input_port))?;
+
+    if let Some(request) = &settings.request {
+        let mut conn_out = OutputPort::connect(port_id)?;
+        conn_out
+            .send(request)
+            .context("Failed to send dump request")?;
+        conn_out.check_inflight()?;
+    }
+
+    let requested = Instant::now();
+    let mut first: Option<Instant> = None;
+    let mut last = requested;
+    let mut messages = Vec::new();
+
+    while !cancel.is_cancelled() {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            // Clock and active sensing keep coming during a dump
+            Ok(data) if data.first() != Some(&0xf0) => {}
+            Ok(data) => {
+                let now = Instant::now();
+                let start = *first.get_or_insert(now);
+                last = now;
+
+                println!("Received SysEx message, {} bytes", data.len());
+                messages.push(CapturedMessage {
+                    time: now - start,
+                    data,
+                });
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        match first {
+            Some(_) if last.elapsed() >= settings.idle => break,
+            None if settings.request.is_some() && requested.elapsed() >= REQUEST_TIMEOUT => {
+                return Err(anyhow!("No answer to the dump request"));
+            }
+            _ => {}
+        };
+    }
+
+    Ok(messages)
+}
+
+/// Writes captured messages as a raw `.syx`, or as a `.mid` keeping the time
+/// between them.
+pub fn save(path: &Path, messages: &[CapturedMessage]) -> Result<()> {
+    let is_midi = match path.extension() {
+        Some(extension) => {
+            extension.eq_ignore_ascii_case("mid") || extension.eq_ignore_ascii_case("midi")
+        }
+        None => false,
+    };
+
+    let data = if is_midi {
+        midi_file(messages)
+    } else {
+        messages
+            .iter()
+            .flat_map(|message| message.data.iter().copied())
+            .collect()
+    };
+
+    fs::write(path, data).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// A single track file at the SysEx file timing, so every tick is a millisecond.
+fn midi_file(messages: &[CapturedMessage]) -> Vec<u8> {
+    let mut track = Vec::new();
+
+    // Tempo of `syx::TEMPO` microseconds per quarter note
+    track.extend_from_slice(&[0x00, 0xff, 0x51, 0x03]);
+    track.extend_from_slice(&(syx::TEMPO as u32).to_be_bytes()[1..]);
+
+    let mut tick = 0;
+    for message in messages {
+        let time = message.time.as_millis() as u64;
+        write_varlen(&mut track, time - tick);
+        tick = time;
+
+        // Stored without the leading F0, behind its length
+        track.push(0xf0);
+        write_varlen(&mut track, message.data.len() as u64 - 1);
+        track.extend_from_slice(&message.data[1..]);
+    }
+
+    track.extend_from_slice(&[0x00, 0xff, 0x2f, 0x00]);
+
+    let mut data = Vec::with_capacity(track.len() + 22);
+    data.extend_from_slice(b"MThd");
+    data.extend_from_slice(&6u32.to_be_bytes());
+    data.extend_from_slice(&0u16.to_be_bytes());
+    data.extend_from_slice(&1u16.to_be_bytes());
+    data.extend_from_slice(&(syx::DIVISION as u16).to_be_bytes());
+    data.extend_from_slice(b"MTrk");
+    data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    data.extend_from_slice(&track);
+
+    data
+}
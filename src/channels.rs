@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+
+use crate::midi_file::{DataEvent, LocalEvent};
+
+/// A channel of the file moved to another, for files written for a different
+/// multitimbral layout than the synth's.
+#[derive(Clone, Copy)]
+pub struct ChannelMapping {
+    /// From 0.
+    pub from: u8,
+    /// From 0.
+    pub to: u8,
+}
+
+impl ChannelMapping {
+    /// Parses `<from>:<to>`, with channels from 1.
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || format!("Invalid channel mapping: {}", value);
+        let (from, to) = value.split_once(':').with_context(invalid)?;
+
+        let parse_channel = |channel: &str| -> Result<u8> {
+            let channel: u8 = channel.trim().parse().with_context(invalid)?;
+            if !(1..=16).contains(&channel) {
+                return Err(anyhow!("Channel must be between 1 and 16"));
+            }
+
+            Ok(channel - 1)
+        };
+
+        Ok(Self {
+            from: parse_channel(from)?,
+            to: parse_channel(to)?,
+        })
+    }
+}
+
+/// Rewrites the channel of every voice message. All mappings apply at once,
+/// so `1:2` and `2:1` swap the two channels.
+pub fn apply(mut events: Vec<DataEvent>, mappings: &[ChannelMapping]) -> Vec<DataEvent> {
+    if mappings.is_empty() {
+        return events;
+    }
+
+    let mut table: Vec<u8> = (0..16).collect();
+    for mapping in mappings {
+        table[mapping.from as usize] = mapping.to;
+    }
+
+    for event in &mut events {
+        if let LocalEvent::Midi(data) = &mut event.data {
+            // System messages have no channel
+            if (0x80..0xf0).contains(&data[0]) {
+                data[0] = data[0] & 0xf0 | table[(data[0] & 0x0f) as usize];
+            }
+        }
+    }
+
+    events
+}
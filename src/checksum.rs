@@ -0,0 +1,79 @@
+use std::ops::Range;
+
+const ROLAND_ID: u8 = 0x41;
+const YAMAHA_ID: u8 = 0x43;
+
+// Roland data request and data set commands, the ones carrying a checksum
+const ROLAND_RQ1: u8 = 0x11;
+const ROLAND_DT1: u8 = 0x12;
+
+/// The checksum both Roland and Yamaha use: the byte that brings the sum of
+/// the checksummed bytes to a multiple of 128.
+pub fn checksum(data: &[u8]) -> u8 {
+    let sum: u32 = data.iter().map(|&byte| (byte & 0x7f) as u32).sum();
+
+    ((128 - sum % 128) % 128) as u8
+}
+
+/// The bytes covered by the checksum of a Roland or Yamaha message, the
+/// checksum itself being the byte right after them.
+fn checksummed(message: &[u8]) -> Option<Range<usize>> {
+    let len = message.len();
+    if len < 5 || message[0] != 0xf0 || message[len - 1] != 0xf7 {
+        return None;
+    }
+
+    let end = len - 2;
+
+    match message[1] {
+        ROLAND_ID => {
+            // Newer model IDs are extended with leading zeroes
+            let mut command = 3;
+            while command < end && message[command] == 0x00 {
+                command += 1;
+            }
+            command += 1;
+
+            match message.get(command) {
+                Some(&ROLAND_RQ1) | Some(&ROLAND_DT1) if command + 1 < end => {
+                    Some(command + 1..end)
+                }
+                _ => None,
+            }
+        }
+        // Only bulk dumps carry a checksum, parameter changes do not
+        YAMAHA_ID if message[2] & 0xf0 == 0x00 && len >= 8 => {
+            // DX-era formats sum the data alone, the model ID based formats
+            // of later devices (XG and onward) the byte count and address too
+            if message[3] < 0x10 {
+                Some(6..end)
+            } else {
+                Some(4..end)
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Whether the checksum of a Roland or Yamaha message is right, `None` for
+/// messages without one.
+pub fn verify(message: &[u8]) -> Option<bool> {
+    let range = checksummed(message)?;
+
+    Some(message[range.end] == checksum(&message[range]))
+}
+
+/// Rewrites the checksum of a Roland or Yamaha message, returning whether it
+/// was wrong.
+pub fn patch(message: &mut [u8]) -> bool {
+    let range = match checksummed(message) {
+        Some(range) => range,
+        None => return false,
+    };
+
+    let expected = checksum(&message[range.clone()]);
+    let wrong = message[range.end] != expected;
+    message[range.end] = expected;
+
+    wrong
+}
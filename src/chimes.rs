@@ -0,0 +1,127 @@
+use chrono::{DateTime, Duration, Local, Timelike};
+
+use crate::midi_file::{self, DataEvent, Note};
+
+/// Ticks per quarter note of the generated sequences.
+pub const DIVISION: u64 = 480;
+
+// Each bell of a change is struck this far apart, at the default 120 BPM.
+const NOTE_SPACING: u64 = DIVISION * 3 / 2;
+const NOTE_LENGTH: u64 = DIVISION * 2;
+const CHANGE_GAP: u64 = DIVISION * 3;
+const STRIKE_SPACING: u64 = DIVISION * 4;
+
+// The five changes of the Westminster Quarters, in E major
+const CHANGES: [[u8; 4]; 5] = [
+    [68, 66, 64, 59],
+    [64, 68, 66, 59],
+    [64, 66, 68, 64],
+    [68, 64, 66, 59],
+    [59, 66, 68, 64],
+];
+const HOUR_BELL: u8 = 52;
+
+#[derive(Clone)]
+pub struct ChimeSettings {
+    pub channel: u8,
+    pub program: u8,
+    pub velocity: u8,
+    pub strike_hours: bool,
+}
+
+impl Default for ChimeSettings {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            // Tubular Bells
+            program: 14,
+            velocity: 100,
+            strike_hours: true,
+        }
+    }
+}
+
+/// Keeps track of when the next quarter-hour chime is due.
+pub struct ChimeSchedule {
+    settings: ChimeSettings,
+    next: DateTime<Local>,
+}
+
+impl ChimeSchedule {
+    pub fn new(settings: ChimeSettings) -> Self {
+        Self {
+            settings,
+            next: next_quarter(Local::now()),
+        }
+    }
+
+    /// Returns the sequence for the chime that became due since the last call,
+    /// if any.
+    pub fn poll(&mut self) -> Option<Vec<DataEvent>> {
+        let now = Local::now();
+        if now < self.next {
+            return None;
+        }
+
+        let due = self.next;
+        self.next = next_quarter(now);
+
+        Some(westminster(due.minute() / 15, due.hour(), &self.settings))
+    }
+}
+
+fn next_quarter(now: DateTime<Local>) -> DateTime<Local> {
+    let elapsed = Duration::minutes((now.minute() % 15) as i64)
+        + Duration::seconds(now.second() as i64)
+        + Duration::nanoseconds(now.nanosecond() as i64);
+
+    now - elapsed + Duration::minutes(15)
+}
+
+/// Builds the chime for the given quarter (0 being the full hour), followed by
+/// the hour strikes when enabled.
+pub fn westminster(quarter: u32, hour: u32, settings: &ChimeSettings) -> Vec<DataEvent> {
+    let changes: &[usize] = match quarter {
+        1 => &[0],
+        2 => &[1, 2],
+        3 => &[3, 4, 0],
+        _ => &[1, 2, 3, 4],
+    };
+
+    let mut notes = Vec::new();
+    let mut time = 0;
+
+    for &change in changes {
+        for &key in &CHANGES[change] {
+            notes.push(bell(time, key, settings));
+            time += NOTE_SPACING;
+        }
+
+        time += CHANGE_GAP - NOTE_SPACING;
+    }
+
+    if quarter == 0 && settings.strike_hours {
+        let strikes = match hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+
+        time += STRIKE_SPACING - CHANGE_GAP;
+
+        for _ in 0..strikes {
+            notes.push(bell(time, HOUR_BELL, settings));
+            time += STRIKE_SPACING;
+        }
+    }
+
+    midi_file::sequence_notes(&notes, settings.channel, Some(settings.program))
+}
+
+fn bell(time: u64, key: u8, settings: &ChimeSettings) -> Note {
+    Note {
+        time,
+        length: NOTE_LENGTH,
+        key,
+        velocity: settings.velocity,
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::mpsc::{self, Receiver};
+
+use anyhow::{Context, Result};
+
+use crate::backend::{InputPort, MidiInput};
+use crate::midi_file::{self, DataEvent};
+
+// System real-time messages
+pub const SONG_POSITION: u8 = 0xf2;
+pub const CLOCK: u8 = 0xf8;
+pub const START: u8 = 0xfa;
+pub const CONTINUE: u8 = 0xfb;
+pub const STOP: u8 = 0xfc;
+
+/// Clocks per quarter note.
+const PPQN: u64 = 24;
+
+/// Song positions count sixteenth notes, up to 14 bits of them.
+const SIXTEENTHS_PER_QUARTER: u64 = 4;
+const MAX_SONG_POSITION: u64 = 0x3fff;
+
+/// Merges a Start and 24 clocks per quarter note into the events, for drum
+/// machines and arpeggiators to follow. Being placed by tick, the clocks
+/// speed up and slow down with every tempo change like the notes do.
+pub fn apply(events: Vec<DataEvent>, division: u64) -> Vec<DataEvent> {
+    let length: u64 = events.iter().map(|event| event.delta_time).sum();
+
+    // Divisions that are no multiple of 24 round each clock to a tick
+    let mut messages = vec![(0, [START, 0, 0])];
+    messages.extend(
+        (0..)
+            .map(|clock| clock * division / PPQN)
+            .take_while(|&tick| tick < length)
+            .map(|tick| (tick, [CLOCK, 0, 0])),
+    );
+
+    midi_file::merge_messages(events, messages)
+}
+
+/// The messages moving clocked gear to `tick`: a stop, the song position of
+/// the sixteenth note at or before it and a continue.
+pub fn relocate(tick: u64, division: u64) -> [Vec<u8>; 3] {
+    let position = (tick * SIXTEENTHS_PER_QUARTER / division.max(1)).min(MAX_SONG_POSITION);
+
+    [
+        vec![STOP],
+        vec![
+            SONG_POSITION,
+            (position & 0x7f) as u8,
+            (position >> 7) as u8,
+        ],
+        vec![CONTINUE],
+    ]
+}
+
+/// What a clock master asked for besides clocks.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Transport {
+    Start,
+    Continue,
+    Stop,
+    /// Moved to a song position, given in ticks.
+    Locate(u64),
+}
+
+/// Follows the clock of a master on an input port in place of the tempo map,
+/// so playback keeps time with a hardware sequencer.
+pub struct ClockFollower {
+    _input: InputPort,
+    receiver: Receiver<Vec<u8>>,
+    division: u64,
+    /// Ticks reached times 24, as a clock is rarely a whole number of ticks.
+    /// Negative while the master plays a loop's padding.
+    position: i64,
+    running: bool,
+}
+
+impl ClockFollower {
+    pub fn connect(port_id: u32, division: u64) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let input = InputPort::connect(port_id, sender)
+            .with_context(|| format!("Failed to open clock input port {}", port_id))?;
+
+        Ok(Self {
+            _input: input,
+            receiver,
+            division,
+            position: 0,
+            running: false,
+        })
+    }
+
+    /// Takes in the messages that arrived, up to the first transport message,
+    /// which is returned. The rest wait for the next call, so every one of
+    /// them is seen in order.
+    pub fn poll(&mut self) -> Option<Transport> {
+        while let Ok(message) = self.receiver.try_recv() {
+            match message.first() {
+                Some(&CLOCK) if self.running => self.position += self.division as i64,
+                Some(&START) => {
+                    self.position = 0;
+                    self.running = true;
+                    return Some(Transport::Start);
+                }
+                Some(&CONTINUE) => {
+                    self.running = true;
+                    return Some(Transport::Continue);
+                }
+                Some(&STOP) => {
+                    self.running = false;
+                    return Some(Transport::Stop);
+                }
+                Some(&SONG_POSITION) if message.len() >= 3 => {
+                    let position = (message[1] & 0x7f) as u64 | ((message[2] & 0x7f) as u64) << 7;
+                    return Some(Transport::Locate(
+                        position * self.division / SIXTEENTHS_PER_QUARTER,
+                    ));
+                }
+                _ => {}
+            };
+        }
+
+        None
+    }
+
+    /// Whether the master reached `tick`.
+    pub fn reached(&self, tick: u64) -> bool {
+        self.position >= (tick * PPQN) as i64
+    }
+
+    /// Counts on from `tick` after playback moved there.
+    pub fn locate(&mut self, tick: u64) {
+        self.position = (tick * PPQN) as i64;
+    }
+
+    /// Moves back by `ticks` as a loop starts over.
+    pub fn rewind(&mut self, ticks: u64) {
+        self.position -= (ticks * PPQN) as i64;
+    }
+}
+
+/// Whether a message only keeps other devices in time, such as a clock or a
+/// time code quarter frame, which is not reported as played and which
+/// chasing leaves out.
+pub fn is_sync(data: &[u8]) -> bool {
+    matches!(data.first(), Some(&status) if status == 0xf1 || status >= CLOCK)
+}
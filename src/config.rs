@@ -0,0 +1,262 @@
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Name of the settings file, looked for next to the executable and then in
+/// the user's configuration directory.
+pub const FILE_NAME: &str = "midi_play.toml";
+
+/// Default settings, read from a flat TOML file like:
+///
+/// ```text
+/// # Play on the SC-88 unless told otherwise
+/// port-name = "SC-88"
+/// encoding = "shift_jis"
+/// min-notes = 10
+/// ```
+///
+/// Keys are named after the command line flags, whose values win over the
+/// file. Only strings, integers, floats and booleans are understood.
+pub struct Config {
+    /// Where the settings were read from, or are to be saved when there was
+    /// no file yet.
+    pub path: PathBuf,
+    /// The file as read, for keeping its comments when saving.
+    lines: Vec<String>,
+    values: Vec<(String, String)>,
+    changed: bool,
+}
+
+impl Config {
+    /// Loads the first settings file found, or else starts empty settings
+    /// belonging in the user's configuration directory.
+    pub fn find() -> Result<Self> {
+        let paths = search_paths();
+
+        for path in &paths {
+            match fs::read_to_string(path) {
+                Ok(text) => {
+                    return Self::from_text(path.clone(), &text)
+                        .with_context(|| format!("Invalid settings {}", path.display()));
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+                }
+            }
+        }
+
+        Ok(Self {
+            path: paths
+                .last()
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from(FILE_NAME)),
+            lines: Vec::new(),
+            values: Vec::new(),
+            changed: false,
+        })
+    }
+
+    /// Settings read from `text`, to be saved at `path`.
+    pub fn from_text(path: PathBuf, text: &str) -> Result<Self> {
+        Ok(Self {
+            path,
+            lines: text.lines().map(String::from).collect(),
+            values: parse(text)?,
+            changed: false,
+        })
+    }
+
+    /// Every setting, in the order of the file, with strings unquoted.
+    pub fn values(&self) -> &[(String, String)] {
+        &self.values
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.values.iter_mut().find(|(name, _)| name == key) {
+            Some((_, old)) if old == value => {}
+            Some((_, old)) => {
+                *old = value.to_string();
+                self.changed = true;
+            }
+            None => {
+                self.values.push((key.to_string(), value.to_string()));
+                self.changed = true;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        let count = self.values.len();
+        self.values.retain(|(name, _)| name != key);
+        self.changed |= self.values.len() != count;
+    }
+
+    /// Writes the settings back if any of them changed.
+    pub fn save(&mut self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let text = self.to_text();
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        fs::write(&self.path, &text)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+
+        self.lines = text.lines().map(String::from).collect();
+        self.changed = false;
+
+        Ok(())
+    }
+
+    /// The settings as a file, replacing the lines of changed settings in
+    /// place and adding new ones at the end.
+    pub fn to_text(&self) -> String {
+        let mut written = Vec::new();
+        let mut text = String::new();
+
+        for line in &self.lines {
+            let key = match line.split_once('=') {
+                Some((key, _)) if !line.trim_start().starts_with('#') => key.trim(),
+                _ => {
+                    text.push_str(line);
+                    text.push('\n');
+                    continue;
+                }
+            };
+
+            if let Some((_, value)) = self.values.iter().find(|(name, _)| name == key) {
+                if !written.contains(&key) {
+                    text.push_str(&format_setting(key, value));
+                    written.push(key);
+                }
+            }
+        }
+
+        for (key, value) in &self.values {
+            if !written.contains(&key.as_str()) {
+                text.push_str(&format_setting(key, value));
+            }
+        }
+
+        text
+    }
+}
+
+/// A `key = value` line, quoting anything but numbers and booleans.
+fn format_setting(key: &str, value: &str) -> String {
+    if value == "true" || value == "false" || value.parse::<f64>().is_ok() {
+        return format!("{} = {}\n", key, value);
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+
+    format!("{} = \"{}\"\n", key, quoted)
+}
+
+fn search_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Some(dir) = env::current_exe().ok().as_deref().and_then(Path::parent) {
+        paths.push(dir.join(FILE_NAME));
+    }
+
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    if let Some(dir) = config_dir {
+        paths.push(dir.join("midi_play").join(FILE_NAME));
+    }
+
+    paths
+}
+
+fn parse(text: &str) -> Result<Vec<(String, String)>> {
+    let mut values = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("Line {}: expected key = value", number + 1))?;
+        let value = parse_value(value.trim()).with_context(|| format!("Line {}", number + 1))?;
+
+        values.push((key.trim().to_string(), value));
+    }
+
+    Ok(values)
+}
+
+/// Unquotes a string, or checks a bare value is a number or boolean, leaving
+/// out any comment after it.
+fn parse_value(value: &str) -> Result<String> {
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut string = String::new();
+        let mut chars = rest.chars();
+
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    other => return Err(anyhow!("Unknown escape: \\{}", other.unwrap_or(' '))),
+                },
+                Some(c) => string.push(c),
+                None => return Err(anyhow!("Unterminated string: {}", value)),
+            }
+        }
+
+        let rest = chars.as_str().trim();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(anyhow!("Unexpected text after string: {}", rest));
+        }
+
+        return Ok(string);
+    }
+
+    let value = match value.find('#') {
+        Some(index) => value[..index].trim(),
+        None => value,
+    };
+
+    if value == "true" || value == "false" || value.parse::<f64>().is_ok() {
+        Ok(value.to_string())
+    } else {
+        Err(anyhow!("Expected a string, number or boolean: {}", value))
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Local;
+
+/// How much a client's commands weigh against another's.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Priority {
+    /// Control from elsewhere, such as over the network.
+    Remote,
+    /// Someone at the console running the show.
+    Operator,
+}
+
+/// Where a command came from.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Client {
+    pub name: String,
+    pub priority: Priority,
+}
+
+impl Client {
+    pub fn new(name: impl Into<String>, priority: Priority) -> Self {
+        Self {
+            name: name.into(),
+            priority,
+        }
+    }
+}
+
+/// What a command touches.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Access {
+    /// Starting, stopping or moving playback, which the transport lock guards.
+    Transport,
+    /// Taking or releasing the transport lock.
+    Lock,
+    Unlock,
+    /// Silencing stuck notes, which anyone may do at any time.
+    Safety,
+}
+
+/// Decides between clients controlling the same playback, so a remote
+/// command cannot take playback away from an operator holding the transport
+/// lock. Only a client of higher priority than the holder can go around the
+/// lock, or break it. Every decision can be written to an audit log.
+#[derive(Default)]
+pub struct Arbiter {
+    holder: Option<Client>,
+    audit_log: Option<File>,
+}
+
+impl Arbiter {
+    /// Appends every decision to `path`, one line each.
+    pub fn with_audit_log(path: &Path) -> Result<Self> {
+        let audit_log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+
+        Ok(Self {
+            holder: None,
+            audit_log: Some(audit_log),
+        })
+    }
+
+    /// The client holding the transport lock.
+    pub fn holder(&self) -> Option<&Client> {
+        self.holder.as_ref()
+    }
+
+    /// Whether `client` may issue `command`, taking or releasing the lock
+    /// for `Access::Lock` and `Access::Unlock`.
+    pub fn allow(&mut self, client: &Client, command: &str, access: Access) -> bool {
+        let allowed = match (access, &self.holder) {
+            (Access::Safety, _) | (_, None) => true,
+            (_, Some(holder)) => holder == client || client.priority > holder.priority,
+        };
+
+        if allowed {
+            match access {
+                Access::Lock => self.holder = Some(client.clone()),
+                Access::Unlock => self.holder = None,
+                Access::Transport | Access::Safety => {}
+            };
+        }
+
+        self.log(client, command, allowed);
+        allowed
+    }
+
+    fn log(&mut self, client: &Client, command: &str, allowed: bool) {
+        let audit_log = match &mut self.audit_log {
+            Some(audit_log) => audit_log,
+            None => return,
+        };
+
+        let line = format!(
+            "{}\t{}\t{:?}\t{}\t{}",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            client.name,
+            client.priority,
+            command,
+            if allowed { "allowed" } else { "denied" }
+        );
+        if let Err(e) = writeln!(audit_log, "{}", line) {
+            eprintln!("Failed to write audit log: {}", e);
+        }
+    }
+}
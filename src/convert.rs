@@ -0,0 +1,50 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rimd::{Event, MetaCommand, MetaEvent, TrackEvent, SMF};
+
+use crate::midi_file::{combine_tracks, write_single_track};
+
+/// Merges every track of a Standard MIDI File into one and writes the result
+/// as a format 0 file, returning how many events it holds.
+pub fn to_single_track(input: &Path, output: &Path) -> Result<usize> {
+    let midi_data = SMF::from_file(input).context("Failed to parse MIDI file")?;
+
+    if midi_data.division < 0 {
+        return Err(anyhow!("SMPTE division not supported"));
+    }
+
+    let mut merged = Vec::new();
+    for track in midi_data.tracks {
+        merged = combine_tracks(merged, track.events);
+    }
+
+    // Every track brought its own end of track, keep only the last one
+    let mut events = Vec::with_capacity(merged.len());
+    let mut pending_ticks = 0;
+    for mut event in merged {
+        match &event.event {
+            Event::Meta(MetaEvent {
+                command: MetaCommand::EndOfTrack,
+                ..
+            }) => {
+                pending_ticks += event.vtime;
+            }
+            _ => {
+                event.vtime += pending_ticks;
+                pending_ticks = 0;
+                events.push(event);
+            }
+        };
+    }
+    events.push(TrackEvent {
+        vtime: pending_ticks,
+        event: Event::Meta(MetaEvent::end_of_track()),
+    });
+
+    let data = write_single_track(midi_data.division as u16, &events);
+    fs::write(output, data).with_context(|| format!("Failed to write {}", output.display()))?;
+
+    Ok(events.len())
+}
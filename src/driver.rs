@@ -5,6 +5,10 @@ use std::mem::{self, MaybeUninit};
 use std::os::windows::ffi::OsStringExt;
 use std::pin::Pin;
 use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
 
 use anyhow::{Context, Result};
 use winapi::shared::basetsd::{DWORD_PTR, UINT_PTR};
@@ -12,24 +16,32 @@ use winapi::shared::minwindef::{DWORD, FALSE, TRUE, UINT};
 use winapi::shared::ntdef::HANDLE;
 use winapi::um::handleapi::CloseHandle;
 use winapi::um::mmeapi::{
-    midiOutClose, midiOutGetDevCapsW, midiOutGetNumDevs, midiOutLongMsg, midiOutOpen,
-    midiOutPrepareHeader, midiOutReset, midiOutShortMsg, midiOutUnprepareHeader,
+    midiInAddBuffer, midiInClose, midiInGetDevCapsW, midiInGetNumDevs, midiInOpen,
+    midiInPrepareHeader, midiInReset, midiInStart, midiInStop, midiInUnprepareHeader, midiOutClose,
+    midiOutGetDevCapsW, midiOutGetNumDevs, midiOutLongMsg, midiOutOpen, midiOutPrepareHeader,
+    midiOutReset, midiOutShortMsg, midiOutUnprepareHeader,
 };
 use winapi::um::mmsystem::{
-    CALLBACK_EVENT, HMIDIOUT, MIDIERR_BASE, MIDIERR_NOTREADY, MIDIERR_STILLPLAYING, MIDIHDR,
-    MIDIOUTCAPSW, MMSYSERR_BADDEVICEID, MMSYSERR_BASE, MMSYSERR_NOERROR,
+    CALLBACK_EVENT, CALLBACK_FUNCTION, HMIDIIN, HMIDIOUT, MIDIERR_BASE, MIDIERR_NOTREADY,
+    MIDIERR_STILLPLAYING, MIDIHDR, MIDIINCAPSW, MIDIOUTCAPSW, MMSYSERR_BADDEVICEID, MMSYSERR_BASE,
+    MMSYSERR_NOERROR,
 };
-use winapi::um::synchapi::CreateEventW;
+use winapi::um::synchapi::{CreateEventW, SetEvent, WaitForSingleObject};
+use winapi::um::winbase::INFINITE;
 
-const MHDR_DONE: DWORD = 0x00000001;
+use crate::backend::{MidiInput, MidiOutput};
+
+pub const MHDR_DONE: DWORD = 0x00000001;
 //const MHDR_PREPARED: DWORD = 0x00000002;
 //const MHDR_INQUEUE: DWORD = 0x00000004;
 //const MHDR_ISSTRM: DWORD = 0x00000008;
 
-const GM1_RESET: &'static [u8] = &[0xf0, 0x7e, 0x7f, 0x09, 0x01, 0xf7];
-const GS1_RESET: &'static [u8] = &[
-    0xf0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7f, 0x00, 0x41, 0xf7,
-];
+const MIM_DATA: UINT = 0x3C3;
+const MIM_LONGDATA: UINT = 0x3C4;
+
+// Buffers handed to the driver for incoming SysEx, longer dumps span several
+const SYSEX_BUFFER_SIZE: usize = 1024;
+const SYSEX_BUFFER_COUNT: usize = 4;
 
 struct InflightRequest {
     #[allow(unused)]
@@ -44,12 +56,16 @@ pub struct WinMidiPort {
     inflight_to_remove: Vec<usize>,
 }
 
-impl WinMidiPort {
-    pub fn count() -> UINT {
+// WinMM handles can be used from any thread, and the port moves between
+// player threads whole, never shared
+unsafe impl Send for WinMidiPort {}
+
+impl MidiOutput for WinMidiPort {
+    fn count() -> u32 {
         unsafe { midiOutGetNumDevs() }
     }
 
-    pub fn name(port_number: UINT) -> Result<String> {
+    fn name(port_number: u32) -> Result<String> {
         let mut device_caps: MaybeUninit<MIDIOUTCAPSW> = MaybeUninit::uninit();
         let result = unsafe {
             midiOutGetDevCapsW(
@@ -78,7 +94,7 @@ impl WinMidiPort {
         Ok(output)
     }
 
-    pub fn connect(port_number: UINT) -> Result<Self> {
+    fn connect(port_number: u32) -> Result<Self> {
         let event_handle = unsafe { CreateEventW(ptr::null_mut(), TRUE, FALSE, ptr::null()) };
         let mut out_handle = MaybeUninit::uninit();
         let result = unsafe {
@@ -106,20 +122,7 @@ impl WinMidiPort {
         })
     }
 
-    pub fn event_handle(&self) -> HANDLE {
-        self.event_handle
-    }
-
-    pub fn send_reset(&mut self) -> Result<()> {
-        self.send(GS1_RESET)
-            .context("Failed to send GS1 reset message")?;
-        self.send(GM1_RESET)
-            .context("Failed to send GM1 reset message")?;
-
-        Ok(())
-    }
-
-    pub fn send(&mut self, message: &[u8]) -> Result<()> {
+    fn send(&mut self, message: &[u8]) -> Result<()> {
         if message.is_empty() {
             eprintln!("Attempted to send empty message");
 
@@ -201,12 +204,7 @@ impl WinMidiPort {
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn have_inflight(&self) -> bool {
-        !self.inflight.is_empty()
-    }
-
-    pub fn check_inflight(&mut self) -> Result<()> {
+    fn check_inflight(&mut self) -> Result<()> {
         self.inflight_to_remove.clear();
 
         for (i, inflight) in self.inflight.iter_mut().enumerate() {
@@ -232,6 +230,21 @@ impl WinMidiPort {
 
         Ok(())
     }
+
+    fn wait_ready(&self) {
+        unsafe { WaitForSingleObject(self.event_handle, INFINITE) };
+    }
+
+    fn mark_ready(&self) {
+        unsafe { SetEvent(self.event_handle) };
+    }
+}
+
+impl WinMidiPort {
+    #[allow(dead_code)]
+    pub fn have_inflight(&self) -> bool {
+        !self.inflight.is_empty()
+    }
 }
 
 impl Drop for WinMidiPort {
@@ -262,3 +275,239 @@ impl Drop for WinMidiPort {
         }
     }
 }
+
+/// State shared with the input callback.
+struct InputState {
+    sender: Sender<Vec<u8>>,
+    /// SysEx received so far, until its terminating `F7` arrives.
+    sysex: Mutex<Vec<u8>>,
+    /// Set while closing, so returned buffers are not queued again.
+    closing: AtomicBool,
+}
+
+struct SysExBuffer {
+    #[allow(unused)]
+    data: Pin<Box<[u8]>>,
+    header: Pin<Box<MIDIHDR>>,
+}
+
+pub struct WinMidiInput {
+    handle: HMIDIIN,
+    state: Pin<Box<InputState>>,
+    buffers: Vec<SysExBuffer>,
+}
+
+impl MidiInput for WinMidiInput {
+    fn count() -> u32 {
+        unsafe { midiInGetNumDevs() }
+    }
+
+    fn name(port_number: u32) -> Result<String> {
+        let mut device_caps: MaybeUninit<MIDIINCAPSW> = MaybeUninit::uninit();
+        let result = unsafe {
+            midiInGetDevCapsW(
+                port_number as UINT_PTR,
+                device_caps.as_mut_ptr(),
+                mem::size_of::<MIDIINCAPSW>() as u32,
+            )
+        };
+
+        if result == MMSYSERR_BADDEVICEID {
+            return Err(anyhow!("Port number out of range"));
+        } else if result != MMSYSERR_NOERROR {
+            return Err(anyhow!(
+                "Failed to retrieve port name: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        let device_caps = unsafe { device_caps.assume_init() };
+        let name = device_caps.szPname.clone();
+        let len = name.iter().position(|&v| v == 0).unwrap_or(name.len() - 1);
+        let output = OsString::from_wide(&name[..len])
+            .to_string_lossy()
+            .into_owned();
+
+        Ok(output)
+    }
+
+    /// SysEx is only forwarded once complete, however many buffers it took.
+    fn connect(port_number: u32, sender: Sender<Vec<u8>>) -> Result<Self> {
+        let state = Box::pin(InputState {
+            sender,
+            sysex: Mutex::new(Vec::new()),
+            closing: AtomicBool::new(false),
+        });
+        let mut in_handle = MaybeUninit::uninit();
+        let result = unsafe {
+            midiInOpen(
+                in_handle.as_mut_ptr(),
+                port_number as UINT,
+                midi_in_callback as *const () as DWORD_PTR,
+                &*state as *const InputState as DWORD_PTR,
+                CALLBACK_FUNCTION,
+            )
+        };
+
+        if result != MMSYSERR_NOERROR {
+            return Err(anyhow!(
+                "Failed to create Windows MM MIDI input port: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        let mut input = Self {
+            handle: unsafe { in_handle.assume_init() },
+            state,
+            buffers: Vec::with_capacity(SYSEX_BUFFER_COUNT),
+        };
+
+        for _ in 0..SYSEX_BUFFER_COUNT {
+            input.add_buffer()?;
+        }
+
+        let result = unsafe { midiInStart(input.handle) };
+        if result != MMSYSERR_NOERROR {
+            return Err(anyhow!(
+                "Failed to start Windows MM MIDI input port: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        Ok(input)
+    }
+}
+
+impl WinMidiInput {
+    fn add_buffer(&mut self) -> Result<()> {
+        let mut data = Pin::new(vec![0u8; SYSEX_BUFFER_SIZE].into_boxed_slice());
+        let header = Box::pin(MIDIHDR {
+            lpData: data.as_mut_ptr() as *mut i8,
+            dwBufferLength: SYSEX_BUFFER_SIZE as DWORD,
+            dwBytesRecorded: 0,
+            dwUser: 0,
+            dwFlags: 0,
+            lpNext: ptr::null_mut(),
+            reserved: 0,
+            dwOffset: 0,
+            dwReserved: unsafe { mem::zeroed() },
+        });
+        self.buffers.push(SysExBuffer { data, header });
+
+        let SysExBuffer { header, .. } = self.buffers.last_mut().unwrap();
+        let header = &mut **header as *mut MIDIHDR;
+        let result =
+            unsafe { midiInPrepareHeader(self.handle, header, mem::size_of::<MIDIHDR>() as u32) };
+        if result != MMSYSERR_NOERROR {
+            self.buffers.pop();
+
+            return Err(anyhow!(
+                "Failed to prepare SysEx input buffer: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        let result =
+            unsafe { midiInAddBuffer(self.handle, header, mem::size_of::<MIDIHDR>() as u32) };
+        if result != MMSYSERR_NOERROR {
+            return Err(anyhow!(
+                "Failed to add SysEx input buffer: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for WinMidiInput {
+    fn drop(&mut self) {
+        self.state.closing.store(true, Ordering::Relaxed);
+
+        unsafe {
+            midiInStop(self.handle);
+
+            // Returns every buffer still queued
+            midiInReset(self.handle);
+
+            for buffer in &mut self.buffers {
+                midiInUnprepareHeader(
+                    self.handle,
+                    &mut *buffer.header,
+                    mem::size_of::<MIDIHDR>() as u32,
+                );
+            }
+
+            let result = midiInClose(self.handle);
+            if result != MMSYSERR_NOERROR {
+                eprintln!(
+                    "Failed to close Windows MM MIDI input port: {}",
+                    result - MMSYSERR_BASE
+                );
+            }
+        }
+    }
+}
+
+/// Number of bytes in a short message with the given status byte.
+fn short_message_len(status: u8) -> usize {
+    match status & 0xf0 {
+        0xc0 | 0xd0 => 2,
+        0x80..=0xe0 => 3,
+        _ => match status {
+            0xf1 | 0xf3 => 2,
+            0xf2 => 3,
+            _ => 1,
+        },
+    }
+}
+
+extern "system" fn midi_in_callback(
+    handle: HMIDIIN,
+    message: UINT,
+    instance: DWORD_PTR,
+    param1: DWORD_PTR,
+    _param2: DWORD_PTR,
+) {
+    let state = unsafe { &*(instance as *const InputState) };
+
+    match message {
+        MIM_DATA => {
+            let packet = (param1 as DWORD).to_le_bytes();
+            let len = short_message_len(packet[0]);
+
+            // The receiving side going away is not something we can report from here
+            let _ = state.sender.send(packet[..len].to_vec());
+        }
+        MIM_LONGDATA => {
+            if state.closing.load(Ordering::Relaxed) {
+                return;
+            }
+
+            let header = param1 as *mut MIDIHDR;
+            let data = unsafe {
+                slice::from_raw_parts(
+                    (*header).lpData as *const u8,
+                    (*header).dwBytesRecorded as usize,
+                )
+            };
+
+            if let Ok(mut sysex) = state.sysex.lock() {
+                // A new dump starting means the last one was cut short
+                if data.first() == Some(&0xf0) {
+                    sysex.clear();
+                }
+
+                sysex.extend_from_slice(data);
+
+                if sysex.last() == Some(&0xf7) {
+                    let _ = state.sender.send(mem::take(&mut *sysex));
+                }
+            }
+
+            // Hand the buffer back for the rest of the dump
+            unsafe { midiInAddBuffer(handle, header, mem::size_of::<MIDIHDR>() as u32) };
+        }
+        _ => {}
+    };
+}
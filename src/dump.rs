@@ -0,0 +1,253 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::backend::{InputPort, MidiInput, MidiOutput, OutputPort};
+use crate::cancel::CancellationToken;
+use crate::checksum;
+use crate::syx;
+
+/// How long a device gets to acknowledge a packet.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_RETRIES: u32 = 3;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+const ROLAND_ID: u8 = 0x41;
+const ROLAND_DT1: u8 = 0x12;
+
+// Roland handshake commands
+const ROLAND_ACK: u8 = 0x43;
+const ROLAND_ERR: u8 = 0x4e;
+const ROLAND_RJC: u8 = 0x4f;
+
+// Universal non-realtime handshake, from the sample dump standard
+const UNIVERSAL_ACK: u8 = 0x7f;
+const UNIVERSAL_NAK: u8 = 0x7e;
+const UNIVERSAL_CANCEL: u8 = 0x7d;
+const UNIVERSAL_WAIT: u8 = 0x7c;
+
+pub struct DumpSettings {
+    /// Where the device's acknowledgements arrive.
+    pub input_port: u32,
+    /// Data bytes per packet, Roland data sets longer than that being split
+    /// up.
+    pub packet_size: Option<usize>,
+    /// Length of the addresses of Roland data sets, 3 for GS devices and 4
+    /// for later ones.
+    pub address_bytes: usize,
+    /// Times a packet is sent again after the device reported an error.
+    pub retries: u32,
+    pub timeout: Duration,
+}
+
+impl Default for DumpSettings {
+    fn default() -> Self {
+        Self {
+            input_port: 0,
+            packet_size: None,
+            address_bytes: 3,
+            retries: DEFAULT_RETRIES,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+/// What a device answered to a packet.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Reply {
+    Ack,
+    /// Received wrong, send it again.
+    Error,
+    /// Stop sending altogether.
+    Reject,
+    /// Busy, keep waiting.
+    Wait,
+}
+
+fn classify(data: &[u8]) -> Option<Reply> {
+    match data {
+        // Handshakes carry no address or data, unlike a data set that may
+        // happen to end in the same byte
+        [0xf0, ROLAND_ID, .., command, 0xf7] if data.len() <= 8 => match *command {
+            ROLAND_ACK => Some(Reply::Ack),
+            ROLAND_ERR => Some(Reply::Error),
+            ROLAND_RJC => Some(Reply::Reject),
+            _ => None,
+        },
+        [0xf0, 0x7e, _, command, _, 0xf7] => match *command {
+            UNIVERSAL_ACK => Some(Reply::Ack),
+            UNIVERSAL_NAK => Some(Reply::Error),
+            UNIVERSAL_CANCEL => Some(Reply::Reject),
+            UNIVERSAL_WAIT => Some(Reply::Wait),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reads the messages of a `.syx` file as packets to send, split up to the
+/// packet size of `settings`.
+pub fn load(path: &Path, settings: &DumpSettings) -> Result<Vec<Vec<u8>>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let messages = syx::split_messages(&data)
+        .with_context(|| format!("Invalid SysEx file {}", path.display()))?;
+
+    Ok(match settings.packet_size {
+        Some(size) => split_packets(&messages, size, settings.address_bytes),
+        None => messages,
+    })
+}
+
+/// Splits Roland data sets carrying more than `size` data bytes into several,
+/// each with its address moved on and its checksum redone. Other messages
+/// are left whole.
+pub fn split_packets(messages: &[Vec<u8>], size: usize, address_bytes: usize) -> Vec<Vec<u8>> {
+    let mut packets = Vec::with_capacity(messages.len());
+
+    for message in messages {
+        match data_set_layout(message, address_bytes) {
+            Some(address) if message.len() - address - address_bytes - 2 > size => {
+                let header = &message[..address];
+                let start = to_number(&message[address..address + address_bytes]);
+                let data = &message[address + address_bytes..message.len() - 2];
+
+                for (i, chunk) in data.chunks(size.max(1)).enumerate() {
+                    let mut packet = header.to_vec();
+                    packet.extend(to_address(start + (i * size) as u64, address_bytes));
+                    packet.extend_from_slice(chunk);
+                    packet.extend_from_slice(&[0x00, 0xf7]);
+                    checksum::patch(&mut packet);
+
+                    packets.push(packet);
+                }
+            }
+            _ => packets.push(message.clone()),
+        }
+    }
+
+    packets
+}
+
+/// Index of the address of a Roland data set.
+fn data_set_layout(message: &[u8], address_bytes: usize) -> Option<usize> {
+    if message.len() < 4 || message[0] != 0xf0 || message[1] != ROLAND_ID {
+        return None;
+    }
+
+    // Newer model IDs are extended with leading zeroes
+    let mut command = 3;
+    while command < message.len() && message[command] == 0x00 {
+        command += 1;
+    }
+    command += 1;
+
+    match message.get(command) {
+        Some(&ROLAND_DT1) if command + 1 + address_bytes + 2 <= message.len() => Some(command + 1),
+        _ => None,
+    }
+}
+
+/// Addresses are 7 bits per byte, most significant first.
+fn to_number(address: &[u8]) -> u64 {
+    address
+        .iter()
+        .fold(0, |number, &byte| number << 7 | (byte & 0x7f) as u64)
+}
+
+fn to_address(number: u64, bytes: usize) -> Vec<u8> {
+    (0..bytes)
+        .rev()
+        .map(|i| (number >> (7 * i)) as u8 & 0x7f)
+        .collect()
+}
+
+/// Sends every packet to the output port, waiting for the device to
+/// acknowledge each on the input port before the next, and sending it again
+/// when the device reports an error. Returns how many packets were sent.
+pub fn send(
+    port_id: u32,
+    packets: &[Vec<u8>],
+    settings: &DumpSettings,
+    cancel: CancellationToken,
+) -> Result<usize> {
+    let (sender, receiver) = mpsc::channel();
+    let _input = InputPort::connect(settings.input_port, sender)
+        .with_context(|| format!("Failed to open input port {}", settings.input_port))?;
+    let mut conn_out = OutputPort::connect(port_id)?;
+
+    for (i, packet) in packets.iter().enumerate() {
+        let mut attempts = 0;
+
+        loop {
+            if cancel.is_cancelled() {
+                return Ok(i);
+            }
+
+            // Anything left over belongs to an earlier packet
+            while receiver.try_recv().is_ok() {}
+
+            conn_out
+                .send(packet)
+                .with_context(|| format!("Failed to send packet {}", i + 1))?;
+            conn_out.check_inflight()?;
+
+            match wait_for_reply(&receiver, settings.timeout, &cancel)? {
+                Some(Reply::Ack) => break,
+                None if cancel.is_cancelled() => return Ok(i),
+                None => {
+                    return Err(anyhow!("Packet {} was not acknowledged", i + 1));
+                }
+                Some(Reply::Reject) => {
+                    return Err(anyhow!("The device cancelled the dump at packet {}", i + 1));
+                }
+                Some(Reply::Error) | Some(Reply::Wait) => {
+                    attempts += 1;
+                    if attempts > settings.retries {
+                        return Err(anyhow!(
+                            "Packet {} failed {} times, giving up",
+                            i + 1,
+                            attempts
+                        ));
+                    }
+
+                    println!("Device reported an error, sending packet {} again", i + 1);
+                }
+            };
+        }
+
+        println!("Sent packet {} of {}", i + 1, packets.len());
+    }
+
+    Ok(packets.len())
+}
+
+/// The first handshake reply, waiting on while the device asks to. `None`
+/// when it timed out or was cancelled.
+fn wait_for_reply(
+    receiver: &Receiver<Vec<u8>>,
+    timeout: Duration,
+    cancel: &CancellationToken,
+) -> Result<Option<Reply>> {
+    let mut started = Instant::now();
+
+    while !cancel.is_cancelled() && started.elapsed() < timeout {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(data) => match classify(&data) {
+                Some(Reply::Wait) => started = Instant::now(),
+                Some(reply) => return Ok(Some(reply)),
+                None => {}
+            },
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow!("The input port closed"));
+            }
+        };
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,428 @@
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossterm::style::{Color, Stylize};
+use rimd::MidiMessage;
+
+use crate::generate::note_name;
+use crate::parameters;
+use crate::player::BasicMidiEvent;
+use crate::signature::{KeySignature, TimeSignature};
+use crate::tempo::format_time;
+
+/// Colors of the channels in the event log, told apart at a glance with the
+/// drums of channel 10 standing out.
+const CHANNEL_COLORS: [Color; 16] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::DarkCyan,
+    Color::DarkGreen,
+    Color::DarkYellow,
+    Color::DarkBlue,
+    Color::Red,
+    Color::DarkMagenta,
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+];
+
+/// Something that happened while a player was running.
+#[derive(Clone)]
+pub enum PlayerEvent {
+    /// Information about the sequence or its playback, such as track names.
+    Message(String),
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        key: u8,
+    },
+    /// Any other MIDI or SysEx message, as it was sent.
+    Midi(BasicMidiEvent),
+    /// Microseconds per quarter note now in effect.
+    Tempo(u64),
+    KeySignature(KeySignature),
+    TimeSignature(TimeSignature),
+    Lyric(String),
+    /// An event went out this long after its time, such as when the port or
+    /// the system could not keep up.
+    Late(Duration),
+    /// Sent about every quarter second while playing, with `length` being
+    /// the ticks of one pass and `elapsed` the song time at `tick`, which is
+    /// `beat_tick` ticks into `beat` of `bar`, both counted from 1.
+    Progress {
+        tick: u64,
+        length: u64,
+        elapsed: Duration,
+        percent: f64,
+        bar: u64,
+        beat: u64,
+        beat_tick: u64,
+    },
+    /// Playback ended, whether it ran out of events, was stopped or failed.
+    Finished,
+}
+
+impl PlayerEvent {
+    /// Describes a message that was sent, as a note event where it is one.
+    pub(crate) fn sent(delta_time: u64, data: Vec<u8>) -> Self {
+        if data.len() == 3 {
+            let channel = data[0] & 0x0f;
+            let key = data[1];
+
+            match data[0] & 0xf0 {
+                0x90 if data[2] > 0 => {
+                    return PlayerEvent::NoteOn {
+                        channel,
+                        key,
+                        velocity: data[2],
+                    }
+                }
+                0x80 | 0x90 => return PlayerEvent::NoteOff { channel, key },
+                _ => {}
+            };
+        }
+
+        PlayerEvent::Midi(BasicMidiEvent {
+            delta_time,
+            msg: MidiMessage::from_bytes(data),
+        })
+    }
+
+    /// The MIDI message behind a note or MIDI event.
+    pub fn midi_message(&self) -> Option<Vec<u8>> {
+        match self {
+            PlayerEvent::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => Some(vec![0x90 | channel, *key, *velocity]),
+            PlayerEvent::NoteOff { channel, key } => Some(vec![0x80 | channel, *key, 0]),
+            PlayerEvent::Midi(event) => Some(event.msg.data.clone()),
+            _ => None,
+        }
+    }
+
+    /// A single line JSON object describing the event, which happened `time`
+    /// into playback.
+    pub fn to_json(&self, time: Duration) -> String {
+        let message = self.midi_message();
+        let (kind, data) = match (self, &message) {
+            (PlayerEvent::Message(text), _) => ("message", json_string(text)),
+            (PlayerEvent::Tempo(tempo), _) => ("tempo", tempo.to_string()),
+            (PlayerEvent::KeySignature(key), _) => ("key_signature", json_string(&key.to_string())),
+            (PlayerEvent::TimeSignature(signature), _) => (
+                "time_signature",
+                format!(
+                    "{{\"numerator\":{},\"denominator\":{}}}",
+                    signature.numerator,
+                    signature.note_value()
+                ),
+            ),
+            (PlayerEvent::Lyric(text), _) => ("lyric", json_string(text)),
+            (PlayerEvent::Late(late), _) => ("late", format!("{:.3}", late.as_secs_f64())),
+            (
+                PlayerEvent::Progress {
+                    tick,
+                    length,
+                    elapsed,
+                    percent,
+                    bar,
+                    beat,
+                    beat_tick,
+                },
+                _,
+            ) => (
+                "progress",
+                format!(
+                    "{{\"tick\":{},\"length\":{},\"elapsed\":{:.3},\"percent\":{:.1},\
+                     \"bar\":{},\"beat\":{},\"beat_tick\":{}}}",
+                    tick,
+                    length,
+                    elapsed.as_secs_f64(),
+                    percent,
+                    bar,
+                    beat,
+                    beat_tick
+                ),
+            ),
+            (PlayerEvent::Finished, _) => ("finished", String::from("null")),
+            (_, Some(message)) => {
+                let bytes: Vec<String> = message.iter().map(|byte| byte.to_string()).collect();
+                (message_kind(message), format!("[{}]", bytes.join(",")))
+            }
+            (_, None) => ("unknown", String::from("null")),
+        };
+
+        let channel = match message.as_deref() {
+            Some([status, ..]) if *status < 0xf0 => ((status & 0x0f) + 1).to_string(),
+            _ => String::from("null"),
+        };
+
+        format!(
+            "{{\"timestamp\":{:.3},\"type\":\"{}\",\"channel\":{},\"data\":{}}}",
+            time.as_secs_f64(),
+            kind,
+            channel,
+            data
+        )
+    }
+
+    /// A line of the event log, which happened `time` into playback, in
+    /// columns of time, channel, kind and what it holds, so the same kind of
+    /// event lines up from line to line. With `color` it is colored by
+    /// channel, with late events in red and the rest dimmed.
+    pub fn log_line(&self, time: Duration, color: bool) -> String {
+        let message = self.midi_message();
+        let channel = match message.as_deref() {
+            Some([status, ..]) if *status < 0xf0 => Some(status & 0x0f),
+            _ => None,
+        };
+
+        let (label, detail) = match (self, message.as_deref()) {
+            (_, Some(message)) => (message_label(message), message_detail(message)),
+            (PlayerEvent::Message(text), _) => ("Message", text.clone()),
+            (PlayerEvent::Tempo(tempo), _) => (
+                "Tempo",
+                format!("{:.2} BPM ({} us)", 60_000_000.0 / *tempo as f64, tempo),
+            ),
+            (PlayerEvent::KeySignature(key), _) => ("Key", key.to_string()),
+            (PlayerEvent::TimeSignature(signature), _) => ("Time signature", signature.to_string()),
+            (PlayerEvent::Lyric(text), _) => ("Lyric", text.clone()),
+            (PlayerEvent::Late(late), _) => {
+                ("Late", format!("{:.1} ms", late.as_secs_f64() * 1000.0))
+            }
+            (
+                PlayerEvent::Progress {
+                    elapsed,
+                    percent,
+                    bar,
+                    beat,
+                    ..
+                },
+                _,
+            ) => (
+                "Progress",
+                format!(
+                    "{} ({:.1}%), bar {} beat {}",
+                    format_time(*elapsed),
+                    percent,
+                    bar,
+                    beat
+                ),
+            ),
+            (PlayerEvent::Finished, _) => ("Finished", String::new()),
+            (_, None) => ("Unknown", String::new()),
+        };
+
+        let line = format!(
+            "{:>10}  {:<5}  {:<14}  {}",
+            format_time(time),
+            channel.map_or(String::new(), |channel| format!("ch {:>2}", channel + 1)),
+            label,
+            detail
+        );
+        let line = line.trim_end().to_string();
+
+        if !color {
+            return line;
+        }
+
+        match (self, channel) {
+            (_, Some(channel)) => line.with(CHANNEL_COLORS[channel as usize]).to_string(),
+            (PlayerEvent::Late(_), None) => line.red().bold().to_string(),
+            (PlayerEvent::Lyric(_), None) | (PlayerEvent::Message(_), None) => line,
+            _ => line.dark_grey().to_string(),
+        }
+    }
+}
+
+/// How the event log labels a message, at most 14 characters.
+fn message_label(message: &[u8]) -> &'static str {
+    match message_kind(message) {
+        "note_off" => "Note off",
+        "note_on" => "Note on",
+        "poly_aftertouch" => "Aftertouch",
+        "control_change" => "Control change",
+        "program_change" => "Program change",
+        "channel_pressure" => "Pressure",
+        "pitch_bend" => "Pitch bend",
+        "sysex" => "SysEx",
+        _ => "System",
+    }
+}
+
+/// What a message holds, with keys by name and SysEx described when known.
+fn message_detail(message: &[u8]) -> String {
+    match message {
+        [status, key, velocity] if status & 0xf0 == 0x90 && *velocity > 0 => {
+            format!("{:<4} {:>3}  velocity {}", note_name(*key), key, velocity)
+        }
+        [status, key, _] if status & 0xf0 == 0x80 || status & 0xf0 == 0x90 => {
+            format!("{:<4} {:>3}", note_name(*key), key)
+        }
+        [status, key, pressure] if status & 0xf0 == 0xa0 => {
+            format!("{:<4} {:>3}  pressure {}", note_name(*key), key, pressure)
+        }
+        [status, controller, value] if status & 0xf0 == 0xb0 => {
+            format!("{:>3} = {}", controller, value)
+        }
+        [status, value] if status & 0xf0 == 0xc0 || status & 0xf0 == 0xd0 => {
+            format!("{:>3}", value)
+        }
+        [status, low, high] if status & 0xf0 == 0xe0 => {
+            format!("{:+}", ((*high as i32) << 7 | *low as i32) - 0x2000)
+        }
+        _ => {
+            let bytes: Vec<String> = message.iter().map(|byte| format!("{:02X}", byte)).collect();
+            match parameters::describe(message) {
+                Some(description) => format!("{} [{}]", description, bytes.join(" ")),
+                None => bytes.join(" "),
+            }
+        }
+    }
+}
+
+fn message_kind(message: &[u8]) -> &'static str {
+    match message.first().map(|status| status & 0xf0) {
+        Some(0x80) => "note_off",
+        Some(0x90) if message.get(2) == Some(&0) => "note_off",
+        Some(0x90) => "note_on",
+        Some(0xa0) => "poly_aftertouch",
+        Some(0xb0) => "control_change",
+        Some(0xc0) => "program_change",
+        Some(0xd0) => "channel_pressure",
+        Some(0xe0) => "pitch_bend",
+        Some(0xf0) if message[0] == 0xf0 => "sysex",
+        _ => "system",
+    }
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        };
+    }
+
+    escaped.push('"');
+    escaped
+}
+
+impl fmt::Display for PlayerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlayerEvent::Message(message) => write!(f, "{}", message),
+            PlayerEvent::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => write!(f, "NoteOn {}: [{},{}]", channel + 1, key, velocity),
+            PlayerEvent::NoteOff { channel, key } => {
+                write!(f, "NoteOff {}: [{}]", channel + 1, key)
+            }
+            PlayerEvent::Midi(event) => write!(f, "{} {}", event.delta_time, event),
+            PlayerEvent::Tempo(tempo) => write!(f, "new tempo: {}", tempo),
+            PlayerEvent::KeySignature(key) => write!(f, "Key signature: {}", key),
+            PlayerEvent::TimeSignature(signature) => write!(f, "Time signature: {}", signature),
+            PlayerEvent::Lyric(lyric) => write!(f, "Lyric: {}", lyric),
+            PlayerEvent::Late(late) => {
+                write!(f, "Late by {:.1} ms", late.as_secs_f64() * 1000.0)
+            }
+            PlayerEvent::Progress {
+                tick,
+                length,
+                elapsed,
+                percent,
+                bar,
+                beat,
+                ..
+            } => write!(
+                f,
+                "Progress: {} ({:.1}%), bar {} beat {}, tick {}/{}",
+                format_time(*elapsed),
+                percent,
+                bar,
+                beat,
+                tick,
+                length
+            ),
+            PlayerEvent::Finished => write!(f, "Finished"),
+        }
+    }
+}
+
+type Callback = Box<dyn FnMut(&PlayerEvent) + Send>;
+
+#[derive(Default)]
+struct Subscribers {
+    channels: Vec<Sender<PlayerEvent>>,
+    callbacks: Vec<Callback>,
+}
+
+/// Hands the events of a player to every subscriber, whether they subscribed
+/// before or during playback.
+#[derive(Clone, Default)]
+pub struct PlayerEvents {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl PlayerEvents {
+    /// Returns a channel receiving every event from now on.
+    pub fn subscribe(&self) -> Receiver<PlayerEvent> {
+        let (sender, receiver) = mpsc::channel();
+
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.channels.push(sender);
+        }
+
+        receiver
+    }
+
+    /// Calls `callback` for every event from now on. It runs on the playback
+    /// thread, so anything slow delays the music.
+    pub fn on_event(&self, callback: impl FnMut(&PlayerEvent) + Send + 'static) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.callbacks.push(Box::new(callback));
+        }
+    }
+
+    pub(crate) fn send(&self, event: PlayerEvent) {
+        let mut subscribers = match self.subscribers.lock() {
+            Ok(subscribers) => subscribers,
+            Err(_) => return,
+        };
+
+        for callback in &mut subscribers.callbacks {
+            callback(&event);
+        }
+
+        // Forget channels whose receiver went away
+        subscribers
+            .channels
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    pub(crate) fn message(&self, message: impl Into<String>) {
+        self.send(PlayerEvent::Message(message.into()));
+    }
+}
@@ -0,0 +1,82 @@
+use anyhow::Result;
+
+use crate::midi_file::{DataEvent, LocalEvent};
+
+/// A kind of message that can be dropped before playback.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MessageClass {
+    /// Polyphonic key pressure.
+    KeyPressure,
+    ChannelPressure,
+    Controller,
+    Program,
+    PitchBend,
+    SysEx,
+}
+
+impl MessageClass {
+    /// Parses a comma separated list of classes, `aftertouch` standing for
+    /// both kinds of pressure.
+    pub fn parse_list(value: &str) -> Result<Vec<Self>> {
+        let mut classes = Vec::new();
+
+        for name in value.split(',').map(str::trim) {
+            match name {
+                "aftertouch" => {
+                    classes.extend([MessageClass::KeyPressure, MessageClass::ChannelPressure])
+                }
+                "key-pressure" | "poly-aftertouch" => classes.push(MessageClass::KeyPressure),
+                "channel-pressure" => classes.push(MessageClass::ChannelPressure),
+                "cc" | "controller" => classes.push(MessageClass::Controller),
+                "program" => classes.push(MessageClass::Program),
+                "pitchbend" => classes.push(MessageClass::PitchBend),
+                "sysex" => classes.push(MessageClass::SysEx),
+                _ => return Err(anyhow!("Unknown message class: {}", name)),
+            };
+        }
+
+        Ok(classes)
+    }
+
+    fn matches(self, event: &LocalEvent) -> bool {
+        match (self, event) {
+            (MessageClass::SysEx, LocalEvent::SysEx(_)) => true,
+            (_, LocalEvent::Midi(data)) => {
+                let status = match self {
+                    MessageClass::KeyPressure => 0xa0,
+                    MessageClass::Controller => 0xb0,
+                    MessageClass::Program => 0xc0,
+                    MessageClass::ChannelPressure => 0xd0,
+                    MessageClass::PitchBend => 0xe0,
+                    MessageClass::SysEx => return false,
+                };
+
+                data[0] & 0xf0 == status
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Leaves out every message of the classes in `drop`, their delta times
+/// carried over to the next event so everything else keeps its timing.
+pub fn apply(events: Vec<DataEvent>, drop: &[MessageClass]) -> Vec<DataEvent> {
+    if drop.is_empty() {
+        return events;
+    }
+
+    let mut kept = Vec::with_capacity(events.len());
+    let mut carried = 0;
+
+    for mut event in events {
+        if drop.iter().any(|class| class.matches(&event.data)) {
+            carried += event.delta_time;
+        } else {
+            event.delta_time += carried;
+            carried = 0;
+            kept.push(event);
+        }
+    }
+
+    kept
+}
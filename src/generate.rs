@@ -0,0 +1,426 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::midi_file::{self, DataEvent, Note};
+
+/// Ticks per quarter note of the generated material.
+pub const DIVISION: u64 = 480;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pattern {
+    Scale,
+    Chords,
+    Walk,
+    Euclid,
+}
+
+/// One voice of a Euclidean rhythm: `pulses` hits spread as evenly as
+/// possible over `steps`, shifted right by `rotation` steps.
+#[derive(Clone)]
+pub struct EuclidLayer {
+    pub key: u8,
+    pub pulses: u32,
+    pub steps: u32,
+    pub rotation: u32,
+}
+
+impl EuclidLayer {
+    /// Parses `KEY:PULSES/STEPS` with an optional `+ROTATION`, e.g. `38:2/16+4`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || format!("Invalid Euclidean layer: {}", value);
+
+        let (key, rhythm) = split_once(value, ':').with_context(invalid)?;
+        let (rhythm, rotation) = match split_once(rhythm, '+') {
+            Some((rhythm, rotation)) => (rhythm, rotation.parse().with_context(invalid)?),
+            None => (rhythm, 0),
+        };
+        let (pulses, steps) = split_once(rhythm, '/').with_context(invalid)?;
+
+        let key = match key.parse() {
+            Ok(key) => key,
+            Err(_) => parse_note(key)?,
+        };
+        let pulses = pulses.parse().with_context(invalid)?;
+        let steps: u32 = steps.parse().with_context(invalid)?;
+
+        if steps == 0 || pulses > steps {
+            return Err(anyhow!("{}: pulses must fit within the steps", invalid()));
+        }
+
+        Ok(Self {
+            key,
+            pulses,
+            steps,
+            rotation,
+        })
+    }
+
+    fn hits(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.steps).filter(move |step| {
+            let step = (step + self.steps - self.rotation % self.steps) % self.steps;
+            (step * self.pulses) % self.steps < self.pulses
+        })
+    }
+}
+
+fn split_once(value: &str, delimiter: char) -> Option<(&str, &str)> {
+    let index = value.find(delimiter)?;
+    Some((&value[..index], &value[index + 1..]))
+}
+
+#[derive(Clone, Copy)]
+pub enum Mode {
+    Major,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Minor,
+    Locrian,
+    HarmonicMinor,
+    Pentatonic,
+    Chromatic,
+}
+
+impl Mode {
+    fn intervals(self) -> &'static [u8] {
+        match self {
+            Mode::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Mode::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Mode::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Mode::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Mode::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Mode::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Mode::Locrian => &[0, 1, 3, 5, 6, 8, 10],
+            Mode::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Mode::Pentatonic => &[0, 2, 4, 7, 9],
+            Mode::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "major" | "ionian" => Mode::Major,
+            "dorian" => Mode::Dorian,
+            "phrygian" => Mode::Phrygian,
+            "lydian" => Mode::Lydian,
+            "mixolydian" => Mode::Mixolydian,
+            "minor" | "aeolian" => Mode::Minor,
+            "locrian" => Mode::Locrian,
+            "harmonic-minor" => Mode::HarmonicMinor,
+            "pentatonic" => Mode::Pentatonic,
+            "chromatic" => Mode::Chromatic,
+            _ => return Err(anyhow!("Unknown mode: {}", value)),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct GenerateSettings {
+    pub pattern: Pattern,
+    pub root: u8,
+    pub mode: Mode,
+    pub bpm: u32,
+    pub octaves: u8,
+    pub channel: u8,
+    pub velocity: u8,
+    pub program: Option<u8>,
+    /// Number of notes in a random walk.
+    pub length: usize,
+    pub layers: Vec<EuclidLayer>,
+    /// Euclidean steps per quarter note.
+    pub subdivision: u32,
+}
+
+impl GenerateSettings {
+    pub fn new(pattern: Pattern) -> Self {
+        Self {
+            pattern,
+            root: 60,
+            mode: Mode::Major,
+            bpm: 120,
+            octaves: 1,
+            // Rhythms go to the GM drum channel by default
+            channel: if pattern == Pattern::Euclid { 9 } else { 0 },
+            velocity: 100,
+            program: None,
+            length: 32,
+            layers: Vec::new(),
+            subdivision: 4,
+        }
+    }
+
+    /// Microseconds per quarter note for the requested BPM.
+    pub fn tempo(&self) -> u64 {
+        60_000_000 / self.bpm.max(1) as u64
+    }
+}
+
+impl Pattern {
+    pub fn parse(value: &str) -> Result<Self> {
+        Ok(match value {
+            "scale" => Pattern::Scale,
+            "chords" | "chord" => Pattern::Chords,
+            "walk" => Pattern::Walk,
+            "euclid" => Pattern::Euclid,
+            _ => return Err(anyhow!("Unknown pattern: {}", value)),
+        })
+    }
+}
+
+/// Parses a note name such as `C`, `F#3` or `Bb5` into a MIDI key, with the
+/// octave defaulting to 4 (middle C being `C4`).
+pub fn parse_note(value: &str) -> Result<u8> {
+    let mut chars = value.chars();
+    let letter = chars.next().context("Empty note name")?;
+    let mut key: i32 = match letter.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return Err(anyhow!("Invalid note name: {}", value)),
+    };
+
+    let mut rest = chars.as_str();
+    if let Some(stripped) = rest.strip_prefix('#') {
+        key += 1;
+        rest = stripped;
+    } else if let Some(stripped) = rest.strip_prefix('b') {
+        key -= 1;
+        rest = stripped;
+    }
+
+    let octave: i32 = if rest.is_empty() {
+        4
+    } else {
+        rest.parse()
+            .with_context(|| format!("Invalid octave in note: {}", value))?
+    };
+
+    let key = (octave + 1) * 12 + key;
+    if !(0..=127).contains(&key) {
+        return Err(anyhow!("Note out of range: {}", value));
+    }
+
+    Ok(key as u8)
+}
+
+/// The name of a MIDI key, such as `C4` for middle C or `F#2`.
+pub fn note_name(key: u8) -> String {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
+    format!("{}{}", NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+pub fn generate(settings: &GenerateSettings) -> Vec<DataEvent> {
+    let keys = scale_keys(settings);
+
+    let notes = match settings.pattern {
+        Pattern::Scale => scale(&keys, settings),
+        Pattern::Chords => chords(&keys, settings),
+        Pattern::Walk => walk(&keys, settings),
+        Pattern::Euclid => euclid(&euclid_layers(settings), settings),
+    };
+
+    midi_file::sequence_notes(&notes, settings.channel, settings.program)
+}
+
+/// Length in ticks of one pass for patterns that repeat until stopped.
+pub fn loop_length(settings: &GenerateSettings) -> Option<u64> {
+    match settings.pattern {
+        Pattern::Euclid => {
+            let steps = euclid_layers(settings)
+                .iter()
+                .fold(1, |cycle, layer| lcm(cycle, layer.steps as u64));
+
+            Some(steps * step_ticks(settings))
+        }
+        _ => None,
+    }
+}
+
+fn euclid_layers(settings: &GenerateSettings) -> Vec<EuclidLayer> {
+    if !settings.layers.is_empty() {
+        return settings.layers.clone();
+    }
+
+    // Kick, snare on the backbeat and a busier closed hi-hat
+    vec![
+        EuclidLayer {
+            key: 36,
+            pulses: 4,
+            steps: 16,
+            rotation: 0,
+        },
+        EuclidLayer {
+            key: 38,
+            pulses: 2,
+            steps: 16,
+            rotation: 4,
+        },
+        EuclidLayer {
+            key: 42,
+            pulses: 5,
+            steps: 8,
+            rotation: 0,
+        },
+    ]
+}
+
+fn step_ticks(settings: &GenerateSettings) -> u64 {
+    DIVISION / settings.subdivision.max(1) as u64
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    a / gcd(a, b) * b
+}
+
+/// Every key of the scale across the requested octaves, including the root
+/// at the top.
+fn scale_keys(settings: &GenerateSettings) -> Vec<u8> {
+    let intervals = settings.mode.intervals();
+    let mut keys = Vec::new();
+
+    for octave in 0..settings.octaves.max(1) {
+        for interval in intervals {
+            let key = settings.root as u32 + octave as u32 * 12 + *interval as u32;
+            if key <= 127 {
+                keys.push(key as u8);
+            }
+        }
+    }
+
+    let top = settings.root as u32 + settings.octaves.max(1) as u32 * 12;
+    if top <= 127 {
+        keys.push(top as u8);
+    }
+
+    keys
+}
+
+fn note(time: u64, length: u64, key: u8, settings: &GenerateSettings) -> Note {
+    Note {
+        time,
+        length,
+        key,
+        velocity: settings.velocity,
+    }
+}
+
+/// Up and back down the scale, one note per beat.
+fn scale(keys: &[u8], settings: &GenerateSettings) -> Vec<Note> {
+    keys.iter()
+        .chain(keys.iter().rev().skip(1))
+        .enumerate()
+        .map(|(i, &key)| note(i as u64 * DIVISION, DIVISION * 9 / 10, key, settings))
+        .collect()
+}
+
+/// A triad on every degree of the scale, two beats each.
+fn chords(keys: &[u8], settings: &GenerateSettings) -> Vec<Note> {
+    let degrees = settings.mode.intervals().len();
+    let mut notes = Vec::new();
+
+    for degree in 0..degrees {
+        let time = degree as u64 * DIVISION * 2;
+
+        for step in &[0, 2, 4] {
+            let index = degree + step;
+            let key = match keys.get(index % degrees) {
+                Some(&key) => key as u32 + (index / degrees) as u32 * 12,
+                None => continue,
+            };
+
+            if key <= 127 {
+                notes.push(note(
+                    time,
+                    DIVISION * 2 - DIVISION / 10,
+                    key as u8,
+                    settings,
+                ));
+            }
+        }
+    }
+
+    notes
+}
+
+/// A random walk over the scale in eighth notes.
+fn walk(keys: &[u8], settings: &GenerateSettings) -> Vec<Note> {
+    let mut rng = XorShift::from_time();
+    let mut index = 0usize;
+    let mut notes = Vec::with_capacity(settings.length);
+
+    for i in 0..settings.length {
+        notes.push(note(
+            i as u64 * DIVISION / 2,
+            DIVISION / 2 - DIVISION / 20,
+            keys[index],
+            settings,
+        ));
+
+        // Step up to two degrees in either direction, staying inside the scale
+        let step = (rng.next_u64() % 5) as isize - 2;
+        index = (index as isize + step).max(0).min(keys.len() as isize - 1) as usize;
+    }
+
+    notes
+}
+
+/// Every layer repeated over one full cycle of all of them.
+fn euclid(layers: &[EuclidLayer], settings: &GenerateSettings) -> Vec<Note> {
+    let step_ticks = step_ticks(settings);
+    let cycle = layers
+        .iter()
+        .fold(1, |cycle, layer| lcm(cycle, layer.steps as u64));
+    let mut notes = Vec::new();
+
+    for layer in layers {
+        for repeat in 0..cycle / layer.steps as u64 {
+            for step in layer.hits() {
+                let time = (repeat * layer.steps as u64 + step as u64) * step_ticks;
+                notes.push(note(time, step_ticks / 2, layer.key, settings));
+            }
+        }
+    }
+
+    notes
+}
+
+/// Small xorshift generator, plenty for musical randomness.
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn from_time() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+
+        // The state must never be zero
+        Self(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+}
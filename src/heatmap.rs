@@ -0,0 +1,116 @@
+use std::ops::RangeInclusive;
+
+// From unused to the most played key of the song
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+// Marks a played key the device cannot reproduce
+const OUT_OF_RANGE: char = '!';
+
+/// Counts note ons per channel and key over a song.
+pub struct NoteUsage {
+    counts: [[u32; 128]; 16],
+    /// Keys the device can actually play.
+    range: Option<RangeInclusive<u8>>,
+}
+
+impl NoteUsage {
+    pub fn new(range: Option<RangeInclusive<u8>>) -> Self {
+        Self {
+            counts: [[0; 128]; 16],
+            range,
+        }
+    }
+
+    /// Starts over for the next song.
+    pub fn reset(&mut self) {
+        self.counts = [[0; 128]; 16];
+    }
+
+    pub fn process(&mut self, message: &[u8]) {
+        if message.len() < 3 || message[0] & 0xf0 != 0x90 || message[2] == 0 {
+            return;
+        }
+
+        let channel = (message[0] & 0x0f) as usize;
+        let key = (message[1] & 0x7f) as usize;
+
+        self.counts[channel][key] = self.counts[channel][key].saturating_add(1);
+    }
+
+    /// One row of 128 keys per channel that played anything, shaded relative to
+    /// the most played key, followed by the out-of-range summary.
+    pub fn render(&self) -> Vec<String> {
+        let max = self
+            .counts
+            .iter()
+            .flat_map(|keys| keys.iter())
+            .copied()
+            .max()
+            .unwrap_or(0);
+
+        if max == 0 {
+            return Vec::new();
+        }
+
+        // Octave numbers above every C, middle C being C4
+        let mut ruler = String::from("Note usage ");
+        for key in 0..128u8 {
+            ruler.push(match key {
+                0 => '-',
+                key if key % 12 == 0 => char::from(b'0' + key / 12 - 1),
+                _ => ' ',
+            });
+        }
+
+        let mut lines = vec![ruler];
+        let mut out_of_range = Vec::new();
+
+        for (channel, keys) in self.counts.iter().enumerate() {
+            if keys.iter().all(|count| *count == 0) {
+                continue;
+            }
+
+            let mut row = format!("Channel {:>2} ", channel + 1);
+            for (key, &count) in keys.iter().enumerate() {
+                if count > 0 && !self.in_range(key as u8) {
+                    out_of_range.push((channel, key, count));
+                    row.push(OUT_OF_RANGE);
+                } else {
+                    row.push(shade(count, max));
+                }
+            }
+
+            lines.push(row);
+        }
+
+        for (channel, key, count) in out_of_range {
+            lines.push(format!(
+                "  - Channel {}: key {} out of range, played {} times",
+                channel + 1,
+                key,
+                count
+            ));
+        }
+
+        lines
+    }
+
+    fn in_range(&self, key: u8) -> bool {
+        match &self.range {
+            Some(range) => range.contains(&key),
+            None => true,
+        }
+    }
+}
+
+fn shade(count: u32, max: u32) -> char {
+    if count == 0 {
+        return SHADES[0] as char;
+    }
+
+    // Anything played gets at least the lightest visible shade
+    let levels = (SHADES.len() - 1) as u64;
+    let level = 1 + (count as u64 * (levels - 1) / max as u64) as usize;
+
+    SHADES[level] as char
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use rimd::MetaCommand;
+
+use crate::midi_file::{LocalEvent, Sequence};
+use crate::player::DEFAULT_TEMPO;
+use crate::tempo::{format_time, TempoMap};
+use crate::text;
+use crate::timeline;
+
+/// How often the status line is rendered by default, often enough for the
+/// time to tick over smoothly without keeping a core busy.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(33);
+
+/// A compact status line for performing from, showing the song, where it is
+/// and the marker coming up next.
+pub struct Hud {
+    title: String,
+    division: u64,
+    signatures: Vec<(u64, (u8, u8))>,
+    /// Marker texts by tick.
+    markers: Vec<(u64, String)>,
+    tempo_map: TempoMap,
+    length: Duration,
+}
+
+impl Hud {
+    pub fn new(sequence: &Sequence) -> Self {
+        let mut markers = Vec::new();
+        let mut tick = 0;
+
+        for event in &sequence.events {
+            tick += event.delta_time;
+
+            match &event.data {
+                LocalEvent::Meta(meta) if matches!(meta.command, MetaCommand::MarkerText) => {
+                    markers.push((tick, text::decode(&meta.data, sequence.encoding)));
+                }
+                _ => {}
+            }
+        }
+
+        let tempo_map = TempoMap::from_events(&sequence.events, DEFAULT_TEMPO);
+        let length = tempo_map.time_at(tick, sequence.division);
+
+        Self {
+            title: sequence.title.clone(),
+            division: sequence.division,
+            signatures: timeline::time_signatures(&sequence.events),
+            markers,
+            tempo_map,
+            length,
+        }
+    }
+
+    /// The status at `tick`, like
+    /// "Title | bar 12 beat 3 | 0:24.000 / 3:10.500 | next: Chorus at bar 17".
+    pub fn render(&self, tick: u64) -> String {
+        let elapsed = self.tempo_map.time_at(tick, self.division);
+        let mut line = format!(
+            "{} | {} | {} / {}",
+            self.title,
+            timeline::bar_and_beat(tick, self.division, &self.signatures),
+            format_time(elapsed),
+            format_time(self.length)
+        );
+
+        if let Some((marker_tick, marker)) = self.markers.iter().find(|&&(at, _)| at > tick) {
+            let (bar, _, _) = timeline::locate(*marker_tick, self.division, &self.signatures);
+
+            line.push_str(&format!(" | next: {} at bar {}", marker.trim(), bar));
+        }
+
+        line
+    }
+}
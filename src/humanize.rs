@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::generate::XorShift;
+use crate::midi_file::{DataEvent, LocalEvent};
+use crate::tempo::TempoMap;
+
+/// How far notes may wander from where a quantized file put them.
+#[derive(Clone, Copy, Default)]
+pub struct Humanize {
+    /// Largest shift of a note on, earlier or later.
+    pub timing: Duration,
+    /// Largest change of a note's velocity, up or down.
+    pub velocity: u8,
+}
+
+impl Humanize {
+    /// Parses a comma separated list like `timing=5ms,velocity=8`, with the
+    /// timing in milliseconds when no unit is given.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut humanize = Self::default();
+
+        for setting in value.split(',').map(str::trim) {
+            let invalid = || format!("Invalid humanize setting: {}", setting);
+            let (name, amount) = setting.split_once('=').with_context(invalid)?;
+            let amount = amount.trim();
+
+            match name.trim() {
+                "timing" => {
+                    let millis = amount.strip_suffix("ms").unwrap_or(amount);
+                    humanize.timing = Duration::from_millis(millis.parse().with_context(invalid)?);
+                }
+                "velocity" => {
+                    humanize.velocity = amount.parse().with_context(invalid)?;
+                    if humanize.velocity > 127 {
+                        return Err(anyhow!("Humanize velocity must be at most 127"));
+                    }
+                }
+                name => return Err(anyhow!("Unknown humanize setting: {}", name)),
+            };
+        }
+
+        Ok(humanize)
+    }
+}
+
+/// Shifts every note on by a random time and changes its velocity by a
+/// random amount, both within the bounds of `humanize`. A note on never
+/// crosses another event of its key, so it can neither swap places with its
+/// own note off nor with the note before it.
+pub fn apply(
+    events: Vec<DataEvent>,
+    division: u64,
+    tempo: u64,
+    humanize: Option<Humanize>,
+) -> Vec<DataEvent> {
+    let humanize = match humanize {
+        Some(humanize) => humanize,
+        None => return events,
+    };
+
+    let tempo_map = TempoMap::from_events(&events, tempo);
+    let mut random = XorShift::from_time();
+    let mut random_offset = |range: u64| -> i64 {
+        match range {
+            0 => 0,
+            _ => (random.next_u64() % (range * 2 + 1)) as i64 - range as i64,
+        }
+    };
+
+    let mut tick = 0;
+    let mut timed: Vec<(u64, DataEvent)> = events
+        .into_iter()
+        .map(|event| {
+            tick += event.delta_time;
+            (tick, event)
+        })
+        .collect();
+
+    // Ticks of the note messages of every channel and key, in order
+    let note_key = |event: &DataEvent| match event.data {
+        LocalEvent::Midi(data) if matches!(data[0] & 0xf0, 0x80 | 0x90) => {
+            Some(((data[0] & 0x0f) as usize, (data[1] & 0x7f) as usize))
+        }
+        _ => None,
+    };
+    let mut keys: Vec<Vec<u64>> = vec![Vec::new(); 16 * 128];
+    for (tick, event) in &timed {
+        if let Some((channel, key)) = note_key(event) {
+            keys[channel * 128 + key].push(*tick);
+        }
+    }
+    let mut seen = vec![0; 16 * 128];
+
+    for (tick, event) in &mut timed {
+        let (channel, key) = match note_key(event) {
+            Some(note) => note,
+            None => continue,
+        };
+        let ticks = &keys[channel * 128 + key];
+        let index = seen[channel * 128 + key];
+        seen[channel * 128 + key] += 1;
+
+        let data = match &mut event.data {
+            LocalEvent::Midi(data) if data[0] & 0xf0 == 0x90 && data[2] > 0 => data,
+            _ => continue,
+        };
+
+        let velocity = data[2] as i64 + random_offset(humanize.velocity as u64);
+        data[2] = velocity.clamp(1, 127) as u8;
+
+        let micros = random_offset(humanize.timing.as_micros() as u64);
+        let shift = micros * division as i64 / tempo_map.tempo_at(*tick).max(1) as i64;
+        let earliest = match index {
+            0 => 0,
+            _ => ticks[index - 1] as i64,
+        };
+        let latest = ticks.get(index + 1).map_or(i64::MAX, |&next| next as i64);
+        *tick = (*tick as i64 + shift).clamp(earliest, latest) as u64;
+    }
+
+    // Stable, so events landing on the same tick keep their order
+    timed.sort_by_key(|&(tick, _)| tick);
+
+    let mut last_tick = 0;
+    timed
+        .into_iter()
+        .map(|(tick, mut event)| {
+            event.delta_time = tick - last_tick;
+            last_tick = tick;
+            event
+        })
+        .collect()
+}
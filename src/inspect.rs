@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+use rimd::{Event, MetaCommand, SMF};
+use std::path::Path;
+
+use crate::midi_file;
+use crate::parameters;
+use crate::player::DEFAULT_TEMPO;
+use crate::signature::{KeySignature, TimeSignature};
+use crate::tempo::{format_time, TempoMap};
+use crate::text;
+use crate::timeline::bar_and_beat;
+
+/// Describes a Standard MIDI File without playing it: its header, tempo map,
+/// time and key signatures, tracks and length. Positions are given as bar and
+/// beat following the time signatures, or 4/4 without any.
+pub fn inspect(path: &Path, encoding: Option<&'static Encoding>) -> Result<Vec<String>> {
+    let midi_data = SMF::from_file(path).context("Failed to parse MIDI file")?;
+    let encoding = encoding.unwrap_or_else(|| text::detect(&midi_data.tracks));
+
+    if midi_data.division < 0 {
+        return Err(anyhow!("SMPTE division not supported"));
+    }
+
+    let division = midi_data.division as u64;
+    let mut tempos = Vec::new();
+    let mut time_signatures = Vec::new();
+    let mut key_signatures = Vec::new();
+    let mut parameters = Vec::new();
+    let mut other_sysex = 0;
+    let mut tracks = Vec::new();
+    let mut length = 0;
+
+    for (i, track) in midi_data.tracks.iter().enumerate() {
+        let mut tick = 0;
+        let mut midi_events = 0;
+        let mut meta_events = 0;
+        let mut channels = [false; 16];
+
+        for event in &track.events {
+            tick += event.vtime;
+
+            match &event.event {
+                Event::Midi(message) => {
+                    midi_events += 1;
+
+                    match message.data.first() {
+                        Some(&status) if status < 0xf0 => channels[(status & 0x0f) as usize] = true,
+                        Some(0xf0) => match parameters::describe(&message.data) {
+                            Some(description) => parameters.push((tick, description)),
+                            None => other_sysex += 1,
+                        },
+                        _ => {}
+                    };
+                }
+                Event::Meta(meta) => {
+                    meta_events += 1;
+
+                    match meta.command {
+                        MetaCommand::TempoSetting => tempos.push((tick, meta.data_as_u64(3))),
+                        MetaCommand::TimeSignature => {
+                            if let Some(signature) = TimeSignature::from_meta(&meta.data) {
+                                time_signatures
+                                    .push((tick, (signature.numerator, signature.denominator)));
+                            }
+                        }
+                        MetaCommand::KeySignature => {
+                            if let Some(key) = KeySignature::from_meta(&meta.data) {
+                                key_signatures.push((tick, key));
+                            }
+                        }
+                        _ => {}
+                    };
+                }
+            };
+        }
+
+        length = length.max(tick);
+
+        let name = match text::track_name(track, encoding) {
+            Some(name) => format!(" \"{}\"", name),
+            None => String::new(),
+        };
+        let channels: Vec<String> = (0..16)
+            .filter(|&channel| channels[channel])
+            .map(|channel| (channel + 1).to_string())
+            .collect();
+        let channels = match channels.len() {
+            0 => String::from("no channels"),
+            _ => format!("channels {}", channels.join(", ")),
+        };
+
+        tracks.push(format!(
+            "  #{}{}: {} events ({} MIDI, {} meta), {}",
+            i + 1,
+            name,
+            midi_events + meta_events,
+            midi_events,
+            meta_events,
+            channels
+        ));
+    }
+
+    let tempo_map = TempoMap::new(DEFAULT_TEMPO, tempos);
+    time_signatures.sort_by_key(|&(tick, _)| tick);
+    key_signatures.sort_by_key(|&(tick, _)| tick);
+    parameters.sort_by_key(|(tick, _)| *tick);
+
+    let position = |tick| {
+        format!(
+            "{} ({}, tick {})",
+            bar_and_beat(tick, division, &time_signatures),
+            format_time(tempo_map.time_at(tick, division)),
+            tick
+        )
+    };
+
+    let title =
+        text::title(&midi_data.tracks, encoding).unwrap_or_else(|| midi_file::file_title(path));
+
+    let mut lines = vec![
+        format!("File: {}", path.display()),
+        format!("Title: {}", title),
+        format!(
+            "Format: {}, {} tracks, {} ticks per quarter note",
+            midi_data.format,
+            midi_data.tracks.len(),
+            division
+        ),
+        format!(
+            "Duration: {} ({} ticks)",
+            format_time(tempo_map.time_at(length, division)),
+            length
+        ),
+    ];
+
+    lines.push(String::from("Tempo map:"));
+    if tempo_map.changes().is_empty() {
+        lines.push(format!("  none, {:.2} BPM throughout", bpm(DEFAULT_TEMPO)));
+    }
+    for &(tick, tempo) in tempo_map.changes() {
+        lines.push(format!("  {}: {:.2} BPM", position(tick), bpm(tempo)));
+    }
+
+    lines.push(String::from("Time signatures:"));
+    if time_signatures.is_empty() {
+        lines.push(String::from("  none"));
+    }
+    for &(tick, (numerator, denominator)) in &time_signatures {
+        let signature = TimeSignature {
+            numerator,
+            denominator,
+        };
+        lines.push(format!("  {}: {}", position(tick), signature));
+    }
+
+    lines.push(String::from("Key signatures:"));
+    if key_signatures.is_empty() {
+        lines.push(String::from("  none"));
+    }
+    for &(tick, key) in &key_signatures {
+        lines.push(format!("  {}: {}", position(tick), key));
+    }
+
+    lines.push(String::from("GS/XG parameters:"));
+    if parameters.is_empty() {
+        lines.push(String::from("  none"));
+    }
+    for (tick, description) in &parameters {
+        lines.push(format!("  {}: {}", position(*tick), description));
+    }
+    if other_sysex > 0 {
+        lines.push(format!("  and {} other SysEx messages", other_sysex));
+    }
+
+    lines.push(String::from("Tracks:"));
+    lines.extend(tracks);
+
+    Ok(lines)
+}
+
+fn bpm(tempo: u64) -> f64 {
+    60_000_000.0 / tempo.max(1) as f64
+}
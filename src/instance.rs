@@ -0,0 +1,259 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::thread;
+#[cfg(windows)]
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+/// How often a busy pipe is tried again before starting up on our own.
+#[cfg(windows)]
+const BUSY_ATTEMPTS: u32 = 10;
+
+/// Where the running instance of this user listens, one per user as the
+/// MIDI devices are shared by everyone on the machine while queues are not.
+/// Sockets go in the runtime directory of the user, or a directory of their
+/// own in the temporary one.
+fn address() -> PathBuf {
+    let user = env::var("USERNAME")
+        .or_else(|_| env::var("USER"))
+        .unwrap_or_default();
+
+    #[cfg(windows)]
+    let address = PathBuf::from(format!(r"\\.\pipe\midi_play-{}", user));
+    #[cfg(not(windows))]
+    let address = match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("midi_play.sock"),
+        _ => env::temp_dir()
+            .join(format!("midi_play-{}", user))
+            .join("midi_play.sock"),
+    };
+
+    address
+}
+
+/// Hands `paths` to the queue of the instance already running, returning
+/// false when there is none. Paths are made absolute first, as the running
+/// instance may be somewhere else.
+pub fn forward(paths: &[PathBuf]) -> Result<bool> {
+    let mut connection = match connect()? {
+        Some(connection) => connection,
+        None => return Ok(false),
+    };
+
+    let mut message = String::new();
+    for path in paths {
+        let path = fs::canonicalize(path)
+            .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+            .context("Failed to find the current directory")?;
+        let path = path.to_str().context("Path is not valid Unicode")?;
+        message.push_str(path);
+        message.push('\n');
+    }
+
+    connection
+        .write_all(message.as_bytes())
+        .context("Failed to hand files to the running instance")?;
+
+    Ok(true)
+}
+
+#[cfg(windows)]
+fn connect() -> Result<Option<fs::File>> {
+    use winapi::shared::winerror::ERROR_PIPE_BUSY;
+
+    for _ in 0..BUSY_ATTEMPTS {
+        match fs::OpenOptions::new().write(true).open(address()) {
+            Ok(pipe) => return Ok(Some(pipe)),
+            // Another instance is being served, wait for the next pipe
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(_) => return Ok(None),
+        };
+    }
+
+    Err(anyhow!("The running instance is not taking files"))
+}
+
+#[cfg(not(windows))]
+fn connect() -> Result<Option<std::os::unix::net::UnixStream>> {
+    use std::os::unix::net::UnixStream;
+
+    Ok(UnixStream::connect(address()).ok())
+}
+
+/// Takes the files later instances hand over, calling `on_path` for each on
+/// a thread of its own, for as long as the process runs.
+pub fn listen(on_path: impl FnMut(PathBuf) + Send + 'static) -> Result<()> {
+    let server = Server::create(true)?;
+
+    thread::Builder::new()
+        .name(String::from("Single instance"))
+        .spawn(move || {
+            if let Err(e) = server.serve(on_path) {
+                eprintln!("Stopped taking files from other instances: {:?}", e);
+            }
+        })
+        .context("Failed to spawn single instance thread")?;
+
+    Ok(())
+}
+
+/// Reads the paths of one connection, one per line.
+fn read_paths(connection: impl io::Read, on_path: &mut impl FnMut(PathBuf)) {
+    for line in BufReader::new(connection).lines() {
+        match line {
+            Ok(line) if !line.is_empty() => on_path(PathBuf::from(line)),
+            Ok(_) => {}
+            Err(_) => break,
+        };
+    }
+}
+
+#[cfg(windows)]
+struct Server {
+    pipe: fs::File,
+}
+
+#[cfg(windows)]
+impl Server {
+    /// Creates an instance of the pipe, the `first` failing if another
+    /// process already owns it.
+    fn create(first: bool) -> Result<Self> {
+        use std::os::windows::ffi::OsStrExt;
+        use std::os::windows::io::FromRawHandle;
+        use std::ptr;
+
+        use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+        use winapi::um::namedpipeapi::CreateNamedPipeW;
+        use winapi::um::winbase::{
+            FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_INBOUND, PIPE_READMODE_BYTE,
+            PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+        };
+
+        let name: Vec<u16> = address().as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut open_mode = PIPE_ACCESS_INBOUND;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+
+        let pipe = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                0,
+                4096,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if pipe == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error()).context("Failed to create pipe");
+        }
+
+        Ok(Self {
+            pipe: unsafe { fs::File::from_raw_handle(pipe as _) },
+        })
+    }
+
+    fn serve(self, mut on_path: impl FnMut(PathBuf)) -> Result<()> {
+        use std::os::windows::io::AsRawHandle;
+        use std::ptr;
+
+        use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+        use winapi::um::namedpipeapi::ConnectNamedPipe;
+
+        let mut server = self;
+        loop {
+            let connected =
+                unsafe { ConnectNamedPipe(server.pipe.as_raw_handle() as _, ptr::null_mut()) };
+            let error = io::Error::last_os_error();
+            if connected == 0 && error.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                return Err(error).context("Failed to wait for another instance");
+            }
+
+            // The next instance of the pipe is up before this one is read
+            let next = Server::create(false)?;
+            read_paths(&server.pipe, &mut on_path);
+            server = next;
+        }
+    }
+}
+
+#[cfg(not(windows))]
+struct Server {
+    listener: std::os::unix::net::UnixListener,
+}
+
+#[cfg(not(windows))]
+impl Server {
+    /// Creates the socket, readable by this user only, taking over one left
+    /// behind by an instance that did not exit cleanly.
+    fn create(_first: bool) -> Result<Self> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let address = address();
+        if let Some(dir) = address.parent() {
+            private_dir(dir)?;
+        }
+
+        if let Ok(metadata) = fs::symlink_metadata(&address) {
+            if !metadata.file_type().is_socket() {
+                return Err(anyhow!("{} is not a socket", address.display()));
+            }
+            if UnixStream::connect(&address).is_ok() {
+                return Err(anyhow!(
+                    "Another instance is listening on {}",
+                    address.display()
+                ));
+            }
+
+            fs::remove_file(&address)
+                .with_context(|| format!("Failed to remove {}", address.display()))?;
+        }
+
+        let listener = UnixListener::bind(&address)
+            .with_context(|| format!("Failed to listen on {}", address.display()))?;
+        fs::set_permissions(&address, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to set permissions of {}", address.display()))?;
+
+        Ok(Self { listener })
+    }
+
+    fn serve(self, mut on_path: impl FnMut(PathBuf)) -> Result<()> {
+        for connection in self.listener.incoming() {
+            let connection = connection.context("Failed to accept another instance")?;
+            read_paths(connection, &mut on_path);
+        }
+
+        Ok(())
+    }
+}
+
+/// Creates `dir` for this user only, or checks that no one else can get into
+/// the one there, as the socket in it would otherwise be open to them until
+/// its permissions are set.
+#[cfg(not(windows))]
+fn private_dir(dir: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+    };
+
+    let metadata =
+        fs::symlink_metadata(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    if !metadata.is_dir() || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(anyhow!("{} is open to other users", dir.display()));
+    }
+
+    Ok(())
+}
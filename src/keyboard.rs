@@ -0,0 +1,103 @@
+use std::io::{self, BufRead};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use midi_play::backend::{MidiOutput, OutputPort};
+use midi_play::cancel::CancellationToken;
+
+// Tracker-style layout: the bottom row is the lower octave with sharps on the
+// row above it, the top row the octave above with sharps on the number row.
+const LOWER_ROW: &str = "zsxdcvgbhnjm";
+const UPPER_ROW: &str = "q2w3er5t6y7u";
+
+const NOTE_LENGTH: Duration = Duration::from_millis(300);
+
+#[derive(Clone)]
+pub struct KeyboardSettings {
+    pub channel: u8,
+    pub velocity: u8,
+    pub program: Option<u8>,
+    /// Octave of the lower row, middle C being in octave 4.
+    pub octave: u8,
+}
+
+impl Default for KeyboardSettings {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            velocity: 100,
+            program: None,
+            octave: 4,
+        }
+    }
+}
+
+fn key_for(c: char, octave: u8) -> Option<u8> {
+    let (offset, row) = match LOWER_ROW.find(c) {
+        Some(index) => (index, 0),
+        None => (UPPER_ROW.find(c)?, 1),
+    };
+
+    let key = (octave as usize + 1 + row) * 12 + offset;
+    if key <= 127 {
+        Some(key as u8)
+    } else {
+        None
+    }
+}
+
+/// Plays notes typed on the computer keyboard, one line at a time, for quick
+/// sound checks. `+` and `-` shift the octave.
+pub fn run(port_id: u32, mut settings: KeyboardSettings, cancel: CancellationToken) -> Result<()> {
+    let mut conn_out = OutputPort::connect(port_id)?;
+    let channel = settings.channel & 0x0f;
+
+    if let Some(program) = settings.program {
+        conn_out
+            .send(&[0xc0 | channel, program])
+            .context("Failed to send program change")?;
+    }
+
+    println!(
+        "Type notes on {} (lower octave) and {} (upper octave), then Enter. \
+         + and - change the octave.",
+        LOWER_ROW, UPPER_ROW
+    );
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.context("Failed to read keyboard input")?;
+
+        for c in line.chars() {
+            if cancel.is_cancelled() {
+                return Ok(());
+            }
+
+            match c {
+                '+' => settings.octave = (settings.octave + 1).min(8),
+                '-' => settings.octave = settings.octave.saturating_sub(1),
+                ' ' => thread::sleep(NOTE_LENGTH),
+                c => match key_for(c.to_ascii_lowercase(), settings.octave) {
+                    Some(key) => {
+                        conn_out
+                            .send(&[0x90 | channel, key, settings.velocity])
+                            .context("Failed to send note on")?;
+                        thread::sleep(NOTE_LENGTH);
+                        conn_out
+                            .send(&[0x80 | channel, key, 0])
+                            .context("Failed to send note off")?;
+                    }
+                    None => println!("No note on {:?}", c),
+                },
+            };
+        }
+
+        if cancel.is_cancelled() {
+            break;
+        }
+    }
+
+    Ok(())
+}
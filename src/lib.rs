@@ -0,0 +1,79 @@
+//! Plays Standard MIDI Files and generated sequences on hardware MIDI ports,
+//! through WinMM or WinRT on Windows and midir elsewhere, on RTP-MIDI network
+//! sessions, or on the built-in SoundFont synthesizer through WASAPI. `player::Player` is
+//! the entry point. With the `tokio` feature, `Player::play_async` plays
+//! without tying up a thread of the caller, and with the `sqlite` feature
+//! lists such as the quarantine can be kept in an SQLite database.
+
+#[macro_use]
+extern crate anyhow;
+
+pub mod automation;
+pub mod backend;
+#[cfg(windows)]
+mod bindings;
+pub mod bundle;
+pub mod cancel;
+pub mod capture;
+pub mod channels;
+pub mod checksum;
+pub mod chimes;
+pub mod clock;
+pub mod config;
+pub mod control;
+pub mod convert;
+#[cfg(windows)]
+mod driver;
+pub mod dump;
+pub mod events;
+pub mod filter;
+pub mod generate;
+pub mod heatmap;
+pub mod hud;
+pub mod humanize;
+pub mod inspect;
+pub mod instance;
+pub mod log_file;
+pub mod lyrics;
+pub mod metronome;
+pub mod midi_file;
+#[cfg(not(windows))]
+mod midir_driver;
+pub mod note_tracker;
+pub mod osc;
+pub mod parameters;
+pub mod player;
+pub mod polyphony;
+pub mod position;
+pub mod profile;
+pub mod programs;
+pub mod quarantine;
+pub mod remote;
+pub mod routing;
+pub mod rtp_midi;
+pub mod safety;
+pub mod search;
+pub mod setlist;
+pub mod signature;
+pub mod soundfont;
+pub mod stats;
+pub mod storage;
+#[cfg(windows)]
+mod stream;
+pub mod synth;
+#[cfg(windows)]
+mod synth_driver;
+pub mod syx;
+pub mod tempo;
+pub mod text;
+mod thread_boost;
+pub mod thru;
+pub mod timecode;
+pub mod timeline;
+mod timer;
+pub mod validate;
+pub mod velocity;
+pub mod watchdog;
+pub mod websocket;
+#[cfg(windows)]
+mod winrt_driver;
@@ -0,0 +1,101 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+
+/// Rotated logs kept next to the current one, as `<path>.1` being the newest
+/// up to `<path>.5`.
+const ROTATED_FILES: u32 = 5;
+
+/// Every event and message of a session written to disk with the time of day,
+/// whatever the console shows, for looking into glitchy playback afterwards.
+pub struct LogFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Bytes in the current file.
+    size: u64,
+    /// Size past which the file is moved aside for a new one.
+    max_size: Option<u64>,
+    failed: bool,
+}
+
+impl LogFile {
+    /// Appends to `path`, moving it aside once it grows past `max_size`.
+    pub fn open(path: &Path, max_size: Option<u64>) -> Result<Self> {
+        let file = open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let size = file.metadata().map_or(0, |metadata| metadata.len());
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: BufWriter::new(file),
+            size,
+            max_size,
+            failed: false,
+        })
+    }
+
+    /// Writes a line stamped with the time of day. Only the first failure is
+    /// returned, so a full disk does not flood the console.
+    pub fn write_line(&mut self, line: &str) -> Result<()> {
+        let line = format!(
+            "{}  {}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            line
+        );
+
+        let written = self.rotate_for(line.len() as u64).and_then(|_| {
+            self.file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+            Ok(())
+        });
+
+        match written {
+            Err(e) if !self.failed => {
+                self.failed = true;
+                Err(e).with_context(|| format!("Failed to write {}", self.path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes out what is buffered, done after every batch of events so the
+    /// log is complete up to a crash.
+    pub fn flush(&mut self) {
+        let _ = self.file.flush();
+    }
+
+    /// Starts a new file when `length` more bytes would take the current one
+    /// past the maximum size, unless it is empty.
+    fn rotate_for(&mut self, length: u64) -> io::Result<()> {
+        match self.max_size {
+            Some(max_size) if self.size > 0 && self.size + length > max_size => {}
+            _ => return Ok(()),
+        };
+
+        self.file.flush()?;
+        for index in (1..ROTATED_FILES).rev() {
+            let from = self.rotated(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+
+        self.file = BufWriter::new(open(&self.path)?);
+        self.size = 0;
+
+        Ok(())
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -0,0 +1,60 @@
+use std::mem;
+
+/// Lines kept on display, the one being sung last.
+pub const DISPLAY_LINES: usize = 3;
+
+/// Lyric syllables put together into the lines of a karaoke display. A
+/// syllable starting with `/` or ending with a line break starts a new line,
+/// one starting with `\` a new verse.
+#[derive(Default)]
+pub struct LyricSheet {
+    lines: Vec<String>,
+    current: String,
+}
+
+impl LyricSheet {
+    pub fn push(&mut self, syllable: &str) {
+        let syllable = if let Some(rest) = syllable.strip_prefix('\\') {
+            self.clear();
+            rest
+        } else if let Some(rest) = syllable.strip_prefix('/') {
+            self.end_line();
+            rest
+        } else {
+            syllable
+        };
+
+        let text = syllable.trim_end_matches(['\r', '\n']);
+        self.current.push_str(text);
+
+        if text.len() != syllable.len() {
+            self.end_line();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.current.clear();
+    }
+
+    /// The last lines, one per line of text.
+    pub fn render(&self) -> String {
+        let mut lines: Vec<&str> = self.lines.iter().map(String::as_str).collect();
+        if !self.current.is_empty() {
+            lines.push(&self.current);
+        }
+
+        let start = lines.len().saturating_sub(DISPLAY_LINES);
+        lines[start..].join("\n")
+    }
+
+    fn end_line(&mut self) {
+        if !self.current.is_empty() {
+            self.lines.push(mem::take(&mut self.current));
+        }
+
+        if self.lines.len() > DISPLAY_LINES {
+            self.lines.remove(0);
+        }
+    }
+}
@@ -1,152 +1,961 @@
 #[macro_use]
 extern crate anyhow;
 
-use std::collections::VecDeque;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
 use std::env;
-use std::fmt;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
 use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-//use rimd::SMFFormat;
-use rimd::{MetaCommand, MidiMessage, SMF};
-use winapi::shared::minwindef::UINT;
-use winapi::um::synchapi::{SetEvent, WaitForSingleObject};
-use winapi::um::winbase::INFINITE;
+use crossterm::tty::IsTty;
+use encoding_rs::Encoding;
+
+use midi_play::automation::Ramp;
+use midi_play::backend::{self, InputPort, MidiInput, MidiOutput, OutputPort};
+use midi_play::bundle;
+use midi_play::cancel::CancellationToken;
+use midi_play::capture;
+use midi_play::channels::ChannelMapping;
+use midi_play::chimes::{self, ChimeSchedule};
+use midi_play::config::Config;
+use midi_play::control::{Access, Arbiter, Client, Priority};
+use midi_play::convert;
+use midi_play::dump;
+use midi_play::events::PlayerEvent;
+use midi_play::filter::MessageClass;
+use midi_play::generate::{self, GenerateSettings};
+use midi_play::heatmap::NoteUsage;
+use midi_play::hud::{self, Hud};
+use midi_play::humanize::Humanize;
+use midi_play::inspect;
+use midi_play::instance;
+use midi_play::log_file::LogFile;
+use midi_play::lyrics::LyricSheet;
+use midi_play::metronome::{Click, CountIn};
+use midi_play::midi_file::{self, DataEvent, Sequence};
+use midi_play::osc::EventBridge;
+use midi_play::player::{Engine, Player, PlayerConfig, PortHandoff, DEFAULT_TEMPO, SPEED_RANGE};
+use midi_play::polyphony::{self, PolyphonyLimit, VoiceTracker};
+use midi_play::position::{Locator, Position};
+use midi_play::programs::ProgramOverride;
+use midi_play::quarantine::Quarantine;
+use midi_play::remote::{RemoteCommand, RemoteServer, RemoteStatus};
+use midi_play::routing::Route;
+use midi_play::safety::SafetyLimits;
+use midi_play::search;
+use midi_play::setlist::SetList;
+use midi_play::stats::PlaybackStats;
+use midi_play::syx;
+use midi_play::tempo::format_time;
+use midi_play::thru::{self, ThruReceiver};
+use midi_play::timecode::FrameRate;
+use midi_play::timeline;
+use midi_play::validate;
+use midi_play::velocity::VelocityCurve;
+use midi_play::watchdog::{self, ResumePoint};
+use midi_play::websocket::EventServer;
+
+mod keyboard;
+mod options;
+mod tui;
+
+use crate::options::{Options, Output, SettingsAction};
+use crate::tui::{Action, Tui};
+
+// Spaces overwriting the previous progress line
+const PROGRESS_WIDTH: usize = 79;
+
+// How often the port list is checked for devices coming and going
+const PORT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often a watched player saves where it is
+const RESUME_INTERVAL: Duration = Duration::from_secs(1);
+
+// How far the seek commands move playback
+const SEEK_STEP: Duration = Duration::from_secs(5);
+
+// How much the speed commands change the speed, in percent
+const SPEED_STEP: u32 = 10;
+
+/// What can be typed or sent while playing.
+#[derive(Clone)]
+enum Command {
+    /// Switch A/B comparisons over.
+    Switch,
+    /// Silence stuck notes.
+    Panic,
+    /// Keep other clients from controlling playback.
+    Lock,
+    Unlock,
+    /// Jump to the marker after the one playing.
+    NextMarker,
+    /// Jump to the marker before the one playing, or the start.
+    PreviousMarker,
+    /// Pause, or resume when paused.
+    Pause,
+    /// Go on with the next file.
+    NextFile,
+    /// Move playback by `SEEK_STEP`, back or forward.
+    Seek {
+        back: bool,
+    },
+    /// Play faster or slower by `SPEED_STEP`.
+    Speed {
+        faster: bool,
+    },
+    /// Show which channels are muted.
+    MuteMenu,
+    /// Mute the channels, counted from 0, or play them again.
+    ToggleMute(Vec<u8>),
+    /// Sent over HTTP or by another instance.
+    Remote(RemoteCommand),
+}
 
-mod bindings;
-mod driver;
-mod midi_file;
-mod thread_boost;
+/// A player running on one of the chosen ports.
+struct ActivePlayer {
+    port_id: u32,
+    player: Player,
+    events: Receiver<PlayerEvent>,
+    started: Instant,
+    voices: VoiceTracker,
+    stats: PlaybackStats,
+    note_usage: Option<NoteUsage>,
+    /// Playing time after which the player is stopped, for previews.
+    stop_after: Option<Duration>,
+    hud: Option<Hud>,
+    /// Finds markers and other positions of the song, to jump to.
+    locator: Option<Locator>,
+    /// Last tick reported by the player.
+    tick: u64,
+    /// Song time and percentage last reported by the player.
+    elapsed: Duration,
+    percent: f64,
+}
 
-use crate::driver::WinMidiPort;
-use crate::midi_file::{DataEvent, LocalEvent};
-use crate::thread_boost::ThreadBoost;
+impl ActivePlayer {
+    /// Takes in what the player sent since the last call, with how far into
+    /// playback it was received, returning whether it finished.
+    fn update(&mut self, events: &mut Vec<(Duration, PlayerEvent)>) -> bool {
+        if let Some(stop_after) = self.stop_after {
+            if self.started.elapsed() >= stop_after {
+                self.player.stop();
+            }
+        }
 
-static RUNNING: AtomicBool = AtomicBool::new(true);
+        loop {
+            match self.events.try_recv() {
+                Ok(PlayerEvent::Finished) | Err(TryRecvError::Disconnected) => return true,
+                Ok(event) => {
+                    if let PlayerEvent::Progress {
+                        tick,
+                        elapsed,
+                        percent,
+                        ..
+                    } = event
+                    {
+                        self.tick = tick;
+                        self.elapsed = elapsed;
+                        self.percent = percent;
+                    }
+                    self.stats.process(&event);
 
-struct PlayerInstance {
-    chosen_port_number: Option<UINT>,
-    port_list: Vec<String>,
-    files_to_play: VecDeque<PathBuf>,
-    events: Vec<BasicMidiEvent>,
-    current_player: Option<PlayerReceiver>,
-    current_player_handle: Option<JoinHandle<()>>,
+                    if let Some(message) = event.midi_message() {
+                        self.voices.process(&message);
+
+                        if let Some(note_usage) = &mut self.note_usage {
+                            note_usage.process(&message);
+                        }
+                    }
+
+                    events.push((self.started.elapsed(), event));
+                }
+                Err(TryRecvError::Empty) => return false,
+            };
+        }
+    }
+
+    fn hud_line(&self) -> Option<String> {
+        Some(self.hud.as_ref()?.render(self.tick))
+    }
+
+    /// The marker after the one playing, or with `back` the one before it,
+    /// which is none from the first marker on.
+    fn marker(&self, back: bool) -> Option<&(u64, String)> {
+        let markers = self.locator.as_ref()?.markers();
+        let current = markers.iter().rposition(|&(tick, _)| tick <= self.tick);
+
+        let index = match (current, back) {
+            (Some(index), false) => index + 1,
+            (None, false) => 0,
+            (Some(index), true) => index.checked_sub(1)?,
+            (None, true) => return None,
+        };
+
+        markers.get(index)
+    }
+
+    /// The statistics, polyphony report and heatmap of what was played.
+    fn report(&self) -> Vec<String> {
+        let mut lines = self.stats.report();
+        lines.extend(self.voices.report());
+
+        if let Some(note_usage) = &self.note_usage {
+            lines.extend(note_usage.render());
+        }
+
+        lines
+    }
+}
+
+/// A playlist played over and over, for unattended installations.
+struct Autoplay {
+    playlist: Vec<PathBuf>,
+    /// Where the position is saved for a relaunch to resume from.
+    resume_path: PathBuf,
+    /// Tick to seek the next song to, when resuming.
+    resume_tick: Option<u64>,
+    last_saved: Instant,
+    /// Whether any song started since the playlist was queued, so a playlist
+    /// of only unplayable files is not queued again forever.
+    started_any: bool,
+    /// Songs of the playlist still at the front of the queue, ahead of any
+    /// file queued on top of it.
+    queued: usize,
+    /// Index in the playlist of the song playing, if it is from there.
+    playing: Option<usize>,
 }
 
-struct BasicMidiEvent {
-    delta_time: u64,
-    msg: MidiMessage,
+impl Autoplay {
+    /// Notes that the file at the front of the queue was taken off it.
+    fn take_next(&mut self) {
+        self.playing = match self.queued {
+            0 => None,
+            queued => {
+                self.queued -= 1;
+                Some(self.playlist.len() - queued)
+            }
+        };
+    }
 }
 
-struct PlayerReceiver {
-    log: Receiver<String>,
-    event: Receiver<BasicMidiEvent>,
+/// Where playback of a MIDI file starts, ends and loops, in its ticks.
+#[derive(Clone, Copy, Default)]
+struct Bounds {
+    start: Option<u64>,
+    end: Option<u64>,
+    section: Option<(u64, u64)>,
 }
 
-impl fmt::Display for BasicMidiEvent {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.msg.data.len() == 2 {
-            write!(f, "{}: [{}]", self.msg.status(), self.msg.data[1])
-        } else if self.msg.data.len() == 3 {
-            write!(
-                f,
-                "{}: [{},{}]",
-                self.msg.status(),
-                self.msg.data[1],
-                self.msg.data[2]
-            )
-        } else if self.msg.data.len() == 0 {
-            write!(f, "{}: [no data]", self.msg.status())
-        } else {
-            write!(f, "{}: {:?}", self.msg.status(), self.msg.data)
+impl Bounds {
+    fn apply(self, mut player: Player) -> Player {
+        if let Some(start) = self.start {
+            player = player.starting_at(start);
+        }
+        if let Some(end) = self.end {
+            player = player.ending_at(end);
         }
+        if let Some((from, to)) = self.section {
+            player = player.section(from, to);
+        }
+
+        player
     }
 }
 
+struct PlayerInstance {
+    /// Ports every file is played on, the first also getting chimes and
+    /// generated material.
+    chosen_ports: Vec<u32>,
+    port_list: Vec<String>,
+    last_port_check: Instant,
+    files_to_play: VecDeque<PathBuf>,
+    /// The next file, parsed while the one before it plays.
+    preloaded: Option<(PathBuf, Result<Sequence>)>,
+    /// Set for gapless playback.
+    handoff: Option<PortHandoff>,
+    /// Pause between files, counted from when the last one finished.
+    gap: Duration,
+    finished_at: Option<Instant>,
+    /// Start every queued file at once instead of layering each on all ports.
+    together: bool,
+    events: Vec<(Duration, PlayerEvent)>,
+    output: Output,
+    /// Print every event in text output instead of the status line.
+    verbose: bool,
+    /// Color the event log by channel.
+    colors: bool,
+    /// Tempo and notes sounding, for the status line.
+    tempo: u64,
+    sounding: HashSet<(u8, u8)>,
+    /// Whether the console ends with a progress line to overwrite.
+    progress_shown: Cell<bool>,
+    /// Show the status line of `Hud` instead of the played events.
+    hud: bool,
+    /// The status line last shown.
+    hud_line: String,
+    /// Shortest time between renders of the status line.
+    hud_interval: Duration,
+    hud_drawn: Instant,
+    /// Takes over the terminal in place of the console output, from `--tui`.
+    tui: Option<RefCell<Tui>>,
+    /// Gets every event and message, whatever the console shows.
+    log_file: Option<RefCell<LogFile>>,
+    /// Where the lyrics of the first player are kept up to date.
+    lyrics_file: Option<PathBuf>,
+    lyrics: LyricSheet,
+    /// Cues for the songs of a set list, sent to `cue_port`.
+    set_list: Option<SetList>,
+    cue_port: Option<u32>,
+    cue_channel: u8,
+    autoplay: Option<Autoplay>,
+    players: Vec<ActivePlayer>,
+    thru_handle: Option<JoinHandle<()>>,
+    thru: Option<ThruReceiver>,
+    safety: Option<SafetyLimits>,
+    /// Sent by every player after its reset.
+    startup: Vec<Vec<u8>>,
+    automation: Vec<Ramp>,
+    channel_map: Vec<ChannelMapping>,
+    programs: Vec<ProgramOverride>,
+    compare_port: Option<u32>,
+    /// Speed in percent and muted channels, kept from file to file.
+    speed: u32,
+    muted: u16,
+    /// Commands typed or sent while playing, with who sent them.
+    commands: Option<Receiver<(Client, Command)>>,
+    arbiter: Arbiter,
+    chimes: Option<ChimeSchedule>,
+    engine: Engine,
+    voice_limit: usize,
+    /// Keys flagged in the heatmap, which is only kept when set.
+    heatmap: Option<Option<RangeInclusive<u8>>>,
+    syx_delay: Duration,
+    fix_checksums: bool,
+    /// How much of every MIDI file to play, when only previewing them.
+    preview: Option<Duration>,
+    /// MIDI files with fewer notes are skipped.
+    min_notes: usize,
+    /// Files that failed to load, skipped instead of stopping the queue.
+    quarantine: Quarantine,
+    text_encoding: Option<&'static Encoding>,
+    reconnect: Option<Duration>,
+    fallback_port: Option<String>,
+    backup_port: Option<u32>,
+    fade_out: Option<Duration>,
+    fade_controller: u8,
+    sysex_delay: Option<Duration>,
+    lead_in: Option<Duration>,
+    /// Clicks before every song, though not chimes and `.syx` files.
+    count_in: Option<CountIn>,
+    metronome: Option<Click>,
+    clock: bool,
+    timecode: Option<FrameRate>,
+    sync_port: Option<u32>,
+    drop: Vec<MessageClass>,
+    humanize: Option<Humanize>,
+    velocity_curve: Option<VelocityCurve>,
+    polyphony_limit: Option<PolyphonyLimit>,
+    routes: Vec<Route>,
+    also_ports: Vec<u32>,
+    /// Receives the events of the main players, not of the cues.
+    bridge: Option<EventBridge>,
+    /// Browser pages following along, from `--ws-listen`.
+    event_server: Option<EventServer>,
+    /// Takes commands over HTTP, from `--http-listen`.
+    remote: Option<RemoteServer>,
+    /// Where every MIDI file starts and ends instead of playing in full.
+    start: Option<Position>,
+    end: Option<Position>,
+    /// Part of every MIDI file played over and over, from and to.
+    section: Option<(Position, Position)>,
+    /// Cancelled to stop every player and the thru thread.
+    session: CancellationToken,
+}
+
 impl PlayerInstance {
-    fn new() -> Self {
+    fn new(session: CancellationToken) -> Self {
         Self {
-            chosen_port_number: None,
+            chosen_ports: Vec::new(),
             port_list: Vec::new(),
+            last_port_check: Instant::now(),
             files_to_play: VecDeque::new(),
+            preloaded: None,
+            handoff: None,
+            gap: Duration::ZERO,
+            finished_at: None,
+            together: false,
             events: Vec::new(),
-            current_player: None,
-            current_player_handle: None,
+            output: Output::Text,
+            verbose: false,
+            colors: false,
+            tempo: DEFAULT_TEMPO,
+            sounding: HashSet::new(),
+            progress_shown: Cell::new(false),
+            hud: false,
+            hud_line: String::new(),
+            hud_interval: hud::DEFAULT_INTERVAL,
+            hud_drawn: Instant::now(),
+            tui: None,
+            log_file: None,
+            lyrics_file: None,
+            lyrics: LyricSheet::default(),
+            set_list: None,
+            cue_port: None,
+            cue_channel: 15,
+            autoplay: None,
+            players: Vec::new(),
+            thru_handle: None,
+            thru: None,
+            safety: None,
+            startup: Vec::new(),
+            automation: Vec::new(),
+            channel_map: Vec::new(),
+            programs: Vec::new(),
+            compare_port: None,
+            speed: 100,
+            muted: 0,
+            commands: None,
+            arbiter: Arbiter::default(),
+            chimes: None,
+            engine: Engine::Realtime,
+            voice_limit: polyphony::DEFAULT_VOICE_LIMIT,
+            heatmap: None,
+            syx_delay: syx::DEFAULT_DELAY,
+            fix_checksums: false,
+            preview: None,
+            min_notes: 1,
+            quarantine: Quarantine::default(),
+            text_encoding: None,
+            reconnect: None,
+            fallback_port: None,
+            backup_port: None,
+            fade_out: None,
+            fade_controller: 11,
+            sysex_delay: None,
+            lead_in: None,
+            count_in: None,
+            metronome: None,
+            clock: false,
+            timecode: None,
+            sync_port: None,
+            drop: Vec::new(),
+            humanize: None,
+            velocity_curve: None,
+            polyphony_limit: None,
+            routes: Vec::new(),
+            also_ports: Vec::new(),
+            bridge: None,
+            event_server: None,
+            remote: None,
+            start: None,
+            end: None,
+            section: None,
+            session,
         }
     }
 
-    fn add_message(&mut self, msg: impl Into<String>) {
-        println!("{}", msg.into());
+    fn add_message(&self, msg: impl Into<String>) {
+        let msg = msg.into();
+        self.write_log(&msg);
+
+        if let Some(tui) = &self.tui {
+            tui.borrow_mut().log(msg);
+            return;
+        }
+
+        match self.output {
+            Output::Text => {
+                self.clear_progress();
+                println!("{}", msg);
+            }
+            // Keep stdout to the event stream
+            Output::Json => eprintln!("{}", msg),
+        };
     }
 
-    fn update_state(&mut self) {
-        if self.chosen_port_number.is_none() {
-            match WinMidiPort::count() {
-                0 => {}
-                1 => {
-                    self.chosen_port_number = Some(0);
-                }
-                count => {
-                    self.port_list.clear();
-
-                    for i in 0..count {
-                        if let Ok(name) = WinMidiPort::name(i) {
-                            self.port_list.push(name);
-                        } else {
-                            self.port_list.push(String::from("<unknown>"));
-                        }
-                    }
-                }
+    /// Writes `line` to the log file, if there is one.
+    fn write_log(&self, line: &str) {
+        if let Some(log_file) = &self.log_file {
+            if let Err(e) = log_file.borrow_mut().write_line(line) {
+                eprintln!("{:#}", e);
+            }
+        }
+    }
+
+    /// Follows the tempo and the notes sounding for the status line.
+    fn follow_event(&mut self, event: &PlayerEvent) {
+        match event {
+            PlayerEvent::NoteOn { channel, key, .. } => {
+                self.sounding.insert((*channel, *key));
+            }
+            PlayerEvent::NoteOff { channel, key } => {
+                self.sounding.remove(&(*channel, *key));
+            }
+            PlayerEvent::Tempo(tempo) => self.tempo = *tempo,
+            PlayerEvent::Finished => {
+                self.sounding.clear();
+                self.tempo = DEFAULT_TEMPO;
+            }
+            _ => {}
+        };
+    }
+
+    /// Prints an event the way `--output` asks for, progress in text mode
+    /// being a single status line that keeps overwriting itself. Notes,
+    /// tempo changes and other MIDI messages are only printed when verbose.
+    /// With the HUD only messages and lyrics are printed, above its status
+    /// line, and the TUI takes them all for its meters and log.
+    fn print_event(&self, time: Duration, event: &PlayerEvent) -> Result<()> {
+        self.write_log(&event.log_line(time, false));
+
+        if let Some(tui) = &self.tui {
+            tui.borrow_mut().process(time, event);
+            return Ok(());
+        }
+
+        match (self.output, event) {
+            (Output::Json, PlayerEvent::Message(message)) => eprintln!("{}", message),
+            (Output::Json, _) => println!("{}", event.to_json(time)),
+            (Output::Text, PlayerEvent::Message(message)) => {
+                self.clear_progress();
+                println!("{}", message);
+            }
+            (Output::Text, PlayerEvent::Lyric(_)) => {
+                self.clear_progress();
+                println!("{}", event.log_line(time, self.colors));
+            }
+            (Output::Text, _) if self.hud => {}
+            (Output::Text, PlayerEvent::Progress { .. }) if self.verbose => {
+                self.show_status(&event.to_string())?
+            }
+            (
+                Output::Text,
+                PlayerEvent::Progress {
+                    elapsed,
+                    percent,
+                    bar,
+                    beat,
+                    ..
+                },
+            ) => {
+                let bpm = 60_000_000.0 / self.tempo as f64 * self.speed as f64 / 100.0;
+                self.show_status(&format!(
+                    "{} ({:.1}%)  bar {}:{}  {:.0} BPM  {} notes",
+                    format_time(*elapsed),
+                    percent,
+                    bar,
+                    beat,
+                    bpm,
+                    self.sounding.len()
+                ))?
+            }
+            (Output::Text, PlayerEvent::NoteOn { .. })
+            | (Output::Text, PlayerEvent::NoteOff { .. })
+            | (Output::Text, PlayerEvent::Midi(_))
+            | (Output::Text, PlayerEvent::Tempo(_))
+                if !self.verbose => {}
+            (Output::Text, _) => {
+                self.clear_progress();
+                println!("{}", event.log_line(time, self.colors));
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Overwrites the status line with the HUD of the first player having
+    /// one, when it changed, at most once per `hud_interval`.
+    fn update_hud(&mut self) -> Result<()> {
+        if self.hud_drawn.elapsed() < self.hud_interval {
+            return Ok(());
+        }
+        self.hud_drawn = Instant::now();
+
+        let line = match self.players.iter().find_map(ActivePlayer::hud_line) {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+
+        if line != self.hud_line || !self.progress_shown.get() {
+            self.show_status(&line)?;
+            self.hud_line = line;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the keys pressed in the TUI as console commands and draws it.
+    fn update_tui(&mut self) -> Result<()> {
+        let actions = match &self.tui {
+            Some(tui) => tui.borrow_mut().poll_keys()?,
+            None => return Ok(()),
+        };
+
+        let console = Client::new("console", Priority::Operator);
+        for action in actions {
+            match action {
+                Action::Command(command) => self.run_command(console.clone(), command),
+                Action::Quit => self.session.cancel(),
             };
         }
 
+        if let Some(tui) = &self.tui {
+            tui.borrow_mut()
+                .draw(&self.remote_status(), self.speed, self.muted)?;
+        }
+
+        Ok(())
+    }
+
+    /// Shows a single line that the next one overwrites, cut to fit.
+    fn show_status(&self, line: &str) -> Result<()> {
+        let line: String = line.chars().take(PROGRESS_WIDTH).collect();
+
+        print!("\r{:<width$}", line, width = PROGRESS_WIDTH);
+        io::stdout().flush().context("Failed to write progress")?;
+        self.progress_shown.set(true);
+
+        Ok(())
+    }
+
+    fn clear_progress(&self) {
+        if self.progress_shown.replace(false) {
+            print!("\r{:width$}\r", "", width = PROGRESS_WIDTH);
+        }
+    }
+
+    fn update_state(&mut self) {
+        if self.chosen_ports.is_empty() {
+            self.port_list = port_names();
+
+            if self.port_list.len() == 1 {
+                self.chosen_ports.push(0);
+            }
+        } else if self.last_port_check.elapsed() >= PORT_POLL_INTERVAL {
+            self.refresh_ports();
+        }
+
         // Update player status
-        if let Some(current_player) = &self.current_player {
-            let mut new_events = Vec::new();
+        let mut new_events = Vec::new();
+        let mut finished = Vec::new();
+        let mut i = 0;
+        let mut lyrics = Vec::new();
+
+        while i < self.players.len() {
+            let start = new_events.len();
+            let done = self.players[i].update(&mut new_events);
+
+            // Layered players all send the same lyrics, only take them once
+            if i == 0 && finished.is_empty() {
+                lyrics.extend(
+                    new_events[start..]
+                        .iter()
+                        .filter_map(|(_, event)| match event {
+                            PlayerEvent::Lyric(lyric) => Some(lyric.clone()),
+                            _ => None,
+                        }),
+                );
+            }
 
-            let mut disconnected = false;
+            if done {
+                finished.push(self.players.remove(i));
+            } else {
+                i += 1;
+            }
+        }
 
-            loop {
-                match current_player.log.try_recv() {
-                    Ok(msg) => {
-                        println!("{}", msg);
-                    }
-                    Err(e) => match e {
-                        TryRecvError::Empty => break,
-                        TryRecvError::Disconnected => {
-                            disconnected = true;
-                            break;
-                        }
-                    },
-                };
+        if !lyrics.is_empty() {
+            for lyric in &lyrics {
+                self.lyrics.push(lyric);
             }
-            loop {
-                match current_player.event.try_recv() {
-                    Ok(event) => new_events.push(event),
-                    Err(e) => match e {
-                        TryRecvError::Empty => break,
-                        TryRecvError::Disconnected => {
-                            disconnected = true;
-                            break;
-                        }
-                    },
-                };
+            self.write_lyrics();
+        }
+
+        self.events.extend(new_events);
+
+        if !finished.is_empty() {
+            self.finished_at = Some(Instant::now());
+        }
+
+        for player in finished {
+            if self.chosen_ports.len() > 1 {
+                self.add_message(format!("Finished on port {}", player.port_id));
             }
 
-            if disconnected {
-                self.current_player = None;
+            for line in player.report() {
+                self.add_message(line);
             }
+        }
 
-            self.events.extend(new_events);
+        let commands: Vec<_> = match &self.commands {
+            Some(commands) => commands.try_iter().collect(),
+            None => Vec::new(),
+        };
+        for (client, command) in commands {
+            self.run_command(client, command);
         }
 
+        if let Some(remote) = &self.remote {
+            remote.set_status(self.remote_status());
+        }
+
+        self.save_resume_point();
+
         // Handle playing next file
-        if !self.files_to_play.is_empty() && self.current_player.is_none() {
+        if self.files_to_play.is_empty() && self.players.is_empty() {
+            self.queue_playlist();
+        }
+        let gap_over = match self.finished_at {
+            Some(finished_at) => finished_at.elapsed() >= self.gap,
+            None => true,
+        };
+        if !self.files_to_play.is_empty() && self.players.is_empty() && gap_over {
             self.play_next_file();
+
+            let resume_tick = self
+                .autoplay
+                .as_mut()
+                .and_then(|autoplay| autoplay.resume_tick.take());
+            if let Some(tick) = resume_tick {
+                for active in &self.players {
+                    active.player.seek(tick);
+                }
+            }
+        }
+
+        // Nothing left to hand the port on to
+        if self.files_to_play.is_empty() && self.players.is_empty() {
+            if let Some(handoff) = &self.handoff {
+                handoff.close();
+            }
+        }
+        self.preload_next();
+
+        // Handle chimes that became due
+        if let Some(events) = self.chimes.as_mut().and_then(|chimes| chimes.poll()) {
+            if !self.players.is_empty() {
+                self.add_message("Skipping chime while another player is active");
+            } else if let Err(e) = self
+                .play_sequence(chimes::DIVISION, DEFAULT_TEMPO, events, None)
+                .context("Failed to play chime")
+            {
+                self.add_message(format!("{:?}", e));
+            }
+        }
+    }
+
+    /// Parses the next file while the current one plays, so it can start the
+    /// moment the current one ends.
+    fn preload_next(&mut self) {
+        if self.handoff.is_none() || self.players.is_empty() {
+            return;
+        }
+
+        let path = match self.files_to_play.front() {
+            Some(path) if !syx::is_syx(path) => path,
+            _ => return,
+        };
+        if let Some((preloaded, _)) = &self.preloaded {
+            if preloaded == path {
+                return;
+            }
+        }
+
+        let sequence = midi_file::load(path, self.text_encoding);
+        self.preloaded = Some((path.clone(), sequence));
+    }
+
+    /// Starts the autoplay playlist over once it ran out.
+    fn queue_playlist(&mut self) {
+        let autoplay = match &mut self.autoplay {
+            Some(autoplay) => autoplay,
+            None => return,
+        };
+
+        if !autoplay.started_any {
+            self.add_message("Nothing in the playlist could be played");
+            self.autoplay = None;
+            self.session.cancel();
+            return;
+        }
+
+        autoplay.started_any = false;
+        autoplay.queued = autoplay.playlist.len();
+        self.files_to_play.extend(autoplay.playlist.iter().cloned());
+    }
+
+    /// Writes where the autoplay playlist is every `RESUME_INTERVAL`.
+    fn save_resume_point(&mut self) {
+        let autoplay = match &mut self.autoplay {
+            Some(autoplay) => autoplay,
+            None => return,
+        };
+        // Files queued on top of the playlist are not resumed
+        let (active, index) = match (self.players.first(), autoplay.playing) {
+            (Some(active), Some(index)) if autoplay.last_saved.elapsed() >= RESUME_INTERVAL => {
+                (active, index)
+            }
+            _ => return,
+        };
+
+        autoplay.last_saved = Instant::now();
+
+        let point = ResumePoint {
+            index,
+            tick: active.tick,
+        };
+        if let Err(e) = point.save(&autoplay.resume_path) {
+            self.add_message(format!("{:?}", e));
+        }
+    }
+
+    /// Puts the current lines of the lyrics in the lyrics file, if there is
+    /// one.
+    fn write_lyrics(&self) {
+        if let Some(path) = &self.lyrics_file {
+            if let Err(e) = fs::write(path, self.lyrics.render())
+                .with_context(|| format!("Failed to write {}", path.display()))
+            {
+                self.add_message(format!("{:?}", e));
+            }
+        }
+    }
+
+    /// Picks up devices that were plugged in or removed, following the chosen
+    /// ports by name as their numbers shift.
+    fn refresh_ports(&mut self) {
+        self.last_port_check = Instant::now();
+
+        let port_list = port_names();
+        if port_list == self.port_list {
+            return;
+        }
+
+        let old_list = mem::replace(&mut self.port_list, port_list);
+
+        for (i, name) in self.port_list.iter().enumerate() {
+            if !old_list.contains(name) {
+                self.add_message(format!("Port connected: {}: {}", i, name));
+            }
+        }
+        for name in &old_list {
+            if !self.port_list.contains(name) {
+                self.add_message(format!("Port disconnected: {}", name));
+            }
+        }
+
+        let find = |port_id: u32| {
+            let name = old_list.get(port_id as usize)?;
+            let new_id = self.port_list.iter().position(|other| other == name)?;
+            Some(new_id as u32)
+        };
+
+        let chosen_ports: Vec<u32> = self
+            .chosen_ports
+            .iter()
+            .filter_map(|&port_id| find(port_id))
+            .collect();
+        let compare_port = self.compare_port.and_then(find);
+
+        if self.compare_port.is_some() && compare_port.is_none() {
+            self.add_message("Compared port is gone, A/B comparison stops with this song");
+        }
+        self.compare_port = compare_port;
+
+        // Keep playing on something rather than nothing
+        self.chosen_ports = match (chosen_ports.is_empty(), self.port_list.len()) {
+            (false, _) | (true, 0) => chosen_ports,
+            (true, count) => {
+                let port_id = (count - 1) as u32;
+                self.add_message(format!("Chosen ports are gone, now using port {}", port_id));
+                vec![port_id]
+            }
+        };
+    }
+
+    fn player_config(&self, port_id: u32) -> PlayerConfig {
+        PlayerConfig {
+            port_id,
+            engine: self.engine,
+            thru: self.thru.clone(),
+            safety: self.safety.clone(),
+            startup: self.startup.clone(),
+            automation: self.automation.clone(),
+            channel_map: self.channel_map.clone(),
+            programs: self.programs.clone(),
+            compare_port: self.compare_port,
+            text_encoding: self.text_encoding,
+            reconnect: self.reconnect,
+            fallback_port: self.fallback_port.clone(),
+            backup_port: self.backup_port,
+            fade_out: self.fade_out,
+            fade_controller: self.fade_controller,
+            sysex_delay: self.sysex_delay,
+            lead_in: self.lead_in,
+            count_in: self.count_in,
+            metronome: self.metronome,
+            clock: self.clock,
+            timecode: self.timecode,
+            sync_port: self.sync_port,
+            drop: self.drop.clone(),
+            humanize: self.humanize,
+            velocity_curve: self.velocity_curve.clone(),
+            polyphony_limit: self.polyphony_limit,
+            routes: self.routes.clone(),
+            also_ports: self.also_ports.clone(),
+            handoff: self.handoff.clone(),
+            cancel: self.session.clone(),
+        }
+    }
+
+    /// Plays events that were generated rather than loaded from a file, on
+    /// the first port.
+    fn play_sequence(
+        &mut self,
+        division: u64,
+        tempo: u64,
+        events: Vec<DataEvent>,
+        loop_length: Option<u64>,
+    ) -> Result<()> {
+        let port_id = *self.chosen_ports.first().context("No port ID set")?;
+        let config = PlayerConfig {
+            count_in: None,
+            metronome: None,
+            clock: false,
+            timecode: None,
+            sync_port: None,
+            ..self.player_config(port_id)
+        };
+        let mut player = Player::from_events(division, tempo, events, config);
+
+        if let Some(loop_length) = loop_length {
+            player = player.looping(loop_length);
+        }
+
+        self.bridge_events(&player);
+        self.start_player(port_id, player, None)
+    }
+
+    fn play_generated(&mut self, settings: &GenerateSettings) {
+        let events = generate::generate(settings);
+
+        if let Err(e) = self
+            .play_sequence(
+                generate::DIVISION,
+                settings.tempo(),
+                events,
+                generate::loop_length(settings),
+            )
+            .context("Failed to play generated material")
+        {
+            self.add_message(format!("{:?}", e));
         }
     }
 
@@ -160,231 +969,1092 @@ impl PlayerInstance {
     }
 
     fn play_next_file_inner(&mut self) -> Result<()> {
-        let port_id = self.chosen_port_number.context("No port ID set")?;
-        let next_file_path = self.files_to_play.pop_front().context("No files to play")?;
-        let (log_sender, log_receiver) = mpsc::channel();
-        let (event_sender, event_receiver) = mpsc::channel();
-        let player = FilePlayer::new(next_file_path, port_id, log_sender, event_sender)
-            .context("Failed to build player")?;
-
-        let handle = thread::Builder::new()
-            .name(String::from("MIDI Player"))
-            .spawn(move || {
-                if let Err(e) = player.play_events() {
-                    eprintln!("Failed to play events: {:?}", e);
-                }
-            })
-            .context("Failed to spawn player thread")?;
+        if self.chosen_ports.is_empty() {
+            return Err(anyhow!("No port ID set"));
+        }
 
-        self.current_player = Some(PlayerReceiver {
-            log: log_receiver,
-            event: event_receiver,
-        });
-        self.current_player_handle = Some(handle);
+        if self.together {
+            let files: Vec<PathBuf> = self.files_to_play.drain(..).collect();
+
+            for (i, path) in files.iter().enumerate() {
+                let port_id = self.chosen_ports[i % self.chosen_ports.len()];
+                self.play_file(path, port_id)?;
+            }
+        } else {
+            let next_file_path = self.files_to_play.pop_front().context("No files to play")?;
+            if let Some(autoplay) = &mut self.autoplay {
+                autoplay.take_next();
+            }
+
+            for port_id in self.chosen_ports.clone() {
+                if !self.play_file(&next_file_path, port_id)? {
+                    break;
+                }
+            }
+        }
 
         Ok(())
     }
-}
 
-fn main() -> Result<()> {
-    ctrlc::set_handler(|| {
-        RUNNING.store(false, Ordering::Relaxed);
-    })
-    .context("Failed to set Ctrl-C handler")?;
+    /// Starts playing `path` on `port_id`, returning false when it was skipped
+    /// for having too few notes.
+    fn play_file(&mut self, path: &Path, port_id: u32) -> Result<bool> {
+        if self.quarantine.contains(path) {
+            self.add_message(format!("Skipping {}, it is in quarantine", path.display()));
+            return Ok(false);
+        }
 
-    let mut player = PlayerInstance::new();
+        let config = self.player_config(port_id);
+        let (player, stop_after, hud, locator, cues) = if syx::is_syx(path) {
+            let mut events = match syx::load(path, self.syx_delay) {
+                Ok(events) => events,
+                Err(e) => return self.quarantine_file(path, e),
+            };
+            if self.fix_checksums {
+                let fixed = syx::fix_checksums(&mut events);
+                if fixed > 0 {
+                    self.add_message(format!("Fixed {} SysEx checksums", fixed));
+                }
+            } else {
+                for i in syx::bad_checksums(&events) {
+                    self.add_message(format!("SysEx message {} has a wrong checksum", i + 1));
+                }
+            }
+            self.add_message(format!(
+                "Sending {} SysEx messages from {} to port {}",
+                events.len(),
+                path.display(),
+                port_id
+            ));
+
+            // Never cut short, a partial dump could leave the device half set up.
+            // The messages are already spaced out by the delay, and sent
+            // even with --drop sysex, which is about MIDI files.
+            let config = PlayerConfig {
+                sysex_delay: None,
+                count_in: None,
+                metronome: None,
+                clock: false,
+                timecode: None,
+                sync_port: None,
+                drop: Vec::new(),
+                routes: Vec::new(),
+                ..config
+            };
+            let player = Player::from_events(syx::DIVISION, syx::TEMPO, events, config);
+            (player, None, None, None, None)
+        } else {
+            let loaded = match self.preloaded.take() {
+                Some((preloaded, sequence)) if preloaded == path => sequence,
+                _ => midi_file::load(path, self.text_encoding),
+            };
+            let sequence = match loaded {
+                Ok(sequence) => sequence,
+                Err(e) => return self.quarantine_file(path, e),
+            };
 
-    // Build initial state
-    player.update_state();
+            let notes = midi_file::count_notes(&sequence.events);
+            if notes < self.min_notes {
+                self.add_message(format!(
+                    "Skipping {}, it has only {} notes",
+                    path.display(),
+                    notes
+                ));
+                return Ok(false);
+            }
 
-    println!("Ports:");
+            self.add_message(format!(
+                "Now playing {} on port {}",
+                sequence.title, port_id
+            ));
+
+            // Cues go out once, alongside the first port
+            let cues = match (&self.set_list, self.cue_port) {
+                (Some(set_list), Some(cue_port)) if self.chosen_ports.first() == Some(&port_id) => {
+                    set_list
+                        .song(path)
+                        .map(|song| (cue_port, song.cue_events(&sequence, self.cue_channel)))
+                        .filter(|(_, events)| !events.is_empty())
+                }
+                _ => None,
+            };
 
-    for (i, port_name) in player.port_list.iter().enumerate() {
-        println!("{}: {}", i, port_name);
+            let hud = if self.hud {
+                Some(Hud::new(&sequence))
+            } else {
+                None
+            };
+
+            if self.players.is_empty() {
+                self.lyrics.clear();
+                self.write_lyrics();
+            }
+
+            // The form of the song, which its cues follow as well
+            let jumps = match self
+                .set_list
+                .as_ref()
+                .and_then(|set_list| set_list.song(path))
+            {
+                Some(song) => song.jump_ticks(&sequence)?,
+                None => Vec::new(),
+            };
+
+            let bounds = self.bounds(&sequence, path)?;
+
+            let locator = Locator::new(&sequence);
+
+            let division = sequence.division;
+            let player = Player::from_sequence(sequence, config).jumping(jumps.clone());
+            (
+                bounds.apply(player),
+                self.preview,
+                hud,
+                Some(locator),
+                cues.map(|cues| (division, cues, jumps, bounds)),
+            )
+        };
+
+        self.bridge_events(&player);
+        self.start_player(port_id, player, stop_after)?;
+        if let Some(active) = self.players.last_mut() {
+            active.hud = hud;
+            active.locator = locator.clone();
+        }
+
+        if let Some((division, (cue_port, events), jumps, bounds)) = cues {
+            // Only the cue notes, none of the processing of the song itself
+            let config = PlayerConfig {
+                thru: None,
+                safety: None,
+                startup: Vec::new(),
+                automation: Vec::new(),
+                channel_map: Vec::new(),
+                programs: Vec::new(),
+                compare_port: None,
+                reconnect: None,
+                fallback_port: None,
+                backup_port: None,
+                fade_out: None,
+                handoff: None,
+                metronome: None,
+                clock: false,
+                timecode: None,
+                sync_port: None,
+                humanize: None,
+                velocity_curve: None,
+                polyphony_limit: None,
+                routes: Vec::new(),
+                also_ports: Vec::new(),
+                ..self.player_config(cue_port)
+            };
+            let player =
+                Player::from_events(division, DEFAULT_TEMPO, events, config).jumping(jumps);
+            let player = bounds.apply(player);
+
+            self.add_message(format!("Sending cues to port {}", cue_port));
+            self.start_player(cue_port, player, stop_after)?;
+
+            // The cues jump along with the song
+            if let Some(active) = self.players.last_mut() {
+                active.locator = locator;
+            }
+        }
+
+        Ok(true)
     }
 
-    if player.port_list.is_empty() {
-        println!("No ports!");
-        return Ok(());
-    } else {
-        player.chosen_port_number = Some((player.port_list.len() - 1) as u32);
+    /// The ticks of `--start`, `--end` and `--loop-section` in `sequence`.
+    fn bounds(&self, sequence: &Sequence, path: &Path) -> Result<Bounds> {
+        let tick = |position: &Option<Position>| -> Result<Option<u64>> {
+            position
+                .as_ref()
+                .map(|position| position.tick(sequence))
+                .transpose()
+        };
+        let (start, end) = (tick(&self.start)?, tick(&self.end)?);
+
+        if let (Some(start), Some(end)) = (start, end) {
+            if start >= end {
+                return Err(anyhow!(
+                    "{} ends at tick {}, before it starts at tick {}",
+                    path.display(),
+                    end,
+                    start
+                ));
+            }
+        }
+
+        let section = match &self.section {
+            Some((from, to)) => {
+                let (from, to) = (from.tick(sequence)?, to.tick(sequence)?);
+                if from >= to {
+                    return Err(anyhow!(
+                        "The section of {} ends at tick {}, before it starts at tick {}",
+                        path.display(),
+                        to,
+                        from
+                    ));
+                }
+
+                self.add_message(format!("Looping ticks {} to {}", from, to));
+                Some((from, to))
+            }
+            None => None,
+        };
+
+        Ok(Bounds {
+            start,
+            end,
+            section,
+        })
     }
 
-    for path in env::args_os().skip(1) {
-        player.files_to_play.push_back(PathBuf::from(path));
+    /// Records a file that failed to load so the queue can go on without it.
+    fn quarantine_file(&mut self, path: &Path, error: anyhow::Error) -> Result<bool> {
+        let reason = format!("{:#}", error);
+        self.add_message(format!("Quarantined {}: {}", path.display(), reason));
+        self.quarantine.add(path, &reason)?;
+
+        Ok(false)
     }
 
-    // Begin playback
-    if !player.files_to_play.is_empty() {
-        player.play_next_file();
+    /// Carries out a command from `client`, unless the transport lock keeps
+    /// them from it.
+    fn run_command(&mut self, client: Client, command: Command) {
+        let (name, access) = match &command {
+            Command::Switch => ("switch", Access::Transport),
+            Command::Panic => ("panic", Access::Safety),
+            Command::Lock => ("lock", Access::Lock),
+            Command::Unlock => ("unlock", Access::Unlock),
+            Command::NextMarker => ("next marker", Access::Transport),
+            Command::PreviousMarker => ("previous marker", Access::Transport),
+            Command::Pause => ("pause", Access::Transport),
+            Command::NextFile => ("next file", Access::Transport),
+            Command::Seek { .. } => ("seek", Access::Transport),
+            Command::Speed { .. } => ("speed", Access::Transport),
+            Command::MuteMenu => ("mute menu", Access::Safety),
+            Command::ToggleMute(_) => ("mute", Access::Transport),
+            Command::Remote(RemoteCommand::Play) => ("play", Access::Transport),
+            Command::Remote(RemoteCommand::Pause) => ("pause", Access::Transport),
+            Command::Remote(RemoteCommand::Stop) => ("stop", Access::Transport),
+            Command::Remote(RemoteCommand::Skip) => ("skip", Access::Transport),
+            Command::Remote(RemoteCommand::Seek(_)) => ("seek", Access::Transport),
+            Command::Remote(RemoteCommand::Queue(_)) => ("queue", Access::Transport),
+        };
+
+        if !self.arbiter.allow(&client, name, access) {
+            let holder = self.arbiter.holder().map_or("", |holder| &holder.name);
+            self.add_message(format!(
+                "Ignoring {} from {}, {} holds the transport lock",
+                name, client.name, holder
+            ));
+            return;
+        }
 
-        while RUNNING.load(Ordering::Relaxed) {
-            player.update_state();
+        // Streamed files are queued with the driver as a whole
+        #[cfg(windows)]
+        if self.engine == Engine::Stream
+            && matches!(
+                command,
+                Command::Panic
+                    | Command::NextMarker
+                    | Command::PreviousMarker
+                    | Command::Pause
+                    | Command::Seek { .. }
+                    | Command::Speed { .. }
+                    | Command::ToggleMute(_)
+                    | Command::Remote(RemoteCommand::Pause)
+                    | Command::Remote(RemoteCommand::Seek(_))
+            )
+        {
+            self.add_message(format!("The stream engine cannot {}", name));
+            return;
+        }
 
-            for event in player.events.drain(..) {
-                println!("{} {}", event.delta_time, event);
+        match &command {
+            Command::Lock => self.add_message(format!("Transport locked by {}", client.name)),
+            Command::Unlock => self.add_message("Transport unlocked"),
+            Command::Remote(RemoteCommand::Stop) => {
+                self.files_to_play.clear();
+                if let Some(autoplay) = &mut self.autoplay {
+                    autoplay.queued = 0;
+                }
+                self.add_message(format!("Stopped by {}", client.name));
+            }
+            Command::Remote(RemoteCommand::Queue(path)) => {
+                self.add_message(format!("Queued {} for {}", path.display(), client.name));
+                self.files_to_play.push_back(path.clone());
+            }
+            Command::Speed { faster } => {
+                self.speed = if *faster {
+                    self.speed + SPEED_STEP
+                } else {
+                    self.speed.saturating_sub(SPEED_STEP)
+                };
+                self.speed = self.speed.clamp(*SPEED_RANGE.start(), *SPEED_RANGE.end());
+                self.add_message(format!("Playing at {}% speed", self.speed));
+            }
+            Command::MuteMenu => {
+                let channels: Vec<String> = (0..16)
+                    .map(|channel| match self.muted & 1 << channel {
+                        0 => format!("{}", channel + 1),
+                        _ => format!("[{}]", channel + 1),
+                    })
+                    .collect();
+                self.add_message(format!("Muted in brackets: {}", channels.join(" ")));
+                self.add_message("Type the channels to mute or unmute, like 10 or 2,3");
             }
+            Command::ToggleMute(channels) => {
+                for channel in channels {
+                    self.muted ^= 1 << channel;
+                    let state = match self.muted & 1 << channel {
+                        0 => "Unmuted",
+                        _ => "Muted",
+                    };
+                    self.add_message(format!("{} channel {}", state, channel + 1));
+                }
+            }
+            _ => {}
+        };
+
+        let mut jumped_to = Vec::new();
+        let mut missed = Vec::new();
+        for active in &mut self.players {
+            match &command {
+                Command::Switch if self.compare_port.is_some() => active.player.switch_output(),
+                Command::Panic => active.player.panic(),
+                Command::NextMarker | Command::PreviousMarker => {
+                    let back = matches!(command, Command::PreviousMarker);
+                    let (tick, name) = match active.marker(back) {
+                        Some((tick, marker)) => (*tick, format!("marker {}", marker.trim())),
+                        None if back => (0, String::from("the start")),
+                        None => continue,
+                    };
 
-            thread::sleep(Duration::from_millis(1));
+                    active.player.seek(tick);
+                    active.tick = tick;
+                    jumped_to.push(name);
+                }
+                Command::Pause if active.player.is_paused() => active.player.resume(),
+                Command::Pause => active.player.pause(),
+                Command::Remote(RemoteCommand::Play) => active.player.resume(),
+                Command::Remote(RemoteCommand::Pause) => active.player.pause(),
+                Command::NextFile
+                | Command::Remote(RemoteCommand::Stop)
+                | Command::Remote(RemoteCommand::Skip) => active.player.stop(),
+                Command::Seek { back } => {
+                    let time = if *back {
+                        active.elapsed.saturating_sub(SEEK_STEP)
+                    } else {
+                        active.elapsed + SEEK_STEP
+                    };
+                    let tick = match &active.locator {
+                        Some(locator) => locator.tick(&Position::Time(time)).unwrap_or(0),
+                        None => continue,
+                    };
+
+                    active.player.seek(tick);
+                    active.tick = tick;
+                    active.elapsed = time;
+                }
+                Command::Speed { .. } => active.player.set_speed(self.speed),
+                Command::ToggleMute(channels) => {
+                    for &channel in channels {
+                        active
+                            .player
+                            .set_muted(channel, self.muted & 1 << channel != 0);
+                    }
+                }
+                Command::Remote(RemoteCommand::Seek(position)) => {
+                    // Generated material and SysEx have no positions to seek to
+                    let tick = match active
+                        .locator
+                        .as_ref()
+                        .map(|locator| locator.tick(position))
+                    {
+                        Some(Ok(tick)) => tick,
+                        Some(Err(e)) => {
+                            missed.push(format!("Cannot seek: {:#}", e));
+                            continue;
+                        }
+                        None => continue,
+                    };
+
+                    active.player.seek(tick);
+                    active.tick = tick;
+                    jumped_to.push(format!("tick {}", tick));
+                }
+                _ => {}
+            };
+        }
+
+        // Layered players all jump to the same marker
+        jumped_to.dedup();
+        for name in jumped_to {
+            self.add_message(format!("Jumping to {}", name));
+        }
+        missed.dedup();
+        for message in missed {
+            self.add_message(message);
         }
     }
 
-    if let Some(handle) = player.current_player_handle.take() {
-        if let Err(e) = handle.join() {
-            return Err(anyhow!("Failed to join player thread: {:?}", e));
+    /// What the remote control reports, from the first player.
+    fn remote_status(&self) -> RemoteStatus {
+        let active = self.players.first();
+
+        RemoteStatus {
+            title: active
+                .and_then(|active| active.locator.as_ref())
+                .map(|locator| locator.title().to_string()),
+            paused: matches!(active, Some(active) if active.player.is_paused()),
+            elapsed: active.map_or(Duration::ZERO, |active| active.elapsed),
+            percent: active.map_or(0.0, |active| active.percent),
+            queue: self.files_to_play.iter().cloned().collect(),
         }
     }
 
-    Ok(())
+    /// Forwards the events of `player` to the bridge as they are played.
+    fn bridge_events(&self, player: &Player) {
+        if let Some(bridge) = &self.bridge {
+            let bridge = bridge.clone();
+            player.events().on_event(move |event| bridge.send(event));
+        }
+    }
+
+    fn start_player(
+        &mut self,
+        port_id: u32,
+        mut player: Player,
+        stop_after: Option<Duration>,
+    ) -> Result<()> {
+        let events = player.events().subscribe();
+        player.set_speed(self.speed);
+        for channel in 0..16 {
+            player.set_muted(channel, self.muted & 1 << channel != 0);
+        }
+        player.play()?;
+
+        let note_usage = self
+            .heatmap
+            .as_ref()
+            .map(|key_range| NoteUsage::new(key_range.clone()));
+
+        if let Some(autoplay) = &mut self.autoplay {
+            autoplay.started_any = true;
+        }
+
+        self.players.push(ActivePlayer {
+            port_id,
+            player,
+            events,
+            started: Instant::now(),
+            voices: VoiceTracker::new(self.voice_limit),
+            stats: PlaybackStats::default(),
+            note_usage,
+            stop_after,
+            hud: None,
+            locator: None,
+            tick: 0,
+            elapsed: Duration::ZERO,
+            percent: 0.0,
+        });
+
+        Ok(())
+    }
 }
 
-struct FilePlayer {
-    //path: PathBuf,
-    port_id: UINT,
-    //format: SMFFormat,
-    division: u64,
-    events: Vec<DataEvent>,
-    log: Sender<String>,
-    event_log: Sender<BasicMidiEvent>,
+/// The one port whose name contains `name`, ignoring case, as device numbers
+/// change between boots while names stay. A port named exactly that wins.
+fn find_port(port_list: &[String], name: &str) -> Result<u32> {
+    let wanted = name.to_lowercase();
+    if let Some(port) = port_list
+        .iter()
+        .position(|port_name| port_name.to_lowercase() == wanted)
+    {
+        return Ok(port as u32);
+    }
+
+    let matches: Vec<usize> = port_list
+        .iter()
+        .enumerate()
+        .filter(|(_, port_name)| port_name.to_lowercase().contains(&wanted))
+        .map(|(i, _)| i)
+        .collect();
+
+    match matches[..] {
+        [port] => Ok(port as u32),
+        [] => Err(anyhow!("No port name contains \"{}\"", name)),
+        _ => {
+            let names: Vec<&str> = matches.iter().map(|&i| port_list[i].as_str()).collect();
+            Err(anyhow!(
+                "\"{}\" matches several ports: {}",
+                name,
+                names.join(", ")
+            ))
+        }
+    }
 }
 
-impl FilePlayer {
-    fn new(
-        path: PathBuf,
-        port_id: UINT,
-        log: Sender<String>,
-        event_log: Sender<BasicMidiEvent>,
-    ) -> Result<Self> {
-        let midi_data = SMF::from_file(&path).context("Failed to parse MIDI file")?;
+/// Channels typed as numbers from 1 to 16, separated by commas or spaces,
+/// counted from 0.
+fn parse_channels(text: &str) -> Option<Vec<u8>> {
+    let channels: Option<Vec<u8>> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|number| !number.is_empty())
+        .map(|number| match number.parse() {
+            Ok(channel @ 1..=16) => Some(channel - 1),
+            _ => None,
+        })
+        .collect();
+
+    channels.filter(|channels| !channels.is_empty())
+}
 
-        if midi_data.division < 0 {
-            return Err(anyhow!("SMPTE division not supported"));
+/// Whether stdout is a terminal taking ANSI colors, unless `NO_COLOR` asks
+/// for none.
+fn console_colors() -> bool {
+    if env::var_os("NO_COLOR").is_some() || !io::stdout().is_tty() {
+        return false;
+    }
+
+    #[cfg(windows)]
+    let supported = crossterm::ansi_support::supports_ansi();
+    #[cfg(not(windows))]
+    let supported = true;
+
+    supported
+}
+
+fn port_names() -> Vec<String> {
+    (0..OutputPort::count())
+        .map(|i| OutputPort::name(i).unwrap_or_else(|_| String::from("<unknown>")))
+        .collect()
+}
+
+/// Reads stdin on its own thread, which is left blocked on it at exit.
+/// Reads commands typed while playing, one per line: Enter alone switches
+/// A/B comparisons over, a space pauses and resumes, `n` goes on with the
+/// next file, the arrow keys or `<` and `>` seek, `+` and `-` change the
+/// speed, `p` silences stuck notes, `]` and `[` jump to the next and
+/// previous markers and `m` lists the channels to mute, taking the channels
+/// on the next line.
+fn read_commands(sender: Sender<(Client, Command)>) -> Result<()> {
+    let console = Client::new("console", Priority::Operator);
+
+    thread::Builder::new()
+        .name(String::from("Commands"))
+        .spawn(move || {
+            let stdin = io::stdin();
+            let mut muting = false;
+            for line in stdin.lock().lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+
+                let channels = parse_channels(line.trim_start_matches('m'));
+                let command = match (line.trim(), channels) {
+                    (_, Some(channels)) if muting || line.starts_with('m') => {
+                        Command::ToggleMute(channels)
+                    }
+                    // Only spaces, which trim away
+                    ("", _) if !line.is_empty() => Command::Pause,
+                    ("", _) => Command::Switch,
+                    ("pause", _) => Command::Pause,
+                    ("n", _) | ("next", _) => Command::NextFile,
+                    ("\u{1b}[D", _) | ("<", _) => Command::Seek { back: true },
+                    ("\u{1b}[C", _) | (">", _) => Command::Seek { back: false },
+                    ("+", _) => Command::Speed { faster: true },
+                    ("-", _) => Command::Speed { faster: false },
+                    ("m", _) | ("mute", _) => Command::MuteMenu,
+                    ("p", _) | ("panic", _) => Command::Panic,
+                    ("lock", _) => Command::Lock,
+                    ("unlock", _) => Command::Unlock,
+                    ("]", _) => Command::NextMarker,
+                    ("[", _) | ("b", _) | ("back", _) => Command::PreviousMarker,
+                    _ => {
+                        muting = false;
+                        continue;
+                    }
+                };
+                muting = matches!(command, Command::MuteMenu);
+
+                if sender.send((console.clone(), command)).is_err() {
+                    break;
+                }
+            }
+        })
+        .context("Failed to spawn command thread")?;
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let session = CancellationToken::new();
+    let ctrlc_session = session.clone();
+    ctrlc::set_handler(move || ctrlc_session.cancel()).context("Failed to set Ctrl-C handler")?;
+
+    let mut config = Config::find()?;
+    let options = Options::from_args(&config)?;
+
+    if options.autoplay.is_some() && !watchdog::is_watched() {
+        println!("Starting the player under a watchdog, Ctrl-C to stop");
+        return watchdog::supervise(&session);
+    }
+
+    // Inspecting, validating and converting need no port at all
+    if options.inspect {
+        if options.files.is_empty() {
+            return Err(anyhow!("No files to inspect"));
         }
 
-        let mut events = None;
+        for path in &options.files {
+            for line in inspect::inspect(path, options.text_encoding)? {
+                println!("{}", line);
+            }
+        }
 
-        for (i, track) in midi_data.tracks.into_iter().enumerate() {
-            log.send(format!("Track #{}", i + 1))?;
+        return Ok(());
+    }
 
-            if let Some(name) = track.name {
-                log.send(format!("  - Name: {}", name))?;
+    if options.validate {
+        if options.files.is_empty() {
+            return Err(anyhow!("No files to validate"));
+        }
+
+        let mut problems = 0;
+        for path in &options.files {
+            let report = validate::validate(path, options.text_encoding)?;
+            for line in report.lines {
+                println!("{}", line);
             }
-            if let Some(copyright) = track.copyright {
-                log.send(format!("  - Copyright: {}", copyright))?;
+
+            problems += report.problems;
+        }
+
+        if problems > 0 {
+            return Err(anyhow!("Found {} problems", problems));
+        }
+
+        return Ok(());
+    }
+
+    if let Some(query) = &options.find {
+        if options.files.is_empty() {
+            return Err(anyhow!("No files to search"));
+        }
+
+        for path in &options.files {
+            let sequence = midi_file::load(path, options.text_encoding)?;
+            let matches = search::find(&sequence, query);
+
+            println!("File: {}", path.display());
+            for found in &matches {
+                println!("  {}: {}", found.position, found.description);
             }
+            println!("{} matches", matches.len());
+        }
 
-            if let Some(previous_events) = events.take() {
-                events = Some(midi_file::combine_tracks(previous_events, track.events));
-            } else {
-                events = Some(track.events);
+        return Ok(());
+    }
+
+    if options.show_quarantine {
+        if let Some(list) = &options.quarantine {
+            for line in Quarantine::load(list)?.report() {
+                println!("{}", line);
             }
         }
 
-        let events = events.context("No events found")?;
+        return Ok(());
+    }
 
-        Ok(Self {
-            //path,
-            port_id,
-            //format: midi_data.format,
-            division: midi_data.division as u64,
-            events: midi_file::combine_events(events),
-            log,
-            event_log,
-        })
+    if let Some(path) = &options.timeline {
+        let sequence = midi_file::load(path, options.text_encoding)?;
+        let bars = timeline::timeline(&sequence.events, sequence.division);
+        println!("{}", timeline::to_json(&bars));
+
+        return Ok(());
+    }
+
+    if let Some((input, output)) = &options.convert {
+        let count = convert::to_single_track(input, output)?;
+        println!("Wrote {} events to {}", count, output.display());
+
+        return Ok(());
     }
 
-    fn play_events(self) -> Result<()> {
-        let mut conn_out = WinMidiPort::connect(self.port_id)?;
+    if let Some((action, path)) = &options.settings {
+        match action {
+            SettingsAction::Export => {
+                for name in bundle::export(&config, path)? {
+                    println!("Packed {}", name);
+                }
+            }
+            SettingsAction::Import => {
+                for path in bundle::import(&config, path)? {
+                    println!("Unpacked {}", path.display());
+                }
+            }
+        };
 
-        // Reset so sounds play correctly
-        conn_out.send_reset()?;
+        return Ok(());
+    }
 
-        let thread_boost = ThreadBoost::new();
-        self.log
-            .send(format!("Task Index: {}", thread_boost.task_index()))?;
+    // Opening files from Explorer one by one builds a queue in the first
+    if options.single_instance && !options.files.is_empty() && instance::forward(&options.files)? {
+        println!(
+            "Queued {} files in the running instance",
+            options.files.len()
+        );
+        return Ok(());
+    }
 
-        // Default tempo is 120 beats per minute
-        let mut current_tempo = 500000;
+    backend::select(options.backend);
+    backend::select_reset(options.reset);
+    if let Some(path) = options.soundfont {
+        backend::select_soundfont(path);
+    }
+    if let Some(latency) = options.ble_latency {
+        backend::select_ble_latency(latency);
+    }
+    backend::select_rtp_sessions(options.rtp_sessions);
+
+    let mut player = PlayerInstance::new(session.clone());
+    player.output = options.output;
+    player.verbose = options.verbose;
+    player.colors = options.output == Output::Text && console_colors();
+    if let Some(path) = &options.log_file {
+        player.log_file = Some(RefCell::new(LogFile::open(path, options.log_rotate)?));
+    }
 
-        // Use the last event time as the waiting start time
-        let mut waiting_start = Instant::now();
+    // Build initial state
+    player.update_state();
 
-        let mut iter = self.events.into_iter();
-        loop {
-            let event = match iter.next() {
-                Some(event) => event,
-                None => break,
-            };
-            if !RUNNING.load(Ordering::Relaxed) {
-                break;
+    player.add_message("Ports:");
+
+    for (i, port_name) in player.port_list.iter().enumerate() {
+        player.add_message(format!("{}: {}", i, port_name));
+    }
+
+    if options.thru_port.is_some()
+        || options.capture.is_some()
+        || options.dump.is_some()
+        || options.sync_port.is_some()
+    {
+        player.add_message("Input ports:");
+
+        for i in 0..InputPort::count() {
+            let name = InputPort::name(i).unwrap_or_else(|_| String::from("<unknown>"));
+            player.add_message(format!("{}: {}", i, name));
+        }
+    }
+
+    if player.port_list.is_empty() {
+        player.add_message("No ports!");
+        return Ok(());
+    } else if !options.ports.is_empty() || !options.port_names.is_empty() {
+        for &port in &options.ports {
+            if port as usize >= player.port_list.len() {
+                return Err(anyhow!("Port {} does not exist", port));
             }
+        }
+
+        player.chosen_ports = options.ports.clone();
+        for name in &options.port_names {
+            player
+                .chosen_ports
+                .push(find_port(&player.port_list, name)?);
+        }
+    } else {
+        player.chosen_ports = vec![(player.port_list.len() - 1) as u32];
+    }
 
-            //println!("event: {}", event);
+    if let Some(settings) = options.keyboard {
+        let port_id = player.chosen_ports[0];
+        return keyboard::run(port_id, settings, session);
+    }
 
-            unsafe { WaitForSingleObject(conn_out.event_handle(), INFINITE) };
+    if let Some((path, settings)) = options.capture {
+        let port_id = player.chosen_ports[0];
+        if settings.request.is_none() {
+            println!("Waiting for a SysEx dump, start it on the device");
+        }
 
-            if event.delta_time > 0 {
-                let waiting_micros = event.delta_time * current_tempo / self.division;
-                //println!("waiting: {}", waiting_micros);
+        let messages = capture::capture(port_id, &settings, session)?;
+        if messages.is_empty() {
+            println!("Nothing captured");
+            return Ok(());
+        }
 
-                let waiting_time = Duration::from_micros(waiting_micros);
+        capture::save(&path, &messages)?;
+        println!("Saved {} messages to {}", messages.len(), path.display());
+        return Ok(());
+    }
 
-                loop {
-                    let now = Instant::now();
+    if let Some((path, settings)) = options.dump {
+        let port_id = player.chosen_ports[0];
+        let packets = dump::load(&path, &settings)?;
+        if packets.is_empty() {
+            println!("Nothing to send in {}", path.display());
+            return Ok(());
+        }
 
-                    if now.duration_since(waiting_start) >= waiting_time {
-                        break;
-                    } else {
-                        conn_out.check_inflight()?;
+        let sent = dump::send(port_id, &packets, &settings, session)?;
+        println!("Sent {} of {} packets", sent, packets.len());
+        return Ok(());
+    }
 
-                        if !RUNNING.load(Ordering::Relaxed) {
-                            return Ok(());
-                        }
+    if let Some(path) = &options.autoplay {
+        let resume_path = ResumePoint::path_for(path);
+        let mut files = options.files.clone();
+        let mut resume_tick = None;
+
+        if let Some(point) = ResumePoint::load(&resume_path)? {
+            if point.index < files.len() {
+                player.add_message(format!("Resuming at song {}", point.index + 1));
+                files.drain(..point.index);
+                resume_tick = Some(point.tick).filter(|&tick| tick > 0);
+            }
+        }
+
+        player.autoplay = Some(Autoplay {
+            playlist: options.files,
+            resume_path,
+            resume_tick,
+            last_saved: Instant::now(),
+            started_any: true,
+            queued: files.len(),
+            playing: None,
+        });
+        player.files_to_play.extend(files);
+    } else {
+        player.files_to_play.extend(options.files);
+    }
+    player.together = options.together;
+    player.syx_delay = options.syx_delay;
+    player.fix_checksums = options.fix_checksums;
+    player.hud = options.hud;
+    player.hud_interval = options.hud_interval;
+    player.lyrics_file = options.lyrics_file;
+    player.set_list = options.set_list;
+    player.cue_port = options.cue_port;
+    player.cue_channel = options.cue_channel;
+    player.preview = options.preview;
+    player.min_notes = options.min_notes;
+    player.text_encoding = options.text_encoding;
+    player.reconnect = options.reconnect;
+    player.fallback_port = options.fallback_port;
+    player.fade_out = options.fade_out;
+    player.fade_controller = options.fade_controller;
+    player.sysex_delay = options.sysex_delay;
+    player.lead_in = options.lead_in;
+    player.count_in = options.count_in;
+    player.metronome = options.metronome;
+    player.clock = options.clock;
+    player.timecode = options.timecode;
+    player.sync_port = options.sync_port;
+    if options.gapless {
+        player.handoff = Some(PortHandoff::default());
+    }
+    player.gap = options.gap;
+    player.drop = options.drop;
+    player.humanize = options.humanize;
+    player.velocity_curve = options.velocity_curve;
+    player.polyphony_limit = options.polyphony_limit;
+    for route in &options.routes {
+        if route.port_id as usize >= player.port_list.len() {
+            return Err(anyhow!("Port {} does not exist", route.port_id));
+        }
+    }
+    player.routes = options.routes;
+    for port_id in &options.also_ports {
+        if *port_id as usize >= player.port_list.len() {
+            return Err(anyhow!("Port {} does not exist", port_id));
+        }
+    }
+    player.also_ports = options.also_ports;
+    if let Some(target) = &options.osc {
+        player.bridge = Some(EventBridge::connect(target, options.osc_format)?);
+    }
+    if let Some(address) = &options.ws_listen {
+        player.event_server = Some(EventServer::listen(address)?);
+    }
+    player.start = options.start;
+    player.end = options.end;
+    player.section = options.section;
+    if let Some(backup_port) = options.backup_port {
+        if backup_port as usize >= player.port_list.len() {
+            return Err(anyhow!("Port {} does not exist", backup_port));
+        }
+
+        player.backup_port = Some(backup_port);
+    }
+    if let Some(list) = &options.quarantine {
+        player.quarantine = Quarantine::load(list)?;
+    }
+    player.safety = options.safety;
+    if let Some(profile) = options.profile {
+        player.startup = profile.startup;
+        player.automation = profile.automation;
+    }
+    player.automation.extend(options.ramps);
+    player.channel_map = options.channel_map;
+    player.programs = options.programs;
+
+    if let Some(compare_port) = options.compare_port {
+        if compare_port as usize >= player.port_list.len() {
+            return Err(anyhow!("Port {} does not exist", compare_port));
+        }
+
+        player.compare_port = Some(compare_port);
+        player.add_message("Press Enter to switch between the compared ports");
+    }
+    let (commands, received) = mpsc::channel();
+    // The TUI reads the keys itself
+    if !options.tui {
+        read_commands(commands.clone())?;
+    }
+    if let Some(address) = &options.http_listen {
+        let commands = commands.clone();
+        let token = options.http_token.clone();
+        let server = RemoteServer::listen(address, token, move |client, command| {
+            let _ = commands.send((client, Command::Remote(command)));
+        })?;
+        player.add_message(format!("Taking remote commands on http://{}", address));
+        player.remote = Some(server);
+    }
+    if options.single_instance {
+        let opened = Client::new("another instance", Priority::Operator);
+        let listening = instance::listen(move |path| {
+            let _ = commands.send((opened.clone(), Command::Remote(RemoteCommand::Queue(path))));
+        });
+
+        if let Err(e) = listening {
+            player.add_message(format!("Not taking files from other instances: {:#}", e));
+        }
+    }
+    player.commands = Some(received);
+    if let Some(path) = &options.audit_log {
+        player.arbiter = Arbiter::with_audit_log(path)?;
+    }
+    if !options.tui {
+        player.add_message("Type p and Enter to silence stuck notes, or a space to pause");
+    }
+    player.chimes = options.chimes.map(ChimeSchedule::new);
+    player.engine = options.engine;
+    player.voice_limit = options.voice_limit;
+    if options.heatmap {
+        player.heatmap = Some(options.key_range);
+    }
+
+    // Keep the input port open for the whole session
+    let _thru_input = match options.thru_port {
+        Some(input_port) => {
+            let (input, receiver) = thru::open(input_port)?;
+            player.thru = Some(receiver);
+            Some(input)
+        }
+        None => None,
+    };
+
+    let nothing_to_play =
+        player.files_to_play.is_empty() && player.chimes.is_none() && options.generate.is_none();
+
+    // Without anything to play, thru forwarding gets the output port to itself
+    if nothing_to_play {
+        if let (Some(receiver), Some(&port_id)) = (player.thru.take(), player.chosen_ports.first())
+        {
+            let safety = player.safety.take();
+            let cancel = session.clone();
+            let handle = thread::Builder::new()
+                .name(String::from("MIDI Thru"))
+                .spawn(move || {
+                    if let Err(e) = thru::forward(receiver, port_id, safety, cancel) {
+                        eprintln!("Failed to forward thru messages: {:?}", e);
                     }
-                }
+                })
+                .context("Failed to spawn thru thread")?;
+
+            player.thru_handle = Some(handle);
 
-                waiting_start = Instant::now();
+            while !session.is_cancelled() {
+                thread::sleep(Duration::from_millis(10));
             }
+        }
+    }
 
-            match event.data {
-                LocalEvent::Meta(meta) => {
-                    self.log.send(format!("{}", meta))?;
+    // Generated material plays once, or loops until stopped, then we are done
+    if let Some(settings) = &options.generate {
+        player.play_generated(settings);
+    }
 
-                    match meta.command {
-                        MetaCommand::TempoSetting => {
-                            current_tempo = meta.data_as_u64(3);
-                            self.log.send(format!("new tempo: {}", current_tempo))?;
-                        }
-                        _ => {}
-                    };
+    // Begin playback
+    if !nothing_to_play {
+        if options.tui {
+            player.tui = Some(RefCell::new(Tui::new(options.hud_interval)?));
+        }
 
-                    // Set the event so we are not stuck waiting for too long
-                    unsafe { SetEvent(conn_out.event_handle()) };
-                }
-                LocalEvent::SysEx(data) => {
-                    //println!("delta time: {}, data: {:02x?}", event.delta_time, data);
-                    conn_out
-                        .send(&data)
-                        .context("Failed to send MIDI message")?;
-
-                    self.event_log.send(BasicMidiEvent {
-                        delta_time: event.delta_time,
-                        msg: MidiMessage::from_bytes(data),
-                    })?;
-                }
-                LocalEvent::Midi(data) => {
-                    //println!("delta time: {}, data: {:02x?}", event.delta_time, data);
-                    conn_out
-                        .send(&data)
-                        .context("Failed to send MIDI message")?;
-                    self.event_log.send(BasicMidiEvent {
-                        delta_time: event.delta_time,
-                        msg: MidiMessage::from_bytes(data.to_vec()),
-                    })?;
+        while !session.is_cancelled() {
+            player.update_state();
+
+            for (time, event) in mem::take(&mut player.events) {
+                player.follow_event(&event);
+                player.print_event(time, &event)?;
+                if let Some(server) = &player.event_server {
+                    server.send(time, &event);
                 }
-            };
+            }
+            if let Some(log_file) = &player.log_file {
+                log_file.borrow_mut().flush();
+            }
+            if player.hud {
+                player.update_hud()?;
+            }
+            player.update_tui()?;
+
+            if options.generate.is_some() && player.players.is_empty() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    for active in player.players.drain(..) {
+        active.player.join()?;
+    }
+
+    if let Some(handle) = player.thru_handle.take() {
+        if let Err(e) = handle.join() {
+            return Err(anyhow!("Failed to join thru thread: {:?}", e));
         }
+    }
 
-        Ok(())
+    remember_settings(&mut config, &player, &options.remembered)
+}
+
+/// Writes the port and the playlist options given on the command line back
+/// to the settings file, so the next launch starts the same way. Layered
+/// ports are left alone, as the file holds a single port name.
+fn remember_settings(
+    config: &mut Config,
+    player: &PlayerInstance,
+    remembered: &[(&str, Option<String>)],
+) -> Result<()> {
+    if let [port_id] = player.chosen_ports[..] {
+        if let Some(name) = player.port_list.get(port_id as usize) {
+            config.set("port-name", name);
+        }
     }
+
+    for (key, value) in remembered {
+        match value {
+            Some(value) => config.set(key, value),
+            None => config.remove(key),
+        }
+    }
+
+    config.save()
 }
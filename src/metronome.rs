@@ -0,0 +1,106 @@
+use crate::midi_file::{self, DataEvent, Note};
+use crate::timeline;
+
+/// Side stick, the rim shot of the GM drum map.
+pub const DEFAULT_NOTE: u8 = 37;
+
+/// How much louder the first beat of a bar is.
+const ACCENT: u8 = 27;
+
+/// The note a metronome clicks with.
+#[derive(Clone, Copy)]
+pub struct Click {
+    /// From 0.
+    pub channel: u8,
+    pub note: u8,
+    pub velocity: u8,
+}
+
+impl Default for Click {
+    fn default() -> Self {
+        Self {
+            channel: 9,
+            note: DEFAULT_NOTE,
+            velocity: 100,
+        }
+    }
+}
+
+/// Bars of clicks played before a song starts, for performers playing along.
+#[derive(Clone, Copy)]
+pub struct CountIn {
+    pub bars: u32,
+    pub click: Click,
+}
+
+/// The clicks of the count-in in ticks of `division`, with every beat of the
+/// bars of `signature`, given as its numerator and the power of two of its
+/// denominator. Returns them with the length of the count-in.
+pub fn count_in(division: u64, signature: (u8, u8), count_in: &CountIn) -> (Vec<DataEvent>, u64) {
+    let (numerator, denominator) = signature;
+    let beat_length = ((division * 4) >> denominator).max(1);
+    let beats = count_in.bars as u64 * numerator.max(1) as u64;
+
+    let notes: Vec<Note> = (0..beats)
+        .map(|beat| Note {
+            time: beat * beat_length,
+            length: (beat_length / 4).max(1),
+            key: count_in.click.note,
+            velocity: accented(count_in.click.velocity, beat % numerator.max(1) as u64 == 0),
+        })
+        .collect();
+
+    (
+        midi_file::sequence_notes(&notes, count_in.click.channel, None),
+        beats * beat_length,
+    )
+}
+
+/// Merges a click on every beat of the events into them, following their
+/// time signatures, or 4/4 without one. The tempo map times the clicks along
+/// with everything else.
+pub fn apply(events: Vec<DataEvent>, division: u64, click: Option<Click>) -> Vec<DataEvent> {
+    let click = match click {
+        Some(click) => click,
+        None => return events,
+    };
+
+    let length: u64 = events.iter().map(|event| event.delta_time).sum();
+    let mut signatures = timeline::time_signatures(&events);
+    if signatures.first().map(|&(tick, _)| tick) != Some(0) {
+        signatures.insert(0, (0, (4, 2)));
+    }
+
+    let channel = click.channel & 0x0f;
+    let mut messages = Vec::new();
+
+    for (i, &(start, (numerator, denominator))) in signatures.iter().enumerate() {
+        let end = match signatures.get(i + 1) {
+            Some(&(next, _)) => next.min(length),
+            None => length,
+        };
+        let beat_length = ((division * 4) >> denominator).max(1);
+
+        // A signature changing mid-bar starts a new bar, as in the timeline
+        for (tick, beat) in (start..end).step_by(beat_length as usize).zip(0u64..) {
+            let velocity = accented(click.velocity, beat % numerator.max(1) as u64 == 0);
+            let off = (tick + (beat_length / 4).max(1)).min(length.max(tick + 1));
+
+            messages.push((tick, [0x90 | channel, click.note, velocity]));
+            messages.push((off, [0x80 | channel, click.note, 0]));
+        }
+    }
+
+    // Stable, so a note off stays ahead of the click that follows it
+    messages.sort_by_key(|&(tick, _)| tick);
+
+    midi_file::merge_messages(events, messages)
+}
+
+fn accented(velocity: u8, downbeat: bool) -> u8 {
+    if downbeat {
+        velocity.saturating_add(ACCENT).min(127)
+    } else {
+        velocity
+    }
+}
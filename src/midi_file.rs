@@ -1,10 +1,18 @@
 //use std::mem;
+use std::path::Path;
 
-use rimd::{Event, MetaEvent, Status, TrackEvent};
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+use rimd::{Event, MetaCommand, MetaEvent, Status, TrackEvent, SMF};
+
+use crate::text;
 
 pub struct DataEvent {
     pub delta_time: u64,
     pub data: LocalEvent,
+    /// The track of the file the event came from, from 0, which events made
+    /// up along the way do not have.
+    pub track: Option<usize>,
 }
 
 pub enum LocalEvent {
@@ -13,12 +21,274 @@ pub enum LocalEvent {
     Meta(MetaEvent),
 }
 
+/// A note at an absolute tick position, for building generated sequences.
+pub struct Note {
+    pub time: u64,
+    pub length: u64,
+    pub key: u8,
+    pub velocity: u8,
+}
+
 impl DataEvent {
-    fn new(delta_time: u64, data: LocalEvent) -> Self {
-        Self { delta_time, data }
+    pub fn new(delta_time: u64, data: LocalEvent) -> Self {
+        Self {
+            delta_time,
+            data,
+            track: None,
+        }
     }
 }
 
+/// Counts the notes started, leaving out note ons with a velocity of zero.
+pub fn count_notes(events: &[DataEvent]) -> usize {
+    events
+        .iter()
+        .filter(|event| match &event.data {
+            LocalEvent::Midi(data) => data[0] & 0xf0 == 0x90 && data[2] > 0,
+            _ => false,
+        })
+        .count()
+}
+
+/// The tracks of a Standard MIDI File merged into a single stream.
+pub struct Sequence {
+    pub division: u64,
+    pub events: Vec<DataEvent>,
+    /// Track names and copyrights.
+    pub track_info: Vec<String>,
+    /// Of the text meta events, such as lyrics.
+    pub encoding: &'static Encoding,
+    /// What to show as playing, from the file's own text or else its name.
+    pub title: String,
+}
+
+/// Parses and merges a Standard MIDI File. Its text is decoded as `encoding`,
+/// or else as detected.
+pub fn load(path: &Path, encoding: Option<&'static Encoding>) -> Result<Sequence> {
+    let midi_data = SMF::from_file(path).context("Failed to parse MIDI file")?;
+
+    if midi_data.division < 0 {
+        return Err(anyhow!("SMPTE division not supported"));
+    }
+
+    let encoding = encoding.unwrap_or_else(|| text::detect(&midi_data.tracks));
+    let title = text::title(&midi_data.tracks, encoding).unwrap_or_else(|| file_title(path));
+    let mut events = None;
+    let mut track_info = Vec::new();
+
+    for (i, track) in midi_data.tracks.into_iter().enumerate() {
+        track_info.push(format!("Track #{}", i + 1));
+
+        if let Some(name) = text::track_name(&track, encoding) {
+            track_info.push(format!("  - Name: {}", name));
+        }
+        if let Some(copyright) = text::track_copyright(&track, encoding) {
+            track_info.push(format!("  - Copyright: {}", copyright));
+        }
+
+        let mut track_events = combine_events(track.events);
+        for event in &mut track_events {
+            event.track = Some(i);
+        }
+
+        if let Some(previous_events) = events.take() {
+            events = Some(merge_tracks(previous_events, track_events));
+        } else {
+            events = Some(track_events);
+        }
+    }
+
+    let events = events.context("No events found")?;
+
+    Ok(Sequence {
+        division: midi_data.division as u64,
+        events,
+        track_info,
+        encoding,
+        title,
+    })
+}
+
+/// The file name without its extension, for files that do not name
+/// themselves.
+pub fn file_title(path: &Path) -> String {
+    match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => path.display().to_string(),
+    }
+}
+
+/// How many bytes of a short message with `status` are sent, as
+/// `LocalEvent::Midi` always holds three.
+pub fn message_length(status: u8) -> usize {
+    match status {
+        0xc0..=0xdf | 0xf1 | 0xf3 => 2,
+        0xf4..=0xff => 1,
+        _ => 3,
+    }
+}
+
+/// Interleaves messages given by absolute tick, sorted, with a delta-timed
+/// event stream, each after the events of its tick.
+pub fn merge_messages(events: Vec<DataEvent>, messages: Vec<(u64, [u8; 3])>) -> Vec<DataEvent> {
+    let mut combined = Vec::with_capacity(events.len() + messages.len());
+    let mut messages = messages.into_iter().peekable();
+    let mut time = 0;
+    let mut last_time = 0;
+
+    for mut event in events {
+        time += event.delta_time;
+
+        while let Some(&(message_time, message)) = messages.peek() {
+            if message_time >= time {
+                break;
+            }
+
+            combined.push(DataEvent::new(
+                message_time - last_time,
+                LocalEvent::Midi(message),
+            ));
+            last_time = message_time;
+            messages.next();
+        }
+
+        event.delta_time = time - last_time;
+        last_time = time;
+        combined.push(event);
+    }
+
+    for (message_time, message) in messages {
+        let message_time = message_time.max(last_time);
+
+        combined.push(DataEvent::new(
+            message_time - last_time,
+            LocalEvent::Midi(message),
+        ));
+        last_time = message_time;
+    }
+
+    combined
+}
+
+/// Turns notes into a delta-timed event stream on `channel`, preceded by a
+/// program change when one is given.
+pub fn sequence_notes(notes: &[Note], channel: u8, program: Option<u8>) -> Vec<DataEvent> {
+    let channel = channel & 0x0f;
+
+    // Note offs sort before note ons sharing the same tick
+    let mut messages = Vec::with_capacity(notes.len() * 2);
+    for note in notes {
+        messages.push((note.time, 1, [0x90 | channel, note.key, note.velocity]));
+        messages.push((note.time + note.length, 0, [0x80 | channel, note.key, 0]));
+    }
+    messages.sort_by_key(|&(time, order, _)| (time, order));
+
+    let mut events = Vec::with_capacity(messages.len() + 1);
+    if let Some(program) = program {
+        events.push(DataEvent::new(
+            0,
+            LocalEvent::Midi([0xc0 | channel, program, 0]),
+        ));
+    }
+
+    let mut last_time = 0;
+    for (time, _, message) in messages {
+        events.push(DataEvent::new(time - last_time, LocalEvent::Midi(message)));
+        last_time = time;
+    }
+
+    events
+}
+
+/// Serializes events as a single track Standard MIDI File, leaving out meta
+/// events of unknown type.
+pub fn write_single_track(division: u16, events: &[TrackEvent]) -> Vec<u8> {
+    let mut track = Vec::new();
+    let mut pending_ticks = 0;
+
+    for event in events {
+        let delta_time = pending_ticks + event.vtime;
+
+        match &event.event {
+            Event::Midi(message) => {
+                write_varlen(&mut track, delta_time);
+
+                // SysEx is stored without its F0, behind its length
+                match message.data.split_first() {
+                    Some((0xf0, data)) => {
+                        track.push(0xf0);
+                        write_varlen(&mut track, data.len() as u64);
+                        track.extend_from_slice(data);
+                    }
+                    _ => track.extend_from_slice(&message.data),
+                };
+            }
+            Event::Meta(meta) => match meta_type(meta.command) {
+                Some(meta_type) => {
+                    write_varlen(&mut track, delta_time);
+                    track.extend_from_slice(&[0xff, meta_type]);
+                    write_varlen(&mut track, meta.data.len() as u64);
+                    track.extend_from_slice(&meta.data);
+                }
+                None => {
+                    pending_ticks = delta_time;
+                    continue;
+                }
+            },
+        };
+
+        pending_ticks = 0;
+    }
+
+    let mut data = Vec::with_capacity(track.len() + 22);
+    data.extend_from_slice(b"MThd");
+    data.extend_from_slice(&6u32.to_be_bytes());
+    data.extend_from_slice(&0u16.to_be_bytes());
+    data.extend_from_slice(&1u16.to_be_bytes());
+    data.extend_from_slice(&division.to_be_bytes());
+    data.extend_from_slice(b"MTrk");
+    data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    data.extend_from_slice(&track);
+
+    data
+}
+
+fn meta_type(command: MetaCommand) -> Option<u8> {
+    match command {
+        MetaCommand::SequenceNumber => Some(0x00),
+        MetaCommand::TextEvent => Some(0x01),
+        MetaCommand::CopyrightNotice => Some(0x02),
+        MetaCommand::SequenceOrTrackName => Some(0x03),
+        MetaCommand::InstrumentName => Some(0x04),
+        MetaCommand::LyricText => Some(0x05),
+        MetaCommand::MarkerText => Some(0x06),
+        MetaCommand::CuePoint => Some(0x07),
+        MetaCommand::MIDIChannelPrefixAssignment => Some(0x20),
+        MetaCommand::MIDIPortPrefixAssignment => Some(0x21),
+        MetaCommand::EndOfTrack => Some(0x2f),
+        MetaCommand::TempoSetting => Some(0x51),
+        MetaCommand::SMPTEOffset => Some(0x54),
+        MetaCommand::TimeSignature => Some(0x58),
+        MetaCommand::KeySignature => Some(0x59),
+        MetaCommand::SequencerSpecificEvent => Some(0x7f),
+        MetaCommand::Unknown => None,
+    }
+}
+
+/// Appends a variable-length quantity as used for SMF delta times and
+/// lengths.
+pub fn write_varlen(data: &mut Vec<u8>, value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    data.extend(bytes.iter().rev());
+}
+
 pub fn combine_tracks(
     track1_events: Vec<TrackEvent>,
     track2_events: Vec<TrackEvent>,
@@ -64,13 +334,57 @@ pub fn combine_tracks(
     combined
 }
 
+/// Merges two delta-timed event streams, the events of `first` going ahead
+/// of those of `second` on the same tick, like `combine_tracks`.
+fn merge_tracks(first: Vec<DataEvent>, second: Vec<DataEvent>) -> Vec<DataEvent> {
+    let mut merged = Vec::with_capacity(first.len() + second.len());
+    let mut first = first.into_iter().peekable();
+    let mut second = second.into_iter().peekable();
+
+    // Ticks into each stream that the merged events reached
+    let (mut first_time, mut second_time) = (0, 0);
+    let mut last_time = 0;
+
+    loop {
+        let take_second = match (first.peek(), second.peek()) {
+            (Some(a), Some(b)) => second_time + b.delta_time < first_time + a.delta_time,
+            (Some(_), None) => false,
+            (None, Some(_)) => true,
+            (None, None) => break,
+        };
+
+        let (mut event, time) = if take_second {
+            match second.next() {
+                Some(event) => {
+                    second_time += event.delta_time;
+                    (event, second_time)
+                }
+                None => break,
+            }
+        } else {
+            match first.next() {
+                Some(event) => {
+                    first_time += event.delta_time;
+                    (event, first_time)
+                }
+                None => break,
+            }
+        };
+
+        event.delta_time = time - last_time;
+        last_time = time;
+        merged.push(event);
+    }
+
+    merged
+}
+
 pub fn combine_events(events: Vec<TrackEvent>) -> Vec<DataEvent> {
     let mut combined = Vec::with_capacity(events.len());
     //let mut current_vtime = 0;
     //let mut current_data = Vec::new();
-    let mut iter = events.into_iter();
 
-    while let Some(event) = iter.next() {
+    for event in events {
         match event.event {
             /*
             Event::Midi(midi_msg) if current_data.is_empty() => {
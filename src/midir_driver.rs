@@ -0,0 +1,121 @@
+use std::sync::mpsc::Sender;
+
+use anyhow::{Context, Result};
+use midir::{Ignore, MidiInputConnection, MidiOutputConnection};
+
+use crate::backend::{MidiInput, MidiOutput};
+
+const CLIENT_NAME: &str = "midi_play";
+
+/// Output port on ALSA (Linux) or CoreMIDI (macOS) through `midir`.
+pub struct MidirPort {
+    connection: MidiOutputConnection,
+}
+
+impl MidiOutput for MidirPort {
+    fn count() -> u32 {
+        match midir::MidiOutput::new(CLIENT_NAME) {
+            Ok(output) => output.port_count() as u32,
+            Err(_) => 0,
+        }
+    }
+
+    fn name(port_number: u32) -> Result<String> {
+        let output =
+            midir::MidiOutput::new(CLIENT_NAME).context("Failed to initialize MIDI output")?;
+        let ports = output.ports();
+        let port = ports
+            .get(port_number as usize)
+            .context("Port number out of range")?;
+
+        output
+            .port_name(port)
+            .context("Failed to retrieve port name")
+    }
+
+    fn connect(port_number: u32) -> Result<Self> {
+        let output =
+            midir::MidiOutput::new(CLIENT_NAME).context("Failed to initialize MIDI output")?;
+        let ports = output.ports();
+        let port = ports
+            .get(port_number as usize)
+            .context("Port number out of range")?;
+        let connection = output
+            .connect(port, CLIENT_NAME)
+            .map_err(|e| anyhow!("Failed to create MIDI output port: {}", e))?;
+
+        Ok(Self { connection })
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        if message.is_empty() {
+            eprintln!("Attempted to send empty message");
+
+            return Ok(());
+        }
+
+        self.connection
+            .send(message)
+            .context("Failed to send message")
+    }
+}
+
+impl Drop for MidirPort {
+    fn drop(&mut self) {
+        // Reset so other applications do not inherit our state
+        if let Err(e) = self.send_reset().context("Failed to send reset") {
+            eprintln!("{:?}", e);
+        }
+    }
+}
+
+pub struct MidirInput {
+    #[allow(unused)]
+    connection: MidiInputConnection<()>,
+}
+
+impl MidiInput for MidirInput {
+    fn count() -> u32 {
+        match midir::MidiInput::new(CLIENT_NAME) {
+            Ok(input) => input.port_count() as u32,
+            Err(_) => 0,
+        }
+    }
+
+    fn name(port_number: u32) -> Result<String> {
+        let input =
+            midir::MidiInput::new(CLIENT_NAME).context("Failed to initialize MIDI input")?;
+        let ports = input.ports();
+        let port = ports
+            .get(port_number as usize)
+            .context("Port number out of range")?;
+
+        input
+            .port_name(port)
+            .context("Failed to retrieve port name")
+    }
+
+    fn connect(port_number: u32, sender: Sender<Vec<u8>>) -> Result<Self> {
+        let mut input =
+            midir::MidiInput::new(CLIENT_NAME).context("Failed to initialize MIDI input")?;
+        input.ignore(Ignore::None);
+
+        let ports = input.ports();
+        let port = ports
+            .get(port_number as usize)
+            .context("Port number out of range")?;
+        let connection = input
+            .connect(
+                port,
+                CLIENT_NAME,
+                move |_, message, _| {
+                    // The receiving side going away is not something we can report from here
+                    let _ = sender.send(message.to_vec());
+                },
+                (),
+            )
+            .map_err(|e| anyhow!("Failed to create MIDI input port: {}", e))?;
+
+        Ok(Self { connection })
+    }
+}
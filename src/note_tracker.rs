@@ -0,0 +1,107 @@
+/// Follows the notes sounding on each channel as messages go out, counting a
+/// note released under the sustain pedal as sounding until the pedal lifts.
+pub struct NoteTracker {
+    /// Note ons received per key, as files may stack the same note.
+    held: [[u8; 128]; 16],
+    /// Keys released while the sustain pedal was down.
+    sustained: [[bool; 128]; 16],
+    pedal: [bool; 16],
+    voices: [usize; 16],
+}
+
+impl Default for NoteTracker {
+    fn default() -> Self {
+        Self {
+            held: [[0; 128]; 16],
+            sustained: [[false; 128]; 16],
+            pedal: [false; 16],
+            voices: [0; 16],
+        }
+    }
+}
+
+impl NoteTracker {
+    pub fn process(&mut self, message: &[u8]) {
+        if message.len() < 3 {
+            return;
+        }
+
+        let status = message[0] & 0xf0;
+        let channel = (message[0] & 0x0f) as usize;
+        let key = (message[1] & 0x7f) as usize;
+
+        match status {
+            0x90 if message[2] > 0 => {
+                if self.held[channel][key] == 0 && !self.sustained[channel][key] {
+                    self.voices[channel] += 1;
+                }
+
+                self.held[channel][key] = self.held[channel][key].saturating_add(1);
+                self.sustained[channel][key] = false;
+            }
+            0x80 | 0x90 => {
+                if self.held[channel][key] == 0 {
+                    return;
+                }
+
+                self.held[channel][key] -= 1;
+                if self.held[channel][key] == 0 {
+                    if self.pedal[channel] {
+                        self.sustained[channel][key] = true;
+                    } else {
+                        self.voices[channel] -= 1;
+                    }
+                }
+            }
+            // Sustain pedal
+            0xb0 if message[1] == 64 => {
+                self.pedal[channel] = message[2] >= 64;
+
+                if !self.pedal[channel] {
+                    self.release_sustained(channel);
+                }
+            }
+            // All sound off and all notes off
+            0xb0 if message[1] == 120 || message[1] == 123 => {
+                self.held[channel] = [0; 128];
+                self.sustained[channel] = [false; 128];
+                self.voices[channel] = 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets every note, such as after the output was silenced.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Voices sounding on `channel`, from 0.
+    pub fn voices(&self, channel: usize) -> usize {
+        self.voices[channel]
+    }
+
+    pub fn total(&self) -> usize {
+        self.voices.iter().sum()
+    }
+
+    /// The (channel, key) of every note whose key is still down, leaving out
+    /// those only held by the pedal.
+    pub fn held(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.held.iter().enumerate().flat_map(|(channel, keys)| {
+            keys.iter()
+                .enumerate()
+                .filter(|(_, &count)| count > 0)
+                .map(move |(key, _)| (channel as u8, key as u8))
+        })
+    }
+
+    fn release_sustained(&mut self, channel: usize) {
+        for key in 0..128 {
+            if self.sustained[channel][key] {
+                self.sustained[channel][key] = false;
+                self.voices[channel] -= 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,1166 @@
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+
+use midi_play::automation::Ramp;
+use midi_play::backend::{Backend, Reset};
+use midi_play::capture::{self, CaptureSettings};
+use midi_play::channels::ChannelMapping;
+use midi_play::chimes::ChimeSettings;
+use midi_play::config::Config;
+use midi_play::dump::DumpSettings;
+use midi_play::filter::MessageClass;
+use midi_play::generate::{self, EuclidLayer, GenerateSettings, Mode, Pattern};
+use midi_play::hud;
+use midi_play::humanize::Humanize;
+use midi_play::metronome::{Click, CountIn};
+use midi_play::osc::BridgeFormat;
+use midi_play::player::Engine;
+use midi_play::polyphony::{self, PolyphonyLimit, Steal};
+use midi_play::position::Position;
+use midi_play::profile::{self, Profile};
+use midi_play::programs::ProgramOverride;
+use midi_play::routing::Route;
+use midi_play::safety::SafetyLimits;
+use midi_play::search::Query;
+use midi_play::setlist::SetList;
+use midi_play::syx;
+use midi_play::text;
+use midi_play::timecode::FrameRate;
+use midi_play::velocity::VelocityCurve;
+
+use crate::keyboard::KeyboardSettings;
+
+/// How played events are printed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Output {
+    Text,
+    /// One JSON object per line on stdout, everything else going to stderr.
+    Json,
+}
+
+impl Output {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            _ => Err(anyhow!("Unknown output format: {}", value)),
+        }
+    }
+}
+
+/// What the `settings` subcommand does with its bundle.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SettingsAction {
+    Export,
+    Import,
+}
+
+pub struct Options {
+    pub backend: Backend,
+    /// Played by the synth backend, from `--soundfont`.
+    pub soundfont: Option<PathBuf>,
+    /// How late BLE devices sound, from `--ble-latency`.
+    pub ble_latency: Option<Duration>,
+    /// RTP-MIDI hosts listed as ports, from `--rtp-midi`.
+    pub rtp_sessions: Vec<String>,
+    /// Sent to every port when opened and closed.
+    pub reset: Reset,
+    pub engine: Engine,
+    pub output: Output,
+    /// Print every played event in text output instead of a status line.
+    pub verbose: bool,
+    /// Output ports to play on instead of the last one. Every file is layered
+    /// on all of them, chimes and generated material go to the first.
+    pub ports: Vec<u32>,
+    /// Parts of port names, matched case-insensitively, for ports to play on
+    /// along with `ports`.
+    pub port_names: Vec<String>,
+    /// Start every file at once, each on the next port in turn.
+    pub together: bool,
+    /// Second port for A/B comparisons, switched to with Enter.
+    pub compare_port: Option<u32>,
+    /// Input port whose events are forwarded to the output port.
+    pub thru_port: Option<u32>,
+    /// Player-piano limits, enabled by `--piano-safety` or any of its overrides.
+    pub safety: Option<SafetyLimits>,
+    /// Westminster chimes every quarter hour, enabled by `--chimes`.
+    pub chimes: Option<ChimeSettings>,
+    /// Set by the `generate <pattern>` subcommand.
+    pub generate: Option<GenerateSettings>,
+    /// Pause between the messages of `.syx` files.
+    pub syx_delay: Duration,
+    /// Show a compact status line of title, bar, time and next marker in
+    /// place of the played events.
+    pub hud: bool,
+    /// Shortest time between HUD renders, from `--hud-rate`.
+    pub hud_interval: Duration,
+    /// Take over the terminal with the playlist, progress, channel meters
+    /// and event log, drawn at the HUD rate.
+    pub tui: bool,
+    /// File kept holding the last lines of the lyrics, for showing them on
+    /// another screen, such as through a streaming text source.
+    pub lyrics_file: Option<PathBuf>,
+    /// Songs to play with the cues for the band, from `--setlist`.
+    pub set_list: Option<SetList>,
+    /// Playlist played over and over from startup, relaunched by a watchdog
+    /// whenever it fails, set by `--autoplay-on-start`.
+    pub autoplay: Option<PathBuf>,
+    /// File every command is logged to, with who sent it and whether the
+    /// transport lock let it through.
+    pub audit_log: Option<PathBuf>,
+    /// File every event and message is written to, from `--log-file`, and
+    /// the size it is rotated at.
+    pub log_file: Option<PathBuf>,
+    pub log_rotate: Option<u64>,
+    /// Port the cue notes of the set list go to, on `cue_channel`.
+    pub cue_port: Option<u32>,
+    /// From 0.
+    pub cue_channel: u8,
+    /// Stop every file after this long and move on to the next one.
+    pub preview: Option<Duration>,
+    /// How long to keep reopening an output that failed mid-song.
+    pub reconnect: Option<Duration>,
+    /// Port name to continue on when the failed output does not come back.
+    pub fallback_port: Option<String>,
+    /// Turn the volume down for this long when stopped mid-song.
+    pub fade_out: Option<Duration>,
+    /// Controller faded out, volume (7) or expression (11).
+    pub fade_controller: u8,
+    /// Pause after every SysEx message of a MIDI file, from
+    /// `--sysex-delay-ms`, which also spaces out `.syx` files.
+    pub sysex_delay: Option<Duration>,
+    /// Wait after opening a port and resetting it, from `--lead-in`.
+    pub lead_in: Option<Duration>,
+    /// Bars of clicks before every song, from `--count-in`.
+    pub count_in: Option<CountIn>,
+    /// Clicks on every beat, from `--metronome`.
+    pub metronome: Option<Click>,
+    /// Send MIDI clock, start, stop and continue along with playback.
+    pub clock: bool,
+    /// Frame rate of the MIDI Time Code sent along with playback, from
+    /// `--mtc`.
+    pub timecode: Option<FrameRate>,
+    /// Input port of a clock master to follow, from `--sync-input`.
+    pub sync_port: Option<u32>,
+    /// Classes of messages left out of MIDI files, from `--drop` and
+    /// `--no-sysex`.
+    pub drop: Vec<MessageClass>,
+    /// Random bounds of note timing and velocity, from `--humanize`.
+    pub humanize: Option<Humanize>,
+    /// How note velocities are reshaped, from `--velocity-curve`.
+    pub velocity_curve: Option<VelocityCurve>,
+    /// Cap on the notes held at once, from `--max-polyphony` and `--steal`.
+    pub polyphony_limit: Option<PolyphonyLimit>,
+    /// Tracks and channels sent to ports of their own, from `--route`.
+    pub routes: Vec<Route>,
+    /// Ports every message is copied to as well, from `--also-port`.
+    pub also_ports: Vec<u32>,
+    /// Host every played event is sent to, from `--osc`, and how.
+    pub osc: Option<String>,
+    pub osc_format: BridgeFormat,
+    /// Address browser pages connect to for the events, from `--ws-listen`.
+    pub ws_listen: Option<String>,
+    /// Address remote control requests are taken on, from `--http-listen`.
+    pub http_listen: Option<String>,
+    /// What remote commands have to carry, from `--http-token`.
+    pub http_token: Option<String>,
+    /// Hand the files to an instance already running, from
+    /// `--single-instance`.
+    pub single_instance: bool,
+    /// Where every MIDI file starts and ends, from `--start` and `--end`.
+    pub start: Option<Position>,
+    pub end: Option<Position>,
+    /// Part of every MIDI file played over and over, from `--loop-section`.
+    pub section: Option<(Position, Position)>,
+    /// Port every message is mirrored to, taking over when the output fails.
+    pub backup_port: Option<u32>,
+    /// Encoding of track names and lyrics, instead of guessing it.
+    pub text_encoding: Option<&'static Encoding>,
+    /// List of files that failed to load, which are skipped from then on.
+    pub quarantine: Option<PathBuf>,
+    /// Print the quarantine list instead of playing.
+    pub show_quarantine: bool,
+    /// Skip MIDI files with fewer notes than this, such as ones holding only
+    /// meta events.
+    pub min_notes: usize,
+    /// Playlist settings given on the command line, written back to the
+    /// settings file on exit. `None` removes the setting.
+    pub remembered: Vec<(&'static str, Option<String>)>,
+    /// Parse the next file ahead and keep the port open between files.
+    pub gapless: bool,
+    /// Pause between files, from `--gap`.
+    pub gap: Duration,
+    /// Rewrite wrong Roland and Yamaha checksums in `.syx` files before sending.
+    pub fix_checksums: bool,
+    /// Set by the `keyboard` subcommand.
+    pub keyboard: Option<KeyboardSettings>,
+    /// Describe the files instead of playing them, set by the `inspect`
+    /// subcommand.
+    pub inspect: bool,
+    /// Check the files for structural problems instead of playing them, set
+    /// by the `validate` subcommand.
+    pub validate: bool,
+    /// List the events of the files matching this instead of playing them,
+    /// set by `--find`.
+    pub find: Option<Query>,
+    /// Print the channel state at every bar of the file as JSON, set by the
+    /// `timeline <file>` subcommand.
+    pub timeline: Option<PathBuf>,
+    /// Input and output of the `convert <input> <output>` subcommand.
+    pub convert: Option<(PathBuf, PathBuf)>,
+    /// Set by the `settings export|import <bundle>` subcommand.
+    pub settings: Option<(SettingsAction, PathBuf)>,
+    /// File to save to and how to listen, set by the `capture <file>` subcommand.
+    pub capture: Option<(PathBuf, CaptureSettings)>,
+    /// File to send packet by packet and how, set by the `dump <file>`
+    /// subcommand.
+    pub dump: Option<(PathBuf, DumpSettings)>,
+    /// Voices of the target module, for the polyphony report.
+    pub voice_limit: usize,
+    /// Print the note usage of every song once it finishes.
+    pub heatmap: bool,
+    /// Keys the device can play, flagged in the heatmap when exceeded.
+    pub key_range: Option<RangeInclusive<u8>>,
+    /// Device settings loaded by `--profile`.
+    pub profile: Option<Profile>,
+    /// Controller ramps from `--ramp`, on top of those of the profile.
+    pub ramps: Vec<Ramp>,
+    /// Channels of the files moved to others by `--map-channel`.
+    pub channel_map: Vec<ChannelMapping>,
+    /// Programs forced on channels by `--program <channel>:<program>`.
+    pub programs: Vec<ProgramOverride>,
+    pub files: Vec<PathBuf>,
+}
+
+impl Options {
+    /// Parses the command line, on top of the defaults of `config`.
+    pub fn from_args(config: &Config) -> Result<Self> {
+        let mut options = Self {
+            backend: Backend::Native,
+            soundfont: None,
+            ble_latency: None,
+            rtp_sessions: Vec::new(),
+            reset: Reset::GsGm,
+            engine: Engine::Realtime,
+            output: Output::Text,
+            verbose: false,
+            ports: Vec::new(),
+            port_names: Vec::new(),
+            together: false,
+            compare_port: None,
+            thru_port: None,
+            safety: None,
+            chimes: None,
+            generate: None,
+            keyboard: None,
+            inspect: false,
+            validate: false,
+            find: None,
+            timeline: None,
+            convert: None,
+            settings: None,
+            capture: None,
+            dump: None,
+            syx_delay: syx::DEFAULT_DELAY,
+            hud: false,
+            hud_interval: hud::DEFAULT_INTERVAL,
+            tui: false,
+            lyrics_file: None,
+            set_list: None,
+            autoplay: None,
+            audit_log: None,
+            log_file: None,
+            log_rotate: None,
+            cue_port: None,
+            cue_channel: 15,
+            preview: None,
+            min_notes: 1,
+            remembered: Vec::new(),
+            gapless: false,
+            gap: Duration::ZERO,
+            reconnect: None,
+            fallback_port: None,
+            backup_port: None,
+            fade_out: None,
+            fade_controller: 11,
+            sysex_delay: None,
+            lead_in: None,
+            count_in: None,
+            metronome: None,
+            clock: false,
+            timecode: None,
+            sync_port: None,
+            drop: Vec::new(),
+            humanize: None,
+            velocity_curve: None,
+            polyphony_limit: None,
+            routes: Vec::new(),
+            also_ports: Vec::new(),
+            osc: None,
+            osc_format: BridgeFormat::Osc,
+            ws_listen: None,
+            http_listen: None,
+            http_token: None,
+            single_instance: false,
+            start: None,
+            end: None,
+            section: None,
+            text_encoding: None,
+            quarantine: None,
+            show_quarantine: false,
+            fix_checksums: false,
+            voice_limit: polyphony::DEFAULT_VOICE_LIMIT,
+            heatmap: false,
+            key_range: None,
+            profile: None,
+            ramps: Vec::new(),
+            channel_map: Vec::new(),
+            programs: Vec::new(),
+            files: Vec::new(),
+        };
+        let mut profile_path = None;
+        let mut profile_values = Vec::new();
+
+        options
+            .apply_config(config, &mut profile_path)
+            .with_context(|| format!("Invalid settings {}", config.path.display()))?;
+
+        // Ports named by the settings only count when the command line names none
+        let config_port_names = mem::take(&mut options.port_names);
+
+        let mut args = env::args_os().skip(1).peekable();
+
+        if args.peek().and_then(|arg| arg.to_str()) == Some("generate") {
+            args.next();
+
+            let pattern = next_value(&mut args, "generate")?;
+            options.generate = Some(GenerateSettings::new(Pattern::parse(&pattern)?));
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("send-syx") {
+            // Only an alias for playing the files, which can be given anyway
+            args.next();
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("keyboard") {
+            args.next();
+
+            options.keyboard = Some(KeyboardSettings::default());
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("inspect") {
+            args.next();
+
+            options.inspect = true;
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("validate") {
+            args.next();
+
+            options.validate = true;
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("timeline") {
+            args.next();
+
+            options.timeline = Some(PathBuf::from(next_value(&mut args, "timeline")?));
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("convert") {
+            args.next();
+
+            let input = PathBuf::from(next_value(&mut args, "convert")?);
+            let output = PathBuf::from(next_value(&mut args, "convert")?);
+            options.convert = Some((input, output));
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("settings") {
+            args.next();
+
+            let action = match next_value(&mut args, "settings")?.as_str() {
+                "export" => SettingsAction::Export,
+                "import" => SettingsAction::Import,
+                action => return Err(anyhow!("Unknown settings action: {}", action)),
+            };
+            let path = PathBuf::from(next_value(&mut args, "settings")?);
+            options.settings = Some((action, path));
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("capture") {
+            args.next();
+
+            let path = PathBuf::from(next_value(&mut args, "capture")?);
+            options.capture = Some((path, CaptureSettings::default()));
+        } else if args.peek().and_then(|arg| arg.to_str()) == Some("dump") {
+            args.next();
+
+            let path = PathBuf::from(next_value(&mut args, "dump")?);
+            options.dump = Some((path, DumpSettings::default()));
+        }
+
+        let mut click = Click::default();
+        let mut steal = Steal::Oldest;
+
+        while let Some(arg) = args.next() {
+            match arg.to_str() {
+                Some("--backend") => {
+                    let backend = next_value(&mut args, "--backend")?;
+                    options.backend = Backend::parse(&backend)?;
+                }
+                Some("--soundfont") => {
+                    options.soundfont = Some(PathBuf::from(next_value(&mut args, "--soundfont")?));
+                }
+                Some("--ble-latency") => {
+                    let value = next_value(&mut args, "--ble-latency")?;
+                    options.ble_latency = Some(parse_duration(&value)?);
+                }
+                Some("--rtp-midi") => {
+                    let host = next_value(&mut args, "--rtp-midi")?;
+                    options.rtp_sessions.push(host);
+                }
+                Some("--reset") => {
+                    let reset = next_value(&mut args, "--reset")?;
+                    options.reset = Reset::parse(&reset)?;
+                }
+                Some("--engine") => {
+                    let engine = next_value(&mut args, "--engine")?;
+                    options.engine = Engine::parse(&engine)?;
+                }
+                Some("--output") => {
+                    let output = next_value(&mut args, "--output")?;
+                    options.output = Output::parse(&output)?;
+                }
+                Some("--verbose") => options.verbose = true,
+                Some("--port") => {
+                    options.ports.push(parse_value(&mut args, "--port")?);
+                }
+                Some("--port-name") => {
+                    options
+                        .port_names
+                        .push(next_value(&mut args, "--port-name")?);
+                }
+                Some("--together") => {
+                    options.together = true;
+                }
+                Some("--compare") => {
+                    options.compare_port = Some(parse_value(&mut args, "--compare")?);
+                }
+                Some("--thru") => {
+                    options.thru_port = Some(parse_value(&mut args, "--thru")?);
+                }
+                Some("--piano-safety") => {
+                    options.safety.get_or_insert_with(SafetyLimits::default);
+                }
+                Some("--max-notes") => {
+                    let max_notes = parse_value(&mut args, "--max-notes")?;
+                    options.safety_limits().max_notes = max_notes;
+                }
+                Some("--min-repeat-ms") => {
+                    let millis = parse_value(&mut args, "--min-repeat-ms")?;
+                    options.safety_limits().min_repeat_interval = Duration::from_millis(millis);
+                }
+                Some("--max-velocity") => {
+                    let max_velocity = parse_value(&mut args, "--max-velocity")?;
+                    options.safety_limits().max_velocity = max_velocity;
+                }
+                Some("--forbid-notes") => {
+                    let value = next_value(&mut args, "--forbid-notes")?;
+                    options.safety_limits().forbidden.push(parse_range(&value)?);
+                }
+                Some("--delay") => {
+                    let value = next_value(&mut args, "--delay")?;
+                    options.syx_delay = parse_duration(&value)?;
+                }
+                Some("--sysex-delay-ms") => {
+                    let millis = parse_value(&mut args, "--sysex-delay-ms")?;
+                    options.syx_delay = Duration::from_millis(millis);
+                    options.sysex_delay = Some(options.syx_delay);
+                }
+                Some("--lead-in") => {
+                    let value = next_value(&mut args, "--lead-in")?;
+                    options.lead_in = Some(parse_duration(&value)?);
+                }
+                Some("--count-in") => {
+                    let bars = parse_value(&mut args, "--count-in")?;
+                    if bars == 0 {
+                        return Err(anyhow!("--count-in needs at least one bar"));
+                    }
+                    options.count_in = Some(CountIn { bars, click });
+                }
+                Some("--clock") => {
+                    options.clock = true;
+                }
+                Some("--mtc") => {
+                    let rate = next_value(&mut args, "--mtc")?;
+                    options.timecode = Some(FrameRate::parse(&rate)?);
+                }
+                Some("--sync-input") => {
+                    options.sync_port = Some(parse_value(&mut args, "--sync-input")?);
+                }
+                Some("--metronome") => {
+                    options.metronome = Some(click);
+                }
+                Some("--click-note") => {
+                    click.note = parse_value(&mut args, "--click-note")?;
+                    if click.note > 127 {
+                        return Err(anyhow!("Note must be between 0 and 127"));
+                    }
+                }
+                Some("--click-velocity") => {
+                    click.velocity = parse_value(&mut args, "--click-velocity")?;
+                    if !(1..=127).contains(&click.velocity) {
+                        return Err(anyhow!("Velocity must be between 1 and 127"));
+                    }
+                }
+                Some("--click-channel") => {
+                    let channel: u8 = parse_value(&mut args, "--click-channel")?;
+                    if !(1..=16).contains(&channel) {
+                        return Err(anyhow!("Channel must be between 1 and 16"));
+                    }
+                    click.channel = channel - 1;
+                }
+                Some("--humanize") => {
+                    let value = next_value(&mut args, "--humanize")?;
+                    options.humanize = Some(Humanize::parse(&value)?);
+                }
+                Some("--velocity-curve") => {
+                    let value = next_value(&mut args, "--velocity-curve")?;
+                    options.velocity_curve = Some(VelocityCurve::parse(&value)?);
+                }
+                Some("--max-polyphony") => {
+                    let voices = parse_value(&mut args, "--max-polyphony")?;
+                    if voices == 0 {
+                        return Err(anyhow!("--max-polyphony needs at least one voice"));
+                    }
+                    options.polyphony_limit = Some(PolyphonyLimit { voices, steal });
+                }
+                Some("--steal") => {
+                    steal = Steal::parse(&next_value(&mut args, "--steal")?)?;
+                }
+                Some("--no-sysex") => {
+                    options.drop.push(MessageClass::SysEx);
+                }
+                Some("--drop") => {
+                    let value = next_value(&mut args, "--drop")?;
+                    options.drop.extend(MessageClass::parse_list(&value)?);
+                }
+                Some("--start") => {
+                    options.start = Some(Position::parse(&next_value(&mut args, "--start")?)?);
+                }
+                Some("--start-marker") => {
+                    let marker = next_value(&mut args, "--start-marker")?;
+                    options.start = Some(Position::Marker(marker));
+                }
+                Some("--end") => {
+                    options.end = Some(Position::parse(&next_value(&mut args, "--end")?)?);
+                }
+                Some("--loop-section") => {
+                    let from = next_value(&mut args, "--loop-section")?;
+                    let to = next_value(&mut args, "--loop-section")?;
+                    options.section = Some((Position::parse(&from)?, Position::parse(&to)?));
+                }
+                Some("--hud") => {
+                    options.hud = true;
+                }
+                Some("--tui") => options.tui = true,
+                Some("--hud-rate") => {
+                    let rate: u32 = parse_value(&mut args, "--hud-rate")?;
+                    if !(1..=1000).contains(&rate) {
+                        return Err(anyhow!("--hud-rate must be from 1 to 1000 per second"));
+                    }
+                    options.hud_interval = Duration::from_secs(1) / rate;
+                }
+                Some("--lyrics-file") => {
+                    options.lyrics_file =
+                        Some(PathBuf::from(next_value(&mut args, "--lyrics-file")?));
+                }
+                Some("--setlist") => {
+                    let path = PathBuf::from(next_value(&mut args, "--setlist")?);
+                    let set_list = SetList::load(&path)?;
+
+                    options
+                        .files
+                        .extend(set_list.songs.iter().map(|song| song.path.clone()));
+                    options.set_list = Some(set_list);
+                }
+                Some("--autoplay-on-start") => {
+                    options.autoplay =
+                        Some(PathBuf::from(next_value(&mut args, "--autoplay-on-start")?));
+                }
+                Some("--audit-log") => {
+                    options.audit_log = Some(PathBuf::from(next_value(&mut args, "--audit-log")?));
+                }
+                Some("--log-file") => {
+                    options.log_file = Some(PathBuf::from(next_value(&mut args, "--log-file")?));
+                }
+                Some("--log-rotate") => {
+                    options.log_rotate = Some(parse_size(&next_value(&mut args, "--log-rotate")?)?);
+                }
+                Some("--cue-port") => {
+                    options.cue_port = Some(parse_value(&mut args, "--cue-port")?);
+                }
+                Some("--cue-channel") => {
+                    let channel: u8 = parse_value(&mut args, "--cue-channel")?;
+                    if !(1..=16).contains(&channel) {
+                        return Err(anyhow!("Cue channel must be between 1 and 16"));
+                    }
+
+                    options.cue_channel = channel - 1;
+                }
+                Some("--preview") => {
+                    let value = next_value(&mut args, "--preview")?;
+                    options.preview = Some(parse_duration(&value)?);
+                    options.remembered.push(("preview", Some(value)));
+                }
+                Some("--no-preview") => {
+                    options.preview = None;
+                    options.remembered.push(("preview", None));
+                }
+                Some("--reconnect") => {
+                    let value = next_value(&mut args, "--reconnect")?;
+                    options.reconnect = Some(parse_duration(&value)?);
+                }
+                Some("--fallback-port") => {
+                    options.fallback_port = Some(next_value(&mut args, "--fallback-port")?);
+                }
+                Some("--fade-out") => {
+                    let value = next_value(&mut args, "--fade-out")?;
+                    options.fade_out = Some(parse_duration(&value)?);
+                }
+                Some("--fade-controller") => {
+                    options.fade_controller =
+                        match next_value(&mut args, "--fade-controller")?.as_str() {
+                            "7" | "volume" => 7,
+                            "11" | "expression" => 11,
+                            value => return Err(anyhow!("Cannot fade with controller {}", value)),
+                        };
+                }
+                Some("--route") => {
+                    let value = next_value(&mut args, "--route")?;
+                    options.routes.push(Route::parse(&value)?);
+                }
+                Some("--also-port") => {
+                    let port_id = parse_value(&mut args, "--also-port")?;
+                    if !options.also_ports.contains(&port_id) {
+                        options.also_ports.push(port_id);
+                    }
+                }
+                Some("--osc") => options.osc = Some(next_value(&mut args, "--osc")?),
+                Some("--osc-format") => {
+                    let format = next_value(&mut args, "--osc-format")?;
+                    options.osc_format = BridgeFormat::parse(&format)?;
+                }
+                Some("--ws-listen") => {
+                    options.ws_listen = Some(next_value(&mut args, "--ws-listen")?);
+                }
+                Some("--http-listen") => {
+                    options.http_listen = Some(next_value(&mut args, "--http-listen")?);
+                }
+                Some("--http-token") => {
+                    options.http_token = Some(next_value(&mut args, "--http-token")?);
+                }
+                Some("--backup-port") => {
+                    options.backup_port = Some(parse_value(&mut args, "--backup-port")?);
+                }
+                Some("--encoding") => {
+                    let value = next_value(&mut args, "--encoding")?;
+                    options.text_encoding = Some(text::parse_encoding(&value)?);
+                }
+                Some("--quarantine") => {
+                    options.quarantine =
+                        Some(PathBuf::from(next_value(&mut args, "--quarantine")?));
+                }
+                Some("--show-quarantine") => {
+                    options.show_quarantine = true;
+                }
+                Some("--find") => {
+                    let value = next_value(&mut args, "--find")?;
+                    options.find = Some(Query::parse(&value)?);
+                }
+                Some("--min-notes") => {
+                    options.min_notes = parse_value(&mut args, "--min-notes")?;
+                    options
+                        .remembered
+                        .push(("min-notes", Some(options.min_notes.to_string())));
+                }
+                Some("--gapless") => {
+                    options.gapless = true;
+                }
+                Some("--gap") => {
+                    let value = next_value(&mut args, "--gap")?;
+                    options.gap = parse_duration(&value)?;
+                }
+                Some("--fix-checksums") => {
+                    options.fix_checksums = true;
+                }
+                Some("--single-instance") => options.single_instance = true,
+                Some("--profile") => {
+                    profile_path = Some(PathBuf::from(next_value(&mut args, "--profile")?));
+                }
+                Some("--set") => {
+                    let value = next_value(&mut args, "--set")?;
+                    profile_values.push(profile::parse_override(&value)?);
+                }
+                Some("--ramp") => {
+                    let value = next_value(&mut args, "--ramp")?;
+                    options.ramps.push(profile::parse_ramp(&value)?);
+                }
+                Some("--heatmap") => {
+                    options.heatmap = true;
+                }
+                Some("--key-range") => {
+                    let value = next_value(&mut args, "--key-range")?;
+                    options.key_range = Some(parse_range(&value)?);
+                }
+                Some("--voice-limit") => {
+                    options.voice_limit = parse_value(&mut args, "--voice-limit")?;
+                }
+                Some("--chimes") => {
+                    options.chime_settings();
+                }
+                Some("--chime-channel") => {
+                    let channel: u8 = parse_value(&mut args, "--chime-channel")?;
+                    if !(1..=16).contains(&channel) {
+                        return Err(anyhow!("Chime channel must be between 1 and 16"));
+                    }
+
+                    options.chime_settings().channel = channel - 1;
+                }
+                Some("--chime-program") => {
+                    let program = parse_value(&mut args, "--chime-program")?;
+                    if program > 127 {
+                        return Err(anyhow!("Program must be between 0 and 127"));
+                    }
+                    options.chime_settings().program = program;
+                }
+                Some("--no-hour-strikes") => {
+                    options.chime_settings().strike_hours = false;
+                }
+                Some("--input") => {
+                    let input_port = parse_value(&mut args, "--input")?;
+                    match (&mut options.capture, &mut options.dump) {
+                        (Some((_, settings)), _) => settings.input_port = input_port,
+                        (_, Some((_, settings))) => settings.input_port = input_port,
+                        _ => return Err(anyhow!("--input is only valid with `capture` or `dump`")),
+                    };
+                }
+                Some("--request") => {
+                    let request = next_value(&mut args, "--request")?;
+                    options.capture_settings("--request")?.request =
+                        Some(capture::parse_request(&request)?);
+                }
+                Some("--idle") => {
+                    let value = next_value(&mut args, "--idle")?;
+                    options.capture_settings("--idle")?.idle = parse_duration(&value)?;
+                }
+                Some("--packet-size") => {
+                    let size: usize = parse_value(&mut args, "--packet-size")?;
+                    if size == 0 {
+                        return Err(anyhow!("--packet-size must be at least 1"));
+                    }
+                    options.dump_settings("--packet-size")?.packet_size = Some(size);
+                }
+                Some("--address-bytes") => {
+                    let bytes = parse_value(&mut args, "--address-bytes")?;
+                    if !(1..=4).contains(&bytes) {
+                        return Err(anyhow!("--address-bytes must be from 1 to 4"));
+                    }
+                    options.dump_settings("--address-bytes")?.address_bytes = bytes;
+                }
+                Some("--retries") => {
+                    let retries = parse_value(&mut args, "--retries")?;
+                    options.dump_settings("--retries")?.retries = retries;
+                }
+                Some("--ack-timeout") => {
+                    let value = next_value(&mut args, "--ack-timeout")?;
+                    options.dump_settings("--ack-timeout")?.timeout = parse_duration(&value)?;
+                }
+                Some("--root") => {
+                    let root = next_value(&mut args, "--root")?;
+                    options.generate_settings("--root")?.root = generate::parse_note(&root)?;
+                }
+                Some("--mode") => {
+                    let mode = next_value(&mut args, "--mode")?;
+                    options.generate_settings("--mode")?.mode = Mode::parse(&mode)?;
+                }
+                Some("--bpm") => {
+                    let bpm = parse_value(&mut args, "--bpm")?;
+                    options.generate_settings("--bpm")?.bpm = bpm;
+                }
+                Some("--octaves") => {
+                    let octaves = parse_value(&mut args, "--octaves")?;
+                    options.generate_settings("--octaves")?.octaves = octaves;
+                }
+                Some("--octave") => {
+                    let octave: u8 = parse_value(&mut args, "--octave")?;
+                    if octave > 8 {
+                        return Err(anyhow!("Octave must be between 0 and 8"));
+                    }
+
+                    options.keyboard_settings("--octave")?.octave = octave;
+                }
+                Some("--channel") => {
+                    let channel: u8 = parse_value(&mut args, "--channel")?;
+                    if !(1..=16).contains(&channel) {
+                        return Err(anyhow!("Channel must be between 1 and 16"));
+                    }
+
+                    match &mut options.keyboard {
+                        Some(keyboard) => keyboard.channel = channel - 1,
+                        None => options.generate_settings("--channel")?.channel = channel - 1,
+                    };
+                }
+                Some("--velocity") => {
+                    let velocity = parse_value(&mut args, "--velocity")?;
+                    if !(1..=127).contains(&velocity) {
+                        return Err(anyhow!("Velocity must be between 1 and 127"));
+                    }
+                    match &mut options.keyboard {
+                        Some(keyboard) => keyboard.velocity = velocity,
+                        None => options.generate_settings("--velocity")?.velocity = velocity,
+                    };
+                }
+                Some("--map-channel") => {
+                    let value = next_value(&mut args, "--map-channel")?;
+                    options.channel_map.push(ChannelMapping::parse(&value)?);
+                }
+                Some("--program") => {
+                    let value = next_value(&mut args, "--program")?;
+
+                    // A channel in front overrides the file's programs
+                    if value.contains(':') {
+                        options.programs.push(ProgramOverride::parse(&value)?);
+                    } else {
+                        let program = value
+                            .parse()
+                            .with_context(|| format!("Invalid value for --program: {}", value))?;
+                        if program > 127 {
+                            return Err(anyhow!("Program must be between 0 and 127"));
+                        }
+                        match &mut options.keyboard {
+                            Some(keyboard) => keyboard.program = Some(program),
+                            None => options.generate_settings("--program")?.program = Some(program),
+                        };
+                    }
+                }
+                Some("--length") => {
+                    let length = parse_value(&mut args, "--length")?;
+                    options.generate_settings("--length")?.length = length;
+                }
+                Some("--layer") => {
+                    let layer = next_value(&mut args, "--layer")?;
+                    let layer = EuclidLayer::parse(&layer)?;
+                    options.generate_settings("--layer")?.layers.push(layer);
+                }
+                Some("--subdivision") => {
+                    let subdivision = parse_value(&mut args, "--subdivision")?;
+                    options.generate_settings("--subdivision")?.subdivision = subdivision;
+                }
+                _ if options.generate.is_some()
+                    || options.keyboard.is_some()
+                    || options.timeline.is_some()
+                    || options.convert.is_some()
+                    || options.settings.is_some()
+                    || options.capture.is_some()
+                    || options.dump.is_some() =>
+                {
+                    return Err(anyhow!("Unexpected argument: {:?}", arg));
+                }
+                _ => options.files.push(PathBuf::from(arg)),
+            }
+        }
+
+        // The click settings count wherever they came on the command line
+        if let Some(count_in) = &mut options.count_in {
+            count_in.click = click;
+        }
+        if options.metronome.is_some() {
+            options.metronome = Some(click);
+        }
+        if let Some(limit) = &mut options.polyphony_limit {
+            limit.steal = steal;
+        }
+
+        #[cfg(windows)]
+        if options.backend == Backend::Synth && options.soundfont.is_none() {
+            return Err(anyhow!(
+                "The synth backend needs a SoundFont, use --soundfont"
+            ));
+        }
+
+        if options.ports.is_empty() && options.port_names.is_empty() {
+            options.port_names = config_port_names;
+        }
+
+        if let Some(path) = &options.autoplay {
+            let playlist = SetList::load(path)?;
+            if playlist.songs.is_empty() {
+                return Err(anyhow!("No songs in {}", path.display()));
+            }
+
+            options
+                .files
+                .extend(playlist.songs.into_iter().map(|song| song.path));
+        }
+
+        match profile_path {
+            Some(path) => options.profile = Some(Profile::load(&path, &profile_values)?),
+            None if !profile_values.is_empty() => {
+                return Err(anyhow!("--set is only valid with --profile"));
+            }
+            None => {}
+        };
+
+        if options.cue_port.is_some() && options.set_list.is_none() {
+            return Err(anyhow!("--cue-port needs a --setlist with cues"));
+        }
+
+        if options.autoplay.is_some() && options.together {
+            return Err(anyhow!("--autoplay-on-start plays one song at a time"));
+        }
+
+        // Only one player can listen to the clock input
+        if options.sync_port.is_some()
+            && (options.together
+                || options.cue_port.is_some()
+                || options.ports.len() + options.port_names.len() > 1)
+        {
+            return Err(anyhow!("--sync-input plays a single song on a single port"));
+        }
+
+        if options.hud && options.output == Output::Json {
+            return Err(anyhow!("--hud needs text output"));
+        }
+
+        if options.tui && (options.hud || options.output == Output::Json) {
+            return Err(anyhow!("--tui takes the place of --hud and --output json"));
+        }
+
+        if options.log_rotate.is_some() && options.log_file.is_none() {
+            return Err(anyhow!("--log-rotate needs a --log-file"));
+        }
+
+        if options.http_token.is_some() && options.http_listen.is_none() {
+            return Err(anyhow!("--http-token needs --http-listen"));
+        }
+
+        if options.fallback_port.is_some() && options.reconnect.is_none() {
+            return Err(anyhow!("--fallback-port needs --reconnect"));
+        }
+
+        if options.show_quarantine && options.quarantine.is_none() {
+            return Err(anyhow!("--show-quarantine needs the --quarantine list"));
+        }
+
+        if options.compare_port.is_some() && options.ports.len() + options.port_names.len() > 1 {
+            return Err(anyhow!("--compare needs a single --port to compare with"));
+        }
+
+        #[cfg(windows)]
+        if options.engine == Engine::Stream {
+            if options.thru_port.is_some() || options.safety.is_some() {
+                return Err(anyhow!(
+                    "The stream engine cannot merge thru input or apply safety limits"
+                ));
+            }
+            if options.sysex_delay.is_some() {
+                return Err(anyhow!(
+                    "The stream engine cannot pause after SysEx messages"
+                ));
+            }
+            if options.sync_port.is_some() {
+                return Err(anyhow!("The stream engine cannot follow a clock master"));
+            }
+            if options.count_in.is_some() {
+                return Err(anyhow!("The stream engine cannot play a count-in"));
+            }
+            if options.start.is_some() || options.end.is_some() {
+                return Err(anyhow!("The stream engine cannot start or end mid-song"));
+            }
+            if options.section.is_some() {
+                return Err(anyhow!("The stream engine cannot loop a section"));
+            }
+            if options.lead_in.is_some() {
+                return Err(anyhow!("The stream engine cannot wait after the reset"));
+            }
+            if !options.routes.is_empty() || !options.also_ports.is_empty() {
+                return Err(anyhow!("The stream engine cannot play on several ports"));
+            }
+            if options.compare_port.is_some() || options.backup_port.is_some() {
+                return Err(anyhow!(
+                    "The stream engine cannot switch ports while playing"
+                ));
+            }
+            if options.clock || options.timecode.is_some() {
+                return Err(anyhow!("The stream engine cannot send clock or time code"));
+            }
+            if options.fade_out.is_some() {
+                return Err(anyhow!("The stream engine cannot fade out when stopped"));
+            }
+            if options.gapless {
+                return Err(anyhow!("The stream engine cannot play files gaplessly"));
+            }
+            if options.reconnect.is_some() {
+                return Err(anyhow!(
+                    "The stream engine cannot reconnect a failed output"
+                ));
+            }
+            if options.set_list.is_some() {
+                return Err(anyhow!(
+                    "The stream engine cannot follow the form of a set list"
+                ));
+            }
+            if options.backend != Backend::Native {
+                return Err(anyhow!("The stream engine requires the WinMM backend"));
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Takes the defaults of the settings file, keyed by the names of the
+    /// matching flags.
+    fn apply_config(&mut self, config: &Config, profile_path: &mut Option<PathBuf>) -> Result<()> {
+        for (key, value) in config.values() {
+            let invalid = || format!("Invalid value for {}: {}", key, value);
+
+            match key.as_str() {
+                "backend" => self.backend = Backend::parse(value)?,
+                "soundfont" => self.soundfont = Some(PathBuf::from(value)),
+                "ble-latency" => self.ble_latency = Some(parse_duration(value)?),
+                "rtp-midi" => self.rtp_sessions.push(value.clone()),
+                "reset" => self.reset = Reset::parse(value)?,
+                "engine" => self.engine = Engine::parse(value)?,
+                "output" => self.output = Output::parse(value)?,
+                "verbose" => self.verbose = value.parse().with_context(invalid)?,
+                "port-name" => self.port_names.push(value.clone()),
+                "delay" => self.syx_delay = parse_duration(value)?,
+                "preview" => self.preview = Some(parse_duration(value)?),
+                "reconnect" => self.reconnect = Some(parse_duration(value)?),
+                "fallback-port" => self.fallback_port = Some(value.clone()),
+                "osc" => self.osc = Some(value.clone()),
+                "osc-format" => self.osc_format = BridgeFormat::parse(value)?,
+                "ws-listen" => self.ws_listen = Some(value.clone()),
+                "http-listen" => self.http_listen = Some(value.clone()),
+                "http-token" => self.http_token = Some(value.clone()),
+                "autoplay-on-start" => self.autoplay = Some(PathBuf::from(value)),
+                "encoding" => self.text_encoding = Some(text::parse_encoding(value)?),
+                "quarantine" => self.quarantine = Some(PathBuf::from(value)),
+                "log-file" => self.log_file = Some(PathBuf::from(value)),
+                "log-rotate" => self.log_rotate = Some(parse_size(value)?),
+                "min-notes" => self.min_notes = value.parse().with_context(invalid)?,
+                "voice-limit" => self.voice_limit = value.parse().with_context(invalid)?,
+                "key-range" => self.key_range = Some(parse_range(value)?),
+                "heatmap" => self.heatmap = value.parse().with_context(invalid)?,
+                "fix-checksums" => self.fix_checksums = value.parse().with_context(invalid)?,
+                "single-instance" => self.single_instance = value.parse().with_context(invalid)?,
+                "tui" => self.tui = value.parse().with_context(invalid)?,
+                "profile" => *profile_path = Some(PathBuf::from(value)),
+                _ => return Err(anyhow!("Unknown setting: {}", key)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn safety_limits(&mut self) -> &mut SafetyLimits {
+        self.safety.get_or_insert_with(SafetyLimits::default)
+    }
+
+    fn chime_settings(&mut self) -> &mut ChimeSettings {
+        self.chimes.get_or_insert_with(ChimeSettings::default)
+    }
+
+    fn keyboard_settings(&mut self, flag: &str) -> Result<&mut KeyboardSettings> {
+        self.keyboard
+            .as_mut()
+            .with_context(|| format!("{} is only valid with `keyboard`", flag))
+    }
+
+    fn capture_settings(&mut self, flag: &str) -> Result<&mut CaptureSettings> {
+        self.capture
+            .as_mut()
+            .map(|(_, settings)| settings)
+            .with_context(|| format!("{} is only valid with `capture`", flag))
+    }
+
+    fn dump_settings(&mut self, flag: &str) -> Result<&mut DumpSettings> {
+        self.dump
+            .as_mut()
+            .map(|(_, settings)| settings)
+            .with_context(|| format!("{} is only valid with `dump`", flag))
+    }
+
+    fn generate_settings(&mut self, flag: &str) -> Result<&mut GenerateSettings> {
+        self.generate
+            .as_mut()
+            .with_context(|| format!("{} is only valid with `generate`", flag))
+    }
+}
+
+fn next_value(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<String> {
+    let value = args
+        .next()
+        .with_context(|| format!("Missing value for {}", flag))?;
+
+    value
+        .into_string()
+        .map_err(|_| anyhow!("Value for {} is not valid Unicode", flag))
+}
+
+/// Parses a duration in milliseconds, with an optional `ms` or `s` suffix.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let invalid = || format!("Invalid duration: {}", value);
+
+    if let Some(millis) = value.strip_suffix("ms") {
+        Ok(Duration::from_millis(millis.parse().with_context(invalid)?))
+    } else if let Some(seconds) = value.strip_suffix('s') {
+        let seconds: f64 = seconds.parse().with_context(invalid)?;
+        Ok(Duration::from_secs_f64(seconds))
+    } else {
+        Ok(Duration::from_millis(value.parse().with_context(invalid)?))
+    }
+}
+
+/// Parses a size in bytes, with an optional `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Result<u64> {
+    let invalid = || format!("Invalid size: {}", value);
+
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, 'K')) | Some((index, 'k')) => (&value[..index], 1 << 10),
+        Some((index, 'M')) | Some((index, 'm')) => (&value[..index], 1 << 20),
+        Some((index, 'G')) | Some((index, 'g')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+    let size = number
+        .parse::<u64>()
+        .with_context(invalid)?
+        .checked_mul(unit)
+        .with_context(invalid)?;
+    if size == 0 {
+        return Err(anyhow!("Size must be more than 0: {}", value));
+    }
+
+    Ok(size)
+}
+
+/// Parses a single key or an inclusive `LOW-HIGH` range of keys.
+fn parse_range(value: &str) -> Result<RangeInclusive<u8>> {
+    let (low, high) = match value.find('-') {
+        Some(index) => (&value[..index], &value[index + 1..]),
+        None => (value, value),
+    };
+    let low: u8 = low
+        .parse()
+        .with_context(|| format!("Invalid note range: {}", value))?;
+    let high: u8 = high
+        .parse()
+        .with_context(|| format!("Invalid note range: {}", value))?;
+
+    Ok(low..=high)
+}
+
+fn parse_value<T>(args: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: Error + Send + Sync + 'static,
+{
+    let value = next_value(args, flag)?;
+
+    value
+        .parse()
+        .with_context(|| format!("Invalid value for {}: {}", flag, value))
+}
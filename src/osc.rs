@@ -0,0 +1,189 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::events::PlayerEvent;
+
+/// How events are put into datagrams.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BridgeFormat {
+    /// An OSC message per event, under `/midi/`.
+    Osc,
+    /// The bytes of each MIDI message as they were sent, nothing else.
+    Raw,
+}
+
+impl BridgeFormat {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "osc" => Ok(BridgeFormat::Osc),
+            "raw" => Ok(BridgeFormat::Raw),
+            _ => Err(anyhow!("Unknown bridge format: {}", value)),
+        }
+    }
+}
+
+/// Sends the events of players to a UDP host as they are played, for
+/// visualizers and lighting software following along.
+#[derive(Clone)]
+pub struct EventBridge {
+    socket: Arc<UdpSocket>,
+    format: BridgeFormat,
+}
+
+/// An argument of an OSC message.
+enum Argument<'a> {
+    Int(i32),
+    Float(f32),
+    Text(&'a str),
+    Blob(&'a [u8]),
+}
+
+impl EventBridge {
+    /// Opens a socket sending to `target`, given as `host:port`.
+    pub fn connect(target: &str, format: BridgeFormat) -> Result<Self> {
+        let address = target
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", target))?
+            .next()
+            .with_context(|| format!("No address for {}", target))?;
+        let bind = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(bind).context("Failed to open socket")?;
+        socket
+            .connect(address)
+            .with_context(|| format!("Failed to connect to {}", target))?;
+
+        Ok(Self {
+            socket: Arc::new(socket),
+            format,
+        })
+    }
+
+    /// Sends `event`, dropping it if the network does not take it, as a late
+    /// light cue is worth less than music that keeps time.
+    pub fn send(&self, event: &PlayerEvent) {
+        let packet = match self.format {
+            BridgeFormat::Osc => osc_packet(event),
+            BridgeFormat::Raw => event.midi_message(),
+        };
+
+        if let Some(packet) = packet {
+            let _ = self.socket.send(&packet);
+        }
+    }
+}
+
+/// The OSC message for `event`, with channels counted from 1 as they are
+/// everywhere else.
+fn osc_packet(event: &PlayerEvent) -> Option<Vec<u8>> {
+    use Argument::*;
+
+    let (address, arguments) = match event {
+        PlayerEvent::NoteOn {
+            channel,
+            key,
+            velocity,
+        } => (
+            "/midi/note_on",
+            vec![
+                Int(*channel as i32 + 1),
+                Int(*key as i32),
+                Int(*velocity as i32),
+            ],
+        ),
+        PlayerEvent::NoteOff { channel, key } => (
+            "/midi/note_off",
+            vec![Int(*channel as i32 + 1), Int(*key as i32)],
+        ),
+        PlayerEvent::Midi(event) => {
+            let data = &event.msg.data;
+            let channel = Int((data[0] & 0x0f) as i32 + 1);
+
+            match (data[0] & 0xf0, data.len()) {
+                (0xb0, 3) => (
+                    "/midi/control_change",
+                    vec![channel, Int(data[1] as i32), Int(data[2] as i32)],
+                ),
+                (0xc0, 2) => ("/midi/program_change", vec![channel, Int(data[1] as i32)]),
+                (0xd0, 2) => ("/midi/channel_pressure", vec![channel, Int(data[1] as i32)]),
+                (0xe0, 3) => {
+                    let bend = ((data[2] as i32) << 7 | data[1] as i32) - 0x2000;
+                    ("/midi/pitch_bend", vec![channel, Int(bend)])
+                }
+                _ => ("/midi/message", vec![Blob(data)]),
+            }
+        }
+        PlayerEvent::Tempo(tempo) => (
+            "/midi/tempo",
+            vec![Float((60_000_000.0 / *tempo as f64) as f32)],
+        ),
+        PlayerEvent::TimeSignature(signature) => (
+            "/midi/time_signature",
+            vec![
+                Int(signature.numerator as i32),
+                Int(signature.note_value() as i32),
+            ],
+        ),
+        PlayerEvent::Lyric(lyric) => ("/midi/lyric", vec![Text(lyric)]),
+        PlayerEvent::Progress {
+            elapsed,
+            percent,
+            bar,
+            beat,
+            ..
+        } => (
+            "/midi/position",
+            vec![
+                Float(elapsed.as_secs_f32()),
+                Float(*percent as f32),
+                Int(*bar as i32),
+                Int(*beat as i32),
+            ],
+        ),
+        PlayerEvent::Finished => ("/midi/finished", Vec::new()),
+        _ => return None,
+    };
+
+    let mut packet = Vec::new();
+    push_string(&mut packet, address.as_bytes());
+
+    let mut tags = vec![b','];
+    tags.extend(arguments.iter().map(|argument| match argument {
+        Int(_) => b'i',
+        Float(_) => b'f',
+        Text(_) => b's',
+        Blob(_) => b'b',
+    }));
+    push_string(&mut packet, &tags);
+
+    for argument in &arguments {
+        match argument {
+            Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Float(value) => packet.extend_from_slice(&value.to_be_bytes()),
+            Text(text) => push_string(&mut packet, text.as_bytes()),
+            Blob(data) => {
+                packet.extend_from_slice(&(data.len() as i32).to_be_bytes());
+                packet.extend_from_slice(data);
+                pad(&mut packet);
+            }
+        };
+    }
+
+    Some(packet)
+}
+
+/// Appends an OSC string, which is null terminated and padded to four bytes.
+fn push_string(packet: &mut Vec<u8>, text: &[u8]) {
+    packet.extend_from_slice(text);
+    packet.push(0);
+    pad(packet);
+}
+
+fn pad(packet: &mut Vec<u8>) {
+    let padded = (packet.len() + 3) & !3;
+    packet.resize(padded, 0);
+}
@@ -0,0 +1,162 @@
+use crate::checksum;
+
+/// Roland GS system and effect parameters, at address `40 00 xx` and
+/// `40 01 xx`.
+const GS_SYSTEM: &[(u8, u8, &str)] = &[
+    (0x00, 0x04, "Master Volume"),
+    (0x00, 0x05, "Master Key Shift"),
+    (0x00, 0x06, "Master Pan"),
+    (0x00, 0x7f, "Reset"),
+    (0x01, 0x30, "Reverb Macro"),
+    (0x01, 0x31, "Reverb Character"),
+    (0x01, 0x32, "Reverb Pre-LPF"),
+    (0x01, 0x33, "Reverb Level"),
+    (0x01, 0x34, "Reverb Time"),
+    (0x01, 0x35, "Reverb Delay Feedback"),
+    (0x01, 0x38, "Chorus Macro"),
+    (0x01, 0x39, "Chorus Pre-LPF"),
+    (0x01, 0x3a, "Chorus Level"),
+    (0x01, 0x3b, "Chorus Feedback"),
+    (0x01, 0x3c, "Chorus Delay"),
+    (0x01, 0x3d, "Chorus Rate"),
+    (0x01, 0x3e, "Chorus Depth"),
+    (0x01, 0x3f, "Chorus Send To Reverb"),
+];
+
+/// Roland GS part parameters, at address `40 1p xx`.
+const GS_PART: &[(u8, &str)] = &[
+    (0x00, "Tone Number"),
+    (0x02, "Rx. Channel"),
+    (0x13, "Mono/Poly Mode"),
+    (0x15, "Use For Rhythm Part"),
+    (0x16, "Pitch Key Shift"),
+    (0x19, "Part Level"),
+    (0x1a, "Velocity Sense Depth"),
+    (0x1b, "Velocity Sense Offset"),
+    (0x1c, "Part Pan"),
+    (0x1d, "Key Range Low"),
+    (0x1e, "Key Range High"),
+    (0x21, "Chorus Send"),
+    (0x22, "Reverb Send"),
+    (0x30, "Vibrato Rate"),
+    (0x31, "Vibrato Depth"),
+    (0x32, "TVF Cutoff"),
+    (0x33, "TVF Resonance"),
+    (0x34, "Envelope Attack"),
+    (0x35, "Envelope Decay"),
+    (0x36, "Envelope Release"),
+    (0x37, "Vibrato Delay"),
+];
+
+/// Yamaha XG system and effect parameters, at address `00 00 xx` and
+/// `02 01 xx`.
+const XG_SYSTEM: &[(u8, u8, u8, &str)] = &[
+    (0x00, 0x00, 0x04, "Master Volume"),
+    (0x00, 0x00, 0x05, "Master Attenuator"),
+    (0x00, 0x00, 0x06, "Transpose"),
+    (0x00, 0x00, 0x7d, "Drum Setup Reset"),
+    (0x00, 0x00, 0x7e, "System On"),
+    (0x00, 0x00, 0x7f, "All Parameter Reset"),
+    (0x02, 0x01, 0x00, "Reverb Type"),
+    (0x02, 0x01, 0x0c, "Reverb Return"),
+    (0x02, 0x01, 0x20, "Chorus Type"),
+    (0x02, 0x01, 0x2c, "Chorus Return"),
+    (0x02, 0x01, 0x40, "Variation Type"),
+];
+
+/// Yamaha XG multi part parameters, at address `08 pp xx`.
+const XG_PART: &[(u8, &str)] = &[
+    (0x01, "Bank Select MSB"),
+    (0x02, "Bank Select LSB"),
+    (0x03, "Program Number"),
+    (0x04, "Rcv Channel"),
+    (0x05, "Mono/Poly Mode"),
+    (0x07, "Part Mode"),
+    (0x08, "Note Shift"),
+    (0x0b, "Volume"),
+    (0x0e, "Pan"),
+    (0x11, "Dry Level"),
+    (0x12, "Chorus Send"),
+    (0x13, "Reverb Send"),
+    (0x14, "Variation Send"),
+    (0x15, "Vibrato Rate"),
+    (0x16, "Vibrato Depth"),
+    (0x17, "Vibrato Delay"),
+    (0x18, "Filter Cutoff"),
+    (0x19, "Filter Resonance"),
+    (0x1a, "EG Attack"),
+    (0x1b, "EG Decay"),
+    (0x1c, "EG Release"),
+];
+
+/// Describes a Roland GS DT1 or Yamaha XG parameter change, such as
+/// "GS Part 3 Reverb Send = 64", or `None` for any other message.
+pub fn describe(message: &[u8]) -> Option<String> {
+    let description = match message {
+        [0xf0, 0x41, _, 0x42, 0x12, ..] if message.len() >= 11 => {
+            let data = &message[8..message.len() - 2];
+            gs_parameter(message[5], message[6], message[7])? + &values(data)
+        }
+        [0xf0, 0x43, device, 0x4c, ..] if device & 0xf0 == 0x10 && message.len() >= 8 => {
+            let data = &message[7..message.len() - 1];
+            xg_parameter(message[4], message[5], message[6])? + &values(data)
+        }
+        _ => return None,
+    };
+
+    match checksum::verify(message) {
+        Some(false) => Some(description + " (bad checksum)"),
+        _ => Some(description),
+    }
+}
+
+fn gs_parameter(high: u8, middle: u8, low: u8) -> Option<String> {
+    if high != 0x40 {
+        return None;
+    }
+
+    if let 0x10..=0x1f = middle {
+        // Block 0 is the rhythm part 10, the others follow around it
+        let part = match middle & 0x0f {
+            0 => 10,
+            block @ 1..=9 => block,
+            block => block + 1,
+        };
+        let name = find(GS_PART, low)?;
+
+        return Some(format!("GS Part {} {}", part, name));
+    }
+
+    GS_SYSTEM
+        .iter()
+        .find(|&&(table_middle, table_low, _)| (table_middle, table_low) == (middle, low))
+        .map(|(_, _, name)| format!("GS {}", name))
+}
+
+fn xg_parameter(high: u8, middle: u8, low: u8) -> Option<String> {
+    if high == 0x08 {
+        let name = find(XG_PART, low)?;
+
+        return Some(format!("XG Part {} {}", middle + 1, name));
+    }
+
+    XG_SYSTEM
+        .iter()
+        .find(|&&(table_high, table_middle, table_low, _)| {
+            (table_high, table_middle, table_low) == (high, middle, low)
+        })
+        .map(|(_, _, _, name)| format!("XG {}", name))
+}
+
+fn find(table: &[(u8, &'static str)], low: u8) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|&&(address, _)| address == low)
+        .map(|&(_, name)| name)
+}
+
+fn values(data: &[u8]) -> String {
+    let values: Vec<String> = data.iter().map(|value| value.to_string()).collect();
+
+    format!(" = {}", values.join(", "))
+}
@@ -0,0 +1,2061 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use encoding_rs::{Encoding, UTF_8};
+//use rimd::SMFFormat;
+use rimd::{MetaCommand, MetaEvent, MidiMessage};
+#[cfg(feature = "tokio")]
+use tokio::sync::oneshot;
+
+use crate::automation::{self, Ramp};
+use crate::backend::{MidiOutput, OutputPort};
+use crate::cancel::CancellationToken;
+use crate::channels::{self, ChannelMapping};
+use crate::clock::{self, ClockFollower, Transport};
+use crate::events::{PlayerEvent, PlayerEvents};
+use crate::filter::{self, MessageClass};
+use crate::humanize::{self, Humanize};
+use crate::metronome::{self, Click, CountIn};
+use crate::midi_file::{self, DataEvent, LocalEvent, Sequence};
+use crate::note_tracker::NoteTracker;
+use crate::parameters;
+use crate::polyphony::{self, PolyphonyLimit};
+use crate::programs::{self, ProgramOverride};
+use crate::routing::{self, Route, Source};
+use crate::safety::{SafetyLimiter, SafetyLimits, Verdict};
+use crate::signature::{KeySignature, TimeSignature};
+#[cfg(windows)]
+use crate::stream;
+use crate::tempo::{format_time, TempoMap};
+use crate::text;
+use crate::thread_boost::ThreadBoost;
+use crate::thru::{self, ThruReceiver};
+use crate::timecode::{self, FrameRate};
+use crate::timeline;
+use crate::timer::PreciseTimer;
+use crate::velocity::{self, VelocityCurve};
+
+// Default tempo is 120 beats per minute
+pub const DEFAULT_TEMPO: u64 = 500000;
+
+// Longest sleep between thru checks while waiting for the next event
+const THRU_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// How often a paused player checks whether it was resumed, and a waiting one
+// whether it was stopped
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// How often the input is checked for clocks when following a master, well
+// within the time between two clocks
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// No seek pending
+const NO_SEEK: u64 = u64::MAX;
+
+// How often a playing player reports its position
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// How far behind its time an event can go out before it is reported late,
+// around where the drift of a groove starts to be heard
+const LATE_THRESHOLD: Duration = Duration::from_millis(5);
+
+// How often a failed output port is tried again
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(500);
+
+// How long the notes of a scrub stay on, and how many of them at most
+const SCRUB_LENGTH: Duration = Duration::from_millis(120);
+const SCRUB_NOTES: usize = 8;
+
+// Controller changes a fade-out is made of
+const FADE_STEPS: u32 = 32;
+
+/// Slowest and fastest playback, in percent of the tempo of the file.
+pub const SPEED_RANGE: RangeInclusive<u32> = 25..=400;
+
+/// How events are timed and handed to the output port.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Engine {
+    /// Our own scheduler sends each event when it is due.
+    Realtime,
+    /// The whole file is queued with `midiStreamOut` and timed by the driver.
+    #[cfg(windows)]
+    Stream,
+}
+
+impl Engine {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "realtime" => Ok(Engine::Realtime),
+            #[cfg(windows)]
+            "stream" => Ok(Engine::Stream),
+            _ => Err(anyhow!("Unknown engine: {}", value)),
+        }
+    }
+}
+
+/// Output ports left open by players that played to the end, so the next
+/// player on the same port carries on without reopening and resetting it.
+#[derive(Clone, Default)]
+pub struct PortHandoff {
+    ports: Arc<Mutex<Vec<(u32, OutputPort)>>>,
+}
+
+impl PortHandoff {
+    fn take(&self, port_id: u32) -> Option<OutputPort> {
+        let mut ports = self.ports.lock().unwrap();
+        let index = ports.iter().position(|(id, _)| *id == port_id)?;
+
+        Some(ports.remove(index).1)
+    }
+
+    fn park(&self, port_id: u32, port: OutputPort) {
+        self.ports.lock().unwrap().push((port_id, port));
+    }
+
+    /// Closes every port left open, once nothing follows.
+    pub fn close(&self) {
+        self.ports.lock().unwrap().clear();
+    }
+}
+
+/// Settings for the players of a session.
+#[derive(Clone)]
+pub struct PlayerConfig {
+    /// Output port number, as listed by `OutputPort::name`.
+    pub port_id: u32,
+    // Only Windows has an engine other than the realtime one
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub engine: Engine,
+    /// Input messages merged into the output while playing.
+    pub thru: Option<ThruReceiver>,
+    pub safety: Option<SafetyLimits>,
+    /// Sent after the reset, before anything plays.
+    pub startup: Vec<Vec<u8>>,
+    /// Controller ramps merged into whatever is played.
+    pub automation: Vec<Ramp>,
+    /// Channels of the events moved to others, before anything else works
+    /// on them.
+    pub channel_map: Vec<ChannelMapping>,
+    /// Programs sent after `startup`, with the file's own program changes
+    /// on those channels left out.
+    pub programs: Vec<ProgramOverride>,
+    /// Second port for A/B comparisons, kept silent until switched to.
+    pub compare_port: Option<u32>,
+    /// Overrides the detected encoding of track names and lyrics.
+    pub text_encoding: Option<&'static Encoding>,
+    /// How long to keep reopening the output when it fails mid-song, such as
+    /// a USB interface being unplugged, before giving up on the file.
+    pub reconnect: Option<Duration>,
+    /// Name of a port to carry on with when the output does not come back.
+    pub fallback_port: Option<String>,
+    /// Port mirroring the output, which playback continues on when the
+    /// output fails.
+    pub backup_port: Option<u32>,
+    /// How long to turn `fade_controller` down for when stopped mid-song,
+    /// instead of cutting the notes off at once.
+    pub fade_out: Option<Duration>,
+    /// Volume (7) or expression (11).
+    pub fade_controller: u8,
+    /// Pause after every SysEx message, for devices that drop bulk data
+    /// arriving back to back.
+    pub sysex_delay: Option<Duration>,
+    /// Wait after opening the port and resetting it, before anything plays,
+    /// for modules still busy with the reset.
+    pub lead_in: Option<Duration>,
+    /// Clicks played before the first event.
+    pub count_in: Option<CountIn>,
+    /// Clicks merged into the events on every beat.
+    pub metronome: Option<Click>,
+    /// Send MIDI clock along with the events, with start, stop and continue.
+    pub clock: bool,
+    /// Send MIDI Time Code along with the events at this frame rate.
+    pub timecode: Option<FrameRate>,
+    /// Input port whose MIDI clock times playback in place of the tempo map.
+    pub sync_port: Option<u32>,
+    /// Classes of messages left out of the events, but not of `startup`.
+    pub drop: Vec<MessageClass>,
+    /// Random bounds the notes of the events are moved and changed within.
+    pub humanize: Option<Humanize>,
+    /// Reshapes the velocity of the notes of the events.
+    pub velocity_curve: Option<VelocityCurve>,
+    /// Notes held at once, with older or softer ones ended to stay within.
+    pub polyphony_limit: Option<PolyphonyLimit>,
+    /// Tracks and channels played on other ports than `port_id`.
+    pub routes: Vec<Route>,
+    /// Ports every message to `port_id` is copied to as well.
+    pub also_ports: Vec<u32>,
+    /// Where the port is handed on to the next player once this one played
+    /// to the end, and taken from when the last one left it open.
+    pub handoff: Option<PortHandoff>,
+    /// Every player gets a child of this token, so cancelling it stops them
+    /// all while `Player::stop` only stops one.
+    pub cancel: CancellationToken,
+}
+
+/// A MIDI message as it was sent to the output port.
+#[derive(Clone)]
+pub struct BasicMidiEvent {
+    pub delta_time: u64,
+    pub msg: MidiMessage,
+}
+
+impl fmt::Display for BasicMidiEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.msg.data.len() == 2 {
+            write!(f, "{}: [{}]", self.msg.status(), self.msg.data[1])
+        } else if self.msg.data.len() == 3 {
+            write!(
+                f,
+                "{}: [{},{}]",
+                self.msg.status(),
+                self.msg.data[1],
+                self.msg.data[2]
+            )
+        } else if self.msg.data.is_empty() {
+            write!(f, "{}: [no data]", self.msg.status())
+        } else if let Some(description) = parameters::describe(&self.msg.data) {
+            write!(
+                f,
+                "{}: {} {:?}",
+                self.msg.status(),
+                description,
+                self.msg.data
+            )
+        } else {
+            write!(f, "{}: {:?}", self.msg.status(), self.msg.data)
+        }
+    }
+}
+
+/// Requests from a `Player` to its playback thread.
+struct PlayerControl {
+    paused: AtomicBool,
+    cancel: CancellationToken,
+    /// Tick to continue playback from, or `NO_SEEK`.
+    seek: AtomicU64,
+    /// Set to move playback to the other port of an A/B comparison.
+    switch_output: AtomicBool,
+    /// Tick to audition while paused, or `NO_SEEK`.
+    scrub: AtomicU64,
+    /// Set to silence stuck notes without stopping.
+    panic: AtomicBool,
+    /// Playback speed in percent of the tempo of the file.
+    speed: AtomicU32,
+    /// Channels whose notes are left out, one bit each.
+    muted: AtomicU16,
+}
+
+impl PlayerControl {
+    fn new(cancel: CancellationToken) -> Self {
+        Self {
+            paused: AtomicBool::new(false),
+            cancel,
+            seek: AtomicU64::new(NO_SEEK),
+            switch_output: AtomicBool::new(false),
+            scrub: AtomicU64::new(NO_SEEK),
+            panic: AtomicBool::new(false),
+            speed: AtomicU32::new(100),
+            muted: AtomicU16::new(0),
+        }
+    }
+
+    fn speed(&self) -> f64 {
+        self.speed.load(Ordering::Relaxed) as f64 / 100.0
+    }
+
+    fn running(&self) -> bool {
+        !self.cancel.is_cancelled()
+    }
+
+    fn take_seek(&self) -> Option<u64> {
+        match self.seek.swap(NO_SEEK, Ordering::Relaxed) {
+            NO_SEEK => None,
+            tick => Some(tick),
+        }
+    }
+
+    fn take_scrub(&self) -> Option<u64> {
+        match self.scrub.swap(NO_SEEK, Ordering::Relaxed) {
+            NO_SEEK => None,
+            tick => Some(tick),
+        }
+    }
+}
+
+/// Plays a sequence on its own thread, with controls that take effect while
+/// it is playing.
+pub struct Player {
+    /// Waiting to be moved to the playback thread by `play`.
+    player: Option<FilePlayer>,
+    control: Arc<PlayerControl>,
+    handle: Option<JoinHandle<Result<()>>>,
+    events: PlayerEvents,
+}
+
+impl Player {
+    /// Parses a Standard MIDI File, ready to play.
+    pub fn load(path: PathBuf, config: PlayerConfig) -> Result<Self> {
+        let sequence = midi_file::load(&path, config.text_encoding)?;
+
+        Ok(Self::from_sequence(sequence, config))
+    }
+
+    /// Builds a player for a file that was already loaded.
+    pub fn from_sequence(sequence: Sequence, config: PlayerConfig) -> Self {
+        let player = FilePlayer::from_sequence(sequence, config);
+
+        Self::wrap(player)
+    }
+
+    /// Builds a player for events that did not come from a file.
+    pub fn from_events(
+        division: u64,
+        tempo: u64,
+        events: Vec<DataEvent>,
+        config: PlayerConfig,
+    ) -> Self {
+        let player = FilePlayer::from_events(division, tempo, events, config);
+
+        Self::wrap(player)
+    }
+
+    fn wrap(player: FilePlayer) -> Self {
+        Self {
+            control: player.control.clone(),
+            events: player.player_events.clone(),
+            player: Some(player),
+            handle: None,
+        }
+    }
+
+    /// Repeats the events until playback is stopped, each pass lasting
+    /// `length` ticks.
+    pub fn looping(mut self, length: u64) -> Self {
+        self.player = self.player.take().map(|player| player.looping(length));
+        self
+    }
+
+    /// Continues from the second tick of every pair once playback reaches
+    /// the first, each jump taken once and in order, playing a song's
+    /// repeats and D.C. or D.S. form without editing it.
+    pub fn jumping(mut self, jumps: Vec<(u64, u64)>) -> Self {
+        self.player = self.player.take().map(|player| player.jumping(jumps));
+        self
+    }
+
+    /// Starts playback at `tick` instead of the beginning, with the state
+    /// there chased.
+    pub fn starting_at(mut self, tick: u64) -> Self {
+        self.player = self.player.take().map(|player| player.starting_at(tick));
+        self
+    }
+
+    /// Stops playback once it reaches `tick`, releasing the notes still
+    /// held, instead of playing to the end.
+    pub fn ending_at(mut self, tick: u64) -> Self {
+        self.player = self.player.take().map(|player| player.ending_at(tick));
+        self
+    }
+
+    /// Plays from tick `from` and goes back to it every time playback
+    /// reaches `to`, with the state there chased, until stopped. For
+    /// practicing a passage over and over.
+    pub fn section(mut self, from: u64, to: u64) -> Self {
+        self.player = self.player.take().map(|player| player.section(from, to));
+        self
+    }
+
+    /// Starts playback on a new thread, whose error `join` returns.
+    pub fn play(&mut self) -> Result<()> {
+        self.handle = Some(self.spawn(|result| result)?);
+
+        Ok(())
+    }
+
+    /// Starts playback and completes with its result once it finishes.
+    /// Dropping the future stops playback without waiting for the thread, so
+    /// it can be cancelled with `select!` or a timeout.
+    #[cfg(feature = "tokio")]
+    pub async fn play_async(mut self) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.spawn(move |result| {
+            let _ = sender.send(result);
+        })?;
+
+        // The sender only goes away unused if the playback thread panicked
+        receiver
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("Player thread panicked")))
+    }
+
+    /// Runs playback on a new thread, handing its result to `finish` once
+    /// `Finished` was sent.
+    fn spawn<T: Send + 'static>(
+        &mut self,
+        finish: impl FnOnce(Result<()>) -> T + Send + 'static,
+    ) -> Result<JoinHandle<T>> {
+        let player = self.player.take().context("Player was already started")?;
+        let events = self.events.clone();
+
+        thread::Builder::new()
+            .name(String::from("MIDI Player"))
+            .spawn(move || {
+                let result = player.play_events();
+                events.send(PlayerEvent::Finished);
+
+                finish(result)
+            })
+            .context("Failed to spawn player thread")
+    }
+
+    /// Silences the output and holds playback until `resume` is called.
+    pub fn pause(&self) {
+        self.control.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.control.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.paused.load(Ordering::Relaxed)
+    }
+
+    /// Ends playback, after which the thread finishes on its own.
+    pub fn stop(&self) {
+        self.control.cancel.cancel();
+    }
+
+    /// The token `stop` cancels, for stopping the player from elsewhere.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.control.cancel.clone()
+    }
+
+    /// Continues playback from `tick` ticks into the sequence, resending the
+    /// program, controller and tempo changes that came before it.
+    pub fn seek(&self, tick: u64) {
+        self.control.seek.store(tick, Ordering::Relaxed);
+    }
+
+    /// Moves playback to `tick` like `seek`, and while paused also plays a
+    /// short burst of the notes found there, so positions can be auditioned
+    /// while dragging a position slider. Requests arriving faster than the
+    /// bursts play are merged, keeping the latest.
+    pub fn scrub(&self, tick: u64) {
+        self.control.seek.store(tick, Ordering::Relaxed);
+
+        if self.is_paused() {
+            self.control.scrub.store(tick, Ordering::Relaxed);
+        }
+    }
+
+    /// Moves playback to the other port of an A/B comparison, silencing the
+    /// one that was playing and bringing the other up to the same state.
+    pub fn switch_output(&self) {
+        self.control.switch_output.store(true, Ordering::Relaxed);
+    }
+
+    /// Sends all notes off, all sound off and a sustain release on every
+    /// channel, for notes left hanging by the device, while playback goes on.
+    pub fn panic(&self) {
+        self.control.panic.store(true, Ordering::Relaxed);
+    }
+
+    /// Plays faster or slower than the file says, by `percent` of its tempo
+    /// limited to `SPEED_RANGE`, without changing the pitch.
+    pub fn set_speed(&self, percent: u32) {
+        let percent = percent.clamp(*SPEED_RANGE.start(), *SPEED_RANGE.end());
+        self.control.speed.store(percent, Ordering::Relaxed);
+    }
+
+    pub fn speed(&self) -> u32 {
+        self.control.speed.load(Ordering::Relaxed)
+    }
+
+    /// Leaves out the notes of `channel`, counted from 0, or plays them
+    /// again. Notes sounding on it when muted are silenced.
+    pub fn set_muted(&self, channel: u8, muted: bool) {
+        let bit = 1 << (channel & 0x0f);
+        if muted {
+            self.control.muted.fetch_or(bit, Ordering::Relaxed);
+        } else {
+            self.control.muted.fetch_and(!bit, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_muted(&self, channel: u8) -> bool {
+        self.control.muted.load(Ordering::Relaxed) & 1 << (channel & 0x0f) != 0
+    }
+
+    /// Subscriptions to what the player does, ending with
+    /// `PlayerEvent::Finished`.
+    pub fn events(&self) -> &PlayerEvents {
+        &self.events
+    }
+
+    /// Waits for playback to finish, returning its error.
+    pub fn join(mut self) -> Result<()> {
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|e| anyhow!("Failed to join player thread: {:?}", e))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.stop();
+
+        match self.handle.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => eprintln!("Failed to play events: {:?}", e),
+            Some(Err(e)) => eprintln!("Failed to join player thread: {:?}", e),
+            _ => {}
+        }
+    }
+}
+
+/// A port playing along with the output for a route or mirror, with what it
+/// plays held back while the output sounds later, so both sound together.
+struct ExtraPort {
+    port_id: u32,
+    port: OutputPort,
+    hold: Duration,
+    held: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl ExtraPort {
+    fn new(port_id: u32, port: OutputPort, output_latency: Duration) -> Self {
+        Self {
+            port_id,
+            hold: output_latency.saturating_sub(port.latency()),
+            port,
+            held: VecDeque::new(),
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        if self.hold.is_zero() {
+            return self.port.send(data);
+        }
+
+        self.held
+            .push_back((Instant::now() + self.hold, data.to_vec()));
+        self.flush()
+    }
+
+    /// Sends the held messages that are due and frees finished buffers.
+    fn flush(&mut self) -> Result<()> {
+        let now = Instant::now();
+        while matches!(self.held.front(), Some((due, _)) if *due <= now) {
+            if let Some((_, data)) = self.held.pop_front() {
+                self.port.send(&data)?;
+            }
+        }
+
+        self.port.check_inflight()
+    }
+
+    /// Sends everything still held, each once it is due.
+    fn finish(&mut self) -> Result<()> {
+        while let Some((due, data)) = self.held.pop_front() {
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+            self.port.send(&data)?;
+        }
+
+        Ok(())
+    }
+
+    /// The port, without the held messages, which what is sent next
+    /// replaces.
+    fn cancel(&mut self) -> &mut OutputPort {
+        self.held.clear();
+        &mut self.port
+    }
+}
+
+struct FilePlayer {
+    //path: PathBuf,
+    port_id: u32,
+    #[cfg(windows)]
+    engine: Engine,
+    //format: SMFFormat,
+    division: u64,
+    tempo_map: TempoMap,
+    /// Time signatures by tick, for the bar and beat of the progress.
+    signatures: Vec<(u64, (u8, u8))>,
+    events: Vec<DataEvent>,
+    /// Length in ticks of one pass when the events repeat until stopped.
+    loop_length: Option<u64>,
+    /// Ticks to jump from and to, in the order taken.
+    jumps: Vec<(u64, u64)>,
+    /// Ticks of a section played over and over, from and to.
+    section: Option<(u64, u64)>,
+    start: u64,
+    end: Option<u64>,
+    thru: Option<ThruReceiver>,
+    safety: Option<SafetyLimiter>,
+    startup: Vec<Vec<u8>>,
+    compare_port: Option<u32>,
+    reconnect: Option<Duration>,
+    fallback_port: Option<String>,
+    backup_port: Option<u32>,
+    fade_out: Option<Duration>,
+    fade_controller: u8,
+    sysex_delay: Option<Duration>,
+    handoff: Option<PortHandoff>,
+    lead_in: Option<Duration>,
+    count_in: Option<CountIn>,
+    clock: bool,
+    timecode: Option<FrameRate>,
+    sync_port: Option<u32>,
+    routes: Vec<Route>,
+    /// The ports of the routes, each opened once however many routes use it.
+    routed: Vec<ExtraPort>,
+    also_ports: Vec<u32>,
+    /// The open ones of `also_ports`, dropped once they fail.
+    mirrors: Vec<ExtraPort>,
+    /// Of the playing port, to find it again after it failed.
+    port_name: String,
+    control: Arc<PlayerControl>,
+    /// Track names and copyrights, announced once playback starts.
+    track_info: Vec<String>,
+    text_encoding: &'static Encoding,
+    player_events: PlayerEvents,
+    /// Muted channels whose notes were already silenced.
+    silenced: u16,
+}
+
+impl FilePlayer {
+    fn from_sequence(sequence: Sequence, config: PlayerConfig) -> Self {
+        let mut player =
+            Self::from_events(sequence.division, DEFAULT_TEMPO, sequence.events, config);
+        player.track_info = sequence.track_info;
+        player.text_encoding = sequence.encoding;
+
+        player
+    }
+
+    /// Builds a player for events that did not come from a file.
+    fn from_events(
+        division: u64,
+        tempo: u64,
+        events: Vec<DataEvent>,
+        config: PlayerConfig,
+    ) -> Self {
+        let events = channels::apply(events, &config.channel_map);
+        let events = automation::apply(events, division, &config.automation);
+        let events = programs::apply(events, &config.programs);
+        let events = filter::apply(events, &config.drop);
+        let events = humanize::apply(events, division, tempo, config.humanize);
+        let events = velocity::apply(events, config.velocity_curve.as_ref());
+        let events = polyphony::limit(events, config.polyphony_limit);
+        let events = metronome::apply(events, division, config.metronome);
+        let events = if config.clock {
+            clock::apply(events, division)
+        } else {
+            events
+        };
+        let tempo_map = TempoMap::from_events(&events, tempo);
+        let events = match config.timecode {
+            Some(rate) => timecode::apply(events, division, &tempo_map, rate),
+            None => events,
+        };
+        let mut startup = config.startup;
+        startup.extend(config.programs.iter().flat_map(ProgramOverride::messages));
+
+        Self {
+            //path,
+            port_id: config.port_id,
+            #[cfg(windows)]
+            engine: config.engine,
+            //format: midi_data.format,
+            division,
+            tempo_map,
+            signatures: timeline::time_signatures(&events),
+            events,
+            loop_length: None,
+            jumps: Vec::new(),
+            section: None,
+            start: 0,
+            end: None,
+            thru: config.thru,
+            safety: config.safety.map(SafetyLimiter::new),
+            startup,
+            compare_port: config.compare_port,
+            reconnect: config.reconnect,
+            fallback_port: config.fallback_port,
+            backup_port: config.backup_port,
+            fade_out: config.fade_out,
+            fade_controller: config.fade_controller,
+            sysex_delay: config.sysex_delay,
+            handoff: config.handoff,
+            lead_in: config.lead_in,
+            count_in: config.count_in,
+            clock: config.clock,
+            timecode: config.timecode,
+            sync_port: config.sync_port,
+            routes: config.routes,
+            routed: Vec::new(),
+            also_ports: config.also_ports,
+            mirrors: Vec::new(),
+            port_name: String::new(),
+            control: Arc::new(PlayerControl::new(config.cancel.child())),
+            track_info: Vec::new(),
+            text_encoding: config.text_encoding.unwrap_or(UTF_8),
+            player_events: PlayerEvents::default(),
+            silenced: 0,
+        }
+    }
+
+    /// Repeats the events until playback is stopped, each pass lasting
+    /// `length` ticks.
+    fn looping(mut self, length: u64) -> Self {
+        self.loop_length = Some(length);
+        self
+    }
+
+    fn jumping(mut self, jumps: Vec<(u64, u64)>) -> Self {
+        let mut points: Vec<u64> = jumps.iter().map(|&(at, _)| at).collect();
+        points.sort_unstable();
+        points.dedup();
+
+        self.events = with_jump_points(mem::take(&mut self.events), &points);
+        self.jumps = jumps;
+        self
+    }
+
+    fn starting_at(mut self, tick: u64) -> Self {
+        self.start = tick;
+        self
+    }
+
+    fn ending_at(mut self, tick: u64) -> Self {
+        self.events = with_jump_points(mem::take(&mut self.events), &[tick]);
+        self.end = Some(tick);
+        self
+    }
+
+    fn section(mut self, from: u64, to: u64) -> Self {
+        self.events = with_jump_points(mem::take(&mut self.events), &[to]);
+        self.section = Some((from, to));
+        self
+    }
+
+    /// Queues every event with the driver and waits for it to play them.
+    #[cfg(windows)]
+    fn play_stream(self) -> Result<()> {
+        if self.loop_length.is_some() {
+            return Err(anyhow!("Looping is not supported by the stream engine"));
+        }
+        if !self.jumps.is_empty() {
+            return Err(anyhow!("Jumps are not supported by the stream engine"));
+        }
+        if self.section.is_some() {
+            return Err(anyhow!("Sections are not supported by the stream engine"));
+        }
+        if !self.routes.is_empty() || !self.also_ports.is_empty() {
+            return Err(anyhow!(
+                "Routing and mirroring are not supported by the stream engine"
+            ));
+        }
+        if self.start > 0 || self.end.is_some() {
+            return Err(anyhow!(
+                "Start and end points are not supported by the stream engine"
+            ));
+        }
+
+        let mut stream = stream::MidiStream::open(self.port_id, self.division)?;
+        stream.queue(&self.startup, &self.events, self.tempo_map.tempo_at(0))?;
+
+        let thread_boost = ThreadBoost::new();
+        self.player_events
+            .message(format!("Task Index: {}", thread_boost.task_index()));
+        self.player_events
+            .message(format!("Streaming {} events", self.events.len()));
+
+        stream.restart()?;
+
+        while !stream.is_done() {
+            if !self.control.running() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        Ok(())
+    }
+
+    /// Sends every program, controller, pitch bend and SysEx message before
+    /// `tick`, skipping the notes, so playback can continue from there. Returns
+    /// the index and tick of the last event chased.
+    fn chase(
+        &mut self,
+        conn_out: &mut OutputPort,
+        events: &[DataEvent],
+        tick: u64,
+    ) -> Result<(usize, u64)> {
+        self.reset_safety();
+
+        let mut position = 0;
+        let mut index = 0;
+
+        while index < events.len() && position + events[index].delta_time < tick {
+            let event = &events[index];
+            position += event.delta_time;
+            index += 1;
+
+            // Routed events bring the state of their own ports up to date
+            let route = routing::find(&self.routes, event);
+
+            match &event.data {
+                LocalEvent::Meta(_) => {}
+                LocalEvent::SysEx(data) => {
+                    self.route_port(route)
+                        .map(ExtraPort::cancel)
+                        .unwrap_or(&mut *conn_out)
+                        .send(data)
+                        .context("Failed to send MIDI message")?;
+
+                    if let Some(delay) = self.sysex_delay {
+                        thread::sleep(delay);
+                    }
+                }
+                LocalEvent::Midi(data) => match data[0] & 0xf0 {
+                    0x80 | 0x90 | 0xa0 => {}
+                    _ if clock::is_sync(data) => {}
+                    _ => {
+                        let mut data = *data;
+                        if check_safety(&mut self.safety, &self.player_events, &mut data) {
+                            self.route_port(route)
+                                .map(ExtraPort::cancel)
+                                .unwrap_or(&mut *conn_out)
+                                .send(&data[..midi_file::message_length(data[0])])
+                                .context("Failed to send MIDI message")?;
+                        }
+                    }
+                },
+            };
+        }
+
+        Ok((index, position))
+    }
+
+    /// Opens a port and gets it ready to play.
+    fn connect(&self, port_id: u32) -> Result<OutputPort> {
+        let mut conn_out = OutputPort::connect(port_id)?;
+
+        // Reset so sounds play correctly
+        conn_out.send_reset()?;
+
+        for message in &self.startup {
+            conn_out
+                .send(message)
+                .context("Failed to send startup message")?;
+        }
+
+        Ok(conn_out)
+    }
+
+    /// Opens a port of a route or mirror, holding back what it plays by as
+    /// much as `conn_out` sounds later, like BLE devices do.
+    fn connect_extra(&self, port_id: u32, conn_out: &OutputPort) -> Result<ExtraPort> {
+        let extra = ExtraPort::new(port_id, self.connect(port_id)?, conn_out.latency());
+        if !extra.hold.is_zero() {
+            self.player_events.message(format!(
+                "Holding back port {} by {} ms to sound with the output",
+                port_id,
+                extra.hold.as_millis()
+            ));
+        }
+
+        Ok(extra)
+    }
+
+    /// Tells clocked gear and time code receivers playback moved to `tick`.
+    fn locate_sync(&self, conn_out: &mut OutputPort, tick: u64) -> Result<()> {
+        if self.clock {
+            for message in clock::relocate(tick, self.division) {
+                conn_out
+                    .send(&message)
+                    .context("Failed to send song position")?;
+            }
+        }
+
+        if let Some(rate) = self.timecode {
+            let time = self.tempo_map.time_at(tick, self.division);
+            conn_out
+                .send(&timecode::full_frame(time, rate))
+                .context("Failed to send time code")?;
+        }
+
+        Ok(())
+    }
+
+    /// Gives the device `lead_in` to get over the reset, unless stopped.
+    fn wait_lead_in(&self) {
+        let lead_in = match self.lead_in {
+            Some(lead_in) => lead_in,
+            None => return,
+        };
+
+        let started = Instant::now();
+        while self.control.running() && started.elapsed() < lead_in {
+            thread::sleep(PAUSE_POLL_INTERVAL.min(lead_in - started.elapsed()));
+        }
+    }
+
+    /// Plays the count-in clicks at the tempo and in the time signature the
+    /// song starts with, unless stopped.
+    fn play_count_in(&self, conn_out: &mut OutputPort, timer: &PreciseTimer) -> Result<()> {
+        let count_in = match &self.count_in {
+            Some(count_in) => count_in,
+            None => return Ok(()),
+        };
+
+        let signature = match timeline::time_signatures(&self.events).first() {
+            Some(&(0, signature)) => signature,
+            _ => (4, 2),
+        };
+        let (clicks, length) = metronome::count_in(self.division, signature, count_in);
+        let tempo = self.tempo_map.tempo_at(0) as u128;
+        let due = |tick: u64| {
+            Duration::from_micros((tick as u128 * tempo / self.division.max(1) as u128) as u64)
+        };
+
+        let start = Instant::now();
+        let mut tick = 0;
+
+        // The last wait lasts until the end of the count-in
+        for event in clicks.iter().map(Some).chain(Some(None)) {
+            tick = match event {
+                Some(event) => tick + event.delta_time,
+                None => length,
+            };
+
+            loop {
+                if !self.control.running() {
+                    return Ok(());
+                }
+
+                let elapsed = start.elapsed();
+                if elapsed >= due(tick) {
+                    break;
+                }
+                timer.wait(due(tick) - elapsed, PAUSE_POLL_INTERVAL);
+            }
+
+            if let Some(DataEvent {
+                data: LocalEvent::Midi(data),
+                ..
+            }) = event
+            {
+                conn_out
+                    .send(&data[..midi_file::message_length(data[0])])
+                    .context("Failed to send count-in click")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The port the previous player left open, brought back to the startup
+    /// state with controller resets rather than a full reset, which makes
+    /// some devices pause.
+    fn take_handoff(&self) -> Result<Option<OutputPort>> {
+        let mut conn_out = match self.handoff.as_ref().and_then(|h| h.take(self.port_id)) {
+            Some(conn_out) => conn_out,
+            None => return Ok(None),
+        };
+
+        silence(&mut conn_out)?;
+        for channel in 0..16 {
+            conn_out
+                .send(&[0xb0 | channel, 121, 0])
+                .context("Failed to reset controllers")?;
+        }
+        for message in &self.startup {
+            conn_out
+                .send(message)
+                .context("Failed to send startup message")?;
+        }
+
+        Ok(Some(conn_out))
+    }
+
+    /// Holds playback after the output failed with `error`, reopening the
+    /// port by name, or the fallback port, until `reconnect` runs out. The
+    /// state up to `tick` is replayed once it is back. Returns how long
+    /// playback was held up.
+    fn reconnect(
+        &mut self,
+        conn_out: &mut OutputPort,
+        events: &[DataEvent],
+        tick: u64,
+        error: anyhow::Error,
+    ) -> Result<Duration> {
+        let timeout = match self.reconnect {
+            Some(timeout) => timeout,
+            None => return Err(error),
+        };
+
+        self.player_events
+            .message(format!("Output failed, reconnecting: {:#}", error));
+
+        let failed_at = Instant::now();
+        while failed_at.elapsed() < timeout {
+            if !self.control.running() {
+                return Ok(failed_at.elapsed());
+            }
+
+            let port = (0..OutputPort::count())
+                .filter_map(|i| Some((i, OutputPort::name(i).ok()?)))
+                .find(|(_, name)| {
+                    *name == self.port_name || Some(name) == self.fallback_port.as_ref()
+                });
+
+            if let Some((port_id, name)) = port {
+                if let Ok(new_out) = self.connect(port_id) {
+                    *conn_out = new_out;
+                    self.port_id = port_id;
+                    self.port_name = name;
+                    self.chase(conn_out, events, tick + 1)?;
+                    self.player_events
+                        .message(format!("Reconnected to port {}", port_id));
+
+                    return Ok(failed_at.elapsed());
+                }
+            }
+
+            thread::sleep(RECONNECT_INTERVAL);
+        }
+
+        Err(error.context("Output did not come back"))
+    }
+
+    /// Sends `data` to the output and mirrors it to the backup port, going on
+    /// with the backup when the output fails, with the state up to `tick`
+    /// replayed on it. Without a backup the output is reconnected instead and
+    /// `data` sent again, returning how long that held playback up.
+    fn send_mirrored(
+        &mut self,
+        conn_out: &mut OutputPort,
+        backup: &mut Option<(u32, OutputPort)>,
+        events: &[DataEvent],
+        tick: u64,
+        data: &[u8],
+    ) -> Result<Option<Duration>> {
+        self.each_mirror(|mirror| mirror.send(data));
+        if let Some((port_id, backup_out)) = backup {
+            if let Err(e) = backup_out.send(data) {
+                self.player_events.message(format!(
+                    "Backup port {} failed, no longer mirroring: {:#}",
+                    port_id, e
+                ));
+                *backup = None;
+            }
+        }
+
+        let error = match conn_out.send(data).context("Failed to send MIDI message") {
+            Ok(()) => return Ok(None),
+            Err(e) => e,
+        };
+
+        match backup.take() {
+            Some((port_id, backup_out)) => {
+                *conn_out = backup_out;
+                self.port_id = port_id;
+                self.port_name = OutputPort::name(port_id).unwrap_or_default();
+                self.player_events.message(format!(
+                    "Output failed, continuing on backup port {}: {:#}",
+                    port_id, error
+                ));
+                self.chase(conn_out, events, tick + 1)?;
+
+                Ok(None)
+            }
+            None => {
+                let held_up = self.reconnect(conn_out, events, tick, error)?;
+
+                // The message that failed goes out on the reopened port
+                if self.control.running() {
+                    conn_out.send(data).context("Failed to send MIDI message")?;
+                }
+
+                Ok(Some(held_up))
+            }
+        }
+    }
+
+    /// Sends `data` of `event` to the port its route takes it to, or else to
+    /// the output like `send_mirrored`.
+    fn send_routed(
+        &mut self,
+        conn_out: &mut OutputPort,
+        backup: &mut Option<(u32, OutputPort)>,
+        events: &[DataEvent],
+        tick: u64,
+        event: &DataEvent,
+        data: &[u8],
+    ) -> Result<Option<Duration>> {
+        let route = routing::find(&self.routes, event);
+
+        match self.route_port(route) {
+            Some(routed_out) => {
+                routed_out
+                    .send(data)
+                    .context("Failed to send routed MIDI message")?;
+                Ok(None)
+            }
+            None => self.send_mirrored(conn_out, backup, events, tick, data),
+        }
+    }
+
+    /// The open port of the route at `route` of `routes`.
+    fn route_port(&mut self, route: Option<usize>) -> Option<&mut ExtraPort> {
+        let port_id = self.routes.get(route?)?.port_id;
+
+        self.routed
+            .iter_mut()
+            .find(|routed_out| routed_out.port_id == port_id)
+    }
+
+    /// Runs `action` on every mirror, each with its own buffers, no longer
+    /// mirroring to those it fails on.
+    fn each_mirror(&mut self, mut action: impl FnMut(&mut ExtraPort) -> Result<()>) {
+        let player_events = &self.player_events;
+
+        self.mirrors.retain_mut(|mirror| match action(mirror) {
+            Ok(()) => true,
+            Err(e) => {
+                player_events.message(format!(
+                    "Port {} failed, no longer mirroring: {:#}",
+                    mirror.port_id, e
+                ));
+                false
+            }
+        });
+    }
+
+    /// Silences the mirrors and replays the state up to `tick` on them.
+    fn chase_mirrors(&mut self, events: &[DataEvent], tick: u64) -> Result<()> {
+        let mut mirrors = mem::take(&mut self.mirrors);
+        for mirror in &mut mirrors {
+            silence(mirror.cancel())?;
+            self.chase(mirror.cancel(), events, tick)?;
+        }
+        self.mirrors = mirrors;
+
+        Ok(())
+    }
+
+    /// Silences the ports of the routes, whose notes are not tracked.
+    fn silence_routed(&mut self) -> Result<()> {
+        for routed_out in &mut self.routed {
+            silence(routed_out.cancel())?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends what is held back for the routes and mirrors once it is due.
+    fn flush_extra(&mut self) -> Result<()> {
+        for routed_out in &mut self.routed {
+            routed_out.flush()?;
+        }
+        self.each_mirror(ExtraPort::flush);
+
+        Ok(())
+    }
+
+    /// Swaps the playing port with the standby one of an A/B comparison, if
+    /// there is one, replaying the state up to `tick` on the newly playing one.
+    fn switch_output(
+        &mut self,
+        conn_out: &mut OutputPort,
+        standby: &mut Option<(u32, OutputPort)>,
+        events: &[DataEvent],
+        tick: u64,
+    ) -> Result<()> {
+        let (port_id, standby_out) = match standby {
+            Some(standby) => standby,
+            None => return Ok(()),
+        };
+
+        silence(conn_out)?;
+        mem::swap(conn_out, standby_out);
+        mem::swap(&mut self.port_id, port_id);
+        self.port_name = OutputPort::name(self.port_id).unwrap_or_default();
+
+        self.chase(conn_out, events, tick + 1)?;
+        self.player_events
+            .message(format!("Now playing on port {}", self.port_id));
+
+        Ok(())
+    }
+
+    /// Plays the notes sounding at `tick` or starting shortly after it as a
+    /// brief chord, with the programs and controllers in effect there.
+    fn play_scrub(
+        &mut self,
+        conn_out: &mut OutputPort,
+        events: &[DataEvent],
+        tick: u64,
+    ) -> Result<()> {
+        self.chase(conn_out, events, tick + 1)?;
+
+        for mut message in scrub_notes(events, tick, self.division) {
+            if check_safety(&mut self.safety, &self.player_events, &mut message) {
+                conn_out
+                    .send(&message)
+                    .context("Failed to send MIDI message")?;
+            }
+        }
+
+        thread::sleep(SCRUB_LENGTH);
+        self.reset_safety();
+        silence(conn_out)
+    }
+
+    /// Sends where playback is at, unless the last report was less than
+    /// `PROGRESS_INTERVAL` ago.
+    fn report_progress(&self, last_progress: &mut Instant, tick: u64, length: u64) {
+        if last_progress.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        *last_progress = Instant::now();
+
+        let percent = match length {
+            0 => 100.0,
+            _ => (tick as f64 * 100.0 / length as f64).min(100.0),
+        };
+
+        let (bar, beat, beat_tick) = timeline::locate(tick, self.division, &self.signatures);
+
+        self.player_events.send(PlayerEvent::Progress {
+            tick,
+            length,
+            elapsed: self.tempo_map.time_at(tick, self.division),
+            percent,
+            bar,
+            beat,
+            beat_tick,
+        });
+    }
+
+    /// Silences the output until playback is resumed or stopped, returning how
+    /// long it was paused. Scrubbing is heard in the meantime.
+    fn wait_while_paused(
+        &mut self,
+        conn_out: &mut OutputPort,
+        events: &[DataEvent],
+    ) -> Result<Duration> {
+        let paused_at = Instant::now();
+        silence(conn_out)?;
+        self.reset_safety();
+        self.silence_routed()?;
+        self.each_mirror(|mirror| silence(mirror.cancel()));
+        if self.clock {
+            conn_out
+                .send(&[clock::STOP])
+                .context("Failed to send stop")?;
+        }
+        self.player_events.message("Paused");
+
+        while self.control.paused.load(Ordering::Relaxed) && self.control.running() {
+            if let Some(tick) = self.control.take_scrub() {
+                self.play_scrub(conn_out, events, tick)?;
+            }
+
+            self.check_panic(conn_out)?;
+
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+
+        // Left over when resumed mid-burst, the seek already covers it
+        self.control.scrub.store(NO_SEEK, Ordering::Relaxed);
+        if self.clock && self.control.running() {
+            conn_out
+                .send(&[clock::CONTINUE])
+                .context("Failed to send continue")?;
+        }
+        self.player_events.message("Resumed");
+
+        Ok(paused_at.elapsed())
+    }
+
+    /// Silences everything if a panic was asked for, and the channels muted
+    /// since the last call.
+    fn check_panic(&mut self, conn_out: &mut OutputPort) -> Result<()> {
+        let muted = self.control.muted.load(Ordering::Relaxed);
+        let newly_muted = muted & !self.silenced;
+        self.silenced = muted;
+        for channel in (0..16).filter(|channel| newly_muted & 1 << channel != 0) {
+            let all_notes_off = [0xb0 | channel, 123, 0];
+            conn_out
+                .send(&all_notes_off)
+                .context("Failed to send all notes off")?;
+            for routed_out in &mut self.routed {
+                routed_out.cancel().send(&all_notes_off)?;
+            }
+            self.each_mirror(|mirror| mirror.send(&all_notes_off));
+            if let Some(safety) = &mut self.safety {
+                safety.release_channel(channel);
+            }
+        }
+
+        if self.control.panic.swap(false, Ordering::Relaxed) {
+            all_sound_off(conn_out)?;
+            for routed_out in &mut self.routed {
+                all_sound_off(routed_out.cancel())?;
+            }
+            self.each_mirror(|mirror| all_sound_off(mirror.cancel()));
+            self.reset_safety();
+            self.player_events.message("Panic: all notes and sound off");
+        }
+
+        Ok(())
+    }
+
+    /// Has the safety limits forget the notes the output was silenced of.
+    fn reset_safety(&mut self) {
+        if let Some(safety) = &mut self.safety {
+            safety.reset();
+        }
+    }
+
+    fn play_events(mut self) -> Result<()> {
+        for info in mem::take(&mut self.track_info) {
+            self.player_events.message(info);
+        }
+
+        // Looping sequences play until stopped
+        if self.loop_length.is_none() {
+            let length = self.events.iter().map(|event| event.delta_time).sum();
+            let duration = self.tempo_map.time_at(length, self.division);
+            self.player_events
+                .message(format!("{} total", format_time(duration)));
+        }
+
+        #[cfg(windows)]
+        if self.engine == Engine::Stream {
+            return self.play_stream();
+        }
+
+        let mut conn_out = match self.take_handoff()? {
+            Some(conn_out) => conn_out,
+            None => {
+                let conn_out = self.connect(self.port_id)?;
+                self.wait_lead_in();
+                conn_out
+            }
+        };
+        self.port_name = OutputPort::name(self.port_id).unwrap_or_default();
+        let mut standby = match self.compare_port {
+            Some(port_id) => Some((port_id, self.connect(port_id)?)),
+            None => None,
+        };
+        let mut backup = match self.backup_port {
+            Some(port_id) => Some((port_id, self.connect(port_id)?)),
+            None => None,
+        };
+        for port_id in self.also_ports.clone() {
+            let mirror = self.connect_extra(port_id, &conn_out)?;
+            self.mirrors.push(mirror);
+            self.player_events
+                .message(format!("Mirroring to port {}", port_id));
+        }
+        for route in self.routes.clone() {
+            if !self
+                .routed
+                .iter()
+                .any(|routed_out| routed_out.port_id == route.port_id)
+            {
+                let routed_out = self.connect_extra(route.port_id, &conn_out)?;
+                self.routed.push(routed_out);
+            }
+
+            let source = match route.source {
+                Source::Track(track) => format!("track {}", track + 1),
+                Source::Channel(channel) => format!("channel {}", channel + 1),
+            };
+            self.player_events
+                .message(format!("Routing {} to port {}", source, route.port_id));
+        }
+
+        let thread_boost = ThreadBoost::new();
+        self.player_events
+            .message(format!("Task Index: {}", thread_boost.task_index()));
+
+        let timer = PreciseTimer::new();
+        self.play_count_in(&mut conn_out, &timer)?;
+
+        // Waking up now and then lets stopping and pausing take effect
+        // during long gaps between events
+        let max_sleep = if self.thru.is_some() {
+            THRU_POLL_INTERVAL
+        } else {
+            PAUSE_POLL_INTERVAL
+        };
+
+        // Events are due at fixed offsets from the start, so a late wake-up
+        // delays only the event it was waiting for
+        let mut start = Instant::now();
+
+        // Sum of ticks times tempo, only divided down to microseconds when
+        // needed so rounding does not add up either
+        let mut timeline: u128 = 0;
+        // Speed the timeline is played at since `start`
+        let mut speed = self.control.speed();
+        // Tick of the pass that `timeline` has reached
+        let mut timeline_tick = 0;
+
+        let events = mem::take(&mut self.events);
+        let mut index = 0;
+
+        let length = self
+            .loop_length
+            .unwrap_or_else(|| events.iter().map(|event| event.delta_time).sum());
+        let mut last_progress = Instant::now();
+
+        // Ticks since the start of the current pass, to pad out loops
+        let mut position = 0;
+        let mut pending_ticks = 0;
+
+        // Ticks of the next event that already passed before a seek target
+        let mut skipped_ticks = 0;
+
+        let form = mem::take(&mut self.jumps);
+        let mut jumps = form.clone().into_iter().peekable();
+
+        let first_tick = match self.section {
+            Some((from, _)) => from,
+            None => self.start,
+        };
+        if first_tick > 0 {
+            self.control.seek.store(first_tick, Ordering::Relaxed);
+        }
+
+        let mut follower = match self.sync_port {
+            Some(port_id) => {
+                self.player_events.message(format!(
+                    "Waiting for the clock master on input port {} to start",
+                    port_id
+                ));
+                Some(ClockFollower::connect(port_id, self.division)?)
+            }
+            None => None,
+        };
+
+        // Released one by one when stopped, as resets cut some devices short
+        let mut notes = NoteTracker::default();
+        // Level of the fade controller on every channel played on
+        let mut levels = [None; 16];
+
+        'events: loop {
+            if let Some(tick) = self.control.take_seek() {
+                let tick = match self.loop_length {
+                    Some(loop_length) if loop_length > 0 => tick % loop_length,
+                    _ => tick,
+                };
+                self.player_events
+                    .message(format!("Seeking to tick {}", tick));
+
+                silence(&mut conn_out)?;
+                self.silence_routed()?;
+                notes.clear();
+                self.reset_safety();
+                let (seek_index, seek_position) = self.chase(&mut conn_out, &events, tick)?;
+                if let Some((_, backup_out)) = &mut backup {
+                    silence(backup_out)?;
+                    self.chase(backup_out, &events, tick)?;
+                }
+                self.chase_mirrors(&events, tick)?;
+
+                self.locate_sync(&mut conn_out, tick)?;
+                if let Some(follower) = &mut follower {
+                    follower.locate(tick);
+                }
+
+                index = seek_index;
+                position = seek_position;
+                pending_ticks = 0;
+                skipped_ticks = tick - seek_position;
+                start = Instant::now();
+                timeline = 0;
+                timeline_tick = tick;
+            }
+
+            if self.control.switch_output.swap(false, Ordering::Relaxed) {
+                self.switch_output(&mut conn_out, &mut standby, &events, position)?;
+                notes.clear();
+                self.reset_safety();
+            }
+
+            self.check_panic(&mut conn_out)?;
+
+            if index == events.len() {
+                match self.loop_length {
+                    Some(loop_length) if !events.is_empty() => {
+                        timeline += self.tempo_map.span(timeline_tick, loop_length);
+                        timeline_tick = 0;
+                        if let Some(follower) = &mut follower {
+                            follower.rewind(loop_length);
+                        }
+                        pending_ticks = loop_length.saturating_sub(position);
+                        position = 0;
+                        index = 0;
+                    }
+                    _ => break,
+                }
+            }
+
+            let event = &events[index];
+            index += 1;
+
+            if !self.control.running() {
+                break;
+            }
+
+            if self.control.paused.load(Ordering::Relaxed) {
+                if let Some((_, backup_out)) = &mut backup {
+                    silence(backup_out)?;
+                }
+                start += self.wait_while_paused(&mut conn_out, &events)?;
+                notes.clear();
+                self.reset_safety();
+            }
+
+            //println!("event: {}", event);
+
+            conn_out.wait_ready();
+
+            let delta_time = (event.delta_time + pending_ticks).saturating_sub(skipped_ticks);
+            pending_ticks = 0;
+            skipped_ticks = 0;
+            position += event.delta_time;
+
+            // The master's clock decides when the event is due
+            if let Some(follower) = &mut follower {
+                loop {
+                    match follower.poll() {
+                        Some(Transport::Start) => {
+                            self.player_events.message("Clock master started");
+                            release(&mut conn_out, &notes)?;
+                            notes.clear();
+                            self.reset_safety();
+
+                            index = 0;
+                            position = 0;
+                            pending_ticks = 0;
+                            skipped_ticks = 0;
+                            jumps = form.clone().into_iter().peekable();
+                            continue 'events;
+                        }
+                        Some(Transport::Locate(tick)) => {
+                            self.control.seek.store(tick, Ordering::Relaxed);
+                            continue 'events;
+                        }
+                        Some(Transport::Stop) => {
+                            self.player_events.message("Clock master stopped");
+                            release(&mut conn_out, &notes)?;
+                            notes.clear();
+                            self.reset_safety();
+                        }
+                        Some(Transport::Continue) | None => {}
+                    };
+
+                    if !self.control.running() {
+                        break 'events;
+                    }
+                    if self.control.seek.load(Ordering::Relaxed) != NO_SEEK {
+                        continue 'events;
+                    }
+                    self.check_panic(&mut conn_out)?;
+
+                    if follower.reached(position) {
+                        break;
+                    }
+
+                    self.report_progress(&mut last_progress, position - event.delta_time, length);
+                    thread::sleep(FOLLOW_POLL_INTERVAL);
+                }
+            }
+
+            if delta_time > 0 && follower.is_none() {
+                // Waits crossing tempo changes take each part at its own tempo
+                timeline += self.tempo_map.span(timeline_tick, position);
+                timeline_tick = position;
+                let due_micros = (timeline / self.division as u128) as u64;
+                //println!("due: {}", due_micros);
+
+                loop {
+                    // What already played stays where it was, only what is
+                    // left of the wait stretches or shrinks
+                    let new_speed = self.control.speed();
+                    if new_speed != speed {
+                        let played = start.elapsed().mul_f64(speed / new_speed);
+                        start = Instant::now().checked_sub(played).unwrap_or(start);
+                        speed = new_speed;
+                    }
+
+                    let due = Duration::from_micros(due_micros).div_f64(speed);
+                    let elapsed = start.elapsed();
+
+                    if elapsed >= due {
+                        if elapsed - due > LATE_THRESHOLD {
+                            self.player_events.send(PlayerEvent::Late(elapsed - due));
+                        }
+                        break;
+                    } else {
+                        conn_out.check_inflight()?;
+                        self.flush_extra()?;
+
+                        if let Some(thru) = &self.thru {
+                            let safety = &mut self.safety;
+                            let player_events = &self.player_events;
+
+                            thru::drain(thru, |mut message| {
+                                if check_safety(safety, player_events, &mut message) {
+                                    conn_out
+                                        .send(&message)
+                                        .context("Failed to send thru message")?;
+                                }
+
+                                Ok(())
+                            })?;
+                        }
+
+                        if !self.control.running() {
+                            break 'events;
+                        }
+
+                        // Seeking jumps to another point of the timeline
+                        if self.control.seek.load(Ordering::Relaxed) != NO_SEEK {
+                            continue 'events;
+                        }
+
+                        // Only what played before the pending event is replayed
+                        if self.control.switch_output.swap(false, Ordering::Relaxed) {
+                            let tick = position - event.delta_time;
+                            self.switch_output(&mut conn_out, &mut standby, &events, tick)?;
+                            notes.clear();
+                            self.reset_safety();
+                        }
+
+                        self.check_panic(&mut conn_out)?;
+
+                        let tick = position - event.delta_time;
+                        self.report_progress(&mut last_progress, tick, length);
+
+                        if self.control.paused.load(Ordering::Relaxed) {
+                            if let Some((_, backup_out)) = &mut backup {
+                                silence(backup_out)?;
+                            }
+                            start += self.wait_while_paused(&mut conn_out, &events)?;
+                            notes.clear();
+                            self.reset_safety();
+                            continue;
+                        }
+
+                        timer.wait(due - elapsed, max_sleep);
+                    }
+                }
+            }
+
+            self.report_progress(&mut last_progress, position, length);
+
+            // The event at the end point belongs to what is left out
+            if matches!(self.end, Some(end) if position >= end) {
+                release(&mut conn_out, &notes)?;
+                self.silence_routed()?;
+                self.each_mirror(|mirror| release(mirror.cancel(), &notes));
+                if let Some((_, backup_out)) = &mut backup {
+                    release(backup_out, &notes)?;
+                }
+                notes.clear();
+                self.reset_safety();
+                break;
+            }
+
+            // The event at the jump point belongs to what is jumped over
+            let jump = match jumps.peek() {
+                Some(&(at, _)) if position >= at => jumps.next(),
+                _ => match self.section {
+                    Some((from, to)) if position >= to => Some((to, from)),
+                    _ => None,
+                },
+            };
+            if let Some((at, to)) = jump {
+                self.player_events
+                    .message(format!("Jumping from tick {} to {}", at, to));
+
+                release(&mut conn_out, &notes)?;
+                self.silence_routed()?;
+                self.each_mirror(|mirror| release(mirror.cancel(), &notes));
+                notes.clear();
+                self.reset_safety();
+                if let Some((_, backup_out)) = &mut backup {
+                    silence(backup_out)?;
+                }
+
+                if let Some((_, backup_out)) = &mut backup {
+                    self.chase(backup_out, &events, to)?;
+                }
+                self.chase_mirrors(&events, to)?;
+                let (jump_index, jump_position) = self.chase(&mut conn_out, &events, to)?;
+
+                self.locate_sync(&mut conn_out, to)?;
+                if let Some(follower) = &mut follower {
+                    follower.locate(to);
+                }
+
+                index = jump_index;
+                position = jump_position;
+                timeline_tick = to;
+                skipped_ticks = to - jump_position;
+                continue;
+            }
+
+            match &event.data {
+                LocalEvent::Meta(meta) => {
+                    match meta.command {
+                        MetaCommand::TempoSetting => {
+                            let tempo = meta.data_as_u64(3);
+                            self.player_events.send(PlayerEvent::Tempo(tempo));
+                        }
+                        MetaCommand::KeySignature => match KeySignature::from_meta(&meta.data) {
+                            Some(key) => self.player_events.send(PlayerEvent::KeySignature(key)),
+                            None => self.player_events.message(format!("{}", meta)),
+                        },
+                        MetaCommand::TimeSignature => match TimeSignature::from_meta(&meta.data) {
+                            Some(signature) => self
+                                .player_events
+                                .send(PlayerEvent::TimeSignature(signature)),
+                            None => self.player_events.message(format!("{}", meta)),
+                        },
+                        MetaCommand::LyricText => {
+                            let lyric = text::decode(&meta.data, self.text_encoding);
+                            self.player_events.send(PlayerEvent::Lyric(lyric));
+                        }
+                        // A jump point passed again after its jump was taken
+                        MetaCommand::CuePoint if meta.data.is_empty() => {}
+                        _ => self.player_events.message(format!("{}", meta)),
+                    };
+
+                    // Set the event so we are not stuck waiting for too long
+                    conn_out.mark_ready();
+                }
+                LocalEvent::SysEx(data) => {
+                    //println!("delta time: {}, data: {:02x?}", event.delta_time, data);
+                    if let Some(held_up) = self.send_routed(
+                        &mut conn_out,
+                        &mut backup,
+                        &events,
+                        position,
+                        event,
+                        data,
+                    )? {
+                        start += held_up;
+                    }
+
+                    self.player_events
+                        .send(PlayerEvent::sent(event.delta_time, data.clone()));
+
+                    // Everything after the message moves back by the pause
+                    if let Some(delay) = self.sysex_delay {
+                        thread::sleep(delay);
+                        start += delay;
+                    }
+                }
+                LocalEvent::Midi(data) => {
+                    let mut data = *data;
+                    if !check_safety(&mut self.safety, &self.player_events, &mut data) {
+                        continue;
+                    }
+                    let muted = self.control.muted.load(Ordering::Relaxed);
+                    if data[0] & 0xf0 == 0x90 && data[2] > 0 && muted & 1 << (data[0] & 0x0f) != 0 {
+                        continue;
+                    }
+
+                    //println!("delta time: {}, data: {:02x?}", event.delta_time, data);
+                    let message = &data[..midi_file::message_length(data[0])];
+                    if let Some(held_up) = self.send_routed(
+                        &mut conn_out,
+                        &mut backup,
+                        &events,
+                        position,
+                        event,
+                        message,
+                    )? {
+                        start += held_up;
+                    }
+                    if clock::is_sync(&data) {
+                        continue;
+                    }
+                    // Notes of routed ports are silenced instead of released
+                    if routing::find(&self.routes, event).is_none() {
+                        notes.process(&data);
+                        track_level(&mut levels, self.fade_controller, &data);
+                    }
+                    self.player_events
+                        .send(PlayerEvent::sent(event.delta_time, data.to_vec()));
+                }
+            };
+        }
+
+        if self.clock {
+            conn_out
+                .send(&[clock::STOP])
+                .context("Failed to send stop")?;
+        }
+
+        // What the routes and mirrors hold back still has to play out
+        if self.control.running() {
+            for routed_out in &mut self.routed {
+                routed_out.finish()?;
+            }
+            self.each_mirror(ExtraPort::finish);
+        }
+
+        if !self.control.running() {
+            if let Some(length) = self.fade_out {
+                fade(&mut conn_out, self.fade_controller, &levels, length)?;
+            }
+
+            release(&mut conn_out, &notes)?;
+            if self.fade_out.is_some() {
+                restore_levels(&mut conn_out, self.fade_controller, &levels)?;
+            }
+            if let Some((_, backup_out)) = &mut backup {
+                release(backup_out, &notes)?;
+            }
+            self.each_mirror(|mirror| release(mirror.cancel(), &notes));
+            self.silence_routed()?;
+        } else if let Some(handoff) = &self.handoff {
+            handoff.park(self.port_id, conn_out);
+        }
+
+        Ok(())
+    }
+}
+
+/// Puts an event at every tick of `points`, ahead of those already there,
+/// so jumps from between two events are still taken on time.
+fn with_jump_points(events: Vec<DataEvent>, points: &[u64]) -> Vec<DataEvent> {
+    let jump_point = || {
+        LocalEvent::Meta(MetaEvent {
+            command: MetaCommand::CuePoint,
+            length: 0,
+            data: Vec::new(),
+        })
+    };
+
+    let mut combined = Vec::with_capacity(events.len() + points.len());
+    let mut points = points.iter().peekable();
+    let mut tick = 0;
+    let mut last_tick = 0;
+
+    for mut event in events {
+        tick += event.delta_time;
+
+        while let Some(&&point) = points.peek() {
+            if point > tick {
+                break;
+            }
+
+            combined.push(DataEvent::new(point - last_tick, jump_point()));
+            last_tick = point;
+            points.next();
+        }
+
+        event.delta_time = tick - last_tick;
+        last_tick = tick;
+        combined.push(event);
+    }
+
+    // Past the end, such as the cues of a song outlasting them
+    for &point in points {
+        combined.push(DataEvent::new(point - last_tick, jump_point()));
+        last_tick = point;
+    }
+
+    combined
+}
+
+/// The note ons of a scrub at `tick`: notes still sounding there and those
+/// starting within a sixteenth after it, thinned to the loudest few.
+fn scrub_notes(events: &[DataEvent], tick: u64, division: u64) -> Vec<[u8; 3]> {
+    let end = tick + division / 4;
+
+    // Velocity of every (channel, key) sounding
+    let mut sounding: BTreeMap<(u8, u8), u8> = BTreeMap::new();
+    let mut position = 0;
+
+    for event in events {
+        position += event.delta_time;
+        if position > end {
+            break;
+        }
+
+        if let LocalEvent::Midi(data) = &event.data {
+            let note = (data[0] & 0x0f, data[1]);
+
+            match (data[0] & 0xf0, data[2]) {
+                (0x90, velocity) if velocity > 0 => {
+                    sounding.insert(note, velocity);
+                }
+                (0x80, _) | (0x90, _) if position <= tick => {
+                    sounding.remove(&note);
+                }
+                _ => {}
+            };
+        }
+    }
+
+    let mut notes: Vec<[u8; 3]> = sounding
+        .into_iter()
+        .map(|((channel, key), velocity)| [0x90 | channel, key, velocity])
+        .collect();
+    notes.sort_by_key(|note| Reverse(note[2]));
+    notes.truncate(SCRUB_NOTES);
+
+    notes
+}
+
+/// Notes the level `controller` is at on the channel of `data`, taking
+/// channels that never set it to be at its default.
+fn track_level(levels: &mut [Option<u8>; 16], controller: u8, data: &[u8; 3]) {
+    if data[0] >= 0xf0 {
+        return;
+    }
+
+    let level = &mut levels[(data[0] & 0x0f) as usize];
+    if data[0] & 0xf0 == 0xb0 && data[1] == controller {
+        *level = Some(data[2]);
+    } else if level.is_none() {
+        *level = Some(if controller == 7 { 100 } else { 127 });
+    }
+}
+
+/// Turns `controller` down to nothing over `length` on every channel in
+/// `levels`.
+fn fade(
+    conn_out: &mut OutputPort,
+    controller: u8,
+    levels: &[Option<u8>; 16],
+    length: Duration,
+) -> Result<()> {
+    for step in (0..FADE_STEPS).rev() {
+        for (channel, level) in levels.iter().enumerate() {
+            if let Some(level) = level {
+                let value = (*level as u32 * step / FADE_STEPS) as u8;
+                conn_out
+                    .send(&[0xb0 | channel as u8, controller, value])
+                    .context("Failed to send fade")?;
+            }
+        }
+
+        thread::sleep(length / FADE_STEPS);
+    }
+
+    Ok(())
+}
+
+/// Puts `controller` back where it was before a fade, once nothing sounds,
+/// so the next song does not start silent.
+fn restore_levels(
+    conn_out: &mut OutputPort,
+    controller: u8,
+    levels: &[Option<u8>; 16],
+) -> Result<()> {
+    for (channel, level) in levels.iter().enumerate() {
+        if let Some(level) = level {
+            conn_out
+                .send(&[0xb0 | channel as u8, controller, *level])
+                .context("Failed to restore controller")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a note off for every note still held, then silences all channels,
+/// so stopping mid-song lets the notes end with their releases.
+fn release(conn_out: &mut OutputPort, notes: &NoteTracker) -> Result<()> {
+    for (channel, key) in notes.held() {
+        conn_out
+            .send(&[0x80 | channel, key, 0])
+            .context("Failed to send note off")?;
+    }
+
+    silence(conn_out)
+}
+
+/// Releases the sustain pedal and every sounding note on all channels.
+fn silence(conn_out: &mut OutputPort) -> Result<()> {
+    for channel in 0..16 {
+        conn_out
+            .send(&[0xb0 | channel, 64, 0])
+            .context("Failed to release sustain pedal")?;
+        conn_out
+            .send(&[0xb0 | channel, 123, 0])
+            .context("Failed to send all notes off")?;
+    }
+
+    Ok(())
+}
+
+/// Releases the sustain pedal and cuts every note and its release on all
+/// channels.
+fn all_sound_off(conn_out: &mut OutputPort) -> Result<()> {
+    for channel in 0..16 {
+        conn_out
+            .send(&[0xb0 | channel, 64, 0])
+            .context("Failed to release sustain pedal")?;
+        conn_out
+            .send(&[0xb0 | channel, 120, 0])
+            .context("Failed to send all sound off")?;
+        conn_out
+            .send(&[0xb0 | channel, 123, 0])
+            .context("Failed to send all notes off")?;
+    }
+
+    Ok(())
+}
+
+/// Runs `message` through the safety limits, if any, logging anything they
+/// altered. Returns whether the message should still be sent.
+fn check_safety(
+    safety: &mut Option<SafetyLimiter>,
+    player_events: &PlayerEvents,
+    message: &mut [u8],
+) -> bool {
+    let safety = match safety {
+        Some(safety) => safety,
+        None => return true,
+    };
+
+    match safety.filter(message) {
+        Verdict::Send => true,
+        Verdict::Clamped(reason) => {
+            player_events.message(format!("Safety: {}", reason));
+            true
+        }
+        Verdict::Dropped(reason) => {
+            player_events.message(format!("Safety: dropped {}", reason));
+            false
+        }
+    }
+}
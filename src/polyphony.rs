@@ -0,0 +1,200 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::midi_file::{DataEvent, LocalEvent};
+use crate::note_tracker::NoteTracker;
+
+/// Voice count of a typical General MIDI sound module.
+pub const DEFAULT_VOICE_LIMIT: usize = 64;
+
+/// Counts the voices sounding on each channel as messages go out, remembering
+/// the peaks and when the voice limit would have been exceeded.
+pub struct VoiceTracker {
+    limit: usize,
+    start: Instant,
+    notes: NoteTracker,
+    peaks: [usize; 16],
+    peak_total: usize,
+    first_overload: Option<Duration>,
+    overloaded_events: usize,
+}
+
+impl VoiceTracker {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            start: Instant::now(),
+            notes: NoteTracker::default(),
+            peaks: [0; 16],
+            peak_total: 0,
+            first_overload: None,
+            overloaded_events: 0,
+        }
+    }
+
+    /// Starts over for the next song.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.limit);
+    }
+
+    pub fn process(&mut self, message: &[u8]) {
+        self.notes.process(message);
+
+        if message.len() >= 3 && message[0] & 0xf0 == 0x90 && message[2] > 0 {
+            self.update_peaks((message[0] & 0x0f) as usize);
+        }
+    }
+
+    fn update_peaks(&mut self, channel: usize) {
+        self.peaks[channel] = self.peaks[channel].max(self.notes.voices(channel));
+
+        let total = self.total();
+        self.peak_total = self.peak_total.max(total);
+
+        if total > self.limit {
+            self.overloaded_events += 1;
+            let start = self.start;
+            self.first_overload.get_or_insert_with(|| start.elapsed());
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.notes.total()
+    }
+
+    /// Peak voices per channel and overall, and whether the limit was hit.
+    pub fn report(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Peak polyphony: {} of {} voices",
+            self.peak_total, self.limit
+        )];
+
+        for (channel, peak) in self.peaks.iter().enumerate() {
+            if *peak > 0 {
+                lines.push(format!("  - Channel {}: {}", channel + 1, peak));
+            }
+        }
+
+        if let Some(first_overload) = self.first_overload {
+            lines.push(format!(
+                "Voice limit exceeded by {} note ons, first at {:.1}s",
+                self.overloaded_events,
+                first_overload.as_secs_f64()
+            ));
+        }
+
+        lines
+    }
+}
+
+/// Which sounding note makes way when a note on would go over the limit.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Steal {
+    Oldest,
+    /// The softest note on, the oldest of those.
+    Quietest,
+}
+
+impl Steal {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "oldest" => Ok(Steal::Oldest),
+            "quietest" => Ok(Steal::Quietest),
+            _ => Err(anyhow!(
+                "Unknown note stealing: {}, use oldest or quietest",
+                value
+            )),
+        }
+    }
+}
+
+/// A cap on the notes held at once, for vintage modules that drop notes
+/// badly when they run out of voices.
+#[derive(Clone, Copy)]
+pub struct PolyphonyLimit {
+    pub voices: usize,
+    pub steal: Steal,
+}
+
+/// Ends a sounding note ahead of every note on that would go over the
+/// limit, leaving out the stolen note's own note off. Only keys held down
+/// count, as notes kept sounding by the sustain pedal cannot be stolen.
+pub fn limit(events: Vec<DataEvent>, limit: Option<PolyphonyLimit>) -> Vec<DataEvent> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return events,
+    };
+
+    let mut kept = Vec::with_capacity(events.len());
+    // Channel, key and velocity of the held notes, oldest first
+    let mut sounding: Vec<(u8, u8, u8)> = Vec::new();
+    // Note offs of stolen notes still to come, per channel and key
+    let mut stolen = [[0u8; 128]; 16];
+    let mut carried = 0;
+
+    for mut event in events {
+        event.delta_time += carried;
+        carried = 0;
+
+        let data = match &event.data {
+            LocalEvent::Midi(data) => *data,
+            _ => {
+                kept.push(event);
+                continue;
+            }
+        };
+        let channel = data[0] & 0x0f;
+        let key = data[1] & 0x7f;
+
+        match data[0] & 0xf0 {
+            0x90 if data[2] > 0 => {
+                if sounding.len() >= limit.voices.max(1) {
+                    let victim = match limit.steal {
+                        Steal::Oldest => 0,
+                        Steal::Quietest => sounding
+                            .iter()
+                            .enumerate()
+                            .min_by_key(|&(i, &(_, _, velocity))| (velocity, i))
+                            .map_or(0, |(i, _)| i),
+                    };
+                    let (victim_channel, victim_key, _) = sounding.remove(victim);
+                    let stolen_count = &mut stolen[victim_channel as usize][victim_key as usize];
+                    *stolen_count = stolen_count.saturating_add(1);
+
+                    kept.push(DataEvent::new(
+                        event.delta_time,
+                        LocalEvent::Midi([0x80 | victim_channel, victim_key, 0]),
+                    ));
+                    event.delta_time = 0;
+                }
+
+                sounding.push((channel, key, data[2]));
+            }
+            0x80 | 0x90 => {
+                let stolen_count = &mut stolen[channel as usize][key as usize];
+                if *stolen_count > 0 {
+                    *stolen_count -= 1;
+                    carried = event.delta_time;
+                    continue;
+                }
+
+                if let Some(i) = sounding.iter().position(|&(held_channel, held_key, _)| {
+                    (held_channel, held_key) == (channel, key)
+                }) {
+                    sounding.remove(i);
+                }
+            }
+            // All sound off and all notes off
+            0xb0 if data[1] == 120 || data[1] == 123 => {
+                sounding.retain(|&(held_channel, _, _)| held_channel != channel);
+                stolen[channel as usize] = [0; 128];
+            }
+            _ => {}
+        };
+
+        kept.push(event);
+    }
+
+    kept
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use rimd::MetaCommand;
+
+use crate::midi_file::{LocalEvent, Sequence};
+use crate::player::DEFAULT_TEMPO;
+use crate::tempo::TempoMap;
+use crate::text;
+use crate::timeline;
+
+/// A point of a song given on the command line.
+#[derive(Clone, PartialEq)]
+pub enum Position {
+    Tick(u64),
+    /// Bar and beat, both counted from 1, through the time signatures.
+    Bar(u64, u64),
+    /// From the start of the song, through its tempo changes.
+    Time(Duration),
+    /// The first marker with this text, matched ignoring case.
+    Marker(String),
+}
+
+impl Position {
+    /// Parses a tick, a bar and beat as `<bar>.<beat>`, a time as `m:ss.fff`,
+    /// `<seconds>s` or `<millis>ms`, or else the text of a marker.
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(anyhow!("Empty position"));
+        }
+
+        if let Ok(tick) = value.parse() {
+            return Ok(Position::Tick(tick));
+        }
+
+        if let Some((bar, beat)) = value.split_once('.') {
+            if let (Ok(bar), Ok(beat)) = (bar.parse::<u64>(), beat.parse::<u64>()) {
+                if bar == 0 || beat == 0 {
+                    return Err(anyhow!("Bars and beats count from 1: {}", value));
+                }
+
+                return Ok(Position::Bar(bar, beat));
+            }
+        }
+
+        Ok(match parse_time(value) {
+            Some(time) => Position::Time(time),
+            None => Position::Marker(value.to_string()),
+        })
+    }
+
+    /// The tick of the position in `sequence`.
+    pub fn tick(&self, sequence: &Sequence) -> Result<u64> {
+        Locator::new(sequence).tick(self)
+    }
+}
+
+/// What it takes to find positions in a song, kept for after its events
+/// went to the player.
+#[derive(Clone)]
+pub struct Locator {
+    title: String,
+    division: u64,
+    signatures: Vec<(u64, (u8, u8))>,
+    tempo_map: TempoMap,
+    markers: Vec<(u64, String)>,
+}
+
+impl Locator {
+    pub fn new(sequence: &Sequence) -> Self {
+        Self {
+            title: sequence.title.clone(),
+            division: sequence.division,
+            signatures: timeline::time_signatures(&sequence.events),
+            tempo_map: TempoMap::from_events(&sequence.events, DEFAULT_TEMPO),
+            markers: markers(sequence),
+        }
+    }
+
+    /// The tick of `position` in the song.
+    pub fn tick(&self, position: &Position) -> Result<u64> {
+        match position {
+            Position::Tick(tick) => Ok(*tick),
+            Position::Bar(bar, beat) => Ok(timeline::tick_of(
+                *bar,
+                *beat,
+                self.division,
+                &self.signatures,
+            )),
+            Position::Time(time) => Ok(self.tempo_map.tick_at(*time, self.division)),
+            Position::Marker(name) => self
+                .markers
+                .iter()
+                .find(|(_, marker)| marker.trim().eq_ignore_ascii_case(name))
+                .map(|(tick, _)| *tick)
+                .with_context(|| format!("No marker {} in {}", name, self.title)),
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// The marker texts of the song by tick.
+    pub fn markers(&self) -> &[(u64, String)] {
+        &self.markers
+    }
+}
+
+/// The marker texts of `sequence` by tick.
+pub fn markers(sequence: &Sequence) -> Vec<(u64, String)> {
+    let mut markers = Vec::new();
+    let mut tick = 0;
+
+    for event in &sequence.events {
+        tick += event.delta_time;
+
+        if let LocalEvent::Meta(meta) = &event.data {
+            if let MetaCommand::MarkerText = meta.command {
+                markers.push((tick, text::decode(&meta.data, sequence.encoding)));
+            }
+        }
+    }
+
+    markers
+}
+
+fn parse_time(value: &str) -> Option<Duration> {
+    let seconds = |value: &str| -> Option<f64> {
+        let seconds: f64 = value.parse().ok()?;
+        if seconds.is_finite() && seconds >= 0.0 {
+            Some(seconds)
+        } else {
+            None
+        }
+    };
+
+    if let Some((minutes, rest)) = value.split_once(':') {
+        let minutes: u64 = minutes.parse().ok()?;
+        Some(Duration::from_secs(minutes * 60) + Duration::from_secs_f64(seconds(rest)?))
+    } else if let Some(millis) = value.strip_suffix("ms") {
+        Some(Duration::from_millis(millis.parse().ok()?))
+    } else {
+        Some(Duration::from_secs_f64(seconds(value.strip_suffix('s')?)?))
+    }
+}
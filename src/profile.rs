@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::automation::Ramp;
+use crate::checksum;
+use crate::syx;
+
+/// Settings for a particular device, read from a text file like:
+///
+/// ```text
+/// # Hall reverb on a GS module
+/// reverb = 4
+/// sysex F0 41 10 42 12 40 01 30 {reverb} 00 F7
+/// cc 1 91 {reverb}
+/// pc 10 25
+/// # Open the filter on channel 1 over the first 8 bars
+/// ramp 1 74 0-{reverb} 1-8
+/// ```
+///
+/// `name = value` sets what `{name}` stands for in the lines after it.
+/// Channels and CC/PC values are decimal, SysEx bytes hex, and Roland and
+/// Yamaha checksums are filled in.
+pub struct Profile {
+    /// Messages sent after the reset, before anything plays.
+    pub startup: Vec<Vec<u8>>,
+    /// Controller ramps played along with every song.
+    pub automation: Vec<Ramp>,
+}
+
+impl Profile {
+    /// Reads a profile, with `overrides` taking the place of the values it
+    /// sets.
+    pub fn load(path: &Path, overrides: &[(String, u8)]) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        Self::parse(&text, overrides).with_context(|| format!("Invalid profile {}", path.display()))
+    }
+
+    pub fn parse(text: &str, overrides: &[(String, u8)]) -> Result<Self> {
+        let mut values: HashMap<&str, u8> = overrides
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .collect();
+        let mut startup = Vec::new();
+        let mut automation = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(index) => &line[..index],
+                None => line,
+            }
+            .trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            match line.find('=') {
+                Some(index) => {
+                    let name = line[..index].trim();
+                    let value = parse_data(line[index + 1..].trim(), &values, 10)
+                        .with_context(|| format!("Line {}", number + 1))?;
+
+                    // Values given on the command line win
+                    if !overrides.iter().any(|(overridden, _)| overridden == name) {
+                        values.insert(name, value);
+                    }
+                }
+                None if line.starts_with("ramp ") => {
+                    let ramp = parse_ramp_line(&line[5..], &values)
+                        .with_context(|| format!("Line {}", number + 1))?;
+                    automation.push(ramp);
+                }
+                None => {
+                    let message = parse_message(line, &values)
+                        .with_context(|| format!("Line {}", number + 1))?;
+                    startup.push(message);
+                }
+            };
+        }
+
+        Ok(Self {
+            startup,
+            automation,
+        })
+    }
+}
+
+/// Parses a ramp given on the command line, written like one in a profile
+/// without the leading `ramp`.
+pub fn parse_ramp(value: &str) -> Result<Ramp> {
+    parse_ramp_line(value, &HashMap::new())
+}
+
+fn parse_ramp_line(line: &str, values: &HashMap<&str, u8>) -> Result<Ramp> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (channel, controller, span, bars) = match words.as_slice() {
+        [channel, controller, span, bars] => (channel, controller, span, bars),
+        _ => {
+            return Err(anyhow!(
+                "Expected ramp <channel> <controller> <from>-<to> <first bar>-<last bar>"
+            ));
+        }
+    };
+
+    let (from, to) = split_range(span)?;
+    let (first, last) = split_range(bars)?;
+    let first: u32 = first
+        .parse()
+        .with_context(|| format!("Invalid bar: {}", first))?;
+    let last: u32 = last
+        .parse()
+        .with_context(|| format!("Invalid bar: {}", last))?;
+
+    if first == 0 || last < first {
+        return Err(anyhow!("Bars must count up from 1: {}", bars));
+    }
+
+    Ok(Ramp {
+        channel: parse_channel(channel, values)?,
+        controller: parse_data(controller, values, 10)?,
+        from: parse_data(from, values, 10)?,
+        to: parse_data(to, values, 10)?,
+        bars: first..=last,
+    })
+}
+
+fn split_range(word: &str) -> Result<(&str, &str)> {
+    let index = word
+        .find('-')
+        .with_context(|| format!("Expected a range like 0-80: {}", word))?;
+
+    Ok((&word[..index], &word[index + 1..]))
+}
+
+/// Parses a `name=value` setting given on the command line.
+pub fn parse_override(value: &str) -> Result<(String, u8)> {
+    let index = value
+        .find('=')
+        .with_context(|| format!("Expected name=value: {}", value))?;
+    let data = parse_data(&value[index + 1..], &HashMap::new(), 10)?;
+
+    Ok((value[..index].to_string(), data))
+}
+
+fn parse_message(line: &str, values: &HashMap<&str, u8>) -> Result<Vec<u8>> {
+    let mut words = line.split_whitespace();
+    let kind = words.next().unwrap_or_default();
+    let words: Vec<&str> = words.collect();
+
+    match (kind, words.as_slice()) {
+        ("cc", [channel, controller, value]) => Ok(vec![
+            0xb0 | parse_channel(channel, values)?,
+            parse_data(controller, values, 10)?,
+            parse_data(value, values, 10)?,
+        ]),
+        ("pc", [channel, program]) => Ok(vec![
+            0xc0 | parse_channel(channel, values)?,
+            parse_data(program, values, 10)?,
+        ]),
+        ("sysex", words) => {
+            let mut message = Vec::with_capacity(words.len());
+            for word in words {
+                message.push(match placeholder(word) {
+                    Some(_) => parse_data(word, values, 16)?,
+                    None => u8::from_str_radix(word, 16)
+                        .with_context(|| format!("Invalid SysEx byte: {}", word))?,
+                });
+            }
+
+            match syx::split_messages(&message) {
+                Ok(messages) if messages.len() == 1 => {}
+                _ => return Err(anyhow!("SysEx must be a single F0 ... F7 message")),
+            };
+
+            checksum::patch(&mut message);
+
+            Ok(message)
+        }
+        ("cc", _) => Err(anyhow!("Expected cc <channel> <controller> <value>")),
+        ("pc", _) => Err(anyhow!("Expected pc <channel> <program>")),
+        _ => Err(anyhow!("Unknown message: {}", kind)),
+    }
+}
+
+fn placeholder(word: &str) -> Option<&str> {
+    word.strip_prefix('{')?.strip_suffix('}')
+}
+
+fn parse_channel(word: &str, values: &HashMap<&str, u8>) -> Result<u8> {
+    let channel = parse_data(word, values, 10)?;
+    if !(1..=16).contains(&channel) {
+        return Err(anyhow!("Channel must be between 1 and 16"));
+    }
+
+    Ok(channel - 1)
+}
+
+/// A data byte, written out in `radix` or as a `{name}` placeholder.
+fn parse_data(word: &str, values: &HashMap<&str, u8>, radix: u32) -> Result<u8> {
+    let value = match placeholder(word) {
+        Some(name) => *values
+            .get(name)
+            .with_context(|| format!("No value for {{{}}}", name))?,
+        None => {
+            u8::from_str_radix(word, radix).with_context(|| format!("Invalid value: {}", word))?
+        }
+    };
+
+    if value > 0x7f {
+        return Err(anyhow!("Value {} is over 127", word));
+    }
+
+    Ok(value)
+}
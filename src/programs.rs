@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+
+use crate::midi_file::{DataEvent, LocalEvent};
+
+/// The GM2 rhythm bank, turning a channel into a drum kit.
+const DRUM_BANK: u8 = 120;
+
+/// What a channel is forced to play.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Program {
+    /// A program number, from 0.
+    Number(u8),
+    /// The standard kit of the GM2 rhythm bank.
+    Drums,
+}
+
+/// A program forced on a channel in place of those the file picks, for
+/// auditioning it with other patches.
+#[derive(Clone, Copy)]
+pub struct ProgramOverride {
+    /// From 0.
+    pub channel: u8,
+    pub program: Program,
+}
+
+impl ProgramOverride {
+    /// Parses `<channel>:<program>`, with the channel from 1 and the program
+    /// from 0 or `drum`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || format!("Invalid program override: {}", value);
+        let (channel, program) = value.split_once(':').with_context(invalid)?;
+
+        let channel: u8 = channel.trim().parse().with_context(invalid)?;
+        if !(1..=16).contains(&channel) {
+            return Err(anyhow!("Channel must be between 1 and 16"));
+        }
+
+        let program = match program.trim() {
+            "drum" | "drums" => Program::Drums,
+            program => {
+                let program: u8 = program.parse().with_context(invalid)?;
+                if program > 127 {
+                    return Err(anyhow!("Program must be between 0 and 127"));
+                }
+
+                Program::Number(program)
+            }
+        };
+
+        Ok(Self {
+            channel: channel - 1,
+            program,
+        })
+    }
+
+    /// The messages selecting the program, sent before playback starts.
+    pub fn messages(&self) -> Vec<Vec<u8>> {
+        let channel = self.channel & 0x0f;
+
+        match self.program {
+            Program::Number(program) => vec![vec![0xc0 | channel, program]],
+            Program::Drums => vec![
+                vec![0xb0 | channel, 0, DRUM_BANK],
+                vec![0xb0 | channel, 32, 0],
+                vec![0xc0 | channel, 0],
+            ],
+        }
+    }
+}
+
+/// Leaves out the program changes of the file on overridden channels, adding
+/// their time to the next event.
+pub fn apply(events: Vec<DataEvent>, overrides: &[ProgramOverride]) -> Vec<DataEvent> {
+    if overrides.is_empty() {
+        return events;
+    }
+
+    let overridden = |data: &[u8; 3]| {
+        data[0] & 0xf0 == 0xc0
+            && overrides
+                .iter()
+                .any(|program| program.channel == data[0] & 0x0f)
+    };
+
+    let mut kept = Vec::with_capacity(events.len());
+    let mut carried = 0;
+
+    for mut event in events {
+        match &event.data {
+            LocalEvent::Midi(data) if overridden(data) => carried += event.delta_time,
+            _ => {
+                event.delta_time += carried;
+                carried = 0;
+                kept.push(event);
+            }
+        }
+    }
+
+    kept
+}
@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::storage::{self, Record, Storage};
+
+/// A file that failed to load, with why.
+pub struct Entry {
+    pub file: PathBuf,
+    pub reason: String,
+}
+
+/// Files that failed to load, so unattended queues skip them instead of
+/// stopping. Kept in `Storage` when given a list, otherwise only for the
+/// session.
+#[derive(Default)]
+pub struct Quarantine {
+    storage: Option<Box<dyn Storage>>,
+    entries: Vec<Entry>,
+}
+
+impl Quarantine {
+    /// Reads the list at `list`, a text file or an SQLite database, which is
+    /// created on the first entry when it does not exist yet.
+    pub fn load(list: &Path) -> Result<Self> {
+        let storage = storage::open(list, "quarantine")?;
+        let entries = storage
+            .records()?
+            .into_iter()
+            .map(|record| Entry {
+                file: record.file,
+                reason: record.value,
+            })
+            .collect();
+
+        Ok(Self {
+            storage: Some(storage),
+            entries,
+        })
+    }
+
+    pub fn contains(&self, file: &Path) -> bool {
+        self.entries.iter().any(|entry| entry.file == file)
+    }
+
+    /// Records `file`, saving it to the list if there is one.
+    pub fn add(&mut self, file: &Path, reason: &str) -> Result<()> {
+        if let Some(storage) = &mut self.storage {
+            storage.add(&Record {
+                file: file.to_path_buf(),
+                value: reason.to_string(),
+            })?;
+        }
+
+        self.entries.push(Entry {
+            file: file.to_path_buf(),
+            reason: reason.to_string(),
+        });
+
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// One line per entry, for showing the quarantine.
+    pub fn report(&self) -> Vec<String> {
+        let mut lines = vec![format!("{} files in quarantine", self.entries.len())];
+
+        for entry in &self.entries {
+            lines.push(format!("  {}: {}", entry.file.display(), entry.reason));
+        }
+
+        lines
+    }
+}
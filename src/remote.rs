@@ -0,0 +1,282 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::control::{Client, Priority};
+use crate::events::json_string;
+use crate::position::Position;
+
+/// How long a client may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest request or header line read, and most headers read, so a client
+/// cannot have the server hold a request of any size.
+const MAX_LINE_LENGTH: u64 = 8192;
+const MAX_HEADERS: usize = 100;
+
+/// Header every command has to come with.
+const TOKEN_HEADER: &str = "X-Midi-Play-Token";
+
+/// Something asked for over HTTP.
+#[derive(Clone)]
+pub enum RemoteCommand {
+    /// Resume paused playback.
+    Play,
+    Pause,
+    /// Stop playback and forget the queue.
+    Stop,
+    /// Stop the song playing, going on with the next.
+    Skip,
+    Seek(Position),
+    /// Play a file after the ones already queued.
+    Queue(PathBuf),
+}
+
+/// What is playing, as `GET /status` reports it.
+#[derive(Clone, Default)]
+pub struct RemoteStatus {
+    /// The song playing, if any.
+    pub title: Option<String>,
+    pub paused: bool,
+    pub elapsed: Duration,
+    pub percent: f64,
+    pub queue: Vec<PathBuf>,
+}
+
+impl RemoteStatus {
+    fn to_json(&self) -> String {
+        let state = match (&self.title, self.paused) {
+            (None, _) => "stopped",
+            (Some(_), true) => "paused",
+            (Some(_), false) => "playing",
+        };
+        let title = match &self.title {
+            Some(title) => json_string(title),
+            None => String::from("null"),
+        };
+        let queue: Vec<String> = self
+            .queue
+            .iter()
+            .map(|path| json_string(&path.display().to_string()))
+            .collect();
+
+        format!(
+            "{{\"state\":\"{}\",\"title\":{},\"elapsed\":{:.3},\"percent\":{:.1},\"queue\":[{}]}}",
+            state,
+            title,
+            self.elapsed.as_secs_f64(),
+            self.percent,
+            queue.join(",")
+        )
+    }
+}
+
+/// Takes commands over HTTP, from a phone or a home automation system,
+/// each coming from a client of `Priority::Remote` so the transport lock of
+/// an operator holds against them.
+///
+/// `POST /play`, `/pause`, `/stop` and `/skip` do what they say, `POST
+/// /seek?to=<position>` takes the positions of `--start` and `POST
+/// /queue?path=<file>` adds a file to the queue. `GET /status` reports what
+/// is playing as JSON.
+///
+/// Commands need an `X-Midi-Play-Token` header, holding the token when one
+/// is set. Browsers only send such a header to another origin after a
+/// preflight request this server does not answer, so a web page cannot
+/// post a form to it.
+pub struct RemoteServer {
+    status: Arc<Mutex<RemoteStatus>>,
+}
+
+impl RemoteServer {
+    /// Starts taking requests on `address`, given as `host:port`, handing
+    /// every command that comes with `token` to `on_command` on the thread of
+    /// the server.
+    pub fn listen(
+        address: &str,
+        token: Option<String>,
+        mut on_command: impl FnMut(Client, RemoteCommand) + Send + 'static,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        let status = Arc::new(Mutex::new(RemoteStatus::default()));
+
+        let shared = status.clone();
+        thread::Builder::new()
+            .name(String::from("Remote control"))
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if let Err(e) = respond(stream, &shared, token.as_deref(), &mut on_command) {
+                        eprintln!("Failed to answer remote request: {:#}", e);
+                    }
+                }
+            })
+            .context("Failed to spawn remote control thread")?;
+
+        Ok(Self { status })
+    }
+
+    /// Replaces what `GET /status` reports.
+    pub fn set_status(&self, status: RemoteStatus) {
+        *self.status.lock().unwrap() = status;
+    }
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &Mutex<RemoteStatus>,
+    token: Option<&str>,
+    on_command: &mut impl FnMut(Client, RemoteCommand),
+) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let request_line = read_line(&mut reader)?;
+
+    // Nothing is taken from the body, nor from the headers but the token
+    let mut given_token = None;
+    let mut headers = 0;
+    loop {
+        let header = read_line(&mut reader)?;
+        if header.trim_end().is_empty() {
+            break;
+        }
+
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(anyhow!("Too many headers"));
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case(TOKEN_HEADER) {
+                given_token = Some(value.trim().to_string());
+            }
+        }
+    }
+    let authorized = match token {
+        Some(token) => given_token.as_deref() == Some(token),
+        None => given_token.is_some(),
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return reply(&mut stream, 400, &error_json("Malformed request")),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    if path == "/status" {
+        return match method {
+            "GET" => reply(&mut stream, 200, &status.lock().unwrap().to_json()),
+            _ => reply(&mut stream, 405, &error_json("Use GET")),
+        };
+    }
+
+    let command = match path {
+        "/play" => Ok(RemoteCommand::Play),
+        "/pause" => Ok(RemoteCommand::Pause),
+        "/stop" => Ok(RemoteCommand::Stop),
+        "/skip" => Ok(RemoteCommand::Skip),
+        "/seek" => parameter(query, "to")
+            .context("Missing to")
+            .and_then(|to| Position::parse(&to))
+            .map(RemoteCommand::Seek),
+        "/queue" => parameter(query, "path")
+            .context("Missing path")
+            .map(|path| RemoteCommand::Queue(PathBuf::from(path))),
+        _ => return reply(&mut stream, 404, &error_json("No such command")),
+    };
+
+    match (method, command) {
+        ("POST", _) if !authorized => reply(
+            &mut stream,
+            403,
+            &error_json(&format!("Missing or wrong {} header", TOKEN_HEADER)),
+        ),
+        ("POST", Ok(command)) => {
+            let peer = stream.peer_addr()?;
+            on_command(
+                Client::new(format!("http {}", peer.ip()), Priority::Remote),
+                command,
+            );
+            reply(&mut stream, 200, "{\"ok\":true}")
+        }
+        ("POST", Err(e)) => reply(&mut stream, 400, &error_json(&format!("{:#}", e))),
+        _ => reply(&mut stream, 405, &error_json("Use POST")),
+    }
+}
+
+fn reply(stream: &mut TcpStream, code: u16, body: &str) -> Result<()> {
+    let reason = match code {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        _ => "Method Not Allowed",
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    )
+    .context("Failed to send response")
+}
+
+/// Reads a line of the request, failing on one longer than
+/// `MAX_LINE_LENGTH`.
+fn read_line(reader: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    reader
+        .take(MAX_LINE_LENGTH)
+        .read_line(&mut line)
+        .context("Failed to read request")?;
+
+    if line.len() as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(anyhow!(
+            "Request line longer than {} bytes",
+            MAX_LINE_LENGTH
+        ));
+    }
+
+    Ok(line)
+}
+
+fn error_json(message: &str) -> String {
+    format!("{{\"ok\":false,\"error\":{}}}", json_string(message))
+}
+
+/// The value of `name` in a query string, percent decoded.
+fn parameter(query: &str, name: &str) -> Option<String> {
+    let value = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)?
+        .1;
+
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            byte => bytes.push(byte),
+        };
+    }
+
+    String::from_utf8(bytes).ok()
+}
@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+
+use crate::midi_file::{DataEvent, LocalEvent};
+
+/// What part of a file a route takes to another port.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Source {
+    /// From 0.
+    Track(usize),
+    /// From 0.
+    Channel(u8),
+}
+
+/// Events of a track or channel sent to a port of their own instead of the
+/// one the file plays on, so a single file can drive several synths.
+#[derive(Clone, Copy)]
+pub struct Route {
+    pub source: Source,
+    pub port_id: u32,
+}
+
+impl Route {
+    /// Parses `track:<track>=port:<port>` or `ch:<channel>=port:<port>`, with
+    /// tracks and channels from 1 and ports as listed.
+    pub fn parse(value: &str) -> Result<Self> {
+        let invalid = || {
+            format!(
+                "Invalid route: {}, use track:<n>=port:<n> or ch:<n>=port:<n>",
+                value
+            )
+        };
+        let (source, port) = value.split_once('=').with_context(invalid)?;
+        let (kind, number) = source.trim().split_once(':').with_context(invalid)?;
+        let number: usize = number.trim().parse().with_context(invalid)?;
+
+        let source = match kind.trim() {
+            "track" if number >= 1 => Source::Track(number - 1),
+            "ch" | "channel" if (1..=16).contains(&number) => Source::Channel(number as u8 - 1),
+            "track" => return Err(anyhow!("Tracks count from 1")),
+            "ch" | "channel" => return Err(anyhow!("Channel must be between 1 and 16")),
+            _ => return Err(anyhow!(invalid())),
+        };
+
+        let port_id = port
+            .trim()
+            .strip_prefix("port:")
+            .with_context(invalid)?
+            .trim()
+            .parse()
+            .with_context(invalid)?;
+
+        Ok(Self { source, port_id })
+    }
+
+    /// Whether the route takes `event`. Channel routes only take voice
+    /// messages, track routes everything of the track that is sent.
+    pub fn matches(&self, event: &DataEvent) -> bool {
+        match self.source {
+            Source::Track(track) => event.track == Some(track),
+            Source::Channel(channel) => match &event.data {
+                LocalEvent::Midi(data) => data[0] < 0xf0 && data[0] & 0x0f == channel,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// The first of `routes` taking `event`, by index.
+pub fn find(routes: &[Route], event: &DataEvent) -> Option<usize> {
+    routes.iter().position(|route| route.matches(event))
+}
@@ -0,0 +1,359 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+use crate::backend::{self, MidiOutput};
+use crate::generate::XorShift;
+
+/// Name the sessions are opened under, shown by the remote end.
+const SESSION_NAME: &str = "midi_play";
+const PROTOCOL_VERSION: u32 = 2;
+/// Payload type of RTP-MIDI packets.
+const PAYLOAD_TYPE: u8 = 0x61;
+/// Invitations sent before giving up on the remote end.
+const INVITATION_ATTEMPTS: u32 = 4;
+const INVITATION_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often the clocks are synchronized, which also keeps the session open.
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+/// How long the session thread waits for a packet before checking whether
+/// the port was closed.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A session command of the AppleMIDI protocol, carried on both ports.
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Invitation,
+    Accepted,
+    Rejected,
+    End,
+    Sync,
+}
+
+impl Command {
+    fn code(self) -> &'static [u8; 2] {
+        match self {
+            Command::Invitation => b"IN",
+            Command::Accepted => b"OK",
+            Command::Rejected => b"NO",
+            Command::End => b"BY",
+            Command::Sync => b"CK",
+        }
+    }
+
+    fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < 4 || packet[..2] != [0xff, 0xff] {
+            return None;
+        }
+
+        [
+            Command::Invitation,
+            Command::Accepted,
+            Command::Rejected,
+            Command::End,
+            Command::Sync,
+        ]
+        .iter()
+        .copied()
+        .find(|command| &packet[2..4] == command.code())
+    }
+}
+
+/// An invitation, acceptance, rejection or end of a session.
+fn session_packet(command: Command, token: u32, ssrc: u32) -> Vec<u8> {
+    let mut packet = vec![0xff, 0xff];
+    packet.extend_from_slice(command.code());
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.extend_from_slice(&token.to_be_bytes());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+    if command == Command::Invitation {
+        packet.extend_from_slice(SESSION_NAME.as_bytes());
+        packet.push(0);
+    }
+
+    packet
+}
+
+/// A clock synchronization with `count` of the three timestamps filled in.
+fn sync_packet(ssrc: u32, count: u8, timestamps: [u64; 3]) -> Vec<u8> {
+    let mut packet = vec![0xff, 0xff];
+    packet.extend_from_slice(Command::Sync.code());
+    packet.extend_from_slice(&ssrc.to_be_bytes());
+    packet.extend_from_slice(&[count, 0, 0, 0]);
+    for timestamp in &timestamps {
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+    }
+
+    packet
+}
+
+fn sync_timestamps(packet: &[u8]) -> Option<(u8, [u64; 3])> {
+    if packet.len() < 36 {
+        return None;
+    }
+
+    let timestamp = |offset: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&packet[offset..offset + 8]);
+        u64::from_be_bytes(bytes)
+    };
+
+    Some((packet[8], [timestamp(12), timestamp(20), timestamp(28)]))
+}
+
+/// Time since `start` in the 100 microsecond units of the session clock.
+fn clock(start: Instant) -> u64 {
+    (start.elapsed().as_micros() / 100) as u64
+}
+
+/// Invites the remote end on `socket` until it answers, as it may drop the
+/// first invitations while it wakes up.
+fn invite(socket: &UdpSocket, remote: SocketAddr, token: u32, ssrc: u32) -> Result<()> {
+    let invitation = session_packet(Command::Invitation, token, ssrc);
+    let mut buffer = [0; 512];
+
+    socket.set_read_timeout(Some(INVITATION_TIMEOUT))?;
+    for _ in 0..INVITATION_ATTEMPTS {
+        socket
+            .send_to(&invitation, remote)
+            .context("Failed to send invitation")?;
+
+        let deadline = Instant::now() + INVITATION_TIMEOUT;
+        while Instant::now() < deadline {
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(_) => break,
+            };
+            if from != remote {
+                continue;
+            }
+
+            match Command::parse(&buffer[..length]) {
+                Some(Command::Accepted) => return Ok(()),
+                Some(Command::Rejected) => return Err(anyhow!("{} rejected the session", remote)),
+                _ => {}
+            };
+        }
+    }
+
+    Err(anyhow!("{} did not answer the invitation", remote))
+}
+
+/// What the session thread shares with the port.
+struct Session {
+    data: UdpSocket,
+    control: UdpSocket,
+    /// Data port of the remote end, its control port is the one before.
+    remote: SocketAddr,
+    ssrc: u32,
+    start: Instant,
+    running: AtomicBool,
+    /// Set once the remote end closed the session.
+    ended: AtomicBool,
+}
+
+impl Session {
+    fn control_remote(&self) -> SocketAddr {
+        let mut control = self.remote;
+        control.set_port(self.remote.port() - 1);
+        control
+    }
+
+    fn start_sync(&self) -> Result<()> {
+        let packet = sync_packet(self.ssrc, 0, [clock(self.start), 0, 0]);
+        self.data
+            .send_to(&packet, self.remote)
+            .context("Failed to send clock synchronization")?;
+
+        Ok(())
+    }
+
+    /// Answers clock synchronizations and notices the end of the session,
+    /// synchronizing every `SYNC_INTERVAL` to keep it open.
+    fn run(&self) -> Result<()> {
+        let mut buffer = [0; 512];
+        let mut last_sync = Instant::now();
+
+        self.data.set_read_timeout(Some(POLL_TIMEOUT))?;
+        self.control.set_nonblocking(true)?;
+
+        while self.running.load(Ordering::Relaxed) && !self.ended.load(Ordering::Relaxed) {
+            if last_sync.elapsed() >= SYNC_INTERVAL {
+                self.start_sync()?;
+                last_sync = Instant::now();
+            }
+
+            if let Ok((length, _)) = self.control.recv_from(&mut buffer) {
+                if Command::parse(&buffer[..length]) == Some(Command::End) {
+                    self.ended.store(true, Ordering::Relaxed);
+                }
+            }
+
+            let packet = match self.data.recv_from(&mut buffer) {
+                Ok((length, from)) if from == self.remote => &buffer[..length],
+                _ => continue,
+            };
+            match (Command::parse(packet), sync_timestamps(packet)) {
+                (Some(Command::Sync), Some((0, [first, _, _]))) => {
+                    let reply = sync_packet(self.ssrc, 1, [first, clock(self.start), 0]);
+                    self.data.send_to(&reply, self.remote)?;
+                }
+                (Some(Command::Sync), Some((1, [first, second, _]))) => {
+                    let reply = sync_packet(self.ssrc, 2, [first, second, clock(self.start)]);
+                    self.data.send_to(&reply, self.remote)?;
+                }
+                (Some(Command::End), _) => self.ended.store(true, Ordering::Relaxed),
+                _ => {}
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// A session with an rtpMIDI or macOS network MIDI host, listed after the
+/// ports of the selected API. Messages go out without a recovery journal,
+/// which LAN sessions do fine without.
+pub struct RtpMidiPort {
+    session: Arc<Session>,
+    token: u32,
+    sequence: u16,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+fn remote(index: u32) -> Result<String> {
+    backend::selected_rtp_sessions()
+        .into_iter()
+        .nth(index as usize)
+        .context("Port number out of range")
+}
+
+impl MidiOutput for RtpMidiPort {
+    fn count() -> u32 {
+        backend::selected_rtp_sessions().len() as u32
+    }
+
+    fn name(port_number: u32) -> Result<String> {
+        Ok(format!("RTP-MIDI {}", remote(port_number)?))
+    }
+
+    fn connect(port_number: u32) -> Result<Self> {
+        let host = remote(port_number)?;
+        let control_remote = host
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", host))?
+            .next()
+            .with_context(|| format!("No address for {}", host))?;
+        let mut data_remote = control_remote;
+        data_remote.set_port(control_remote.port().wrapping_add(1));
+
+        let bind = match control_remote {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let control = UdpSocket::bind(bind).context("Failed to open control socket")?;
+        let data = UdpSocket::bind(bind).context("Failed to open data socket")?;
+
+        let mut random = XorShift::from_time();
+        let token = random.next_u64() as u32;
+        let ssrc = random.next_u64() as u32;
+
+        invite(&control, control_remote, token, ssrc)
+            .with_context(|| format!("Failed to open session with {}", host))?;
+        invite(&data, data_remote, token, ssrc)
+            .with_context(|| format!("Failed to open session with {}", host))?;
+
+        let session = Arc::new(Session {
+            data,
+            control,
+            remote: data_remote,
+            ssrc,
+            start: Instant::now(),
+            running: AtomicBool::new(true),
+            ended: AtomicBool::new(false),
+        });
+        session.start_sync()?;
+
+        let thread = {
+            let session = Arc::clone(&session);
+            thread::Builder::new()
+                .name(String::from("rtp-midi"))
+                .spawn(move || session.run())
+                .context("Failed to start session thread")?
+        };
+
+        Ok(Self {
+            session,
+            token,
+            sequence: 0,
+            thread: Some(thread),
+        })
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        if message.is_empty() {
+            eprintln!("Attempted to send empty message");
+
+            return Ok(());
+        }
+        if self.session.ended.load(Ordering::Relaxed) {
+            return Err(anyhow!("The session was ended by the remote end"));
+        }
+        if matches!(&self.thread, Some(thread) if thread.is_finished()) {
+            return match self.thread.take().map(JoinHandle::join) {
+                Some(Ok(Err(e))) => Err(e.context("The session failed")),
+                _ => Err(anyhow!("The session failed")),
+            };
+        }
+
+        let session = &self.session;
+        let mut packet = Vec::with_capacity(message.len() + 14);
+        packet.push(0x80);
+        packet.push(PAYLOAD_TYPE);
+        packet.extend_from_slice(&self.sequence.to_be_bytes());
+        packet.extend_from_slice(&(clock(session.start) as u32).to_be_bytes());
+        packet.extend_from_slice(&session.ssrc.to_be_bytes());
+
+        // The command section, with a long header for SysEx over 15 bytes
+        match message.len() {
+            length @ 0..=15 => packet.push(length as u8),
+            length if length < 0x1000 => {
+                packet.push(0x80 | (length >> 8) as u8);
+                packet.push(length as u8);
+            }
+            length => return Err(anyhow!("Message of {} bytes too long", length)),
+        };
+        packet.extend_from_slice(message);
+
+        session
+            .data
+            .send_to(&packet, session.remote)
+            .context("Failed to send message")?;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        Ok(())
+    }
+}
+
+impl Drop for RtpMidiPort {
+    fn drop(&mut self) {
+        // Reset so the remote end does not keep our state
+        if let Err(e) = self.send_reset().context("Failed to send reset") {
+            eprintln!("{:?}", e);
+        }
+
+        self.session.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        let end = session_packet(Command::End, self.token, self.session.ssrc);
+        let _ = self
+            .session
+            .control
+            .send_to(&end, self.session.control_remote());
+    }
+}
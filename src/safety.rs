@@ -0,0 +1,155 @@
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+/// Limits protecting the mechanics of acoustic player pianos (Disklavier,
+/// PianoDisc) from material they were never meant to reproduce.
+#[derive(Clone)]
+pub struct SafetyLimits {
+    pub max_notes: usize,
+    pub min_repeat_interval: Duration,
+    pub max_velocity: u8,
+    pub forbidden: Vec<RangeInclusive<u8>>,
+}
+
+impl Default for SafetyLimits {
+    fn default() -> Self {
+        Self {
+            max_notes: 16,
+            min_repeat_interval: Duration::from_millis(50),
+            max_velocity: 110,
+            forbidden: Vec::new(),
+        }
+    }
+}
+
+pub enum Verdict {
+    Send,
+    Clamped(String),
+    Dropped(String),
+}
+
+pub struct SafetyLimiter {
+    limits: SafetyLimits,
+    sounding: [[bool; 128]; 16],
+    sounding_count: usize,
+    last_note_on: [Option<Instant>; 128],
+}
+
+impl SafetyLimiter {
+    pub fn new(limits: SafetyLimits) -> Self {
+        Self {
+            limits,
+            sounding: [[false; 128]; 16],
+            sounding_count: 0,
+            last_note_on: [None; 128],
+        }
+    }
+
+    /// Forgets every sounding note, for when the output was silenced without
+    /// the limiter seeing it, such as on seeks, pauses and panics.
+    pub fn reset(&mut self) {
+        self.sounding = [[false; 128]; 16];
+        self.sounding_count = 0;
+    }
+
+    /// Forgets the sounding notes of `channel`, counted from 0.
+    pub fn release_channel(&mut self, channel: u8) {
+        let sounding = &mut self.sounding[channel as usize & 0x0f];
+        self.sounding_count -= sounding.iter().filter(|&&on| on).count();
+        *sounding = [false; 128];
+    }
+
+    /// Checks an outgoing message against the limits, lowering its velocity in
+    /// place when it exceeds the ceiling.
+    pub fn filter(&mut self, message: &mut [u8]) -> Verdict {
+        if message.len() < 3 {
+            return Verdict::Send;
+        }
+
+        let status = message[0] & 0xf0;
+        let channel = (message[0] & 0x0f) as usize;
+        let key = message[1] & 0x7f;
+
+        let note_on = status == 0x90 && message[2] > 0;
+        let note_off = status == 0x80 || (status == 0x90 && message[2] == 0);
+
+        // All sound off and all notes off end whatever the channel holds
+        if status == 0xb0 && (message[1] == 120 || message[1] == 123) {
+            self.release_channel(channel as u8);
+            return Verdict::Send;
+        }
+
+        if note_off {
+            if self.sounding[channel][key as usize] {
+                self.sounding[channel][key as usize] = false;
+                self.sounding_count -= 1;
+            }
+
+            return Verdict::Send;
+        }
+
+        if !note_on {
+            return Verdict::Send;
+        }
+
+        if self
+            .limits
+            .forbidden
+            .iter()
+            .any(|range| range.contains(&key))
+        {
+            return Verdict::Dropped(format!(
+                "note {} on channel {} is in a forbidden range",
+                key,
+                channel + 1
+            ));
+        }
+
+        if self.sounding[channel][key as usize] {
+            return Verdict::Dropped(format!(
+                "note {} on channel {} is already sounding",
+                key,
+                channel + 1
+            ));
+        }
+
+        if self.sounding_count >= self.limits.max_notes {
+            return Verdict::Dropped(format!(
+                "note {} on channel {} exceeds {} simultaneous notes",
+                key,
+                channel + 1,
+                self.limits.max_notes
+            ));
+        }
+
+        let now = Instant::now();
+        if let Some(last) = self.last_note_on[key as usize] {
+            if now.duration_since(last) < self.limits.min_repeat_interval {
+                return Verdict::Dropped(format!(
+                    "note {} on channel {} repeated too quickly",
+                    key,
+                    channel + 1
+                ));
+            }
+        }
+
+        self.sounding[channel][key as usize] = true;
+        self.sounding_count += 1;
+        self.last_note_on[key as usize] = Some(now);
+
+        if message[2] > self.limits.max_velocity {
+            let velocity = message[2];
+            message[2] = self.limits.max_velocity;
+
+            return Verdict::Clamped(format!(
+                "note {} on channel {} velocity {} clamped to {}",
+                key,
+                channel + 1,
+                velocity,
+                self.limits.max_velocity
+            ));
+        }
+
+        Verdict::Send
+    }
+}
@@ -0,0 +1,248 @@
+use std::ops::RangeInclusive;
+
+use anyhow::{Context, Result};
+use rimd::MetaCommand;
+
+use crate::generate;
+use crate::midi_file::{LocalEvent, Sequence};
+use crate::parameters;
+use crate::player::DEFAULT_TEMPO;
+use crate::tempo::{format_time, TempoMap};
+use crate::text;
+use crate::timeline;
+
+/// Which events a query looks for.
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Note,
+    Controller,
+    Program,
+    PitchBend,
+    SysEx,
+    Tempo,
+    Lyric,
+    Marker,
+}
+
+/// Criteria for finding events, such as `cc=64 ch=1` or `note=C4 vel=100-127`.
+pub struct Query {
+    kind: Option<Kind>,
+    /// Key, controller or program number.
+    number: Option<u8>,
+    /// From 0.
+    channel: Option<u8>,
+    /// Velocity, controller value or bend (as its top 7 bits).
+    value: Option<RangeInclusive<u8>>,
+}
+
+/// An event found by a query.
+pub struct Match {
+    pub tick: u64,
+    /// Bar and beat, time and tick.
+    pub position: String,
+    pub description: String,
+}
+
+impl Query {
+    /// Parses space-separated terms, case-insensitively: one of `note[=key]`,
+    /// `cc[=controller]`, `pc[=program]`, `bend`, `sysex`, `tempo`, `lyric`
+    /// or `marker`, along with `ch=1-16` and `value=n` or `value=low-high`
+    /// (also `vel`).
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut query = Self {
+            kind: None,
+            number: None,
+            channel: None,
+            value: None,
+        };
+
+        for term in value.split_whitespace() {
+            let term = term.to_ascii_lowercase();
+            let (name, argument) = match term.split_once('=') {
+                Some((name, argument)) => (name, Some(argument)),
+                None => (term.as_str(), None),
+            };
+
+            let kind = match name {
+                "note" => Kind::Note,
+                "cc" => Kind::Controller,
+                "pc" => Kind::Program,
+                "bend" => Kind::PitchBend,
+                "sysex" => Kind::SysEx,
+                "tempo" => Kind::Tempo,
+                "lyric" => Kind::Lyric,
+                "marker" => Kind::Marker,
+                "ch" => {
+                    let argument = argument.context("Expected ch=1-16")?;
+                    let channel: u8 = argument
+                        .parse()
+                        .with_context(|| format!("Invalid channel: {}", argument))?;
+                    if !(1..=16).contains(&channel) {
+                        return Err(anyhow!("Channel must be between 1 and 16"));
+                    }
+
+                    query.channel = Some(channel - 1);
+                    continue;
+                }
+                "value" | "vel" => {
+                    let argument = argument.with_context(|| format!("Expected {}=n", name))?;
+                    query.value = Some(parse_values(argument)?);
+                    continue;
+                }
+                _ => return Err(anyhow!("Unknown search term: {}", term)),
+            };
+
+            if query.kind.is_some() {
+                return Err(anyhow!("Only one kind of event can be searched for"));
+            }
+
+            query.kind = Some(kind);
+            query.number = match (kind, argument) {
+                (_, None) => None,
+                (Kind::Note, Some(key)) => Some(match key.parse() {
+                    Ok(key) => key,
+                    Err(_) => generate::parse_note(key)?,
+                }),
+                (Kind::Controller, Some(number)) | (Kind::Program, Some(number)) => Some(
+                    number
+                        .parse()
+                        .with_context(|| format!("Invalid number: {}", term))?,
+                ),
+                (_, Some(_)) => return Err(anyhow!("{} takes no number", name)),
+            };
+        }
+
+        match query.kind {
+            None if query.channel.is_none() && query.value.is_none() => {
+                Err(anyhow!("Empty search: {}", value))
+            }
+            Some(Kind::SysEx) | Some(Kind::Tempo) | Some(Kind::Lyric) | Some(Kind::Marker)
+                if query.channel.is_some() || query.value.is_some() =>
+            {
+                Err(anyhow!("ch and value only apply to channel events"))
+            }
+            _ => Ok(query),
+        }
+    }
+
+    /// Describes a channel message when it matches, leaving out note offs.
+    fn channel_message(&self, data: &[u8; 3]) -> Option<String> {
+        let channel = data[0] & 0x0f;
+        let (kind, number, value, description) = match data[0] & 0xf0 {
+            0x90 if data[2] > 0 => (
+                Kind::Note,
+                Some(data[1]),
+                data[2],
+                format!(
+                    "Note on ch {} key {} velocity {}",
+                    channel + 1,
+                    data[1],
+                    data[2]
+                ),
+            ),
+            0xb0 => (
+                Kind::Controller,
+                Some(data[1]),
+                data[2],
+                format!("CC ch {} #{} = {}", channel + 1, data[1], data[2]),
+            ),
+            0xc0 => (
+                Kind::Program,
+                Some(data[1]),
+                0,
+                format!("Program change ch {} = {}", channel + 1, data[1]),
+            ),
+            0xe0 => {
+                let bend = ((data[2] as i32) << 7 | data[1] as i32) - 0x2000;
+                (
+                    Kind::PitchBend,
+                    None,
+                    data[2],
+                    format!("Pitch bend ch {} = {}", channel + 1, bend),
+                )
+            }
+            _ => return None,
+        };
+
+        let matches = (self.kind.is_none() || self.kind == Some(kind))
+            && (self.number.is_none() || self.number == number)
+            && (self.channel.is_none() || self.channel == Some(channel))
+            && match &self.value {
+                Some(range) => range.contains(&value),
+                None => true,
+            };
+
+        if matches {
+            Some(description)
+        } else {
+            None
+        }
+    }
+}
+
+/// Finds the events of the merged sequence matching `query`, in order.
+pub fn find(sequence: &Sequence, query: &Query) -> Vec<Match> {
+    let division = sequence.division;
+    let signatures = timeline::time_signatures(&sequence.events);
+    let tempo_map = TempoMap::from_events(&sequence.events, DEFAULT_TEMPO);
+
+    let mut matches = Vec::new();
+    let mut tick = 0;
+
+    for event in &sequence.events {
+        tick += event.delta_time;
+
+        let description = match (&event.data, query.kind) {
+            (LocalEvent::Midi(data), _) => query.channel_message(data),
+            (LocalEvent::SysEx(data), Some(Kind::SysEx)) => {
+                Some(parameters::describe(data).unwrap_or_else(|| format!("SysEx {:02x?}", data)))
+            }
+            (LocalEvent::Meta(meta), Some(kind)) => match (kind, meta.command) {
+                (Kind::Tempo, MetaCommand::TempoSetting) => Some(format!(
+                    "Tempo {:.2} BPM",
+                    60_000_000.0 / meta.data_as_u64(3).max(1) as f64
+                )),
+                (Kind::Lyric, MetaCommand::LyricText) => Some(format!(
+                    "Lyric {:?}",
+                    text::decode(&meta.data, sequence.encoding)
+                )),
+                (Kind::Marker, MetaCommand::MarkerText) => Some(format!(
+                    "Marker {:?}",
+                    text::decode(&meta.data, sequence.encoding)
+                )),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let Some(description) = description {
+            matches.push(Match {
+                tick,
+                position: format!(
+                    "{} ({}, tick {})",
+                    timeline::bar_and_beat(tick, division, &signatures),
+                    format_time(tempo_map.time_at(tick, division)),
+                    tick
+                ),
+                description,
+            });
+        }
+    }
+
+    matches
+}
+
+/// Parses a single value or an inclusive `LOW-HIGH` range.
+fn parse_values(value: &str) -> Result<RangeInclusive<u8>> {
+    let invalid = || format!("Invalid value: {}", value);
+
+    match value.split_once('-') {
+        Some((low, high)) => {
+            Ok(low.parse().with_context(invalid)?..=high.parse().with_context(invalid)?)
+        }
+        None => {
+            let value = value.parse().with_context(invalid)?;
+            Ok(value..=value)
+        }
+    }
+}
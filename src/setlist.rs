@@ -0,0 +1,275 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rimd::{MetaCommand, MetaEvent};
+
+use crate::midi_file::{DataEvent, LocalEvent, Sequence};
+use crate::position;
+use crate::text;
+use crate::timeline;
+
+const CUE_VELOCITY: u8 = 100;
+
+/// A note to send ahead of a section, at every marker or only those named.
+pub struct Cue {
+    pub key: u8,
+    /// Marker text to cue, matched ignoring case, or any marker.
+    pub marker: Option<String>,
+}
+
+/// A point of a song that jumps can leave from or land on.
+#[derive(Clone, PartialEq)]
+pub enum Mark {
+    Start,
+    End,
+    /// The first marker with this text, matched ignoring case.
+    Marker(String),
+}
+
+impl Mark {
+    fn parse(value: &str) -> Self {
+        match value {
+            "start" => Mark::Start,
+            "end" => Mark::End,
+            marker => Mark::Marker(marker.to_string()),
+        }
+    }
+}
+
+/// Continuing from `to` once playback reaches `at`, for repeats, D.C. and
+/// D.S. jumps and endings.
+pub struct Jump {
+    pub at: Mark,
+    pub to: Mark,
+}
+
+/// An entry of a set list with the cues for its band members.
+pub struct Song {
+    pub path: PathBuf,
+    pub cues: Vec<Cue>,
+    /// Taken one after the other, each the first time its `at` is reached
+    /// after the one before it.
+    pub jumps: Vec<Jump>,
+    /// Beats before the marker that its cue is sent.
+    pub lead: u64,
+    /// Key clicked on every beat of the lead, as a count-off.
+    pub count: Option<u8>,
+}
+
+/// The songs of a performance, read from a text file like:
+///
+/// ```text
+/// # Friday
+/// intro.mid
+/// songs/first.mid
+///     lead 4
+///     count 37
+///     cue 60 Verse
+///     cue 62 Chorus
+///     cue 36 *
+/// songs/second.mid
+///     jump D.C. -> start
+///     jump To Coda -> Coda
+/// ```
+///
+/// Every unindented line is a song, relative to the set list. The indented
+/// lines under it set when its cues play: `lead <beats>` ahead of each
+/// marker, `count <key>` clicking every beat of the lead, and
+/// `cue <key> <marker>` with `*` cueing any marker. `jump <marker> -> <marker>`
+/// lines play the song's form from its markers, `end` and `start` standing
+/// for either end of the file.
+pub struct SetList {
+    pub songs: Vec<Song>,
+}
+
+impl SetList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        Self::parse(&text, dir).with_context(|| format!("Invalid set list {}", path.display()))
+    }
+
+    pub fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let mut songs: Vec<Song> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let content = match line.find('#') {
+                Some(index) => &line[..index],
+                None => line,
+            };
+            if content.trim().is_empty() {
+                continue;
+            }
+
+            if !content.starts_with(char::is_whitespace) {
+                songs.push(Song {
+                    path: dir.join(content.trim()),
+                    cues: Vec::new(),
+                    jumps: Vec::new(),
+                    lead: 0,
+                    count: None,
+                });
+                continue;
+            }
+
+            let song = songs
+                .last_mut()
+                .with_context(|| format!("Line {}: cue before any song", number + 1))?;
+            parse_directive(song, content.trim())
+                .with_context(|| format!("Line {}", number + 1))?;
+        }
+
+        Ok(Self { songs })
+    }
+
+    pub fn song(&self, path: &Path) -> Option<&Song> {
+        self.songs.iter().find(|song| song.path == path)
+    }
+}
+
+fn parse_directive(song: &mut Song, line: &str) -> Result<()> {
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let invalid = || format!("Invalid {}: {}", name, rest);
+
+    match name {
+        "lead" => song.lead = rest.parse().with_context(invalid)?,
+        "count" => song.count = Some(rest.parse().with_context(invalid)?),
+        "cue" => {
+            let (key, marker) = rest.split_once(char::is_whitespace).with_context(invalid)?;
+            let marker = match marker.trim() {
+                "*" => None,
+                marker => Some(marker.to_string()),
+            };
+
+            song.cues.push(Cue {
+                key: key.parse().with_context(invalid)?,
+                marker,
+            });
+        }
+        "jump" => {
+            let (at, to) = rest.split_once("->").with_context(invalid)?;
+            let jump = Jump {
+                at: Mark::parse(at.trim()),
+                to: Mark::parse(to.trim()),
+            };
+
+            if jump.at == Mark::Start || jump.to == Mark::End {
+                return Err(anyhow!(
+                    "Jumps go from a marker or the end to a marker or the start"
+                ));
+            }
+            song.jumps.push(jump);
+        }
+        _ => return Err(anyhow!("Unknown directive: {}", name)),
+    }
+
+    Ok(())
+}
+
+impl Song {
+    /// The ticks of every jump in `sequence`, from and to.
+    pub fn jump_ticks(&self, sequence: &Sequence) -> Result<Vec<(u64, u64)>> {
+        let markers = position::markers(sequence);
+        let tick = sequence.events.iter().map(|event| event.delta_time).sum();
+
+        let find = |mark: &Mark| match mark {
+            Mark::Start => Ok(0),
+            Mark::End => Ok(tick),
+            Mark::Marker(name) => markers
+                .iter()
+                .find(|(_, marker)| marker.trim().eq_ignore_ascii_case(name))
+                .map(|&(tick, _)| tick)
+                .with_context(|| format!("No marker {} in {}", name, self.path.display())),
+        };
+
+        self.jumps
+            .iter()
+            .map(|jump| Ok((find(&jump.at)?, find(&jump.to)?)))
+            .collect()
+    }
+
+    /// The cue notes for `sequence` on `channel`, along with its tempo
+    /// changes so they stay in time with it.
+    pub fn cue_events(&self, sequence: &Sequence, channel: u8) -> Vec<DataEvent> {
+        let division = sequence.division;
+        let signatures = timeline::time_signatures(&sequence.events);
+        let beat_length = |tick: u64| {
+            let denominator = signatures
+                .iter()
+                .rev()
+                .find(|&&(change, _)| change <= tick)
+                .map_or(2, |&(_, (_, denominator))| denominator);
+
+            ((division * 4) >> denominator).max(1)
+        };
+
+        // Absolute tick, then note offs before note ons, for sorting
+        let mut messages: Vec<(u64, u8, LocalEvent)> = Vec::new();
+        // Every cue and click lasts half a beat
+        let mut note = |tick: u64, length: u64, key: u8| {
+            let channel = channel & 0x0f;
+            messages.push((
+                tick,
+                1,
+                LocalEvent::Midi([0x90 | channel, key, CUE_VELOCITY]),
+            ));
+            messages.push((tick + length, 0, LocalEvent::Midi([0x80 | channel, key, 0])));
+        };
+        let mut tempos = Vec::new();
+        let mut tick = 0;
+
+        for event in &sequence.events {
+            tick += event.delta_time;
+
+            let meta = match &event.data {
+                LocalEvent::Meta(meta) => meta,
+                _ => continue,
+            };
+
+            match meta.command {
+                MetaCommand::TempoSetting => tempos.push((tick, meta.data_as_u64(3))),
+                MetaCommand::MarkerText => {
+                    let marker = text::decode(&meta.data, sequence.encoding);
+                    let key = self.cues.iter().find_map(|cue| match &cue.marker {
+                        Some(name) if !name.eq_ignore_ascii_case(marker.trim()) => None,
+                        _ => Some(cue.key),
+                    });
+                    let key = match key {
+                        Some(key) => key,
+                        None => continue,
+                    };
+
+                    let beat = beat_length(tick);
+                    let start = tick.saturating_sub(self.lead * beat);
+                    note(start, beat / 2, key);
+
+                    if let Some(count) = self.count {
+                        for click in (start..tick).step_by(beat as usize) {
+                            note(click, beat / 2, count);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (tick, tempo) in tempos {
+            let meta = MetaEvent::tempo_setting(tempo as u32);
+            messages.push((tick, 0, LocalEvent::Meta(meta)));
+        }
+        messages.sort_by_key(|&(tick, order, _)| (tick, order));
+
+        let mut events = Vec::with_capacity(messages.len());
+        let mut last_tick = 0;
+        for (tick, _, data) in messages {
+            events.push(DataEvent::new(tick - last_tick, data));
+            last_tick = tick;
+        }
+
+        events
+    }
+}
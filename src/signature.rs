@@ -0,0 +1,75 @@
+use std::fmt;
+
+const MAJOR_KEYS: [&str; 15] = [
+    "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+];
+const MINOR_KEYS: [&str; 15] = [
+    "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+];
+
+/// The key of a key signature meta event.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct KeySignature {
+    /// Sharps when positive, flats when negative.
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+impl KeySignature {
+    /// Decodes the data of a key signature meta event.
+    pub fn from_meta(data: &[u8]) -> Option<Self> {
+        match data {
+            [sharps, mode, ..] => Some(Self {
+                sharps: (*sharps as i8).clamp(-7, 7),
+                minor: *mode != 0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeySignature {
+    /// Like "D major" or "Bb minor".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let index = (self.sharps.clamp(-7, 7) + 7) as usize;
+
+        if self.minor {
+            write!(f, "{} minor", MINOR_KEYS[index])
+        } else {
+            write!(f, "{} major", MAJOR_KEYS[index])
+        }
+    }
+}
+
+/// The meter of a time signature meta event.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    /// The power of two of the denominator, as the file stores it.
+    pub denominator: u8,
+}
+
+impl TimeSignature {
+    /// Decodes the data of a time signature meta event.
+    pub fn from_meta(data: &[u8]) -> Option<Self> {
+        match data {
+            [numerator, denominator, ..] => Some(Self {
+                numerator: (*numerator).max(1),
+                denominator: (*denominator).min(6),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The denominator as a note value, such as 8 for eighth notes.
+    pub fn note_value(self) -> u32 {
+        1 << self.denominator
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    /// Like "6/8".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.numerator, self.note_value())
+    }
+}
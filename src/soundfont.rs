@@ -0,0 +1,434 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Generators of the SoundFont 2 specification that the synth uses, by the
+/// number they are stored under.
+pub mod generator {
+    pub const START_ADDRS_OFFSET: usize = 0;
+    pub const END_ADDRS_OFFSET: usize = 1;
+    pub const STARTLOOP_ADDRS_OFFSET: usize = 2;
+    pub const ENDLOOP_ADDRS_OFFSET: usize = 3;
+    pub const START_ADDRS_COARSE_OFFSET: usize = 4;
+    pub const END_ADDRS_COARSE_OFFSET: usize = 12;
+    pub const PAN: usize = 17;
+    pub const DELAY_VOL_ENV: usize = 33;
+    pub const ATTACK_VOL_ENV: usize = 34;
+    pub const HOLD_VOL_ENV: usize = 35;
+    pub const DECAY_VOL_ENV: usize = 36;
+    pub const SUSTAIN_VOL_ENV: usize = 37;
+    pub const RELEASE_VOL_ENV: usize = 38;
+    pub const INSTRUMENT: usize = 41;
+    pub const KEY_RANGE: usize = 43;
+    pub const VEL_RANGE: usize = 44;
+    pub const STARTLOOP_ADDRS_COARSE_OFFSET: usize = 45;
+    pub const INITIAL_ATTENUATION: usize = 48;
+    pub const ENDLOOP_ADDRS_COARSE_OFFSET: usize = 50;
+    pub const COARSE_TUNE: usize = 51;
+    pub const FINE_TUNE: usize = 52;
+    pub const SAMPLE_ID: usize = 53;
+    pub const SAMPLE_MODES: usize = 54;
+    pub const SCALE_TUNING: usize = 56;
+    pub const EXCLUSIVE_CLASS: usize = 57;
+    pub const OVERRIDING_ROOT_KEY: usize = 58;
+
+    /// Every generator number, with those beyond unknown and ignored.
+    pub const COUNT: usize = 61;
+}
+
+/// A sample of the `shdr` chunk, with its points as offsets of the sample
+/// data of the whole file.
+#[derive(Clone)]
+pub struct Sample {
+    pub start: u32,
+    pub end: u32,
+    pub loop_start: u32,
+    pub loop_end: u32,
+    pub sample_rate: u32,
+    pub original_pitch: u8,
+    pub pitch_correction: i8,
+}
+
+/// A sample with the generators of the preset and instrument zones that play
+/// it, which apply for notes within both key and velocity ranges.
+pub struct Region {
+    pub keys: (u8, u8),
+    pub velocities: (u8, u8),
+    pub sample: Sample,
+    /// Instrument values with the preset ones added, as the specification
+    /// asks for everything but the ranges.
+    pub generators: [i32; generator::COUNT],
+}
+
+impl Region {
+    pub fn covers(&self, key: u8, velocity: u8) -> bool {
+        (self.keys.0..=self.keys.1).contains(&key)
+            && (self.velocities.0..=self.velocities.1).contains(&velocity)
+    }
+}
+
+pub struct Preset {
+    pub name: String,
+    pub bank: u16,
+    pub program: u16,
+    pub regions: Vec<Region>,
+}
+
+/// The presets and sample data of a SoundFont 2 file, flattened for playing.
+/// Modulators are left out, the default ones are part of the synth.
+pub struct SoundFont {
+    pub presets: Vec<Preset>,
+    /// Every sample of the file, one after the other, in 16 bits.
+    pub data: Vec<i16>,
+}
+
+/// One generator of a zone, with ranges kept as the two bytes they are.
+#[derive(Clone, Copy)]
+struct Generator {
+    number: u16,
+    amount: [u8; 2],
+}
+
+impl Generator {
+    fn value(self) -> i32 {
+        i16::from_le_bytes(self.amount) as i32
+    }
+}
+
+/// The generators of one zone, set over those of the global zone.
+#[derive(Clone)]
+struct Zone {
+    values: [Option<i32>; generator::COUNT],
+    keys: Option<(u8, u8)>,
+    velocities: Option<(u8, u8)>,
+}
+
+impl Zone {
+    fn new() -> Self {
+        Self {
+            values: [None; generator::COUNT],
+            keys: None,
+            velocities: None,
+        }
+    }
+
+    fn set(&mut self, generators: &[Generator]) {
+        for generator in generators {
+            match generator.number as usize {
+                generator::KEY_RANGE => {
+                    self.keys = Some((generator.amount[0], generator.amount[1]))
+                }
+                generator::VEL_RANGE => {
+                    self.velocities = Some((generator.amount[0], generator.amount[1]))
+                }
+                number if number < generator::COUNT => {
+                    self.values[number] = Some(generator.value())
+                }
+                _ => {}
+            };
+        }
+    }
+
+    fn get(&self, number: usize) -> Option<i32> {
+        self.values[number]
+    }
+}
+
+/// Default value of every generator, for instrument zones.
+fn defaults() -> [i32; generator::COUNT] {
+    let mut values = [0; generator::COUNT];
+    for number in [
+        generator::DELAY_VOL_ENV,
+        generator::ATTACK_VOL_ENV,
+        generator::HOLD_VOL_ENV,
+        generator::DECAY_VOL_ENV,
+        generator::RELEASE_VOL_ENV,
+    ] {
+        values[number] = -12000;
+    }
+    values[generator::SCALE_TUNING] = 100;
+    values[generator::OVERRIDING_ROOT_KEY] = -1;
+
+    values
+}
+
+fn intersect(first: Option<(u8, u8)>, second: Option<(u8, u8)>) -> (u8, u8) {
+    let (first, second) = (first.unwrap_or((0, 127)), second.unwrap_or((0, 127)));
+
+    (first.0.max(second.0), first.1.min(second.1))
+}
+
+/// The chunks of a RIFF list, by id.
+fn chunks(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+
+    while data.len() >= 8 {
+        let id = [data[0], data[1], data[2], data[3]];
+        let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let body = &data[8..];
+        let size = size.min(body.len());
+
+        chunks.push((id, &body[..size]));
+        // Chunks are padded to an even length
+        data = &body[(size + (size & 1)).min(body.len())..];
+    }
+
+    chunks
+}
+
+fn find<'a>(chunks: &[([u8; 4], &'a [u8])], id: &[u8; 4]) -> Result<&'a [u8]> {
+    chunks
+        .iter()
+        .find(|(chunk_id, _)| chunk_id == id)
+        .map(|(_, body)| *body)
+        .with_context(|| format!("Missing {} chunk", String::from_utf8_lossy(id)))
+}
+
+/// The body of the `LIST` chunk of type `list_type`.
+fn list<'a>(chunks: &[([u8; 4], &'a [u8])], list_type: &[u8; 4]) -> Result<&'a [u8]> {
+    chunks
+        .iter()
+        .filter(|(id, body)| id == b"LIST" && body.len() >= 4)
+        .find(|(_, body)| &body[..4] == list_type)
+        .map(|(_, body)| &body[4..])
+        .with_context(|| format!("Missing {} list", String::from_utf8_lossy(list_type)))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn name_at(data: &[u8]) -> String {
+    let name = &data[..20];
+    let length = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+
+    String::from_utf8_lossy(&name[..length]).trim().to_string()
+}
+
+/// Records of `size` bytes, or an error when the chunk is not made of them.
+fn records<'a>(data: &'a [u8], size: usize, id: &str) -> Result<Vec<&'a [u8]>> {
+    let records = data.chunks_exact(size);
+    if !records.remainder().is_empty() || records.len() < 2 {
+        return Err(anyhow!("Invalid {} chunk", id));
+    }
+
+    Ok(records.collect())
+}
+
+/// Generators of every zone of a preset or instrument chunk, the zones of
+/// each record running up to those of the next one.
+fn zones(
+    headers: &[&[u8]],
+    bag_offset: usize,
+    bags: &[&[u8]],
+    generators: &[Generator],
+) -> Result<Vec<Vec<Vec<Generator>>>> {
+    let bag_index = |header: &[u8]| u16_at(header, bag_offset) as usize;
+    let generator_index = |bag: usize| -> Result<usize> {
+        bags.get(bag)
+            .map(|bag| u16_at(bag, 0) as usize)
+            .context("Zone out of range")
+    };
+
+    let mut zones = Vec::with_capacity(headers.len());
+    for pair in headers.windows(2) {
+        let mut record = Vec::new();
+
+        for bag in bag_index(pair[0])..bag_index(pair[1]) {
+            let (first, last) = (generator_index(bag)?, generator_index(bag + 1)?);
+            let zone = generators
+                .get(first..last)
+                .context("Generators out of range")?;
+            record.push(zone.to_vec());
+        }
+
+        zones.push(record);
+    }
+
+    Ok(zones)
+}
+
+/// Splits the zones of a record into its global zone, the first one when it
+/// does not end with `terminal`, and the others.
+fn split_global(
+    zones: &[Vec<Generator>],
+    terminal: usize,
+) -> (Option<&Vec<Generator>>, &[Vec<Generator>]) {
+    let ends_with_terminal =
+        |zone: &Vec<Generator>| matches!(zone.last(), Some(g) if g.number as usize == terminal);
+
+    match zones.first() {
+        Some(first) if !ends_with_terminal(first) => (Some(first), &zones[1..]),
+        _ => (None, zones),
+    }
+}
+
+impl SoundFont {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = fs::read(path)
+            .with_context(|| format!("Failed to read SoundFont {}", path.display()))?;
+
+        Self::parse(&file).with_context(|| format!("Invalid SoundFont {}", path.display()))
+    }
+
+    pub fn parse(file: &[u8]) -> Result<Self> {
+        if file.len() < 12 || &file[..4] != b"RIFF" || &file[8..12] != b"sfbk" {
+            return Err(anyhow!("Not a SoundFont 2 file"));
+        }
+        let top = chunks(&file[12..]);
+
+        let sdta = chunks(list(&top, b"sdta")?);
+        let data = find(&sdta, b"smpl")?
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect::<Vec<_>>();
+
+        let pdta = chunks(list(&top, b"pdta")?);
+        let phdr = records(find(&pdta, b"phdr")?, 38, "phdr")?;
+        let pbag = records(find(&pdta, b"pbag")?, 4, "pbag")?;
+        let pgen = records(find(&pdta, b"pgen")?, 4, "pgen")?;
+        let inst = records(find(&pdta, b"inst")?, 22, "inst")?;
+        let ibag = records(find(&pdta, b"ibag")?, 4, "ibag")?;
+        let igen = records(find(&pdta, b"igen")?, 4, "igen")?;
+        let shdr = records(find(&pdta, b"shdr")?, 46, "shdr")?;
+
+        let generators = |records: &[&[u8]]| -> Vec<Generator> {
+            records
+                .iter()
+                .map(|record| Generator {
+                    number: u16_at(record, 0),
+                    amount: [record[2], record[3]],
+                })
+                .collect()
+        };
+        let preset_zones = zones(&phdr, 24, &pbag, &generators(&pgen))?;
+        let instrument_zones = zones(&inst, 20, &ibag, &generators(&igen))?;
+
+        // The last record of each list only marks where the others end
+        let samples: Vec<Sample> = shdr[..shdr.len() - 1]
+            .iter()
+            .map(|record| Sample {
+                start: u32_at(record, 20),
+                end: u32_at(record, 24),
+                loop_start: u32_at(record, 28),
+                loop_end: u32_at(record, 32),
+                sample_rate: u32_at(record, 36),
+                original_pitch: record[40],
+                pitch_correction: record[41] as i8,
+            })
+            .collect();
+
+        let mut presets = Vec::with_capacity(preset_zones.len());
+        for (header, zones) in phdr.iter().zip(&preset_zones) {
+            let (global, zones) = split_global(zones, generator::INSTRUMENT);
+            let mut global_zone = Zone::new();
+            if let Some(global) = global {
+                global_zone.set(global);
+            }
+
+            let mut regions = Vec::new();
+            for zone in zones {
+                let mut preset_zone = global_zone.clone();
+                preset_zone.set(zone);
+
+                let instrument = match preset_zone.get(generator::INSTRUMENT) {
+                    Some(instrument) => instrument as usize,
+                    None => continue,
+                };
+                let zones = instrument_zones
+                    .get(instrument)
+                    .context("Instrument out of range")?;
+                regions.extend(Self::regions(&preset_zone, zones, &samples)?);
+            }
+
+            presets.push(Preset {
+                name: name_at(header),
+                program: u16_at(header, 20),
+                bank: u16_at(header, 22),
+                regions,
+            });
+        }
+
+        Ok(Self { presets, data })
+    }
+
+    /// Regions of the instrument with `zones`, as played by `preset_zone`.
+    fn regions(
+        preset_zone: &Zone,
+        zones: &[Vec<Generator>],
+        samples: &[Sample],
+    ) -> Result<Vec<Region>> {
+        let (global, zones) = split_global(zones, generator::SAMPLE_ID);
+        let mut global_zone = Zone::new();
+        if let Some(global) = global {
+            global_zone.set(global);
+        }
+
+        let mut regions = Vec::new();
+        for zone in zones {
+            let mut instrument_zone = global_zone.clone();
+            instrument_zone.set(zone);
+
+            let sample = match instrument_zone.get(generator::SAMPLE_ID) {
+                Some(sample) => samples
+                    .get(sample as usize)
+                    .context("Sample out of range")?
+                    .clone(),
+                None => continue,
+            };
+
+            let mut generators = defaults();
+            for (number, value) in generators.iter_mut().enumerate() {
+                if let Some(instrument_value) = instrument_zone.get(number) {
+                    *value = instrument_value;
+                }
+                // Presets cannot move sample points or pick other samples
+                let additive =
+                    !matches!(number, 0..=4 | 12 | 41 | 45..=47 | 50 | 53 | 54 | 57 | 58);
+                if let (true, Some(preset_value)) = (additive, preset_zone.get(number)) {
+                    *value += preset_value;
+                }
+            }
+
+            regions.push(Region {
+                keys: intersect(preset_zone.keys, instrument_zone.keys),
+                velocities: intersect(preset_zone.velocities, instrument_zone.velocities),
+                sample,
+                generators,
+            });
+        }
+
+        Ok(regions)
+    }
+
+    /// The preset of `bank` and `program`, falling back on the program of the
+    /// first bank and then on the first preset, like GM modules do.
+    pub fn preset(&self, bank: u16, program: u16) -> Option<&Preset> {
+        let find = |bank: u16| {
+            self.presets
+                .iter()
+                .find(|preset| preset.bank == bank && preset.program == program)
+        };
+
+        find(bank)
+            .or_else(|| match bank {
+                // Drum kits fall back on the standard kit
+                128 => self
+                    .presets
+                    .iter()
+                    .find(|p| p.bank == 128 && p.program == 0),
+                _ => find(0),
+            })
+            .or_else(|| self.presets.first())
+    }
+}
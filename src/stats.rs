@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use crate::events::PlayerEvent;
+
+/// Counts what a player sent, for a summary once a song finishes that hints
+/// at why it sounds wrong on a limited device.
+#[derive(Default)]
+pub struct PlaybackStats {
+    notes: [usize; 16],
+    tempo_changes: usize,
+    sysex: usize,
+    late_events: usize,
+    latest: Duration,
+}
+
+impl PlaybackStats {
+    pub fn process(&mut self, event: &PlayerEvent) {
+        match event {
+            PlayerEvent::NoteOn { channel, .. } => self.notes[(channel & 0x0f) as usize] += 1,
+            PlayerEvent::Tempo(_) => self.tempo_changes += 1,
+            PlayerEvent::Midi(event) if event.msg.data.first() == Some(&0xf0) => self.sysex += 1,
+            PlayerEvent::Late(late) => {
+                self.late_events += 1;
+                self.latest = self.latest.max(*late);
+            }
+            _ => {}
+        };
+    }
+
+    /// Notes per channel, tempo changes, SysEx messages and late events.
+    pub fn report(&self) -> Vec<String> {
+        let total: usize = self.notes.iter().sum();
+        let mut lines = vec![format!("Notes played: {}", total)];
+
+        for (channel, notes) in self.notes.iter().enumerate() {
+            if *notes > 0 {
+                lines.push(format!("  - Channel {}: {}", channel + 1, notes));
+            }
+        }
+
+        lines.push(format!("Tempo changes: {}", self.tempo_changes));
+        lines.push(format!("SysEx messages: {}", self.sysex));
+        lines.push(match self.late_events {
+            0 => String::from("Late events: none"),
+            late_events => format!(
+                "Late events: {}, up to {:.1} ms late",
+                late_events,
+                self.latest.as_secs_f64() * 1000.0
+            ),
+        });
+
+        lines
+    }
+}
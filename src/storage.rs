@@ -0,0 +1,152 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Something noted about a file, such as why it was quarantined.
+pub struct Record {
+    pub file: PathBuf,
+    pub value: String,
+}
+
+/// Where lists of records about files are kept between sessions.
+pub trait Storage {
+    /// Every record, oldest first.
+    fn records(&self) -> Result<Vec<Record>>;
+
+    fn add(&mut self, record: &Record) -> Result<()>;
+}
+
+/// Opens the list `name` kept at `path`, in an SQLite database when the path
+/// ends in `.db` or `.sqlite`, otherwise in a text file of one
+/// `file<TAB>value` line per record. Either is created on the first record.
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+pub fn open(path: &Path, name: &str) -> Result<Box<dyn Storage>> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        #[cfg(feature = "sqlite")]
+        Some("db") | Some("sqlite") => Ok(Box::new(SqliteStorage::open(path, name)?)),
+        #[cfg(not(feature = "sqlite"))]
+        Some("db") | Some("sqlite") => Err(anyhow!(
+            "{} is an SQLite database, which needs the sqlite feature",
+            path.display()
+        )),
+        _ => Ok(Box::new(TextStorage {
+            path: path.to_path_buf(),
+        })),
+    }
+}
+
+pub struct TextStorage {
+    path: PathBuf,
+}
+
+impl Storage for TextStorage {
+    fn records(&self) -> Result<Vec<Record>> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()));
+            }
+        };
+
+        let records = text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let (file, value) = match line.split_once('\t') {
+                    Some((file, value)) => (file, value),
+                    None => (line, ""),
+                };
+
+                Record {
+                    file: PathBuf::from(file),
+                    value: value.to_string(),
+                }
+            })
+            .collect();
+
+        Ok(records)
+    }
+
+    fn add(&mut self, record: &Record) -> Result<()> {
+        // Keep every record on its own line
+        let value = record.value.replace(['\r', '\n', '\t'], " ");
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+
+        writeln!(file, "{}\t{}", record.file.display(), value)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// A table of an SQLite database, which several lists can share and which
+/// stays quick to query with many thousands of files.
+#[cfg(feature = "sqlite")]
+pub struct SqliteStorage {
+    connection: rusqlite::Connection,
+    table: String,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStorage {
+    pub fn open(path: &Path, table: &str) -> Result<Self> {
+        if !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!("Invalid table name: {}", table));
+        }
+
+        let connection = rusqlite::Connection::open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        connection
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    id INTEGER PRIMARY KEY,
+                    file TEXT NOT NULL,
+                    value TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS {0}_file ON {0} (file);",
+                table
+            ))
+            .with_context(|| format!("Failed to set up {} in {}", table, path.display()))?;
+
+        Ok(Self {
+            connection,
+            table: table.to_string(),
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Storage for SqliteStorage {
+    fn records(&self) -> Result<Vec<Record>> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT file, value FROM {} ORDER BY id",
+            self.table
+        ))?;
+        let rows = statement.query_map([], |row| {
+            Ok(Record {
+                file: PathBuf::from(row.get::<_, String>(0)?),
+                value: row.get(1)?,
+            })
+        })?;
+
+        rows.collect::<rusqlite::Result<_>>()
+            .with_context(|| format!("Failed to read {}", self.table))
+    }
+
+    fn add(&mut self, record: &Record) -> Result<()> {
+        self.connection
+            .execute(
+                &format!("INSERT INTO {} (file, value) VALUES (?1, ?2)", self.table),
+                rusqlite::params![record.file.to_string_lossy(), record.value],
+            )
+            .with_context(|| format!("Failed to write {}", self.table))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use std::mem::{self, MaybeUninit};
+use std::pin::Pin;
+use std::ptr;
+
+use anyhow::Result;
+use rimd::MetaCommand;
+use winapi::shared::basetsd::DWORD_PTR;
+use winapi::shared::minwindef::{DWORD, FALSE, TRUE, UINT};
+use winapi::shared::ntdef::HANDLE;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::mmeapi::{
+    midiOutPrepareHeader, midiOutReset, midiOutUnprepareHeader, midiStreamClose, midiStreamOpen,
+    midiStreamOut, midiStreamProperty, midiStreamRestart, midiStreamStop,
+};
+use winapi::um::mmsystem::{
+    CALLBACK_EVENT, HMIDIOUT, HMIDISTRM, MIDIHDR, MMSYSERR_BASE, MMSYSERR_NOERROR,
+};
+use winapi::um::synchapi::CreateEventW;
+
+use crate::backend;
+use crate::driver::MHDR_DONE;
+use crate::midi_file::{DataEvent, LocalEvent};
+
+const MIDIPROP_SET: DWORD = 0x80000000;
+const MIDIPROP_TIMEDIV: DWORD = 0x00000001;
+
+const MEVT_SHORTMSG: DWORD = 0x00;
+const MEVT_TEMPO: DWORD = 0x01;
+const MEVT_LONGMSG: DWORD = 0x80;
+
+// Drivers reject stream buffers larger than 64 KiB
+const MAX_BUFFER_BYTES: usize = 0x10000;
+
+#[allow(non_snake_case, clippy::upper_case_acronyms)]
+#[repr(C)]
+struct MIDIPROPTIMEDIV {
+    cbStruct: DWORD,
+    dwTimeDiv: DWORD,
+}
+
+struct StreamBuffer {
+    #[allow(unused)]
+    data: Pin<Box<[DWORD]>>,
+    header: Pin<Box<MIDIHDR>>,
+}
+
+/// Playback through `midiStreamOut`, where the driver itself times every
+/// event instead of our busy-wait loop.
+pub struct MidiStream {
+    event_handle: HANDLE,
+    handle: HMIDISTRM,
+    buffers: Vec<StreamBuffer>,
+}
+
+impl MidiStream {
+    pub fn open(port_number: u32, division: u64) -> Result<Self> {
+        let event_handle = unsafe { CreateEventW(ptr::null_mut(), TRUE, FALSE, ptr::null()) };
+        let mut device_id = port_number as UINT;
+        let mut handle = MaybeUninit::uninit();
+        let result = unsafe {
+            midiStreamOpen(
+                handle.as_mut_ptr(),
+                &mut device_id,
+                1,
+                event_handle as DWORD_PTR,
+                0,
+                CALLBACK_EVENT,
+            )
+        };
+
+        if result != MMSYSERR_NOERROR {
+            unsafe { CloseHandle(event_handle) };
+
+            return Err(anyhow!(
+                "Failed to create Windows MM MIDI stream: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        let stream = Self {
+            event_handle,
+            handle: unsafe { handle.assume_init() },
+            buffers: Vec::new(),
+        };
+
+        let mut time_division = MIDIPROPTIMEDIV {
+            cbStruct: mem::size_of::<MIDIPROPTIMEDIV>() as DWORD,
+            dwTimeDiv: division as DWORD,
+        };
+        let result = unsafe {
+            midiStreamProperty(
+                stream.handle,
+                &mut time_division as *mut MIDIPROPTIMEDIV as *mut u8,
+                MIDIPROP_SET | MIDIPROP_TIMEDIV,
+            )
+        };
+
+        if result != MMSYSERR_NOERROR {
+            return Err(anyhow!(
+                "Failed to set MIDI stream time division: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        Ok(stream)
+    }
+
+    /// Encodes the events into stream buffers, preceded by the resets, the
+    /// startup messages and the initial tempo, and hands them all to the
+    /// driver.
+    pub fn queue(&mut self, startup: &[Vec<u8>], events: &[DataEvent], tempo: u64) -> Result<()> {
+        let mut encoder = Encoder::default();
+
+        for message in backend::selected_reset().messages() {
+            encoder.push_long(0, message)?;
+        }
+
+        for message in startup {
+            match message.as_slice() {
+                [0xf0, ..] => encoder.push_long(0, message)?,
+                data => {
+                    let message = data.iter().enumerate().fold(0, |message, (i, &byte)| {
+                        message | (byte as DWORD) << (8 * i)
+                    });
+                    encoder.push(0, (MEVT_SHORTMSG << 24) | message, &[]);
+                }
+            };
+        }
+        encoder.push(0, (MEVT_TEMPO << 24) | tempo as DWORD, &[]);
+
+        // Metas without a stream equivalent still carry their delta time
+        let mut pending_ticks = 0;
+
+        for event in events {
+            let delta_time = (pending_ticks + event.delta_time) as DWORD;
+            pending_ticks = 0;
+
+            match &event.data {
+                LocalEvent::Meta(meta) => match meta.command {
+                    MetaCommand::TempoSetting => {
+                        let tempo = meta.data_as_u64(3) as DWORD;
+                        encoder.push(delta_time, (MEVT_TEMPO << 24) | tempo, &[]);
+                    }
+                    _ => pending_ticks = delta_time as u64,
+                },
+                LocalEvent::SysEx(data) => encoder.push_long(delta_time, data)?,
+                LocalEvent::Midi(data) => {
+                    let message =
+                        data[0] as DWORD | (data[1] as DWORD) << 8 | (data[2] as DWORD) << 16;
+                    encoder.push(delta_time, (MEVT_SHORTMSG << 24) | message, &[]);
+                }
+            };
+        }
+
+        for data in encoder.finish() {
+            self.out(data)?;
+        }
+
+        Ok(())
+    }
+
+    fn out(&mut self, data: Vec<DWORD>) -> Result<()> {
+        let mut data = Pin::new(data.into_boxed_slice());
+        let length = (data.len() * mem::size_of::<DWORD>()) as DWORD;
+        let header = Box::pin(MIDIHDR {
+            lpData: data.as_mut_ptr() as *mut i8,
+            dwBufferLength: length,
+            dwBytesRecorded: length,
+            dwUser: 0,
+            dwFlags: 0,
+            lpNext: ptr::null_mut(),
+            reserved: 0,
+            dwOffset: 0,
+            dwReserved: unsafe { mem::zeroed() },
+        });
+        self.buffers.push(StreamBuffer { data, header });
+
+        let StreamBuffer { header, .. } = self.buffers.last_mut().unwrap();
+        let header = &mut **header as *mut MIDIHDR;
+        let result = unsafe {
+            midiOutPrepareHeader(
+                self.handle as HMIDIOUT,
+                header,
+                mem::size_of::<MIDIHDR>() as u32,
+            )
+        };
+        if result != MMSYSERR_NOERROR {
+            self.buffers.pop();
+
+            return Err(anyhow!(
+                "Failed to prepare stream buffer: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        let result =
+            unsafe { midiStreamOut(self.handle, header, mem::size_of::<MIDIHDR>() as u32) };
+        if result != MMSYSERR_NOERROR {
+            return Err(anyhow!(
+                "Failed to queue stream buffer: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn restart(&self) -> Result<()> {
+        let result = unsafe { midiStreamRestart(self.handle) };
+        if result != MMSYSERR_NOERROR {
+            return Err(anyhow!(
+                "Failed to start MIDI stream: {}",
+                result - MMSYSERR_BASE
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the driver has played every queued buffer.
+    pub fn is_done(&self) -> bool {
+        self.buffers
+            .iter()
+            .all(|buffer| (buffer.header.dwFlags & MHDR_DONE) == MHDR_DONE)
+    }
+}
+
+impl Drop for MidiStream {
+    fn drop(&mut self) {
+        unsafe {
+            midiStreamStop(self.handle);
+
+            let result = midiOutReset(self.handle as HMIDIOUT);
+            if result != MMSYSERR_NOERROR {
+                eprintln!(
+                    "Failed to reset Windows MM MIDI stream: {}",
+                    result - MMSYSERR_BASE
+                );
+            }
+
+            for buffer in &mut self.buffers {
+                midiOutUnprepareHeader(
+                    self.handle as HMIDIOUT,
+                    &mut *buffer.header,
+                    mem::size_of::<MIDIHDR>() as u32,
+                );
+            }
+
+            let result = midiStreamClose(self.handle);
+            if result != MMSYSERR_NOERROR {
+                eprintln!(
+                    "Failed to close Windows MM MIDI stream: {}",
+                    result - MMSYSERR_BASE
+                );
+            }
+
+            CloseHandle(self.event_handle);
+        }
+    }
+}
+
+/// Packs `MIDIEVENT` records into buffers no larger than the driver accepts.
+#[derive(Default)]
+struct Encoder {
+    buffers: Vec<Vec<DWORD>>,
+    current: Vec<DWORD>,
+}
+
+impl Encoder {
+    fn push(&mut self, delta_time: DWORD, event: DWORD, parameters: &[DWORD]) {
+        let words = 3 + parameters.len();
+        if (self.current.len() + words) * mem::size_of::<DWORD>() > MAX_BUFFER_BYTES {
+            self.buffers.push(mem::take(&mut self.current));
+        }
+
+        self.current.push(delta_time);
+        self.current.push(0);
+        self.current.push(event);
+        self.current.extend_from_slice(parameters);
+    }
+
+    /// Fails on messages too long for a buffer, as the driver cannot play a
+    /// SysEx message split across two.
+    fn push_long(&mut self, delta_time: DWORD, data: &[u8]) -> Result<()> {
+        // Parameters are padded to a whole number of DWORDs
+        let mut parameters = vec![0; data.len().div_ceil(4)];
+        if (3 + parameters.len()) * mem::size_of::<DWORD>() > MAX_BUFFER_BYTES {
+            return Err(anyhow!(
+                "SysEx message of {} bytes is too long for the stream engine",
+                data.len()
+            ));
+        }
+
+        for (i, byte) in data.iter().enumerate() {
+            parameters[i / 4] |= (*byte as DWORD) << ((i % 4) * 8);
+        }
+
+        self.push(
+            delta_time,
+            (MEVT_LONGMSG << 24) | data.len() as DWORD,
+            &parameters,
+        );
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<Vec<DWORD>> {
+        if !self.current.is_empty() {
+            self.buffers.push(self.current);
+        }
+
+        self.buffers
+    }
+}
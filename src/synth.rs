@@ -0,0 +1,501 @@
+use std::f32::consts::FRAC_PI_4;
+use std::sync::Arc;
+
+use crate::backend::{GM1_RESET, GS1_RESET, XG_RESET};
+use crate::soundfont::{generator, Region, SoundFont};
+
+/// Voices sounding at once, the oldest ending to make room for more.
+const MAX_VOICES: usize = 256;
+/// Frames rendered between updates of envelopes, pitch bends and volumes.
+const BLOCK_FRAMES: usize = 64;
+/// Gain of the mix, leaving headroom for chords of loud voices.
+const MASTER_GAIN: f32 = 0.3;
+/// Attenuation in centibels at which a voice can no longer be heard.
+const SILENT: f32 = 960.0;
+/// Registered parameter selected when none is.
+const NO_RPN: (u8, u8) = (0x7f, 0x7f);
+
+#[derive(Clone, Copy)]
+struct Channel {
+    bank: u16,
+    program: u16,
+    volume: u8,
+    expression: u8,
+    pan: u8,
+    sustain: bool,
+    /// From -8192 to 8191.
+    pitch_bend: i16,
+    /// Semitones of a full pitch bend.
+    bend_range: u8,
+    rpn: (u8, u8),
+}
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            bank: 0,
+            program: 0,
+            volume: 100,
+            expression: 127,
+            pan: 64,
+            sustain: false,
+            pitch_bend: 0,
+            bend_range: 2,
+            rpn: NO_RPN,
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        let level = self.volume as f32 / 127.0 * self.expression as f32 / 127.0;
+
+        level * level
+    }
+
+    fn bend_cents(&self) -> f32 {
+        self.pitch_bend as f32 / 8192.0 * self.bend_range as f32 * 100.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+/// The volume envelope of a voice, with the times of its stages in seconds
+/// and the levels after the attack as attenuation in centibels.
+struct Envelope {
+    stage: Stage,
+    time: f32,
+    delay: f32,
+    attack: f32,
+    hold: f32,
+    decay: f32,
+    sustain: f32,
+    release: f32,
+    attenuation: f32,
+    level: f32,
+}
+
+fn seconds(timecents: i32) -> f32 {
+    2f32.powf(timecents.clamp(-12000, 8000) as f32 / 1200.0)
+}
+
+fn amplitude(centibels: f32) -> f32 {
+    10f32.powf(-centibels / 200.0)
+}
+
+impl Envelope {
+    fn new(generators: &[i32; generator::COUNT]) -> Self {
+        Self {
+            stage: Stage::Delay,
+            time: 0.0,
+            delay: seconds(generators[generator::DELAY_VOL_ENV]),
+            attack: seconds(generators[generator::ATTACK_VOL_ENV]),
+            hold: seconds(generators[generator::HOLD_VOL_ENV]),
+            decay: seconds(generators[generator::DECAY_VOL_ENV]),
+            sustain: generators[generator::SUSTAIN_VOL_ENV].clamp(0, 1440) as f32,
+            release: seconds(generators[generator::RELEASE_VOL_ENV]),
+            attenuation: 0.0,
+            level: 0.0,
+        }
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.time = 0.0;
+    }
+
+    fn release(&mut self) {
+        if self.stage != Stage::Release && self.stage != Stage::Done {
+            self.attenuation = -200.0 * self.level.max(1e-5).log10();
+            self.enter(Stage::Release);
+        }
+    }
+
+    /// The level after `elapsed` seconds more.
+    fn advance(&mut self, elapsed: f32) -> f32 {
+        self.time += elapsed;
+
+        self.level = match self.stage {
+            Stage::Delay => {
+                if self.time >= self.delay {
+                    self.enter(Stage::Attack);
+                }
+                0.0
+            }
+            Stage::Attack => {
+                if self.time >= self.attack {
+                    self.enter(Stage::Hold);
+                }
+                (self.time / self.attack).min(1.0)
+            }
+            Stage::Hold => {
+                if self.time >= self.hold {
+                    self.enter(Stage::Decay);
+                }
+                1.0
+            }
+            // Decays and releases are times for the full 96 dB
+            Stage::Decay => {
+                self.attenuation += SILENT / self.decay * elapsed;
+                if self.attenuation >= self.sustain {
+                    self.attenuation = self.sustain;
+                    self.enter(Stage::Sustain);
+                }
+                amplitude(self.attenuation)
+            }
+            Stage::Sustain => amplitude(self.sustain),
+            Stage::Release => {
+                self.attenuation += SILENT / self.release * elapsed;
+                if self.attenuation >= SILENT {
+                    self.enter(Stage::Done);
+                }
+                amplitude(self.attenuation)
+            }
+            Stage::Done => 0.0,
+        };
+
+        self.level
+    }
+}
+
+/// A sample playing for a note, from a region of the channel's preset.
+struct Voice {
+    channel: usize,
+    key: u8,
+    /// Order the voice started in, for stealing the oldest.
+    age: u64,
+    exclusive_class: i32,
+    position: f64,
+    end: f64,
+    loop_start: f64,
+    loop_end: f64,
+    looping: bool,
+    /// Whether the loop ends on release, to play the rest of the sample.
+    loop_until_release: bool,
+    /// Pitch relative to the sample, before pitch bends.
+    cents: f32,
+    /// Sample rate of the sample to that of the output.
+    rate: f64,
+    gain: f32,
+    /// From -1 for left to 1 for right.
+    pan: f32,
+    envelope: Envelope,
+    released: bool,
+    /// Whether a note off came while the sustain pedal was down.
+    sustained: bool,
+}
+
+impl Voice {
+    fn new(
+        region: &Region,
+        channel: usize,
+        key: u8,
+        velocity: u8,
+        age: u64,
+        output_rate: u32,
+        data_length: usize,
+    ) -> Self {
+        let generators = &region.generators;
+        let sample = &region.sample;
+        let point = |base: u32, fine: usize, coarse: usize| {
+            let point = base as i64 + generators[fine] as i64 + generators[coarse] as i64 * 32768;
+            point.clamp(0, data_length.saturating_sub(1) as i64) as f64
+        };
+
+        let root_key = match generators[generator::OVERRIDING_ROOT_KEY] {
+            root_key @ 0..=127 => root_key,
+            _ if sample.original_pitch <= 127 => sample.original_pitch as i32,
+            _ => 60,
+        };
+        let cents = (key as i32 - root_key) * generators[generator::SCALE_TUNING]
+            + generators[generator::COARSE_TUNE] * 100
+            + generators[generator::FINE_TUNE]
+            + sample.pitch_correction as i32;
+
+        let attenuation = generators[generator::INITIAL_ATTENUATION].clamp(0, 1440) as f32;
+        let velocity = velocity as f32 / 127.0;
+        let mode = generators[generator::SAMPLE_MODES] & 3;
+
+        Self {
+            channel,
+            key,
+            age,
+            exclusive_class: generators[generator::EXCLUSIVE_CLASS],
+            position: point(
+                sample.start,
+                generator::START_ADDRS_OFFSET,
+                generator::START_ADDRS_COARSE_OFFSET,
+            ),
+            end: point(
+                sample.end,
+                generator::END_ADDRS_OFFSET,
+                generator::END_ADDRS_COARSE_OFFSET,
+            ),
+            loop_start: point(
+                sample.loop_start,
+                generator::STARTLOOP_ADDRS_OFFSET,
+                generator::STARTLOOP_ADDRS_COARSE_OFFSET,
+            ),
+            loop_end: point(
+                sample.loop_end,
+                generator::ENDLOOP_ADDRS_OFFSET,
+                generator::ENDLOOP_ADDRS_COARSE_OFFSET,
+            ),
+            looping: mode == 1 || mode == 3,
+            loop_until_release: mode == 3,
+            cents: cents as f32,
+            rate: sample.sample_rate as f64 / output_rate as f64,
+            gain: amplitude(attenuation) * velocity * velocity,
+            pan: (generators[generator::PAN] as f32 / 500.0).clamp(-1.0, 1.0),
+            envelope: Envelope::new(generators),
+            released: false,
+            sustained: false,
+        }
+    }
+
+    fn release(&mut self) {
+        self.released = true;
+        self.sustained = false;
+        self.envelope.release();
+        if self.loop_until_release {
+            self.looping = false;
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.envelope.stage == Stage::Done
+    }
+
+    /// Adds the voice to `block`, interleaved stereo frames, for as long as
+    /// the sample lasts.
+    fn render(&mut self, data: &[i16], channel: &Channel, block: &mut [f32], sample_rate: u32) {
+        let frames = block.len() / 2;
+        let level = self.envelope.advance(frames as f32 / sample_rate as f32);
+        let gain = self.gain * level * channel.gain() * MASTER_GAIN;
+        if self.done() || gain <= 0.0 {
+            return;
+        }
+
+        let pan = (self.pan + (channel.pan as f32 - 64.0) / 64.0).clamp(-1.0, 1.0);
+        let angle = (pan + 1.0) * FRAC_PI_4;
+        let (left, right) = (gain * angle.cos(), gain * angle.sin());
+        let step = self.rate * 2f64.powf((self.cents + channel.bend_cents()) as f64 / 1200.0);
+        let loop_length = self.loop_end - self.loop_start;
+        let looping = self.looping && loop_length >= 1.0;
+
+        for frame in block.chunks_exact_mut(2) {
+            if looping {
+                while self.position >= self.loop_end {
+                    self.position -= loop_length;
+                }
+            } else if self.position >= self.end {
+                self.envelope.enter(Stage::Done);
+                return;
+            }
+
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let next = match index + 1 {
+                next if looping && next as f64 >= self.loop_end => self.loop_start as usize,
+                next => next,
+            };
+            let current = data.get(index).copied().unwrap_or(0) as f32;
+            let next = data.get(next).copied().unwrap_or(0) as f32;
+            let value = (current + (next - current) * fraction) / 32768.0;
+
+            frame[0] += value * left;
+            frame[1] += value * right;
+            self.position += step;
+        }
+    }
+}
+
+/// A General MIDI software synthesizer playing the presets of a SoundFont,
+/// taking MIDI messages and rendering stereo audio.
+pub struct Synth {
+    soundfont: Arc<SoundFont>,
+    sample_rate: u32,
+    channels: [Channel; 16],
+    voices: Vec<Voice>,
+    next_age: u64,
+}
+
+impl Synth {
+    pub fn new(soundfont: Arc<SoundFont>, sample_rate: u32) -> Self {
+        Self {
+            soundfont,
+            sample_rate,
+            channels: [Channel::new(); 16],
+            voices: Vec::with_capacity(MAX_VOICES),
+            next_age: 0,
+        }
+    }
+
+    /// Plays a MIDI message, with GM, GS and XG resets resetting every
+    /// channel and other SysEx messages ignored.
+    pub fn process(&mut self, message: &[u8]) {
+        let status = match message.first() {
+            Some(&status) => status,
+            None => return,
+        };
+        if status == 0xf0 {
+            if [GM1_RESET, GS1_RESET, XG_RESET].contains(&message) {
+                self.reset();
+            }
+            return;
+        }
+
+        let channel = (status & 0x0f) as usize;
+        let data = |index: usize| message.get(index).copied().unwrap_or(0) & 0x7f;
+
+        match status & 0xf0 {
+            0x80 => self.note_off(channel, data(1)),
+            0x90 if data(2) == 0 => self.note_off(channel, data(1)),
+            0x90 => self.note_on(channel, data(1), data(2)),
+            0xb0 => self.control_change(channel, data(1), data(2)),
+            0xc0 => self.channels[channel].program = data(1) as u16,
+            0xe0 => {
+                self.channels[channel].pitch_bend = ((data(2) as i16) << 7 | data(1) as i16) - 8192;
+            }
+            _ => {}
+        };
+    }
+
+    /// Ends every voice and puts every channel back as it starts.
+    pub fn reset(&mut self) {
+        self.voices.clear();
+        self.channels = [Channel::new(); 16];
+    }
+
+    fn note_on(&mut self, channel: usize, key: u8, velocity: u8) {
+        // Channel 10 plays drum kits, whatever bank is selected
+        let bank = match channel {
+            9 => 128,
+            _ => self.channels[channel].bank,
+        };
+        let soundfont = Arc::clone(&self.soundfont);
+        let preset = match soundfont.preset(bank, self.channels[channel].program) {
+            Some(preset) => preset,
+            None => return,
+        };
+
+        self.note_off(channel, key);
+
+        for region in preset.regions.iter().filter(|r| r.covers(key, velocity)) {
+            let exclusive_class = region.generators[generator::EXCLUSIVE_CLASS];
+            if exclusive_class != 0 {
+                self.voices.retain(|voice| {
+                    voice.channel != channel || voice.exclusive_class != exclusive_class
+                });
+            }
+
+            if self.voices.len() >= MAX_VOICES {
+                let oldest = self
+                    .voices
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, voice)| (!voice.released, voice.age))
+                    .map(|(index, _)| index);
+                if let Some(oldest) = oldest {
+                    self.voices.swap_remove(oldest);
+                }
+            }
+
+            self.voices.push(Voice::new(
+                region,
+                channel,
+                key,
+                velocity,
+                self.next_age,
+                self.sample_rate,
+                soundfont.data.len(),
+            ));
+            self.next_age += 1;
+        }
+    }
+
+    fn note_off(&mut self, channel: usize, key: u8) {
+        let sustain = self.channels[channel].sustain;
+
+        for voice in &mut self.voices {
+            if voice.channel == channel && voice.key == key && !voice.released {
+                if sustain {
+                    voice.sustained = true;
+                } else {
+                    voice.release();
+                }
+            }
+        }
+    }
+
+    fn control_change(&mut self, channel: usize, controller: u8, value: u8) {
+        let state = &mut self.channels[channel];
+
+        match controller {
+            0 => state.bank = value as u16,
+            6 if state.rpn == (0, 0) => state.bend_range = value,
+            7 => state.volume = value,
+            10 => state.pan = value,
+            11 => state.expression = value,
+            64 => {
+                state.sustain = value >= 64;
+                if !state.sustain {
+                    for voice in &mut self.voices {
+                        if voice.channel == channel && voice.sustained {
+                            voice.release();
+                        }
+                    }
+                }
+            }
+            98 | 99 => state.rpn = NO_RPN,
+            100 => state.rpn.1 = value,
+            101 => state.rpn.0 = value,
+            120 => self.voices.retain(|voice| voice.channel != channel),
+            121 => {
+                let bank = state.bank;
+                let program = state.program;
+                let volume = state.volume;
+                let pan = state.pan;
+                *state = Channel {
+                    bank,
+                    program,
+                    volume,
+                    pan,
+                    ..Channel::new()
+                };
+                self.control_change(channel, 64, 0);
+            }
+            123 => {
+                for voice in &mut self.voices {
+                    if voice.channel == channel && !voice.released {
+                        voice.release();
+                    }
+                }
+            }
+            _ => {}
+        };
+    }
+
+    /// Fills `output` with interleaved stereo frames of every sounding voice.
+    pub fn render(&mut self, output: &mut [f32]) {
+        for sample in output.iter_mut() {
+            *sample = 0.0;
+        }
+
+        let data = &self.soundfont.data;
+        for block in output.chunks_mut(BLOCK_FRAMES * 2) {
+            for voice in &mut self.voices {
+                let channel = &self.channels[voice.channel];
+                voice.render(data, channel, block, self.sample_rate);
+            }
+            self.voices.retain(|voice| !voice.done());
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::path::PathBuf;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{Context, Result};
+use winapi::shared::guiddef::IsEqualGUID;
+use winapi::shared::ksmedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT;
+use winapi::shared::minwindef::FALSE;
+use winapi::shared::mmreg::{
+    WAVEFORMATEX, WAVEFORMATEXTENSIBLE, WAVE_FORMAT_EXTENSIBLE, WAVE_FORMAT_IEEE_FLOAT,
+    WAVE_FORMAT_PCM,
+};
+use winapi::shared::ntdef::HANDLE;
+use winapi::shared::winerror::HRESULT;
+use winapi::um::audioclient::{IAudioClient, IAudioRenderClient};
+use winapi::um::audiosessiontypes::{AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK};
+use winapi::um::combaseapi::{
+    CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL,
+};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::mmdeviceapi::{
+    eConsole, eRender, CLSID_MMDeviceEnumerator, IMMDevice, IMMDeviceEnumerator,
+};
+use winapi::um::objbase::COINIT_MULTITHREADED;
+use winapi::um::synchapi::{CreateEventW, WaitForSingleObject};
+use winapi::Interface;
+
+use crate::backend::{self, MidiOutput};
+use crate::soundfont::SoundFont;
+use crate::synth::Synth;
+
+/// Audio buffered ahead in the shared WASAPI buffer, in 100 ns units.
+const BUFFER_DURATION: i64 = 400_000;
+/// Milliseconds to wait for the device to ask for audio before checking
+/// whether the port was closed.
+const WAIT_MILLIS: u32 = 100;
+
+/// The SoundFont of the last port, kept so every song does not load it again.
+static LOADED: Mutex<Option<(PathBuf, Arc<SoundFont>)>> = Mutex::new(None);
+
+fn soundfont() -> Result<(PathBuf, Arc<SoundFont>)> {
+    let path = backend::selected_soundfont().context("No SoundFont selected")?;
+    let mut loaded = LOADED.lock().unwrap();
+
+    match &*loaded {
+        Some((loaded_path, soundfont)) if *loaded_path == path => Ok((path, Arc::clone(soundfont))),
+        _ => {
+            let soundfont = Arc::new(SoundFont::load(&path)?);
+            *loaded = Some((path.clone(), Arc::clone(&soundfont)));
+
+            Ok((path, soundfont))
+        }
+    }
+}
+
+fn check(result: HRESULT, message: &'static str) -> Result<()> {
+    match result {
+        result if result < 0 => Err(anyhow!("{}: HRESULT {:#010x}", message, result)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SampleFormat {
+    Float,
+    Int16,
+}
+
+/// The default audio output device, opened in shared mode with its own mix
+/// format. Only used on the thread that opened it.
+struct AudioOutput {
+    client: *mut IAudioClient,
+    render_client: *mut IAudioRenderClient,
+    event: HANDLE,
+    format: SampleFormat,
+    channels: usize,
+    sample_rate: u32,
+    buffer_frames: u32,
+}
+
+impl AudioOutput {
+    /// Opens the default device, with COM initialized for the calling thread
+    /// until the output is dropped.
+    unsafe fn open() -> Result<Self> {
+        check(
+            CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED),
+            "Failed to initialize COM",
+        )?;
+
+        // Dropping releases whatever was acquired before a failure
+        let mut output = Self {
+            client: ptr::null_mut(),
+            render_client: ptr::null_mut(),
+            event: ptr::null_mut(),
+            format: SampleFormat::Float,
+            channels: 2,
+            sample_rate: 0,
+            buffer_frames: 0,
+        };
+
+        let mut enumerator: *mut IMMDeviceEnumerator = ptr::null_mut();
+        check(
+            CoCreateInstance(
+                &CLSID_MMDeviceEnumerator,
+                ptr::null_mut(),
+                CLSCTX_ALL,
+                &IMMDeviceEnumerator::uuidof(),
+                &mut enumerator as *mut _ as *mut _,
+            ),
+            "Failed to create audio device enumerator",
+        )?;
+        let mut device: *mut IMMDevice = ptr::null_mut();
+        let result = (*enumerator).GetDefaultAudioEndpoint(eRender, eConsole, &mut device);
+        (*enumerator).Release();
+        check(result, "No audio output device")?;
+
+        let result = (*device).Activate(
+            &IAudioClient::uuidof(),
+            CLSCTX_ALL,
+            ptr::null_mut(),
+            &mut output.client as *mut _ as *mut _,
+        );
+        (*device).Release();
+        check(result, "Failed to activate audio client")?;
+
+        let mut format: *mut WAVEFORMATEX = ptr::null_mut();
+        check(
+            (*output.client).GetMixFormat(&mut format),
+            "Failed to get audio format",
+        )?;
+        let sample_format = Self::sample_format(format);
+        output.channels = (*format).nChannels as usize;
+        output.sample_rate = (*format).nSamplesPerSec;
+
+        let result = (*output.client).Initialize(
+            AUDCLNT_SHAREMODE_SHARED,
+            AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+            BUFFER_DURATION,
+            0,
+            format,
+            ptr::null(),
+        );
+        CoTaskMemFree(format as *mut _);
+        check(result, "Failed to initialize audio client")?;
+        output.format = sample_format?;
+
+        output.event = CreateEventW(ptr::null_mut(), FALSE, FALSE, ptr::null());
+        if output.event.is_null() {
+            return Err(anyhow!("Failed to create audio event"));
+        }
+        check(
+            (*output.client).SetEventHandle(output.event),
+            "Failed to set audio event",
+        )?;
+        check(
+            (*output.client).GetBufferSize(&mut output.buffer_frames),
+            "Failed to get audio buffer size",
+        )?;
+        check(
+            (*output.client).GetService(
+                &IAudioRenderClient::uuidof(),
+                &mut output.render_client as *mut _ as *mut _,
+            ),
+            "Failed to get audio render client",
+        )?;
+
+        Ok(output)
+    }
+
+    unsafe fn sample_format(format: *const WAVEFORMATEX) -> Result<SampleFormat> {
+        let tag = (*format).wFormatTag;
+        let bits = (*format).wBitsPerSample;
+        let float = match tag {
+            WAVE_FORMAT_EXTENSIBLE => {
+                let sub_format = (*(format as *const WAVEFORMATEXTENSIBLE)).SubFormat;
+                IsEqualGUID(&sub_format, &KSDATAFORMAT_SUBTYPE_IEEE_FLOAT)
+            }
+            _ => tag == WAVE_FORMAT_IEEE_FLOAT,
+        };
+
+        match (float, bits) {
+            (true, 32) => Ok(SampleFormat::Float),
+            (false, 16) if tag == WAVE_FORMAT_PCM || tag == WAVE_FORMAT_EXTENSIBLE => {
+                Ok(SampleFormat::Int16)
+            }
+            _ => Err(anyhow!("Unsupported audio format with {} bits", bits)),
+        }
+    }
+
+    /// Fills whatever room the buffer has with audio of `synth`, rendered
+    /// into `mix` as stereo and spread over the device's channels.
+    unsafe fn write(&self, synth: &Mutex<Synth>, mix: &mut Vec<f32>) -> Result<()> {
+        let mut padding = 0;
+        check(
+            (*self.client).GetCurrentPadding(&mut padding),
+            "Failed to get audio buffer padding",
+        )?;
+        let frames = (self.buffer_frames - padding) as usize;
+        if frames == 0 {
+            return Ok(());
+        }
+
+        mix.resize(frames * 2, 0.0);
+        synth.lock().unwrap().render(mix);
+
+        let mut buffer = ptr::null_mut();
+        check(
+            (*self.render_client).GetBuffer(frames as u32, &mut buffer),
+            "Failed to get audio buffer",
+        )?;
+
+        let samples = frames * self.channels;
+        let sample = |index: usize| {
+            let (frame, channel) = (index / self.channels, index % self.channels);
+            match (self.channels, channel) {
+                (1, _) => (mix[frame * 2] + mix[frame * 2 + 1]) / 2.0,
+                (_, 0) | (_, 1) => mix[frame * 2 + channel],
+                _ => 0.0,
+            }
+            .clamp(-1.0, 1.0)
+        };
+        match self.format {
+            SampleFormat::Float => {
+                let buffer = slice::from_raw_parts_mut(buffer as *mut f32, samples);
+                for (index, value) in buffer.iter_mut().enumerate() {
+                    *value = sample(index);
+                }
+            }
+            SampleFormat::Int16 => {
+                let buffer = slice::from_raw_parts_mut(buffer as *mut i16, samples);
+                for (index, value) in buffer.iter_mut().enumerate() {
+                    *value = (sample(index) * 32767.0) as i16;
+                }
+            }
+        };
+
+        check(
+            (*self.render_client).ReleaseBuffer(frames as u32, 0),
+            "Failed to release audio buffer",
+        )
+    }
+}
+
+impl Drop for AudioOutput {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.render_client.is_null() {
+                (*self.render_client).Release();
+            }
+            if !self.client.is_null() {
+                (*self.client).Stop();
+                (*self.client).Release();
+            }
+            if !self.event.is_null() {
+                CloseHandle(self.event);
+            }
+            CoUninitialize();
+        }
+    }
+}
+
+/// Opens the output and plays `soundfont` on it until `running` is cleared,
+/// handing the synth back through `ready` once the sample rate is known.
+fn render(
+    soundfont: Arc<SoundFont>,
+    running: Arc<AtomicBool>,
+    ready: SyncSender<Result<Arc<Mutex<Synth>>>>,
+) -> Result<()> {
+    let output = match unsafe { AudioOutput::open() } {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = ready.send(Err(e));
+            return Ok(());
+        }
+    };
+    let synth = Arc::new(Mutex::new(Synth::new(soundfont, output.sample_rate)));
+    let _ = ready.send(Ok(Arc::clone(&synth)));
+
+    let mut mix = Vec::new();
+    unsafe {
+        output.write(&synth, &mut mix)?;
+        check((*output.client).Start(), "Failed to start audio")?;
+
+        while running.load(Ordering::Relaxed) {
+            WaitForSingleObject(output.event, WAIT_MILLIS);
+            output.write(&synth, &mut mix)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The built-in synthesizer as the only output port, playing the selected
+/// SoundFont on the default audio device through WASAPI.
+pub struct SynthPort {
+    synth: Arc<Mutex<Synth>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl MidiOutput for SynthPort {
+    fn count() -> u32 {
+        match backend::selected_soundfont() {
+            Some(_) => 1,
+            None => 0,
+        }
+    }
+
+    fn name(port_number: u32) -> Result<String> {
+        match (port_number, backend::selected_soundfont()) {
+            (0, Some(path)) => Ok(format!(
+                "SoundFont synth ({})",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            )),
+            _ => Err(anyhow!("Port number out of range")),
+        }
+    }
+
+    fn connect(port_number: u32) -> Result<Self> {
+        if port_number != 0 {
+            return Err(anyhow!("Port number out of range"));
+        }
+
+        let (path, soundfont) = soundfont()?;
+        let running = Arc::new(AtomicBool::new(true));
+        let (ready, ready_receiver) = mpsc::sync_channel(1);
+
+        let thread = {
+            let running = Arc::clone(&running);
+            thread::Builder::new()
+                .name(String::from("synth"))
+                .spawn(move || render(soundfont, running, ready))
+                .context("Failed to start synth thread")?
+        };
+        let synth = ready_receiver
+            .recv()
+            .context("Synth thread ended before opening audio")?
+            .with_context(|| format!("Failed to play {}", path.display()))?;
+
+        Ok(Self {
+            synth,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        match &self.thread {
+            Some(thread) if !thread.is_finished() => {}
+            _ => return Err(self.stopped()),
+        };
+
+        self.synth.lock().unwrap().process(message);
+
+        Ok(())
+    }
+}
+
+impl SynthPort {
+    /// Why the audio thread ended, which it only does on errors.
+    fn stopped(&mut self) -> anyhow::Error {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => e.context("Synth audio stopped"),
+            _ => anyhow!("Synth audio stopped"),
+        }
+    }
+}
+
+impl Drop for SynthPort {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
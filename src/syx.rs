@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::checksum;
+use crate::midi_file::{DataEvent, LocalEvent};
+
+/// Ticks per quarter note when sending SysEx files, which at `TEMPO` makes
+/// every tick a millisecond.
+pub const DIVISION: u64 = 1000;
+pub const TEMPO: u64 = 1_000_000;
+
+/// Pause between messages that gives most devices time to store the last one.
+pub const DEFAULT_DELAY: Duration = Duration::from_millis(50);
+
+pub fn is_syx(path: &Path) -> bool {
+    match path.extension() {
+        Some(extension) => extension.eq_ignore_ascii_case("syx"),
+        None => false,
+    }
+}
+
+/// Reads every message of a `.syx` file as a sequence with `delay` between
+/// consecutive messages.
+pub fn load(path: &Path, delay: Duration) -> Result<Vec<DataEvent>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let messages =
+        split_messages(&data).with_context(|| format!("Invalid SysEx file {}", path.display()))?;
+
+    if messages.is_empty() {
+        return Err(anyhow!("No SysEx messages in {}", path.display()));
+    }
+
+    let delay = delay.as_millis() as u64;
+
+    Ok(messages
+        .into_iter()
+        .enumerate()
+        .map(|(i, message)| {
+            let delta_time = if i == 0 { 0 } else { delay };
+            DataEvent::new(delta_time, LocalEvent::SysEx(message))
+        })
+        .collect())
+}
+
+/// Indices of the messages whose Roland or Yamaha checksum is wrong.
+pub fn bad_checksums(events: &[DataEvent]) -> Vec<usize> {
+    events
+        .iter()
+        .enumerate()
+        .filter_map(|(i, event)| match &event.data {
+            LocalEvent::SysEx(message) if checksum::verify(message) == Some(false) => Some(i),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Rewrites every wrong Roland or Yamaha checksum, returning how many there
+/// were.
+pub fn fix_checksums(events: &mut [DataEvent]) -> usize {
+    let mut fixed = 0;
+
+    for event in events {
+        if let LocalEvent::SysEx(message) = &mut event.data {
+            if checksum::patch(message) {
+                fixed += 1;
+            }
+        }
+    }
+
+    fixed
+}
+
+/// Splits raw SysEx data into messages, each from its `F0` to its `F7`.
+pub fn split_messages(data: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+
+    for (offset, &byte) in data.iter().enumerate() {
+        match (byte, &mut current) {
+            (0xf0, Some(_)) => {
+                return Err(anyhow!("Unterminated message before offset {}", offset));
+            }
+            (0xf0, None) => current = Some(vec![byte]),
+            (0xf7, Some(message)) => {
+                message.push(byte);
+                messages.extend(current.take());
+            }
+            (_, Some(message)) => message.push(byte),
+            (_, None) => {
+                return Err(anyhow!(
+                    "Byte {:02x} outside of a message at offset {}",
+                    byte,
+                    offset
+                ));
+            }
+        };
+    }
+
+    if current.is_some() {
+        return Err(anyhow!("Unterminated message at end of file"));
+    }
+
+    Ok(messages)
+}
@@ -0,0 +1,120 @@
+use std::time::Duration;
+
+use rimd::MetaCommand;
+
+use crate::midi_file::{DataEvent, LocalEvent};
+
+/// Every tempo change of a sequence, in microseconds per quarter note by
+/// absolute tick.
+#[derive(Clone)]
+pub struct TempoMap {
+    initial: u64,
+    /// Sorted by tick.
+    changes: Vec<(u64, u64)>,
+}
+
+impl TempoMap {
+    /// The map of tempo changes in `changes`, given in any order, with
+    /// `initial` in effect before the first of them.
+    pub fn new(initial: u64, mut changes: Vec<(u64, u64)>) -> Self {
+        changes.sort_by_key(|&(tick, _)| tick);
+
+        Self { initial, changes }
+    }
+
+    /// Collects the tempo settings of a delta-timed event stream.
+    pub fn from_events(events: &[DataEvent], initial: u64) -> Self {
+        let mut changes = Vec::new();
+        let mut tick = 0;
+
+        for event in events {
+            tick += event.delta_time;
+
+            if let LocalEvent::Meta(meta) = &event.data {
+                if let MetaCommand::TempoSetting = meta.command {
+                    changes.push((tick, meta.data_as_u64(3)));
+                }
+            }
+        }
+
+        Self::new(initial, changes)
+    }
+
+    pub fn changes(&self) -> &[(u64, u64)] {
+        &self.changes
+    }
+
+    /// The tempo in effect at `tick`, including a change on that very tick.
+    pub fn tempo_at(&self, tick: u64) -> u64 {
+        let index = self.changes.partition_point(|&(change, _)| change <= tick);
+
+        match index {
+            0 => self.initial,
+            _ => self.changes[index - 1].1,
+        }
+    }
+
+    /// How long it takes to play up to `tick`.
+    pub fn time_at(&self, tick: u64, division: u64) -> Duration {
+        let micros = self.span(0, tick);
+
+        Duration::from_micros((micros / division.max(1) as u128) as u64)
+    }
+
+    /// The tick reached after `time` of playback, the inverse of `time_at`.
+    pub fn tick_at(&self, time: Duration, division: u64) -> u64 {
+        let target = time.as_micros() * division.max(1) as u128;
+        let mut sum: u128 = 0;
+        let mut position = 0;
+        let mut tempo = self.initial;
+
+        for &(change, new_tempo) in &self.changes {
+            let segment = (change - position) as u128 * tempo as u128;
+            if sum + segment > target {
+                break;
+            }
+
+            sum += segment;
+            position = change;
+            tempo = new_tempo;
+        }
+
+        position + ((target - sum) / tempo.max(1) as u128) as u64
+    }
+
+    /// Ticks times tempo from `from` to `to`, split at every tempo change in
+    /// between. Dividing by the division gives microseconds, which is left to
+    /// the caller so rounding does not add up over many spans.
+    pub fn span(&self, from: u64, to: u64) -> u128 {
+        let mut sum: u128 = 0;
+        let mut position = from;
+        let mut tempo = self.tempo_at(from);
+
+        for &(change, new_tempo) in &self.changes {
+            if change <= from {
+                continue;
+            }
+            if change >= to {
+                break;
+            }
+
+            sum += (change - position) as u128 * tempo as u128;
+            position = change;
+            tempo = new_tempo;
+        }
+
+        sum + to.saturating_sub(position) as u128 * tempo as u128
+    }
+}
+
+/// Formats a duration as minutes, seconds and milliseconds.
+pub fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use rimd::{Event, MetaCommand, Track};
+
+/// Whether a meta event holds text, such as a track name or lyric.
+pub fn is_text(command: &MetaCommand) -> bool {
+    matches!(
+        command,
+        MetaCommand::TextEvent
+            | MetaCommand::CopyrightNotice
+            | MetaCommand::SequenceOrTrackName
+            | MetaCommand::InstrumentName
+            | MetaCommand::LyricText
+            | MetaCommand::MarkerText
+            | MetaCommand::CuePoint
+    )
+}
+
+/// Guesses the encoding of the text meta events of all tracks together, as
+/// single names are too short to tell. Valid UTF-8, including plain ASCII, is
+/// taken as such; anything else is usually Shift-JIS, GBK or a Windows code
+/// page in older files.
+pub fn detect(tracks: &[Track]) -> &'static Encoding {
+    let texts: Vec<&[u8]> = tracks
+        .iter()
+        .flat_map(|track| track.events.iter())
+        .filter_map(|event| match &event.event {
+            Event::Meta(meta) if is_text(&meta.command) => Some(&meta.data[..]),
+            _ => None,
+        })
+        .collect();
+
+    if texts.iter().all(|text| std::str::from_utf8(text).is_ok()) {
+        return UTF_8;
+    }
+
+    let mut detector = EncodingDetector::new();
+    for text in texts {
+        detector.feed(text, false);
+    }
+    detector.feed(&[], true);
+
+    detector.guess(None, true)
+}
+
+/// Looks up an encoding by one of its WHATWG labels, like `shift_jis`, `gbk`
+/// or `windows-1252`.
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| anyhow!("Unknown text encoding: {}", label))
+}
+
+/// Decodes the text of a meta event, dropping the padding some files end
+/// it with.
+pub fn decode(data: &[u8], encoding: &'static Encoding) -> String {
+    let (text, _) = encoding.decode_without_bom_handling(data);
+
+    text.trim_end_matches('\0').to_string()
+}
+
+/// The first name a track gives itself, falling back to the one rimd read
+/// as UTF-8.
+pub fn track_name(track: &Track, encoding: &'static Encoding) -> Option<String> {
+    first_text(track, encoding, |command| {
+        matches!(command, MetaCommand::SequenceOrTrackName)
+    })
+    .or_else(|| track.name.clone())
+}
+
+/// The first copyright notice of a track, falling back like `track_name`.
+pub fn track_copyright(track: &Track, encoding: &'static Encoding) -> Option<String> {
+    first_text(track, encoding, |command| {
+        matches!(command, MetaCommand::CopyrightNotice)
+    })
+    .or_else(|| track.copyright.clone())
+}
+
+fn first_text(
+    track: &Track,
+    encoding: &'static Encoding,
+    wanted: impl Fn(&MetaCommand) -> bool,
+) -> Option<String> {
+    track.events.iter().find_map(|event| match &event.event {
+        Event::Meta(meta) if wanted(&meta.command) => Some(decode(&meta.data, encoding)),
+        _ => None,
+    })
+}
+
+/// A title for showing what is playing: the first karaoke `@T` tag, or else
+/// the first track name that is not blank.
+pub fn title(tracks: &[Track], encoding: &'static Encoding) -> Option<String> {
+    let karaoke = tracks
+        .iter()
+        .flat_map(|track| track.events.iter())
+        .find_map(|event| match &event.event {
+            Event::Meta(meta) if matches!(meta.command, MetaCommand::TextEvent) => {
+                let text = decode(&meta.data, encoding);
+                let tag = text.strip_prefix("@T")?.trim();
+
+                if tag.is_empty() {
+                    None
+                } else {
+                    Some(tag.to_string())
+                }
+            }
+            _ => None,
+        });
+
+    karaoke.or_else(|| {
+        tracks
+            .iter()
+            .filter_map(|track| track_name(track, encoding))
+            .map(|name| name.trim().to_string())
+            .find(|name| !name.is_empty())
+    })
+}
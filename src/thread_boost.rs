@@ -1,8 +1,11 @@
+#[cfg(windows)]
 use windows::{IntoParam, Param};
 
+#[cfg(windows)]
 use crate::bindings::Windows::Win32::Foundation::{BOOL, HANDLE, PWSTR};
 
 // These functions are not included in `windows-rs` yet.
+#[cfg(windows)]
 #[link(name = "avrt")]
 extern "system" {
     pub fn AvSetMmThreadCharacteristicsW(
@@ -15,11 +18,13 @@ extern "system" {
 }
 
 pub struct ThreadBoost {
+    #[cfg(windows)]
     handle: HANDLE,
     task_index: u32,
 }
 
 impl ThreadBoost {
+    #[cfg(windows)]
     pub fn new() -> Self {
         let mut task_name: Param<PWSTR> = "Pro Audio".into_param();
         let mut task_index = 0;
@@ -30,11 +35,18 @@ impl ThreadBoost {
         Self { handle, task_index }
     }
 
+    // MMCSS only exists on Windows, elsewhere the thread keeps its priority.
+    #[cfg(not(windows))]
+    pub fn new() -> Self {
+        Self { task_index: 0 }
+    }
+
     pub fn task_index(&self) -> u32 {
         self.task_index
     }
 }
 
+#[cfg(windows)]
 impl Drop for ThreadBoost {
     fn drop(&mut self) {
         unsafe {
@@ -0,0 +1,77 @@
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::backend::{InputPort, MidiInput, MidiOutput, OutputPort};
+use crate::cancel::CancellationToken;
+use crate::safety::{SafetyLimiter, SafetyLimits, Verdict};
+use crate::thread_boost::ThreadBoost;
+
+/// Messages received on the thru input port, shared with whichever thread
+/// currently owns the output port.
+pub type ThruReceiver = Arc<Mutex<Receiver<Vec<u8>>>>;
+
+pub fn open(input_port: u32) -> Result<(InputPort, ThruReceiver)> {
+    let (sender, receiver) = mpsc::channel();
+    let input = InputPort::connect(input_port, sender)
+        .with_context(|| format!("Failed to open input port {}", input_port))?;
+
+    Ok((input, Arc::new(Mutex::new(receiver))))
+}
+
+/// Hands every pending thru message to `send` without blocking.
+pub fn drain(thru: &ThruReceiver, mut send: impl FnMut(Vec<u8>) -> Result<()>) -> Result<()> {
+    if let Ok(receiver) = thru.try_lock() {
+        while let Ok(message) = receiver.try_recv() {
+            send(message)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Forwards thru messages to the output port until `cancel` is cancelled,
+/// reporting safety interventions on stderr to keep stdout to the output.
+pub fn forward(
+    thru: ThruReceiver,
+    port_id: u32,
+    safety: Option<SafetyLimits>,
+    cancel: CancellationToken,
+) -> Result<()> {
+    let mut conn_out = OutputPort::connect(port_id)?;
+    let mut safety = safety.map(SafetyLimiter::new);
+
+    let _thread_boost = ThreadBoost::new();
+    let receiver = thru
+        .lock()
+        .map_err(|_| anyhow!("Thru receiver lock poisoned"))?;
+
+    while !cancel.is_cancelled() {
+        match receiver.recv_timeout(Duration::from_millis(10)) {
+            Ok(mut message) => {
+                if let Some(safety) = &mut safety {
+                    match safety.filter(&mut message) {
+                        Verdict::Send => {}
+                        Verdict::Clamped(reason) => eprintln!("Safety: {}", reason),
+                        Verdict::Dropped(reason) => {
+                            eprintln!("Safety: dropped {}", reason);
+                            continue;
+                        }
+                    };
+                }
+
+                conn_out
+                    .send(&message)
+                    .context("Failed to send thru message")?;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        conn_out.check_inflight()?;
+    }
+
+    Ok(())
+}
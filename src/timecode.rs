@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::midi_file::{self, DataEvent};
+use crate::tempo::TempoMap;
+
+const QUARTER_FRAME: u8 = 0xf1;
+
+/// Frames per second of the MIDI Time Code sent.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FrameRate {
+    Fps24,
+    Fps25,
+    /// Non-drop 30 frames per second.
+    Fps30,
+}
+
+impl FrameRate {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "24" => Ok(FrameRate::Fps24),
+            "25" => Ok(FrameRate::Fps25),
+            "30" => Ok(FrameRate::Fps30),
+            _ => Err(anyhow!("Unknown frame rate: {}, use 24, 25 or 30", value)),
+        }
+    }
+
+    fn frames_per_second(self) -> u64 {
+        match self {
+            FrameRate::Fps24 => 24,
+            FrameRate::Fps25 => 25,
+            FrameRate::Fps30 => 30,
+        }
+    }
+
+    /// The rate as coded in the hours of a time code.
+    fn code(self) -> u8 {
+        match self {
+            FrameRate::Fps24 => 0,
+            FrameRate::Fps25 => 1,
+            FrameRate::Fps30 => 3,
+        }
+    }
+
+    /// Hours, minutes, seconds and frames of `frame`.
+    fn split(self, frame: u64) -> [u8; 4] {
+        let fps = self.frames_per_second();
+        let seconds = frame / fps;
+
+        [
+            (seconds / 3600 % 24) as u8,
+            (seconds / 60 % 60) as u8,
+            (seconds % 60) as u8,
+            (frame % fps) as u8,
+        ]
+    }
+}
+
+/// Merges quarter frame messages into the events, placed by the tempo map at
+/// the wall-clock time they stand for, so DAWs and lighting consoles can
+/// chase playback. Every eight quarter frames carry the time at the first.
+pub fn apply(
+    events: Vec<DataEvent>,
+    division: u64,
+    tempo_map: &TempoMap,
+    rate: FrameRate,
+) -> Vec<DataEvent> {
+    let length: u64 = events.iter().map(|event| event.delta_time).sum();
+    let quarter_frames = rate.frames_per_second() * 4;
+
+    let messages = (0..)
+        .map(|quarter_frame: u64| {
+            let time = Duration::from_micros(quarter_frame * 1_000_000 / quarter_frames);
+            let tick = tempo_map.tick_at(time, division);
+
+            // Eight quarter frames span two frames
+            let [hours, minutes, seconds, frames] = rate.split(quarter_frame / 8 * 2);
+            let piece = (quarter_frame % 8) as u8;
+            let value = match piece {
+                0 => frames & 0x0f,
+                1 => frames >> 4,
+                2 => seconds & 0x0f,
+                3 => seconds >> 4,
+                4 => minutes & 0x0f,
+                5 => minutes >> 4,
+                6 => hours & 0x0f,
+                _ => hours >> 4 | rate.code() << 1,
+            };
+
+            (tick, [QUARTER_FRAME, piece << 4 | value, 0])
+        })
+        .take_while(|&(tick, _)| tick < length)
+        .collect();
+
+    midi_file::merge_messages(events, messages)
+}
+
+/// The full frame message locating a time code receiver at `time`, sent
+/// when playback moves elsewhere than quarter frames can follow.
+pub fn full_frame(time: Duration, rate: FrameRate) -> Vec<u8> {
+    let frame = time.as_micros() as u64 * rate.frames_per_second() / 1_000_000;
+    let [hours, minutes, seconds, frames] = rate.split(frame);
+
+    vec![
+        0xf0,
+        0x7f,
+        0x7f,
+        0x01,
+        0x01,
+        rate.code() << 5 | hours,
+        minutes,
+        seconds,
+        frames,
+        0xf7,
+    ]
+}
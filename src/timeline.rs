@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+
+use rimd::MetaCommand;
+
+use crate::midi_file::{DataEvent, LocalEvent};
+use crate::player::DEFAULT_TEMPO;
+
+/// Modulation, volume, pan, expression, sustain, the sound controllers and
+/// the reverb and chorus sends.
+const KEY_CONTROLLERS: &[u8] = &[1, 7, 10, 11, 64, 71, 72, 73, 74, 91, 93];
+
+/// What a channel should be set to, as far as the sequence said so.
+#[derive(Clone, Default)]
+pub struct ChannelState {
+    pub program: Option<u8>,
+    pub bank_msb: Option<u8>,
+    pub bank_lsb: Option<u8>,
+    pub controllers: BTreeMap<u8, u8>,
+    pub pitch_bend: Option<u16>,
+}
+
+/// The state at the start of a bar, after the events on its first tick.
+pub struct BarState {
+    /// Counted from 1.
+    pub bar: u32,
+    pub tick: u64,
+    pub tempo: u64,
+    pub time_signature: (u8, u8),
+    /// Channels from 0, leaving out those not touched yet.
+    pub channels: BTreeMap<u8, ChannelState>,
+}
+
+/// Follows the channel state through the events, taking a snapshot at every
+/// bar line.
+pub fn timeline(events: &[DataEvent], division: u64) -> Vec<BarState> {
+    let mut bars = Vec::new();
+    let mut channels: BTreeMap<u8, ChannelState> = BTreeMap::new();
+    let mut tempo = DEFAULT_TEMPO;
+    let mut time_signature = (4, 4);
+    let mut next_bar = 0;
+    let mut tick = 0;
+
+    for event in events {
+        tick += event.delta_time;
+
+        while next_bar < tick {
+            bars.push(snapshot(&bars, next_bar, tempo, time_signature, &channels));
+            next_bar += bar_length(division, time_signature);
+        }
+
+        match &event.data {
+            LocalEvent::Meta(meta) => match meta.command {
+                MetaCommand::TempoSetting => tempo = meta.data_as_u64(3),
+                MetaCommand::TimeSignature if meta.data.len() >= 2 => {
+                    time_signature = (meta.data[0].max(1), 1 << meta.data[1].min(6));
+                }
+                _ => {}
+            },
+            LocalEvent::Midi(data) => {
+                let state = channels.entry(data[0] & 0x0f).or_default();
+
+                match data[0] & 0xf0 {
+                    0xb0 => match data[1] {
+                        0 => state.bank_msb = Some(data[2]),
+                        32 => state.bank_lsb = Some(data[2]),
+                        controller if KEY_CONTROLLERS.contains(&controller) => {
+                            state.controllers.insert(controller, data[2]);
+                        }
+                        _ => {}
+                    },
+                    0xc0 => state.program = Some(data[1]),
+                    0xe0 => state.pitch_bend = Some(data[1] as u16 | (data[2] as u16) << 7),
+                    _ => {}
+                };
+            }
+            LocalEvent::SysEx(_) => {}
+        };
+    }
+
+    while next_bar < tick || bars.is_empty() {
+        bars.push(snapshot(&bars, next_bar, tempo, time_signature, &channels));
+        next_bar += bar_length(division, time_signature);
+    }
+
+    bars
+}
+
+fn snapshot(
+    bars: &[BarState],
+    tick: u64,
+    tempo: u64,
+    time_signature: (u8, u8),
+    channels: &BTreeMap<u8, ChannelState>,
+) -> BarState {
+    BarState {
+        bar: bars.len() as u32 + 1,
+        tick,
+        tempo,
+        time_signature,
+        channels: channels.clone(),
+    }
+}
+
+/// Bar and beat of `tick` as text, like "bar 5 beat 2".
+pub fn bar_and_beat(tick: u64, division: u64, signatures: &[(u64, (u8, u8))]) -> String {
+    let (bar, beat, _) = locate(tick, division, signatures);
+
+    format!("bar {} beat {}", bar, beat)
+}
+
+/// Bar and beat of `tick`, both counted from 1, and the ticks into the beat,
+/// with the time signatures of `signatures` sorted by tick, each given as its
+/// numerator and the power of two of its denominator. A signature changing
+/// mid-bar starts a new bar.
+pub fn locate(tick: u64, division: u64, signatures: &[(u64, (u8, u8))]) -> (u64, u64, u64) {
+    let mut bar = 1;
+    let mut position = 0;
+    let mut beat_length = division.max(1);
+    let mut beats = 4;
+
+    for &(change, (numerator, denominator)) in signatures {
+        if change > tick {
+            break;
+        }
+
+        let bar_length = beat_length * beats;
+        bar += (change - position).div_ceil(bar_length);
+        position = change;
+        beat_length = ((division * 4) >> denominator).max(1);
+        beats = numerator as u64;
+    }
+
+    let bar_length = beat_length * beats;
+    let offset = tick - position;
+
+    (
+        bar + offset / bar_length,
+        offset % bar_length / beat_length + 1,
+        offset % beat_length,
+    )
+}
+
+/// The tick `beat` of `bar` starts at, both counted from 1, the inverse of
+/// `locate`.
+pub fn tick_of(bar: u64, beat: u64, division: u64, signatures: &[(u64, (u8, u8))]) -> u64 {
+    let mut first_bar = 1;
+    let mut position = 0;
+    let mut beat_length = division.max(1);
+    let mut beats = 4;
+
+    for &(change, (numerator, denominator)) in signatures {
+        let bar_length = beat_length * beats;
+        let change_bar = first_bar + (change - position).div_ceil(bar_length);
+        if change_bar > bar {
+            break;
+        }
+
+        first_bar = change_bar;
+        position = change;
+        beat_length = ((division * 4) >> denominator).max(1);
+        beats = numerator as u64;
+    }
+
+    position
+        + (bar.max(first_bar) - first_bar) * beat_length * beats
+        + (beat.max(1) - 1) * beat_length
+}
+
+/// The time signatures of a delta-timed event stream, by absolute tick, in
+/// the form `bar_and_beat` takes.
+pub fn time_signatures(events: &[DataEvent]) -> Vec<(u64, (u8, u8))> {
+    let mut signatures = Vec::new();
+    let mut tick = 0;
+
+    for event in events {
+        tick += event.delta_time;
+
+        match &event.data {
+            LocalEvent::Meta(meta)
+                if matches!(meta.command, MetaCommand::TimeSignature) && meta.data.len() >= 2 =>
+            {
+                signatures.push((tick, (meta.data[0].max(1), meta.data[1].min(6))));
+            }
+            _ => {}
+        }
+    }
+
+    signatures
+}
+
+fn bar_length(division: u64, (numerator, denominator): (u8, u8)) -> u64 {
+    (division * 4 * numerator as u64 / denominator as u64).max(1)
+}
+
+/// The timeline as a JSON document, one bar per line.
+pub fn to_json(bars: &[BarState]) -> String {
+    let bars: Vec<String> = bars
+        .iter()
+        .map(|bar| {
+            let channels: Vec<String> = bar
+                .channels
+                .iter()
+                .map(|(channel, state)| channel_json(*channel, state))
+                .collect();
+
+            format!(
+                "{{\"bar\":{},\"tick\":{},\"tempo\":{},\"time_signature\":\"{}/{}\",\"channels\":[{}]}}",
+                bar.bar,
+                bar.tick,
+                bar.tempo,
+                bar.time_signature.0,
+                bar.time_signature.1,
+                channels.join(",")
+            )
+        })
+        .collect();
+
+    format!("[\n{}\n]", bars.join(",\n"))
+}
+
+fn channel_json(channel: u8, state: &ChannelState) -> String {
+    let controllers: Vec<String> = state
+        .controllers
+        .iter()
+        .map(|(controller, value)| format!("\"{}\":{}", controller, value))
+        .collect();
+
+    format!(
+        "{{\"channel\":{},\"program\":{},\"bank_msb\":{},\"bank_lsb\":{},\"controllers\":{{{}}},\"pitch_bend\":{}}}",
+        channel + 1,
+        json_option(state.program),
+        json_option(state.bank_msb),
+        json_option(state.bank_lsb),
+        controllers.join(","),
+        json_option(state.pitch_bend)
+    )
+}
+
+fn json_option<T: ToString>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => String::from("null"),
+    }
+}
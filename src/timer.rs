@@ -0,0 +1,112 @@
+use std::thread;
+use std::time::Duration;
+
+#[cfg(windows)]
+use std::ptr;
+
+#[cfg(windows)]
+use winapi::shared::minwindef::FALSE;
+#[cfg(windows)]
+use winapi::shared::ntdef::{HANDLE, LARGE_INTEGER};
+#[cfg(windows)]
+use winapi::um::handleapi::CloseHandle;
+#[cfg(windows)]
+use winapi::um::synchapi::{CreateWaitableTimerExW, SetWaitableTimer, WaitForSingleObject};
+#[cfg(windows)]
+use winapi::um::timeapi::{timeBeginPeriod, timeEndPeriod};
+#[cfg(windows)]
+use winapi::um::winbase::INFINITE;
+#[cfg(windows)]
+use winapi::um::winnt::TIMER_ALL_ACCESS;
+
+// Not in `winapi` yet, available since Windows 10 1803.
+#[cfg(windows)]
+const CREATE_WAITABLE_TIMER_HIGH_RESOLUTION: u32 = 0x00000002;
+
+/// How long before a deadline we stop sleeping and spin instead, covering the
+/// wake-up latency of the scheduler.
+const SPIN_THRESHOLD: Duration = Duration::from_micros(1500);
+
+/// Sleeps with better than the default 15.6ms resolution of Windows, using a
+/// high-resolution waitable timer or, on older systems, a raised system timer
+/// resolution.
+pub struct PreciseTimer {
+    #[cfg(windows)]
+    handle: HANDLE,
+}
+
+impl PreciseTimer {
+    #[cfg(windows)]
+    pub fn new() -> Self {
+        let handle = unsafe {
+            CreateWaitableTimerExW(
+                ptr::null_mut(),
+                ptr::null(),
+                CREATE_WAITABLE_TIMER_HIGH_RESOLUTION,
+                TIMER_ALL_ACCESS,
+            )
+        };
+
+        if handle.is_null() {
+            unsafe { timeBeginPeriod(1) };
+        }
+
+        Self { handle }
+    }
+
+    #[cfg(not(windows))]
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    #[cfg(windows)]
+    pub fn sleep(&self, duration: Duration) {
+        if self.handle.is_null() {
+            thread::sleep(duration);
+            return;
+        }
+
+        unsafe {
+            // Negative due times are relative, in 100ns units
+            let mut due_time: LARGE_INTEGER = std::mem::zeroed();
+            *due_time.QuadPart_mut() = -((duration.as_nanos() / 100) as i64);
+
+            if SetWaitableTimer(self.handle, &due_time, 0, None, ptr::null_mut(), FALSE) == 0 {
+                thread::sleep(duration);
+                return;
+            }
+
+            WaitForSingleObject(self.handle, INFINITE);
+        }
+    }
+
+    // Other platforms already sleep with sub-millisecond resolution.
+    #[cfg(not(windows))]
+    pub fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    /// Sleeps through as much of `remaining` as can be slept without
+    /// overshooting, but no longer than `max_sleep`, leaving the rest to the
+    /// caller's spin loop.
+    pub fn wait(&self, remaining: Duration, max_sleep: Duration) {
+        if remaining > SPIN_THRESHOLD {
+            self.sleep((remaining - SPIN_THRESHOLD).min(max_sleep));
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for PreciseTimer {
+    fn drop(&mut self) {
+        unsafe {
+            if self.handle.is_null() {
+                timeEndPeriod(1);
+            } else {
+                CloseHandle(self.handle);
+            }
+        }
+    }
+}
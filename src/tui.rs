@@ -0,0 +1,315 @@
+use std::collections::VecDeque;
+use std::io::{self, Stdout, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, execute, queue};
+
+use midi_play::events::PlayerEvent;
+use midi_play::remote::RemoteStatus;
+use midi_play::tempo::format_time;
+
+use crate::Command;
+
+/// Lines of the event log kept for scrolling back.
+const LOG_LENGTH: usize = 1000;
+
+/// How much of its level a channel meter keeps from one frame to the next.
+const METER_DECAY: f64 = 0.85;
+const METER_WIDTH: usize = 20;
+
+/// What a key asks for.
+pub enum Action {
+    Command(Command),
+    Quit,
+}
+
+/// A terminal UI with the playlist, a progress bar, channel activity meters
+/// and a scrolling event log, in place of the console output. It takes the
+/// alternate screen for as long as it lives.
+pub struct Tui {
+    stdout: Stdout,
+    log: VecDeque<String>,
+    /// Lines scrolled back from the end of the log.
+    scroll: usize,
+    /// Level of every channel, from the velocity of its notes.
+    levels: [f64; 16],
+    /// Channel `m` mutes, counted from 0.
+    selected: u8,
+    bar: u64,
+    beat: u64,
+    /// Shortest time between frames.
+    interval: Duration,
+    drawn: Instant,
+}
+
+impl Tui {
+    pub fn new(interval: Duration) -> Result<Self> {
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode().context("Failed to set up the terminal")?;
+        execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)
+            .context("Failed to set up the terminal")?;
+
+        Ok(Self {
+            stdout,
+            log: VecDeque::new(),
+            scroll: 0,
+            levels: [0.0; 16],
+            selected: 0,
+            bar: 1,
+            beat: 1,
+            interval,
+            drawn: Instant::now(),
+        })
+    }
+
+    pub fn log(&mut self, line: impl Into<String>) {
+        if self.log.len() == LOG_LENGTH {
+            self.log.pop_front();
+        }
+        self.log.push_back(line.into());
+
+        // Stay on what was scrolled back to
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.log.len());
+        }
+    }
+
+    /// Takes in an event played `time` into the song for the meters and the
+    /// log.
+    pub fn process(&mut self, time: Duration, event: &PlayerEvent) {
+        match event {
+            PlayerEvent::NoteOn {
+                channel, velocity, ..
+            } => {
+                let level = &mut self.levels[*channel as usize & 0x0f];
+                *level = level.max(*velocity as f64 / 127.0);
+            }
+            PlayerEvent::NoteOff { .. } => {}
+            PlayerEvent::Progress { bar, beat, .. } => {
+                self.bar = *bar;
+                self.beat = *beat;
+            }
+            _ => self.log(event.log_line(time, false)),
+        };
+    }
+
+    /// Turns the keys pressed since the last call into actions, moving the
+    /// channel selection and the log itself.
+    pub fn poll_keys(&mut self) -> Result<Vec<Action>> {
+        let mut actions = Vec::new();
+
+        while event::poll(Duration::ZERO).context("Failed to read the keyboard")? {
+            let key = match event::read().context("Failed to read the keyboard")? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                _ => continue,
+            };
+
+            let command = match key {
+                KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                }
+                | KeyEvent {
+                    code: KeyCode::Char('q'),
+                    ..
+                } => {
+                    actions.push(Action::Quit);
+                    continue;
+                }
+                KeyEvent { code, .. } => match code {
+                    KeyCode::Char(' ') => Command::Pause,
+                    KeyCode::Char('n') => Command::NextFile,
+                    KeyCode::Left => Command::Seek { back: true },
+                    KeyCode::Right => Command::Seek { back: false },
+                    KeyCode::Char('+') | KeyCode::Char('=') => Command::Speed { faster: true },
+                    KeyCode::Char('-') => Command::Speed { faster: false },
+                    KeyCode::Char('m') => Command::ToggleMute(vec![self.selected]),
+                    KeyCode::Char('p') => Command::Panic,
+                    KeyCode::Char(']') => Command::NextMarker,
+                    KeyCode::Char('[') => Command::PreviousMarker,
+                    KeyCode::Enter => Command::Switch,
+                    KeyCode::Up => {
+                        self.selected = self.selected.saturating_sub(1);
+                        continue;
+                    }
+                    KeyCode::Down => {
+                        self.selected = (self.selected + 1).min(15);
+                        continue;
+                    }
+                    KeyCode::PageUp => {
+                        self.scroll = (self.scroll + 10).min(self.log.len());
+                        continue;
+                    }
+                    KeyCode::PageDown => {
+                        self.scroll = self.scroll.saturating_sub(10);
+                        continue;
+                    }
+                    _ => continue,
+                },
+            };
+
+            actions.push(Action::Command(command));
+        }
+
+        Ok(actions)
+    }
+
+    /// Draws a frame, at most once per interval, with the channels in
+    /// `muted` marked.
+    pub fn draw(&mut self, status: &RemoteStatus, speed: u32, muted: u16) -> Result<()> {
+        if self.drawn.elapsed() < self.interval {
+            return Ok(());
+        }
+        self.drawn = Instant::now();
+
+        let (width, height) = terminal::size().context("Failed to get the terminal size")?;
+        let (width, height) = (width as usize, height as usize);
+
+        let mut lines = Vec::with_capacity(height);
+        lines.push(header(status, speed));
+        lines.push(progress_bar(status, self.bar, self.beat, width));
+        lines.push(String::new());
+
+        // The playlist next to the channel meters
+        let left = width.saturating_sub(METER_WIDTH + 10).min(width / 2);
+        let playlist = playlist(status, 16);
+        lines.push(format!("{:<left$}Channels", "Up next", left = left));
+        for channel in 0..16 {
+            let entry = playlist.get(channel).map(String::as_str).unwrap_or("");
+            let entry: String = entry.chars().take(left.saturating_sub(1)).collect();
+            lines.push(format!(
+                "{:<left$}{}",
+                entry,
+                self.meter(channel as u8, muted),
+                left = left
+            ));
+        }
+        lines.push(String::new());
+
+        // The log fills the rest but for the help line
+        let log_height = height.saturating_sub(lines.len() + 2);
+        let end = self.log.len() - self.scroll;
+        let start = end.saturating_sub(log_height);
+        lines.push(match self.scroll {
+            0 => String::from("Events"),
+            scroll => format!("Events ({} lines back)", scroll),
+        });
+        lines.extend(self.log.range(start..end).cloned());
+        while lines.len() < height.saturating_sub(1) {
+            lines.push(String::new());
+        }
+        lines.truncate(height.saturating_sub(1));
+
+        for (row, line) in lines.iter().enumerate() {
+            let line: String = line.chars().take(width).collect();
+            queue!(
+                self.stdout,
+                cursor::MoveTo(0, row as u16),
+                Print(line),
+                terminal::Clear(ClearType::UntilNewLine)
+            )?;
+        }
+        queue!(
+            self.stdout,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Reverse),
+            Print(format!(
+                "{:<width$}",
+                "space pause  n next  \u{2190}\u{2192} seek  +/- speed  \u{2191}\u{2193} m mute  \
+                 p panic  [ ] markers  PgUp/PgDn log  q quit",
+                width = width
+            )),
+            SetAttribute(Attribute::Reset)
+        )?;
+        self.stdout.flush().context("Failed to draw")?;
+
+        for level in &mut self.levels {
+            *level *= METER_DECAY;
+        }
+
+        Ok(())
+    }
+
+    fn meter(&self, channel: u8, muted: u16) -> String {
+        let filled = (self.levels[channel as usize] * METER_WIDTH as f64).round() as usize;
+        let bar = format!(
+            "{}{}",
+            "\u{2588}".repeat(filled),
+            "\u{2591}".repeat(METER_WIDTH - filled.min(METER_WIDTH))
+        );
+        let cursor = if channel == self.selected { '>' } else { ' ' };
+        let state = if muted & 1 << channel != 0 {
+            " muted"
+        } else {
+            ""
+        };
+
+        format!("{}{:>2} {}{}", cursor, channel + 1, bar, state)
+    }
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        let _ = execute!(self.stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn header(status: &RemoteStatus, speed: u32) -> String {
+    let mut header = match &status.title {
+        Some(title) => title.clone(),
+        None => String::from("Nothing playing"),
+    };
+    if status.paused {
+        header.push_str("  [paused]");
+    }
+    if speed != 100 {
+        header.push_str(&format!("  {}% speed", speed));
+    }
+
+    header
+}
+
+fn progress_bar(status: &RemoteStatus, bar: u64, beat: u64, width: usize) -> String {
+    let label = format!(" {} bar {} beat {}", format_time(status.elapsed), bar, beat);
+    let length = width.saturating_sub(label.chars().count() + 2);
+    let filled = ((status.percent / 100.0).clamp(0.0, 1.0) * length as f64) as usize;
+
+    format!(
+        "[{}{}]{}",
+        "#".repeat(filled),
+        "-".repeat(length - filled),
+        label
+    )
+}
+
+/// The song playing and those queued after it, as many as fit.
+fn playlist(status: &RemoteStatus, rows: usize) -> Vec<String> {
+    let name = |path: &PathBuf| {
+        path.file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let mut playlist: Vec<String> = status
+        .title
+        .iter()
+        .map(|title| format!("> {}", title))
+        .collect();
+    playlist.extend(
+        status
+            .queue
+            .iter()
+            .take(rows.saturating_sub(playlist.len()))
+            .map(|path| format!("  {}", name(path))),
+    );
+
+    playlist
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use encoding_rs::Encoding;
+use rimd::{Event, MetaCommand, SMF};
+
+use crate::text;
+
+/// Problems listed per track before only counting the rest.
+const MAX_LISTED: usize = 20;
+
+/// The result of checking a file.
+pub struct Report {
+    pub lines: Vec<String>,
+    pub problems: usize,
+}
+
+/// Checks every track of a Standard MIDI File for notes left sounding or
+/// started twice, data bytes over 127, a missing end of track and SysEx
+/// without its F7.
+pub fn validate(path: &Path, encoding: Option<&'static Encoding>) -> Result<Report> {
+    let midi_data = SMF::from_file(path).context("Failed to parse MIDI file")?;
+    let encoding = encoding.unwrap_or_else(|| text::detect(&midi_data.tracks));
+
+    let mut lines = vec![format!("File: {}", path.display())];
+    let mut total = 0;
+
+    for (i, track) in midi_data.tracks.iter().enumerate() {
+        let mut problems = Vec::new();
+        let mut tick = 0;
+
+        // Tick of the note on for every (channel, key) still sounding
+        let mut sounding: HashMap<(u8, u8), u64> = HashMap::new();
+        let mut ended = false;
+
+        for event in &track.events {
+            tick += event.vtime;
+
+            if ended {
+                problems.push(format!("tick {}: event after the end of track", tick));
+                ended = false;
+            }
+
+            match &event.event {
+                Event::Midi(message) => {
+                    let data = &message.data;
+                    let status = match data.first() {
+                        Some(&status) => status,
+                        None => continue,
+                    };
+
+                    if status == 0xf0 {
+                        if data.last() != Some(&0xf7) {
+                            problems.push(format!("tick {}: SysEx without F7", tick));
+                        }
+                        continue;
+                    }
+
+                    if let Some(byte) = data[1..].iter().find(|&&byte| byte > 0x7f) {
+                        problems.push(format!(
+                            "tick {}: data byte {:02x} out of range in {:02x?}",
+                            tick, byte, data
+                        ));
+                        continue;
+                    }
+
+                    let channel = status & 0x0f;
+                    match (status & 0xf0, data.get(1), data.get(2)) {
+                        (0x90, Some(&key), Some(&velocity)) if velocity > 0 => {
+                            if let Some(started) = sounding.insert((channel, key), tick) {
+                                problems.push(format!(
+                                    "tick {}: note {} on channel {} starts again while \
+                                     sounding since tick {}",
+                                    tick,
+                                    key,
+                                    channel + 1,
+                                    started
+                                ));
+                            }
+                        }
+                        (0x80, Some(&key), _) | (0x90, Some(&key), _) => {
+                            sounding.remove(&(channel, key));
+                        }
+                        _ => {}
+                    };
+                }
+                Event::Meta(meta) => {
+                    if let MetaCommand::EndOfTrack = meta.command {
+                        ended = true;
+                    }
+                }
+            };
+        }
+
+        let mut orphans: Vec<_> = sounding.into_iter().collect();
+        orphans.sort_by_key(|&(_, started)| started);
+        for ((channel, key), started) in orphans {
+            problems.push(format!(
+                "tick {}: note {} on channel {} never ends",
+                started,
+                key,
+                channel + 1
+            ));
+        }
+
+        if !ended {
+            problems.push(String::from("no end of track"));
+        }
+
+        let name = match text::track_name(track, encoding) {
+            Some(name) => format!(" \"{}\"", name),
+            None => String::new(),
+        };
+
+        match problems.len() {
+            0 => lines.push(format!("Track #{}{}: ok", i + 1, name)),
+            count => {
+                lines.push(format!("Track #{}{}: {} problems", i + 1, name, count));
+                lines.extend(
+                    problems
+                        .iter()
+                        .take(MAX_LISTED)
+                        .map(|problem| format!("  {}", problem)),
+                );
+                if count > MAX_LISTED {
+                    lines.push(format!("  and {} more", count - MAX_LISTED));
+                }
+            }
+        };
+
+        total += problems.len();
+    }
+
+    lines.push(format!(
+        "{} problems in {} tracks",
+        total,
+        midi_data.tracks.len()
+    ));
+
+    Ok(Report {
+        lines,
+        problems: total,
+    })
+}
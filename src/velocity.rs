@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::midi_file::{DataEvent, LocalEvent};
+
+/// Exponents of the soft and hard curves, below and above the linear 1.
+const SOFT: f64 = 0.6;
+const HARD: f64 = 1.6;
+
+/// How note on velocities are reshaped before sending, for modules that
+/// respond to velocity differently from the one a file was written on.
+#[derive(Clone)]
+pub enum VelocityCurve {
+    Linear,
+    /// Louder at low velocities, for modules that sound too quiet.
+    Soft,
+    /// Quieter at low velocities, for modules that sound too loud.
+    Hard,
+    /// Every note at the same velocity.
+    Fixed(u8),
+    /// The velocity for every velocity of the file.
+    Table(Box<[u8; 128]>),
+}
+
+impl VelocityCurve {
+    /// Parses `linear`, `soft`, `hard`, `fixed:<velocity>` or the path of a
+    /// table file.
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "linear" => Ok(VelocityCurve::Linear),
+            "soft" => Ok(VelocityCurve::Soft),
+            "hard" => Ok(VelocityCurve::Hard),
+            _ => match value.strip_prefix("fixed:") {
+                Some(velocity) => {
+                    let velocity: u8 = velocity
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("Invalid fixed velocity: {}", velocity))?;
+                    if !(1..=127).contains(&velocity) {
+                        return Err(anyhow!("Fixed velocity must be between 1 and 127"));
+                    }
+
+                    Ok(VelocityCurve::Fixed(velocity))
+                }
+                None => Self::load(Path::new(value)),
+            },
+        }
+    }
+
+    /// Reads a table of 128 velocities, one for each velocity from 0,
+    /// separated by whitespace or commas, with `#` starting a comment.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read velocity curve {}", path.display()))?;
+
+        let mut table = [0; 128];
+        let mut count = 0;
+
+        for value in text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|value| !value.is_empty())
+        {
+            let velocity: u8 = value
+                .parse()
+                .with_context(|| format!("Invalid velocity in {}: {}", path.display(), value))?;
+            if velocity > 127 || count == table.len() {
+                return Err(anyhow!(
+                    "{} must hold 128 velocities from 0 to 127",
+                    path.display()
+                ));
+            }
+
+            table[count] = velocity;
+            count += 1;
+        }
+
+        if count != table.len() {
+            return Err(anyhow!(
+                "{} holds {} velocities instead of 128",
+                path.display(),
+                count
+            ));
+        }
+
+        Ok(VelocityCurve::Table(Box::new(table)))
+    }
+
+    /// The velocity sent for `velocity`, which stays a note on.
+    pub fn map(&self, velocity: u8) -> u8 {
+        let velocity = velocity.min(127);
+        let curved =
+            |exponent: f64| (127.0 * (velocity as f64 / 127.0).powf(exponent)).round() as u8;
+
+        let mapped = match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Soft => curved(SOFT),
+            VelocityCurve::Hard => curved(HARD),
+            VelocityCurve::Fixed(fixed) => *fixed,
+            VelocityCurve::Table(table) => table[velocity as usize],
+        };
+
+        mapped.clamp(1, 127)
+    }
+}
+
+/// Reshapes the velocity of every note on, leaving note ons of velocity 0,
+/// which are note offs, as they are.
+pub fn apply(mut events: Vec<DataEvent>, curve: Option<&VelocityCurve>) -> Vec<DataEvent> {
+    let curve = match curve {
+        Some(curve) => curve,
+        None => return events,
+    };
+
+    for event in &mut events {
+        if let LocalEvent::Midi(data) = &mut event.data {
+            if data[0] & 0xf0 == 0x90 && data[2] > 0 {
+                data[2] = curve.map(data[2]);
+            }
+        }
+    }
+
+    events
+}
@@ -0,0 +1,94 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::cancel::CancellationToken;
+
+/// Set in the environment of the player the watchdog starts, so it plays
+/// instead of starting a watchdog of its own.
+pub const WATCHED_VARIABLE: &str = "MIDI_PLAY_WATCHED";
+
+/// Pause before relaunching a player that failed, so a device that is gone
+/// for good does not make it spin.
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+
+/// Whether this process was started by `supervise`.
+pub fn is_watched() -> bool {
+    env::var_os(WATCHED_VARIABLE).is_some()
+}
+
+/// Runs this executable again with the same arguments, relaunching it every
+/// time it fails, until it exits successfully or `cancel` is cancelled. For
+/// unattended installations, where nobody is around to start it again.
+pub fn supervise(cancel: &CancellationToken) -> Result<()> {
+    let exe = env::current_exe().context("Failed to find the executable")?;
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+
+    loop {
+        let status = Command::new(&exe)
+            .args(&args)
+            .env(WATCHED_VARIABLE, "1")
+            .status()
+            .with_context(|| format!("Failed to start {}", exe.display()))?;
+
+        // Ctrl-C reaches the player as well, which then exits on its own
+        if status.success() || cancel.is_cancelled() {
+            return Ok(());
+        }
+
+        eprintln!("Player exited with {}, restarting", status);
+        thread::sleep(RESTART_DELAY);
+
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+    }
+}
+
+/// Where a watched player was in its playlist, saved as it plays so a
+/// relaunch picks up from there.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ResumePoint {
+    /// Song of the playlist, from 0.
+    pub index: usize,
+    pub tick: u64,
+}
+
+impl ResumePoint {
+    /// The file kept next to `playlist`.
+    pub fn path_for(playlist: &Path) -> PathBuf {
+        let mut name = playlist.as_os_str().to_os_string();
+        name.push(".resume");
+        PathBuf::from(name)
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        let invalid = || format!("Invalid resume point in {}", path.display());
+        let (index, tick) = text.trim().split_once(' ').with_context(invalid)?;
+
+        Ok(Some(Self {
+            index: index.parse().with_context(invalid)?,
+            tick: tick.parse().with_context(invalid)?,
+        }))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, format!("{} {}\n", self.index, self.tick))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
@@ -0,0 +1,93 @@
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tungstenite::{Message, WebSocket};
+
+use crate::events::PlayerEvent;
+
+/// How long a client may take to finish the handshake or to take a message
+/// before it is dropped.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+/// Broadcasts progress, lyrics and notes to every WebSocket client, one JSON
+/// object per message as `--output json` prints them, for a browser page
+/// showing what is playing. Clients only listen, anything they send is
+/// ignored.
+pub struct EventServer {
+    sender: Sender<String>,
+}
+
+impl EventServer {
+    /// Starts accepting clients on `address`, given as `host:port`.
+    pub fn listen(address: &str) -> Result<Self> {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        let clients = Clients::default();
+        let (sender, receiver) = mpsc::channel();
+
+        let accepted = clients.clone();
+        thread::Builder::new()
+            .name(String::from("WebSocket listener"))
+            .spawn(move || accept(listener, accepted))
+            .context("Failed to spawn WebSocket listener thread")?;
+        thread::Builder::new()
+            .name(String::from("WebSocket broadcast"))
+            .spawn(move || broadcast(receiver, clients))
+            .context("Failed to spawn WebSocket broadcast thread")?;
+
+        Ok(Self { sender })
+    }
+
+    /// Queues `event`, which happened `time` into playback, for every client
+    /// if it is one they are interested in.
+    pub fn send(&self, time: Duration, event: &PlayerEvent) {
+        match event {
+            PlayerEvent::Progress { .. }
+            | PlayerEvent::Lyric(_)
+            | PlayerEvent::NoteOn { .. }
+            | PlayerEvent::NoteOff { .. }
+            | PlayerEvent::Tempo(_)
+            | PlayerEvent::Finished => {
+                let _ = self.sender.send(event.to_json(time));
+            }
+            _ => {}
+        };
+    }
+}
+
+fn accept(listener: TcpListener, clients: Clients) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        // A client that never finishes the handshake must not hold up others
+        let handshake = stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+            .map_err(anyhow::Error::from)
+            .and_then(|_| tungstenite::accept(stream).map_err(|e| anyhow!("{}", e)));
+
+        match handshake {
+            Ok(client) => clients.lock().unwrap().push(client),
+            Err(e) => eprintln!("Failed to accept WebSocket client: {:#}", e),
+        };
+    }
+}
+
+fn broadcast(receiver: Receiver<String>, clients: Clients) {
+    for text in receiver {
+        // Forget clients that went away or cannot keep up
+        clients
+            .lock()
+            .unwrap()
+            .retain_mut(|client| client.send(Message::text(text.clone())).is_ok());
+    }
+}
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::backend::{self, MidiOutput};
+use crate::bindings::Windows::Devices::Enumeration::{
+    DeviceInformation, DeviceInformationCollection,
+};
+use crate::bindings::Windows::Devices::Midi::{IMidiOutPort, MidiOutPort};
+use crate::bindings::Windows::Storage::Streams::DataWriter;
+
+/// Output port on the WinRT `Windows.Devices.Midi` API, which unlike WinMM
+/// reaches BLE MIDI devices and lets several applications share a port.
+pub struct WinRtPort {
+    port: IMidiOutPort,
+    /// The connection interval of BLE devices, nothing for others.
+    latency: Duration,
+}
+
+/// Service of the BLE MIDI specification, part of the id of every BLE device.
+const BLE_MIDI_SERVICE: &str = "03b80e5a-ede8-4b33-a751-6ce34ec4c700";
+
+fn devices() -> Result<DeviceInformationCollection> {
+    let selector = MidiOutPort::GetDeviceSelector()
+        .map_err(|e| anyhow!("Failed to get MIDI device selector: {:?}", e))?;
+
+    DeviceInformation::FindAllAsyncAqsFilter(selector)
+        .and_then(|operation| operation.get())
+        .map_err(|e| anyhow!("Failed to enumerate MIDI output devices: {:?}", e))
+}
+
+fn device(port_number: u32) -> Result<DeviceInformation> {
+    let devices = devices()?;
+    let count = devices
+        .Size()
+        .map_err(|e| anyhow!("Failed to count MIDI output devices: {:?}", e))?;
+
+    if port_number >= count {
+        return Err(anyhow!("Port number out of range"));
+    }
+
+    devices
+        .GetAt(port_number)
+        .map_err(|e| anyhow!("Failed to retrieve MIDI output device: {:?}", e))
+}
+
+fn is_ble(device: &DeviceInformation) -> bool {
+    device
+        .Id()
+        .map(|id| id.to_string().to_lowercase().contains(BLE_MIDI_SERVICE))
+        .unwrap_or(false)
+}
+
+/// The BLE devices among the WinRT ones, which WinMM does not reach.
+fn ble_devices() -> Result<Vec<DeviceInformation>> {
+    let devices = devices()?;
+    let count = devices
+        .Size()
+        .map_err(|e| anyhow!("Failed to count MIDI output devices: {:?}", e))?;
+
+    let mut ble_devices = Vec::new();
+    for index in 0..count {
+        let device = devices
+            .GetAt(index)
+            .map_err(|e| anyhow!("Failed to retrieve MIDI output device: {:?}", e))?;
+        if is_ble(&device) {
+            ble_devices.push(device);
+        }
+    }
+
+    Ok(ble_devices)
+}
+
+fn ble_device(index: u32) -> Result<DeviceInformation> {
+    ble_devices()?
+        .into_iter()
+        .nth(index as usize)
+        .context("Port number out of range")
+}
+
+impl WinRtPort {
+    /// Number of BLE devices, listed after the WinMM ports.
+    pub fn ble_count() -> u32 {
+        ble_devices().map_or(0, |devices| devices.len() as u32)
+    }
+
+    pub fn ble_name(index: u32) -> Result<String> {
+        let name = ble_device(index)?
+            .Name()
+            .map_err(|e| anyhow!("Failed to retrieve port name: {:?}", e))?;
+
+        Ok(name.to_string())
+    }
+
+    pub fn connect_ble(index: u32) -> Result<Self> {
+        Self::open(&ble_device(index)?)
+    }
+
+    fn open(device: &DeviceInformation) -> Result<Self> {
+        let id = device
+            .Id()
+            .map_err(|e| anyhow!("Failed to retrieve port id: {:?}", e))?;
+        let port = MidiOutPort::FromIdAsync(id)
+            .and_then(|operation| operation.get())
+            .map_err(|e| anyhow!("Failed to create WinRT MIDI output port: {:?}", e))?;
+        let latency = if is_ble(device) {
+            backend::selected_ble_latency()
+        } else {
+            Duration::ZERO
+        };
+
+        Ok(Self { port, latency })
+    }
+}
+
+impl MidiOutput for WinRtPort {
+    fn count() -> u32 {
+        devices()
+            .and_then(|devices| {
+                devices
+                    .Size()
+                    .map_err(|e| anyhow!("Failed to count MIDI output devices: {:?}", e))
+            })
+            .unwrap_or(0)
+    }
+
+    fn name(port_number: u32) -> Result<String> {
+        let name = device(port_number)?
+            .Name()
+            .map_err(|e| anyhow!("Failed to retrieve port name: {:?}", e))?;
+
+        Ok(name.to_string())
+    }
+
+    fn connect(port_number: u32) -> Result<Self> {
+        Self::open(&device(port_number)?)
+    }
+
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<()> {
+        if message.is_empty() {
+            eprintln!("Attempted to send empty message");
+
+            return Ok(());
+        }
+
+        let writer = DataWriter::new()
+            .map_err(|e| anyhow!("Failed to create message buffer: {:?}", e))
+            .context("Failed to send message")?;
+        let buffer = writer
+            .WriteBytes(message)
+            .and_then(|_| writer.DetachBuffer())
+            .map_err(|e| anyhow!("Failed to fill message buffer: {:?}", e))
+            .context("Failed to send message")?;
+
+        self.port
+            .SendBuffer(buffer)
+            .map_err(|e| anyhow!("Failed to send message: {:?}", e))
+    }
+}
+
+impl Drop for WinRtPort {
+    fn drop(&mut self) {
+        // Reset so other applications do not inherit our state
+        if let Err(e) = self.send_reset().context("Failed to send reset") {
+            eprintln!("{:?}", e);
+        }
+
+        if let Err(e) = self.port.Close() {
+            eprintln!("Failed to close WinRT MIDI output port: {:?}", e);
+        }
+    }
+}